@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use glam::Vec3;
+use iced::futures::executor::block_on;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use lets_trace_some_rays_in_rust::app::rendering::{DisplaySettings, RenderTask};
+use lets_trace_some_rays_in_rust::ltsr::materials::{Lambertian, Material, Metallic};
+use lets_trace_some_rays_in_rust::ltsr::settings::{RenderSettings, WorkingSpace};
+use lets_trace_some_rays_in_rust::ltsr::{
+    random_in_unit_sphere_polar, random_in_unit_sphere_rejection, ray_color, Hittable, Ray, Scene,
+    Sphere,
+};
+
+/// Fixed seed, so that every run benchmarks the same rays and pixels
+const SEED: u64 = 42;
+const NUM_RAYS: usize = 1024;
+
+/// Rays starting from the origin and pointing roughly towards -Z
+fn random_rays(rng: &mut StdRng) -> Vec<Ray> {
+    (0..NUM_RAYS)
+        .map(|_| {
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), -1.0);
+            Ray::new(Vec3::ZERO, direction)
+        })
+        .collect()
+}
+
+/// A grid of 10x10 spheres in front of the camera, over a ground sphere
+fn moderate_scene() -> Scene {
+    let mut scene = Scene::new();
+
+    let diffuse: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.7, 0.3, 0.3)));
+    let metal: Arc<dyn Material> = Arc::new(Metallic::new(Vec3::new(0.8, 0.8, 0.8), 0.3));
+
+    scene.add_hittable(Box::new(Sphere::new(
+        100.0,
+        Vec3::new(0.0, -100.5, -1.0),
+        diffuse.clone(),
+    )));
+
+    for i in 0..10 {
+        for j in 0..10 {
+            let center = Vec3::new(i as f32 * 0.4 - 2.0, j as f32 * 0.4 - 2.0, -4.0);
+            let material = if (i + j) % 2 == 0 {
+                diffuse.clone()
+            } else {
+                metal.clone()
+            };
+            scene.add_hittable(Box::new(Sphere::new(0.15, center, material)));
+        }
+    }
+
+    scene
+}
+
+fn bench_sphere_hit(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays = random_rays(&mut rng);
+    let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    let sphere = Sphere::new(0.5, Vec3::new(0.0, 0.0, -1.0), material);
+
+    c.bench_function("Sphere::hit", |b| {
+        b.iter(|| {
+            for ray in rays.iter() {
+                black_box(sphere.hit(black_box(ray), 0.001, f32::INFINITY));
+            }
+        })
+    });
+}
+
+fn bench_scene_hit(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays = random_rays(&mut rng);
+    let scene = moderate_scene();
+
+    c.bench_function("Scene::hit (101 spheres)", |b| {
+        b.iter(|| {
+            for ray in rays.iter() {
+                black_box(scene.hit(black_box(ray), 0.001, f32::INFINITY));
+            }
+        })
+    });
+}
+
+fn bench_scene_hit_any(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays = random_rays(&mut rng);
+    let scene = moderate_scene();
+
+    c.bench_function("Scene::hit_any (101 spheres)", |b| {
+        b.iter(|| {
+            for ray in rays.iter() {
+                black_box(scene.hit_any(black_box(ray), 0.001, f32::INFINITY));
+            }
+        })
+    });
+}
+
+fn bench_ray_color(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let rays = random_rays(&mut rng);
+    let scene = moderate_scene();
+    let settings = RenderSettings {
+        max_diffuse_depth: 1,
+        max_specular_depth: 1,
+        ..RenderSettings::default()
+    };
+
+    c.bench_function("ray_color (single bounce)", |b| {
+        b.iter(|| {
+            for ray in rays.iter() {
+                black_box(ray_color(black_box(ray), &scene, &settings));
+            }
+        })
+    });
+}
+
+fn bench_random_in_unit_sphere(c: &mut Criterion) {
+    let mut group = c.benchmark_group("random_in_unit_sphere");
+
+    group.bench_function("rejection", |b| {
+        b.iter(|| {
+            for _ in 0..NUM_RAYS {
+                black_box(random_in_unit_sphere_rejection());
+            }
+        })
+    });
+    group.bench_function("polar", |b| {
+        b.iter(|| {
+            for _ in 0..NUM_RAYS {
+                black_box(random_in_unit_sphere_polar());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_convert_to_display_buffer(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let render_buffer: Vec<f32> = (0..256 * 256 * 4)
+        .map(|_| rng.gen_range(0.0..4.0))
+        .collect();
+
+    c.bench_function("convert_to_display_buffer (256x256)", |b| {
+        b.iter(|| {
+            block_on(RenderTask::convert_to_display_buffer(
+                black_box(render_buffer.clone()),
+                HashMap::new(),
+                false,
+                WorkingSpace::default(),
+                DisplaySettings::default(),
+            ))
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_sphere_hit,
+    bench_scene_hit,
+    bench_scene_hit_any,
+    bench_ray_color,
+    bench_random_in_unit_sphere,
+    bench_convert_to_display_buffer
+);
+criterion_main!(benches);
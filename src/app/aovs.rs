@@ -0,0 +1,197 @@
+use crate::ltsr::settings::RenderPass;
+
+/// Arbitrary Output Variables: extra per-pixel data computed alongside the beauty.
+/// Each AOV is stored as its own buffer, with ``num_channels()`` floats per pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Variance of the luminance of the samples taken for each pixel,
+    /// a good estimate of where the image is still noisy
+    Variance,
+    /// Number of samples taken for each pixel (varies with adaptive sampling)
+    SampleCount,
+    /// World space normal of the surface hit by the primary rays
+    Normal,
+    /// Albedo of the material hit by the primary rays
+    Albedo,
+    /// Distance from the camera to the surface hit by the primary rays,
+    /// 0.0 where they only see the background
+    Depth,
+    /// ID of the object covering most of each pixel, 0.0 for the background.
+    /// Lets compositors isolate single objects
+    ObjectId,
+    /// Part of the beauty lit directly: the background seen by the camera
+    /// or after a single scatter (see ``ltsr::Lighting``)
+    Direct,
+    /// Part of the beauty lit after two or more scatters.
+    /// Direct + Indirect gives back the beauty
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Variance,
+        Aov::SampleCount,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    /// Names of the channels used when writing the AOV to an OpenEXR image
+    pub fn channel_names(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Variance => &["variance"],
+            Aov::SampleCount => &["samples"],
+            Aov::Normal => &["N.X", "N.Y", "N.Z"],
+            Aov::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId => &["objectId"],
+            Aov::Direct => &["direct.R", "direct.G", "direct.B"],
+            Aov::Indirect => &["indirect.R", "indirect.G", "indirect.B"],
+        }
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.channel_names().len()
+    }
+
+    /// The pass computing the AOV: either its own, or the beauty
+    /// for the AOVs that come from its samples
+    pub fn render_pass(&self) -> RenderPass {
+        match self {
+            Aov::Normal => RenderPass::Normal,
+            Aov::Albedo => RenderPass::Albedo,
+            Aov::Depth => RenderPass::Depth,
+            Aov::ObjectId => RenderPass::ObjectId,
+            Aov::Variance | Aov::SampleCount | Aov::Direct | Aov::Indirect => RenderPass::Beauty,
+        }
+    }
+}
+
+impl std::fmt::Display for Aov {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Aov::Variance => "Variance",
+            Aov::SampleCount => "Sample Count",
+            Aov::Normal => "Normal",
+            Aov::Albedo => "Albedo",
+            Aov::Depth => "Depth",
+            Aov::ObjectId => "Object ID",
+            Aov::Direct => "Direct",
+            Aov::Indirect => "Indirect",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// What gets shown in the GUI: the beauty, or one of the AOVs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayPass {
+    #[default]
+    Beauty,
+    Aov(Aov),
+}
+
+impl DisplayPass {
+    /// All the passes that can be displayed, beauty first
+    pub fn all() -> Vec<DisplayPass> {
+        std::iter::once(DisplayPass::Beauty)
+            .chain(Aov::ALL.into_iter().map(DisplayPass::Aov))
+            .collect()
+    }
+
+    /// Data passes hold values that aren't colors, so they bypass the
+    /// tonemapping and color management when displayed
+    pub fn is_data_pass(&self) -> bool {
+        match self {
+            DisplayPass::Beauty => false,
+            DisplayPass::Aov(_) => true,
+        }
+    }
+}
+
+impl std::fmt::Display for DisplayPass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayPass::Beauty => write!(f, "Beauty"),
+            DisplayPass::Aov(aov) => write!(f, "{aov}"),
+        }
+    }
+}
+
+/// Converts an AOV buffer to RGBA, remapping its values to the [0, 1] range
+/// so it can be previewed as a data pass via ``convert_to_display_buffer``
+pub fn aov_to_rgba(aov: Aov, aov_buffer: &[f32]) -> Vec<f32> {
+    let num_channels = aov.num_channels();
+    let mut rgba_buffer = Vec::with_capacity(aov_buffer.len() / num_channels * 4);
+
+    match aov {
+        // Normalized by the maximum value
+        Aov::Variance | Aov::SampleCount | Aov::Depth => {
+            let max_value = aov_buffer
+                .iter()
+                .filter(|value| value.is_finite())
+                .fold(0.0_f32, |max, &value| max.max(value));
+            let scale = if max_value > 0.0 {
+                1.0 / max_value
+            } else {
+                0.0
+            };
+
+            for value in aov_buffer {
+                let normalized = (value * scale).clamp(0.0, 1.0);
+                rgba_buffer.extend_from_slice(&[normalized, normalized, normalized, 1.0]);
+            }
+        }
+        // From [-1, 1] to [0, 1]
+        Aov::Normal => {
+            for normal in aov_buffer.chunks_exact(3) {
+                rgba_buffer.extend_from_slice(&[
+                    normal[0] * 0.5 + 0.5,
+                    normal[1] * 0.5 + 0.5,
+                    normal[2] * 0.5 + 0.5,
+                    1.0,
+                ]);
+            }
+        }
+        // A different color for each ID, so that neighbouring objects stand out
+        Aov::ObjectId => {
+            for id in aov_buffer {
+                let color = if *id > 0.0 {
+                    id_to_color(*id as u32)
+                } else {
+                    [0.0, 0.0, 0.0]
+                };
+                rgba_buffer.extend_from_slice(&[color[0], color[1], color[2], 1.0]);
+            }
+        }
+        Aov::Albedo | Aov::Direct | Aov::Indirect => {
+            for color in aov_buffer.chunks_exact(3) {
+                rgba_buffer.extend_from_slice(&[
+                    color[0].clamp(0.0, 1.0),
+                    color[1].clamp(0.0, 1.0),
+                    color[2].clamp(0.0, 1.0),
+                    1.0,
+                ]);
+            }
+        }
+    }
+
+    rgba_buffer
+}
+
+/// Hashes an object ID into a bright, arbitrary color
+fn id_to_color(id: u32) -> [f32; 3] {
+    // Integer hash by Chris Wellons (lowbias32)
+    let mut hash = id;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb352d);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846ca68b);
+    hash ^= hash >> 16;
+
+    [hash, hash >> 8, hash >> 16].map(|byte| 0.25 + 0.75 * (byte & 0xff) as f32 / 255.0)
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::app::aovs::Aov;
+use crate::app::rendering::{RenderResult, RenderStats, Tile};
+use crate::ltsr::settings::{ImageOrigin, WorkingSpace};
+
+/// First bytes of a checkpoint file, followed by the version of the format
+const MAGIC: &[u8; 8] = b"LTSRCKPT";
+const VERSION: u32 = 1;
+
+/// The state of a render split in passes (see ``render_with_checkpoints``),
+/// enough to pick it up again after a crash
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub width: usize,
+    pub height: usize,
+    /// Passes the render is split in
+    pub passes: usize,
+    /// Passes fully rendered so far. The samples of an interrupted pass are
+    /// kept too (see ``Aov::SampleCount``), only the rest of it is rendered again
+    pub passes_done: usize,
+    /// Samples per pixel of the whole render, and its seed: resuming with other
+    /// ones wouldn't give the image the render would have given
+    pub samples_per_pixel: usize,
+    pub seed: u64,
+    /// The samples accumulated so far, with the beauty and the AOVs
+    pub result: RenderResult,
+}
+
+/// Writes ``checkpoint`` to ``path`` as a small header followed by raw little endian
+/// f32 buffers. It's written next to ``path`` first and then moved over it, so a
+/// crash while saving leaves the previous checkpoint in place
+pub fn save_checkpoint(path: impl AsRef<Path>, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent_dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent_dir)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(std::fs::File::create(&temp_path)?);
+
+    let result = &checkpoint.result;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    for value in [
+        checkpoint.width,
+        checkpoint.height,
+        checkpoint.passes,
+        checkpoint.passes_done,
+        checkpoint.samples_per_pixel,
+    ] {
+        write_usize(&mut writer, value)?;
+    }
+    writer.write_all(&checkpoint.seed.to_le_bytes())?;
+    writer.write_all(&[result.image_origin as u8, result.working_space as u8])?;
+
+    write_usize(&mut writer, result.unrendered.len())?;
+    for tile in result.unrendered.iter() {
+        for value in [tile.x, tile.y, tile.width, tile.height] {
+            write_usize(&mut writer, value)?;
+        }
+    }
+
+    // The beauty is tagged with 0, the AOVs with 1 + their index in ``Aov::ALL``
+    let buffers: Vec<(u8, &Vec<f32>)> = result
+        .beauty
+        .iter()
+        .map(|beauty| (0, beauty))
+        .chain(Aov::ALL.iter().enumerate().filter_map(|(index, aov)| {
+            result.aovs.get(aov).map(|buffer| (index as u8 + 1, buffer))
+        }))
+        .collect();
+    write_usize(&mut writer, buffers.len())?;
+    for (tag, buffer) in buffers {
+        writer.write_all(&[tag])?;
+        write_usize(&mut writer, buffer.len())?;
+        for value in buffer.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+    std::fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// Reads back a checkpoint written by ``save_checkpoint``
+pub fn load_checkpoint(path: impl AsRef<Path>) -> anyhow::Result<Checkpoint> {
+    let path = path.as_ref();
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => anyhow::bail!("Failed to read checkpoint {}: {e}", path.display()),
+    };
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        anyhow::bail!("{} is not a render checkpoint", path.display());
+    }
+    let version = u32::from_le_bytes(read_bytes(&mut reader)?);
+    if version != VERSION {
+        anyhow::bail!(
+            "Unsupported checkpoint version {version} in {}, expected {VERSION}",
+            path.display()
+        );
+    }
+
+    let width = read_usize(&mut reader)?;
+    let height = read_usize(&mut reader)?;
+    let passes = read_usize(&mut reader)?;
+    let passes_done = read_usize(&mut reader)?;
+    let samples_per_pixel = read_usize(&mut reader)?;
+    let seed = u64::from_le_bytes(read_bytes(&mut reader)?);
+
+    let [image_origin, working_space] = read_bytes(&mut reader)?;
+    let image_origin = match image_origin {
+        0 => ImageOrigin::TopLeft,
+        1 => ImageOrigin::BottomLeft,
+        other => anyhow::bail!("Invalid image origin {other} in checkpoint"),
+    };
+    let Some(&working_space) = WorkingSpace::ALL.get(working_space as usize) else {
+        anyhow::bail!("Invalid working space {working_space} in checkpoint");
+    };
+
+    let num_tiles = read_usize(&mut reader)?;
+    let mut unrendered = Vec::new();
+    for _ in 0..num_tiles {
+        unrendered.push(Tile {
+            x: read_usize(&mut reader)?,
+            y: read_usize(&mut reader)?,
+            width: read_usize(&mut reader)?,
+            height: read_usize(&mut reader)?,
+        });
+    }
+
+    let num_pixels = width * height;
+    let mut beauty = None;
+    let mut aovs = HashMap::new();
+    let num_buffers = read_usize(&mut reader)?;
+    for _ in 0..num_buffers {
+        let [tag] = read_bytes(&mut reader)?;
+        let num_values = read_usize(&mut reader)?;
+
+        let num_channels = match tag {
+            0 => 4,
+            tag => match Aov::ALL.get(tag as usize - 1) {
+                Some(aov) => aov.num_channels(),
+                None => anyhow::bail!("Invalid buffer {tag} in checkpoint"),
+            },
+        };
+        if num_values != num_pixels * num_channels {
+            anyhow::bail!(
+                "Checkpoint buffer {tag} has {num_values} values, expected {}",
+                num_pixels * num_channels
+            );
+        }
+
+        let mut buffer = Vec::with_capacity(num_values);
+        for _ in 0..num_values {
+            buffer.push(f32::from_le_bytes(read_bytes(&mut reader)?));
+        }
+        match tag {
+            0 => beauty = Some(buffer),
+            tag => {
+                aovs.insert(Aov::ALL[tag as usize - 1], buffer);
+            }
+        }
+    }
+
+    Ok(Checkpoint {
+        width,
+        height,
+        passes,
+        passes_done,
+        samples_per_pixel,
+        seed,
+        result: RenderResult {
+            beauty,
+            aovs,
+            image_origin,
+            working_space,
+            stats: RenderStats::default(),
+            unrendered,
+        },
+    })
+}
+
+fn write_usize(writer: &mut impl Write, value: usize) -> std::io::Result<()> {
+    writer.write_all(&(value as u64).to_le_bytes())
+}
+
+fn read_usize(reader: &mut impl Read) -> anyhow::Result<usize> {
+    let value = u64::from_le_bytes(read_bytes(reader)?);
+    Ok(usize::try_from(value)?)
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> anyhow::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(e) => anyhow::bail!("Truncated checkpoint: {e}"),
+    }
+}
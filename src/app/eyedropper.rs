@@ -0,0 +1,235 @@
+use std::hash::Hash;
+
+use iced_native::event::{self, Event};
+use iced_native::widget::{image, Tree};
+use iced_native::{
+    layout, mouse, renderer, Clipboard, ContentFit, Element, Layout, Length, Point, Rectangle,
+    Shell, Size, Widget,
+};
+
+/// How an image is placed inside of a widget: pixel ``(x, y)`` of the image
+/// is drawn at ``offset + (x, y) * scale``, relative to the top left corner of the widget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewerTransform {
+    pub scale: f32,
+    pub offset: (f32, f32),
+}
+
+impl ViewerTransform {
+    /// Placement of an image scaled to fit inside of ``bounds`` while keeping
+    /// its aspect ratio, and centered (i.e. ``ContentFit::Contain``)
+    pub fn contain(image_size: (usize, usize), bounds: Size) -> Self {
+        let (width, height) = (image_size.0 as f32, image_size.1 as f32);
+        let scale = f32::min(bounds.width / width, bounds.height / height);
+
+        ViewerTransform {
+            scale,
+            offset: (
+                (bounds.width - width * scale).max(0.0) / 2.0,
+                (bounds.height - height * scale).max(0.0) / 2.0,
+            ),
+        }
+    }
+
+    /// Pixel of the image under ``point`` (relative to the top left corner of the widget),
+    /// counting rows from the top. None outside of the image
+    pub fn to_pixel(
+        &self,
+        point: (f32, f32),
+        image_size: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let x = (point.0 - self.offset.0) / self.scale;
+        let y = (point.1 - self.offset.1) / self.scale;
+
+        if !(0.0..image_size.0 as f32).contains(&x) || !(0.0..image_size.1 as f32).contains(&y) {
+            return None;
+        }
+
+        Some((x as usize, y as usize))
+    }
+}
+
+/// Size of an image of ``image_size`` once fit inside of ``available`` without distorting it.
+/// The rest of ``available`` is left empty, on the sides or above and below the image
+pub fn letterbox(image_size: (usize, usize), available: Size) -> Size {
+    let transform = ViewerTransform::contain(image_size, available);
+
+    Size::new(
+        image_size.0 as f32 * transform.scale,
+        image_size.1 as f32 * transform.scale,
+    )
+}
+
+/// Shows an image fit to the available space, and reports
+/// which of its pixels gets clicked
+pub struct Eyedropper<'a, Message, Handle> {
+    handle: Handle,
+    image_size: (usize, usize),
+    width: Length,
+    height: Length,
+    on_pick: Box<dyn Fn(usize, usize) -> Message + 'a>,
+}
+
+impl<'a, Message, Handle> Eyedropper<'a, Message, Handle> {
+    /// ``image_size`` is the resolution of the image behind ``handle``,
+    /// which ``on_pick`` receives the clicked pixel of (rows from the top)
+    pub fn new(
+        handle: Handle,
+        image_size: (usize, usize),
+        on_pick: impl Fn(usize, usize) -> Message + 'a,
+    ) -> Self {
+        Eyedropper {
+            handle,
+            image_size,
+            width: Length::Fill,
+            height: Length::Fill,
+            on_pick: Box::new(on_pick),
+        }
+    }
+
+    fn pixel_under(&self, layout: Layout<'_>, cursor_position: Point) -> Option<(usize, usize)> {
+        let bounds = layout.bounds();
+        if !bounds.contains(cursor_position) {
+            return None;
+        }
+
+        ViewerTransform::contain(self.image_size, bounds.size()).to_pixel(
+            (cursor_position.x - bounds.x, cursor_position.y - bounds.y),
+            self.image_size,
+        )
+    }
+}
+
+impl<'a, Message, Renderer, Handle> Widget<Message, Renderer> for Eyedropper<'a, Message, Handle>
+where
+    Renderer: iced_native::image::Renderer<Handle = Handle>,
+    Handle: Clone + Hash,
+{
+    fn width(&self) -> Length {
+        self.width
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        image::layout(
+            renderer,
+            limits,
+            &self.handle,
+            self.width,
+            self.height,
+            ContentFit::Contain,
+        )
+    }
+
+    fn draw(
+        &self,
+        _state: &Tree,
+        renderer: &mut Renderer,
+        _theme: &Renderer::Theme,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor_position: Point,
+        _viewport: &Rectangle,
+    ) {
+        image::draw(renderer, layout, &self.handle, ContentFit::Contain)
+    }
+
+    fn on_event(
+        &mut self,
+        _state: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _renderer: &Renderer,
+        _clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+    ) -> event::Status {
+        if let Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) = event {
+            if let Some((x, y)) = self.pixel_under(layout, cursor_position) {
+                shell.publish((self.on_pick)(x, y));
+                return event::Status::Captured;
+            }
+        }
+
+        event::Status::Ignored
+    }
+
+    fn mouse_interaction(
+        &self,
+        _state: &Tree,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        _viewport: &Rectangle,
+        _renderer: &Renderer,
+    ) -> mouse::Interaction {
+        match self.pixel_under(layout, cursor_position) {
+            Some(_) => mouse::Interaction::Crosshair,
+            None => mouse::Interaction::Idle,
+        }
+    }
+}
+
+impl<'a, Message, Renderer, Handle> From<Eyedropper<'a, Message, Handle>>
+    for Element<'a, Message, Renderer>
+where
+    Renderer: iced_native::image::Renderer<Handle = Handle>,
+    Handle: Clone + Hash + 'a,
+    Message: 'a,
+{
+    fn from(eyedropper: Eyedropper<'a, Message, Handle>) -> Self {
+        Element::new(eyedropper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VIEWER_MAX_HEIGHT, VIEWER_MAX_WIDTH};
+
+    #[test]
+    fn wide_images_are_centered_vertically() {
+        let image_size = (400, 200);
+        let transform = ViewerTransform::contain(image_size, Size::new(800.0, 800.0));
+
+        assert_eq!(transform.scale, 2.0);
+        assert_eq!(transform.offset, (0.0, 200.0));
+        assert_eq!(
+            letterbox(image_size, Size::new(800.0, 800.0)),
+            Size::new(800.0, 400.0)
+        );
+    }
+
+    #[test]
+    fn clicks_map_back_to_image_pixels() {
+        let image_size = (400, 200);
+        let transform = ViewerTransform::contain(image_size, Size::new(800.0, 800.0));
+
+        assert_eq!(transform.to_pixel((0.0, 200.0), image_size), Some((0, 0)));
+        assert_eq!(
+            transform.to_pixel((799.9, 599.9), image_size),
+            Some((399, 199))
+        );
+        // Above and below the image
+        assert_eq!(transform.to_pixel((400.0, 100.0), image_size), None);
+        assert_eq!(transform.to_pixel((400.0, 600.0), image_size), None);
+
+        let shrunk = ViewerTransform {
+            scale: 0.5,
+            offset: (10.0, 20.0),
+        };
+        assert_eq!(shrunk.to_pixel((60.0, 70.0), (400, 400)), Some((100, 100)));
+        assert_eq!(shrunk.to_pixel((5.0, 70.0), (400, 400)), None);
+    }
+
+    #[test]
+    fn renders_get_letterboxed_in_the_viewer_without_distortion() {
+        let viewer = Size::new(VIEWER_MAX_WIDTH, VIEWER_MAX_HEIGHT);
+
+        assert_eq!(letterbox((1920, 1080), viewer), Size::new(800.0, 450.0));
+        assert_eq!(letterbox((1080, 1920), viewer), Size::new(288.0, 512.0));
+        assert_eq!(letterbox((2048, 2048), viewer), Size::new(512.0, 512.0));
+    }
+}
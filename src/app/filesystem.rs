@@ -1,9 +1,45 @@
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 use crate::app::rendering::SimpleOpenEXRImage;
-use anyhow;
 use exr::prelude::WritableImage;
 
+/// Path of the file named ``file_name`` (as typed by the user) with the given
+/// ``extension`` in ``output_dir``. Path separators are stripped from the name, so
+/// the file always ends up directly in ``output_dir``, whatever the user typed
+pub fn output_file_path(
+    output_dir: impl AsRef<Path>,
+    file_name: &str,
+    extension: &str,
+) -> anyhow::Result<PathBuf> {
+    let output_dir = output_dir.as_ref();
+
+    let file_name: String = file_name
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '/' | '\\'))
+        .collect();
+    if file_name.is_empty() {
+        anyhow::bail!("Invalid file name: it can't be empty");
+    }
+    if file_name.contains('\0') {
+        anyhow::bail!("Invalid file name '{file_name}': it can't contain NUL characters");
+    }
+
+    let path = output_dir.join(format!("{file_name}.{extension}"));
+
+    // Whatever is left of the name must be a plain file name
+    let stays_in_output_dir = path.parent() == Some(output_dir)
+        && matches!(path.components().next_back(), Some(Component::Normal(_)));
+    if !stays_in_output_dir {
+        anyhow::bail!(
+            "Invalid file name '{file_name}': it must stay in {}",
+            output_dir.display()
+        );
+    }
+
+    Ok(path)
+}
+
 /// Saves a simple OpenEXR image (1 Layer, many channels) to disk
 pub fn save_exr_image_to_disk(
     image: SimpleOpenEXRImage,
@@ -34,3 +70,94 @@ pub fn save_exr_image_to_disk(
 
     Ok(())
 }
+
+/// Saves the RGB channels of an RGBA ``render_buffer`` as a 16bit PNG, without
+/// any tonemapping: values are clamped to [0, 1] and quantized to 16 bits.
+/// Everything above 1.0 is lost, so this is no replacement for the full
+/// dynamic range of an OpenEXR image.
+pub fn save_as_png16(
+    image_path: impl AsRef<Path>,
+    width: usize,
+    height: usize,
+    render_buffer: &[f32],
+) -> anyhow::Result<()> {
+    let pixels: Vec<u16> = render_buffer
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .map(|value| (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+        .collect();
+
+    let image = match image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(
+        width as u32,
+        height as u32,
+        pixels,
+    ) {
+        Some(image) => image,
+        None => {
+            anyhow::bail!(
+                "Render buffer has {} values, expected {} for a {width}x{height} image",
+                render_buffer.len(),
+                width * height * 4
+            );
+        }
+    };
+
+    match image.save(&image_path) {
+        Ok(_) => {
+            eprintln!(
+                "Successfully saved image to {}",
+                image_path.as_ref().display()
+            );
+        }
+        Err(e) => {
+            anyhow::bail!("Failed to write image: {e:?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_names_are_rejected() {
+        assert!(output_file_path("outputs", "", "exr").is_err());
+    }
+
+    #[test]
+    fn whitespace_only_file_names_are_rejected() {
+        assert!(output_file_path("outputs", "  \t ", "exr").is_err());
+    }
+
+    #[test]
+    fn file_names_cant_leave_the_output_dir() {
+        let path = output_file_path("outputs", "../x", "exr").unwrap();
+
+        assert_eq!(path, Path::new("outputs").join("..x.exr"));
+        assert_eq!(path.parent(), Some(Path::new("outputs")));
+    }
+
+    #[test]
+    fn valid_file_names_get_the_extension() {
+        let path = output_file_path("outputs", " render_v001 ", "exr").unwrap();
+
+        assert_eq!(path, Path::new("outputs").join("render_v001.exr"));
+    }
+
+    #[test]
+    fn png16_images_keep_16_bits_of_the_clamped_values() {
+        let render_buffer = [0.5, 0.25, 1.5, 1.0, -0.1, 1.0 / 3.0, 0.0, 1.0];
+        let path = std::env::temp_dir().join(format!("ltsr_test_png16_{}.png", std::process::id()));
+
+        save_as_png16(&path, 2, 1, &render_buffer).unwrap();
+        let image = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let image = image.as_rgb16().expect("The image isn't 16bit RGB");
+        assert_eq!(image.dimensions(), (2, 1));
+        // round(value * 65535), after clamping
+        assert_eq!(image.as_raw(), &[32768, 16384, 65535, 0, 21845, 0]);
+    }
+}
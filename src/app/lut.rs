@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use glam::Vec3;
+
+/// A 3D lookup table, as found in ``.cube`` files (eg: a show LUT),
+/// mapping display values to display values
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeLut {
+    /// Number of samples along each axis of the grid
+    pub size: usize,
+    /// ``size^3`` output colors, with red changing the fastest, then green, then blue
+    pub table: Vec<Vec3>,
+    /// Input values mapped to the first and the last samples of the grid
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+}
+
+impl CubeLut {
+    fn sample(&self, r: usize, g: usize, b: usize) -> Vec3 {
+        self.table[(b * self.size + g) * self.size + r]
+    }
+
+    /// Looks up ``color`` in the table, trilinearly interpolating the closest samples.
+    /// Values outside of the domain are clamped to it
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let max_index = (self.size - 1) as f32;
+        let position = ((color - self.domain_min) / (self.domain_max - self.domain_min))
+            .clamp(Vec3::ZERO, Vec3::ONE)
+            * max_index;
+
+        // Cell containing the color (the last one for the maximum values),
+        // and where the color is within it
+        let lower = position.floor().min(Vec3::splat(max_index - 1.0));
+        let fraction = position - lower;
+        let (r, g, b) = (lower.x as usize, lower.y as usize, lower.z as usize);
+
+        let lerp_r = |g: usize, b: usize| {
+            self.sample(r, g, b)
+                .lerp(self.sample(r + 1, g, b), fraction.x)
+        };
+        let lerp_g = |b: usize| lerp_r(g, b).lerp(lerp_r(g + 1, b), fraction.y);
+
+        lerp_g(b).lerp(lerp_g(b + 1), fraction.z)
+    }
+}
+
+/// Parses a 3D LUT in the ``.cube`` format (as specified by Adobe/Resolve).
+/// 1D LUTs aren't supported
+pub fn parse_cube_lut(cube: &str) -> anyhow::Result<CubeLut> {
+    let mut size = None;
+    let mut table = Vec::new();
+    let mut domain_min = Vec3::ZERO;
+    let mut domain_max = Vec3::ONE;
+
+    let parse_vec3 = |fields: &[&str], line_number: usize| -> anyhow::Result<Vec3> {
+        if fields.len() != 3 {
+            anyhow::bail!(
+                "Line {line_number}: expected 3 values, found {}",
+                fields.len()
+            );
+        }
+
+        let mut values = [0.0; 3];
+        for (value, field) in values.iter_mut().zip(fields) {
+            *value = match field.parse() {
+                Ok(value) => value,
+                Err(e) => {
+                    anyhow::bail!("Line {line_number}: invalid value '{field}': {e}");
+                }
+            };
+        }
+
+        Ok(Vec3::from_array(values))
+    };
+
+    for (line_index, line) in cube.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[0] {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => {
+                anyhow::bail!("Line {line_number}: 1D LUTs are not supported");
+            }
+            "LUT_3D_SIZE" => {
+                size = match fields.get(1).map(|field| field.parse::<usize>()) {
+                    Some(Ok(size)) if size >= 2 => Some(size),
+                    _ => {
+                        anyhow::bail!("Line {line_number}: invalid LUT_3D_SIZE '{line}'");
+                    }
+                };
+            }
+            "DOMAIN_MIN" => domain_min = parse_vec3(&fields[1..], line_number)?,
+            "DOMAIN_MAX" => domain_max = parse_vec3(&fields[1..], line_number)?,
+            _ => table.push(parse_vec3(&fields, line_number)?),
+        }
+    }
+
+    let size = match size {
+        Some(size) => size,
+        None => {
+            anyhow::bail!("Missing LUT_3D_SIZE");
+        }
+    };
+    if table.len() != size.pow(3) {
+        anyhow::bail!(
+            "Expected {} samples for a LUT_3D_SIZE of {size}, found {}",
+            size.pow(3),
+            table.len()
+        );
+    }
+    if domain_min.cmpge(domain_max).any() {
+        anyhow::bail!("DOMAIN_MIN {domain_min} must be below DOMAIN_MAX {domain_max}");
+    }
+
+    Ok(CubeLut {
+        size,
+        table,
+        domain_min,
+        domain_max,
+    })
+}
+
+/// Loads a ``.cube`` 3D LUT from disk
+pub fn load_cube_lut(path: impl AsRef<Path>) -> anyhow::Result<CubeLut> {
+    let path = path.as_ref();
+
+    let cube = match std::fs::read_to_string(path) {
+        Ok(cube) => cube,
+        Err(e) => {
+            anyhow::bail!("Failed to read LUT {}: {e}", path.display());
+        }
+    };
+
+    match parse_cube_lut(&cube) {
+        Ok(lut) => Ok(lut),
+        Err(e) => {
+            anyhow::bail!("Failed to parse LUT {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "\
+TITLE \"Identity\"
+# Red changes the fastest
+LUT_3D_SIZE 2
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    const INVERT: &str = "\
+LUT_3D_SIZE 2
+1 1 1
+0 1 1
+1 0 1
+0 0 1
+1 1 0
+0 1 0
+1 0 0
+0 0 0
+";
+
+    const COLORS: [Vec3; 4] = [
+        Vec3::ZERO,
+        Vec3::ONE,
+        Vec3::new(0.18, 0.5, 0.9),
+        Vec3::new(1.0, 0.25, 0.0),
+    ];
+
+    #[test]
+    fn identity_luts_leave_colors_unchanged() {
+        let lut = parse_cube_lut(IDENTITY).unwrap();
+
+        for color in COLORS {
+            let mapped = lut.apply(color);
+            assert!(mapped.abs_diff_eq(color, 1e-6), "{color} became {mapped}");
+        }
+    }
+
+    #[test]
+    fn inverting_luts_invert_colors() {
+        let lut = parse_cube_lut(INVERT).unwrap();
+
+        for color in COLORS {
+            let mapped = lut.apply(color);
+            assert!(
+                mapped.abs_diff_eq(Vec3::ONE - color, 1e-6),
+                "{color} became {mapped}"
+            );
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use exr::meta::attribute::{AttributeValue, Text};
+use exr::meta::MetaData;
+use glam::Vec3;
+
+use crate::app::rendering::SimpleOpenEXRImage;
+use crate::ltsr::settings::RenderSettings;
+use crate::ltsr::Camera;
+
+/// Prefix of the names of the OpenEXR attributes holding the render metadata
+const PREFIX: &str = "ltsr/";
+
+/// What a saved image was rendered with, to know where it comes from and to render
+/// it again. Stored as custom attributes of the OpenEXR images (see ``tag``)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderMetadata {
+    pub camera_position: Vec3,
+    /// Vertical field of view of the camera, in degrees
+    pub field_of_view: f32,
+    pub samples_per_pixel: usize,
+    pub max_diffuse_depth: i32,
+    pub max_specular_depth: i32,
+    pub seed: u64,
+    pub render_time: Duration,
+}
+
+impl RenderMetadata {
+    pub fn new(camera: &Camera, settings: &RenderSettings, render_time: Duration) -> Self {
+        RenderMetadata {
+            camera_position: camera.position(),
+            field_of_view: camera.vertical_field_of_view(),
+            samples_per_pixel: settings.samples_per_pixel,
+            max_diffuse_depth: settings.max_diffuse_depth,
+            max_specular_depth: settings.max_specular_depth,
+            seed: settings.seed,
+            render_time,
+        }
+    }
+
+    /// Adds the metadata to the attributes of the layer of ``image``
+    pub fn tag(&self, image: &mut SimpleOpenEXRImage) {
+        let attributes = &mut image.layer_data.attributes.other;
+        let mut insert = |name: &str, value: AttributeValue| {
+            attributes.insert(Text::from(format!("{PREFIX}{name}").as_str()), value);
+        };
+
+        let position = self.camera_position;
+        insert(
+            "cameraPosition",
+            AttributeValue::FloatVec3((position.x, position.y, position.z)),
+        );
+        insert("fieldOfView", AttributeValue::F32(self.field_of_view));
+        insert(
+            "samplesPerPixel",
+            AttributeValue::I32(i32::try_from(self.samples_per_pixel).unwrap_or(i32::MAX)),
+        );
+        insert(
+            "maxDiffuseDepth",
+            AttributeValue::I32(self.max_diffuse_depth),
+        );
+        insert(
+            "maxSpecularDepth",
+            AttributeValue::I32(self.max_specular_depth),
+        );
+        // Integers are only 32 bits in OpenEXR: the seed goes in as text
+        insert(
+            "seed",
+            AttributeValue::Text(Text::from(self.seed.to_string().as_str())),
+        );
+        insert(
+            "renderTime",
+            AttributeValue::F64(self.render_time.as_secs_f64()),
+        );
+    }
+}
+
+/// Reads back the metadata saved with ``RenderMetadata::tag`` in the OpenEXR image at ``path``
+pub fn read_render_metadata(path: impl AsRef<Path>) -> anyhow::Result<RenderMetadata> {
+    let path = path.as_ref();
+    let meta_data = match MetaData::read_from_file(path, false) {
+        Ok(meta_data) => meta_data,
+        Err(e) => anyhow::bail!("Failed to read {}: {e}", path.display()),
+    };
+    let Some(header) = meta_data.headers.first() else {
+        anyhow::bail!("{} has no layers", path.display());
+    };
+    let attributes = &header.own_attributes.other;
+
+    let missing = |name: &str| {
+        anyhow::anyhow!(
+            "{} has no valid {PREFIX}{name} attribute, was it rendered here?",
+            path.display()
+        )
+    };
+
+    let (x, y, z) = match attribute(attributes, "cameraPosition") {
+        Some(AttributeValue::FloatVec3(position)) => *position,
+        _ => return Err(missing("cameraPosition")),
+    };
+    let field_of_view = match attribute(attributes, "fieldOfView") {
+        Some(AttributeValue::F32(field_of_view)) => *field_of_view,
+        _ => return Err(missing("fieldOfView")),
+    };
+    let integer = |name: &str| match attribute(attributes, name) {
+        Some(AttributeValue::I32(value)) => Ok(*value),
+        _ => Err(missing(name)),
+    };
+    let samples_per_pixel =
+        usize::try_from(integer("samplesPerPixel")?).map_err(|_| missing("samplesPerPixel"))?;
+    let seed = match attribute(attributes, "seed") {
+        Some(AttributeValue::Text(seed)) => {
+            seed.to_string().parse().map_err(|_| missing("seed"))?
+        }
+        _ => return Err(missing("seed")),
+    };
+    let render_time = match attribute(attributes, "renderTime") {
+        Some(AttributeValue::F64(seconds)) => {
+            Duration::try_from_secs_f64(*seconds).map_err(|_| missing("renderTime"))?
+        }
+        _ => return Err(missing("renderTime")),
+    };
+
+    Ok(RenderMetadata {
+        camera_position: Vec3::new(x, y, z),
+        field_of_view,
+        samples_per_pixel,
+        max_diffuse_depth: integer("maxDiffuseDepth")?,
+        max_specular_depth: integer("maxSpecularDepth")?,
+        seed,
+        render_time,
+    })
+}
+
+fn attribute<'a>(
+    attributes: &'a HashMap<Text, AttributeValue>,
+    name: &str,
+) -> Option<&'a AttributeValue> {
+    attributes.get(&Text::from(format!("{PREFIX}{name}").as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::filesystem::save_exr_image_to_disk;
+    use crate::app::rendering::{convert_to_openexr, ExrSettings};
+    use crate::ltsr::settings::{ImageOrigin, WorkingSpace};
+
+    const SIZE: usize = 4;
+
+    fn test_metadata() -> RenderMetadata {
+        let settings = RenderSettings {
+            samples_per_pixel: 37,
+            seed: u64::MAX - 5,
+            ..RenderSettings::default()
+        };
+        RenderMetadata::new(
+            &Camera::new(1.0, 2.0, 2.0),
+            &settings,
+            Duration::from_millis(1500),
+        )
+    }
+
+    fn test_image() -> SimpleOpenEXRImage {
+        convert_to_openexr(
+            SIZE,
+            SIZE,
+            Some(&[0.5; SIZE * SIZE * 4]),
+            &HashMap::new(),
+            ImageOrigin::default(),
+            WorkingSpace::default(),
+            ExrSettings::default(),
+        )
+        .unwrap()
+    }
+
+    /// Saves ``image`` to a temporary file and reads its metadata back
+    fn save_and_read(image: SimpleOpenEXRImage, name: &str) -> anyhow::Result<RenderMetadata> {
+        let path =
+            std::env::temp_dir().join(format!("ltsr_test_{name}_{}.exr", std::process::id()));
+        save_exr_image_to_disk(image, &path).unwrap();
+        let metadata = read_render_metadata(&path);
+        std::fs::remove_file(&path).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn saved_images_give_back_their_metadata() {
+        let metadata = test_metadata();
+        let mut image = test_image();
+        metadata.tag(&mut image);
+
+        let read = save_and_read(image, "metadata").unwrap();
+
+        assert_eq!(read.samples_per_pixel, 37);
+        assert_eq!(read.seed, u64::MAX - 5);
+        assert_eq!(read, metadata);
+    }
+
+    #[test]
+    fn infinite_render_times_are_rejected() {
+        let mut image = test_image();
+        test_metadata().tag(&mut image);
+        image.layer_data.attributes.other.insert(
+            Text::from("ltsr/renderTime"),
+            AttributeValue::F64(f64::INFINITY),
+        );
+
+        let error = save_and_read(image, "infinite_time").unwrap_err();
+
+        assert!(error.to_string().contains("renderTime"), "{error}");
+    }
+
+    #[test]
+    fn untagged_images_have_no_metadata() {
+        assert!(save_and_read(test_image(), "untagged").is_err());
+    }
+}
@@ -1,42 +1,159 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
-use iced::futures;
 use iced::theme::Theme;
-use iced::widget::{button, column, container, image, progress_bar, row, text, text_input};
-use iced::{Application, Command, Element, Length};
-
-use crate::app::filesystem::save_exr_image_to_disk;
-use crate::app::rendering::{convert_to_openexr, RenderTask};
-use crate::constants::{RENDER_BUFFER_HEIGHT, RENDER_BUFFER_SIZE, RENDER_BUFFER_WIDTH};
-
-mod filesystem;
-mod rendering;
+use iced::widget::{
+    button, checkbox, column, container, image, pick_list, progress_bar, row, slider, text,
+    text_input,
+};
+use iced::{Application, Command, Element, Length, Size, Subscription};
+
+use crate::app::aovs::{aov_to_rgba, Aov, DisplayPass};
+use crate::app::eyedropper::{letterbox, Eyedropper};
+use crate::app::filesystem::{output_file_path, save_exr_image_to_disk};
+use crate::app::lut::load_cube_lut;
+use crate::app::metadata::RenderMetadata;
+use crate::app::postprocessing::compute_auto_exposure;
+use crate::app::rendering::{
+    convert_to_openexr, fill_with_placeholder, placeholder_display_buffer, DisplaySettings,
+    ExrBitDepth, ExrCompression, ExrSettings, OutputColorSpace, RenderControl, RenderOutput,
+    RenderResult, RenderStats, RenderTask, Tile, Tonemap, PLACEHOLDER_CHECKER_SIZE,
+};
+use crate::app::scenes::DemoScene;
+use crate::constants::{
+    RENDER_BUFFER_HEIGHT, RENDER_BUFFER_SIZE, RENDER_BUFFER_WIDTH, VIEWER_MAX_HEIGHT,
+    VIEWER_MAX_WIDTH,
+};
+use crate::ltsr::settings::{ImageOrigin, Quality, RenderPass, RenderSettings, WorkingSpace};
+use crate::ltsr::validation::SceneWarning;
+
+pub mod aovs;
+pub mod checkpoint;
+pub mod eyedropper;
+pub mod filesystem;
+pub mod lut;
+pub mod metadata;
+pub mod postprocessing;
+pub mod rendering;
+pub mod scenes;
 
 #[derive(Debug, Clone)]
 pub enum AppError {
     RenderError,
+    DisplayConversionError,
+    /// The render settings were rejected before rendering anything, with the reason
+    InvalidSettings(String),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::RenderError => write!(f, "the render could not be completed"),
+            AppError::DisplayConversionError => {
+                write!(f, "the render buffer could not be converted for display")
+            }
+            AppError::InvalidSettings(reason) => write!(f, "{reason}"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     FileNameChanged(String),
     SaveFilePressed,
+    HalfFloatToggled(bool),
+    ExrCompressionSelected(ExrCompression),
     RenderPressed,
-    RenderTaskFinished(Result<Vec<f32>, AppError>),
+    HigherQualityPressed,
+    PausePressed,
+    ResumePressed,
+    CancelPressed,
+    PreviewQualityToggled(bool),
+    SceneSelected(DemoScene),
+    WorkingSpaceSelected(WorkingSpace),
+    AovOnlyToggled(bool),
+    AutoExposurePressed,
+    DisplayPassSelected(DisplayPass),
+    OutputColorSpaceSelected(OutputColorSpace),
+    TonemapSelected(Tonemap),
+    DenoiseToggled(bool),
+    DenoiseStrengthChanged(f32),
+    EdgeOverlayToggled(bool),
+    GammaChanged(f32),
+    SplitViewToggled(bool),
+    LutPathChanged(String),
+    LoadLutPressed,
+    EyedropperToggled(bool),
+    PixelPicked(usize, usize),
+    RenderProgressTick,
+    /// Boxed, as a render carries a lot more than the other messages
+    RenderTaskFinished(Result<Box<RenderOutput>, AppError>),
+    /// The converted display buffer
     DisplayConversionTaskFinished(Result<Vec<u8>, AppError>),
 }
 
+/// How often the progress of a running render gets refreshed in the GUI
+const RENDER_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
 /// Stores the state of the Application (GUI and all)
 pub struct LTSRApp {
     pub file_name: String,
-    pub file_name_with_ext: String,
     pub current_render_progress: f32,
     pub render_progress_label: String,
+    /// Likely mistakes in the setup of the scene, shown under the progress
+    pub scene_warnings: Vec<SceneWarning>,
+    /// Whether a render is running in the background
+    pub is_rendering: bool,
+    /// Pauses and resumes the render running in the background
+    pub render_control: Arc<RenderControl>,
 
     /// 8bit image displayed in the GUI
     pub rendered_image: image::Handle,
+    /// RGBA pixels of ``rendered_image``, from the top row down
+    pub display_buffer: Vec<u8>,
+    /// Whether clicking the image reports the values of a pixel, instead of panning it
+    pub eyedropper: bool,
     /// 32bit floating point render buffer storing the rendered image
     pub render_buffer: Vec<f32>,
+    /// Whether the Beauty pass was rendered: otherwise ``render_buffer`` is black
+    pub has_beauty: bool,
+    /// Single channel AOVs rendered alongside the render buffer
+    pub aov_buffers: HashMap<Aov, Vec<f32>>,
+    /// Order of the rows in the render buffer and in the AOVs
+    pub image_origin: ImageOrigin,
+    /// Color space of the render buffer and of the color AOVs
+    pub render_working_space: WorkingSpace,
+    /// Display settings the render running in the background converts itself with
+    pub pending_display_settings: Option<DisplaySettings>,
+    /// Settings and scene of the render running in the background
+    pub pending_render_settings: Option<RenderSettings>,
+    pub pending_demo_scene: Option<DemoScene>,
+    /// Settings the render buffer was rendered with, None until a render finishes
+    pub render_settings: Option<RenderSettings>,
+    /// Scene the render buffer shows, None until a render finishes
+    pub rendered_demo_scene: Option<DemoScene>,
+    /// What the render buffer was rendered with, saved along with it in the OpenEXR images
+    pub render_metadata: Option<RenderMetadata>,
+    /// Parts of the image the last render didn't get to (if it was cancelled),
+    /// shown as the placeholder
+    pub unrendered_tiles: Vec<Tile>,
+    /// Which of the rendered passes is shown in the GUI
+    pub display_pass: DisplayPass,
+    /// How the render buffer gets converted to the 8bit image
+    pub display_settings: DisplaySettings,
+    /// Path of the ``.cube`` LUT to load for display, if any
+    pub lut_path: String,
+    /// How the render buffer gets written to disk
+    pub exr_settings: ExrSettings,
+    /// Preset used for the next render
+    pub quality: Quality,
+    /// Built-in scene rendered by the next render
+    pub demo_scene: DemoScene,
+    /// Color space the next render works in
+    pub working_space: WorkingSpace,
+    /// Whether the next render only fills the AOV shown in the viewer, when it can
+    pub aov_only: bool,
 }
 
 impl Application for LTSRApp {
@@ -49,7 +166,11 @@ impl Application for LTSRApp {
         let file_name = String::from("sample_file");
 
         let render_buffer: Vec<f32> = vec![0.0; RENDER_BUFFER_SIZE];
-        let display_buffer: Vec<u8> = vec![0; RENDER_BUFFER_SIZE];
+        let display_buffer = placeholder_display_buffer(
+            RENDER_BUFFER_WIDTH,
+            RENDER_BUFFER_HEIGHT,
+            PLACEHOLDER_CHECKER_SIZE,
+        );
 
         // Creates an image Handle containing the image pixels directly.
         // This function expects the input data to be provided as a Vec<u8> of RGBA pixels.
@@ -60,15 +181,40 @@ impl Application for LTSRApp {
         );
 
         let render_progress_label = String::from("Render not started.");
+        let demo_scene = DemoScene::default();
+        let scene_warnings = demo_scene.warnings();
 
         (
             LTSRApp {
-                file_name: file_name.clone(),
-                file_name_with_ext: format!("{file_name}.exr"),
+                file_name,
                 current_render_progress: 0.0,
+                is_rendering: false,
+                render_control: Arc::new(RenderControl::new()),
                 rendered_image: image,
+                display_buffer,
+                eyedropper: false,
                 render_buffer,
+                has_beauty: true,
+                aov_buffers: HashMap::new(),
+                image_origin: ImageOrigin::default(),
+                render_working_space: WorkingSpace::default(),
+                pending_display_settings: None,
+                pending_render_settings: None,
+                pending_demo_scene: None,
+                render_settings: None,
+                rendered_demo_scene: None,
+                render_metadata: None,
+                unrendered_tiles: Vec::new(),
+                display_pass: DisplayPass::default(),
+                display_settings: DisplaySettings::default(),
+                lut_path: String::new(),
+                exr_settings: ExrSettings::default(),
+                quality: Quality::default(),
+                demo_scene,
+                working_space: WorkingSpace::default(),
+                aov_only: false,
                 render_progress_label,
+                scene_warnings,
             },
             Command::none(),
         )
@@ -79,32 +225,218 @@ impl Application for LTSRApp {
     }
 
     // Description of the UI
-    fn view(&self) -> Element<Self::Message> {
-        // This stores the image after it has been rendered
-        let image_viewer = image::Viewer::new(self.rendered_image.clone()).min_scale(1.0);
-
-        let rendered_image = container(image_viewer)
-            .width(Length::Fill)
-            .center_x()
-            .max_height(512)
-            .max_width(800);
+    fn view(&self) -> Element<'_, Self::Message> {
+        // This stores the image after it has been rendered.
+        // The eyedropper shows it fit to the viewer, since the viewer pans on clicks
+        let image_viewer: Element<'_, Self::Message> = if self.eyedropper {
+            Eyedropper::new(
+                self.rendered_image.clone(),
+                (RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT),
+                Self::Message::PixelPicked,
+            )
+            .into()
+        } else {
+            image::Viewer::new(self.rendered_image.clone())
+                .min_scale(1.0)
+                .into()
+        };
+
+        // The viewer gets the aspect ratio of the render, so that it isn't stretched
+        // to the available area (which gets letterboxed instead)
+        let viewer_size = letterbox(
+            (RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT),
+            Size::new(VIEWER_MAX_WIDTH, VIEWER_MAX_HEIGHT),
+        );
+        let rendered_image = container(
+            container(image_viewer)
+                .width(Length::Fixed(viewer_size.width))
+                .height(Length::Fixed(viewer_size.height)),
+        )
+        .width(Length::Fill)
+        .height(Length::Fixed(VIEWER_MAX_HEIGHT))
+        .center_x()
+        .center_y();
 
-        // Render button
-        let render_button = button(
+        // Render button, disabled while a render is running
+        let mut render_button = button(
             text("Start new Render")
                 .width(Length::Fill)
                 .horizontal_alignment(iced::alignment::Horizontal::Center),
         )
-        .on_press(Self::Message::RenderPressed)
         .padding(10)
         .width(Length::Fill);
+        if !self.is_rendering {
+            render_button = render_button.on_press(Self::Message::RenderPressed);
+        }
+
+        // Render the same scene again with more samples, on top of the current ones
+        let mut higher_quality_button = button(
+            text("Higher Quality")
+                .width(Length::Fill)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .padding(10)
+        .width(150);
+        if !self.is_rendering && self.has_beauty && self.render_settings.is_some() {
+            higher_quality_button =
+                higher_quality_button.on_press(Self::Message::HigherQualityPressed);
+        }
+
+        // Pause or resume the running render
+        let is_paused = self.render_control.is_paused();
+        let mut pause_button = button(
+            text(if is_paused { "Resume" } else { "Pause" })
+                .width(Length::Fill)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .padding(10)
+        .width(100);
+        if self.is_rendering {
+            pause_button = pause_button.on_press(if is_paused {
+                Self::Message::ResumePressed
+            } else {
+                Self::Message::PausePressed
+            });
+        }
+
+        // Stop the running render, keeping what it has rendered so far
+        let mut cancel_button = button(
+            text("Cancel")
+                .width(Length::Fill)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .padding(10)
+        .width(100);
+        if self.is_rendering && !self.render_control.is_cancelled() {
+            cancel_button = cancel_button.on_press(Self::Message::CancelPressed);
+        }
+
+        // Faster, noisier renders
+        let preview_quality_checkbox = checkbox(
+            "Preview",
+            self.quality == Quality::Preview,
+            Self::Message::PreviewQualityToggled,
+        );
+        // Scene rendered by the next render
+        let demo_scene_list = pick_list(
+            &DemoScene::ALL[..],
+            Some(self.demo_scene),
+            Self::Message::SceneSelected,
+        );
+        let working_space_list = pick_list(
+            &WorkingSpace::ALL[..],
+            Some(self.working_space),
+            Self::Message::WorkingSpaceSelected,
+        );
+        let aov_only_checkbox = checkbox(
+            "Shown AOV only",
+            self.aov_only,
+            Self::Message::AovOnlyToggled,
+        );
+
+        // Exposure
+        let auto_exposure_button = button(
+            text("Auto Exposure")
+                .width(Length::Fill)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .on_press(Self::Message::AutoExposurePressed)
+        .padding(10)
+        .width(200);
+
+        // Pass shown in the viewer
+        let display_pass_list = pick_list(
+            DisplayPass::all(),
+            Some(self.display_pass),
+            Self::Message::DisplayPassSelected,
+        );
+
+        // Curve bringing the HDR values into the display range
+        let tonemap_list = pick_list(
+            &Tonemap::ALL[..],
+            Some(self.display_settings.tonemap),
+            Self::Message::TonemapSelected,
+        );
+
+        // Color space the image gets encoded to
+        let output_color_space_list = pick_list(
+            &OutputColorSpace::ALL[..],
+            Some(self.display_settings.output_color_space),
+            Self::Message::OutputColorSpaceSelected,
+        );
+
+        // Denoising
+        let denoise_checkbox = checkbox(
+            "Denoise",
+            self.display_settings.denoise,
+            Self::Message::DenoiseToggled,
+        );
+        let denoise_strength_slider = slider(
+            0.0..=4.0,
+            self.display_settings.denoise_strength,
+            Self::Message::DenoiseStrengthChanged,
+        )
+        .step(0.1);
+
+        // Geometry inspection
+        let edge_overlay_checkbox = checkbox(
+            "Edges",
+            self.display_settings.edge_overlay,
+            Self::Message::EdgeOverlayToggled,
+        );
+
+        // Color pipeline inspection
+        let gamma_slider = slider(
+            0.2..=3.0,
+            self.display_settings.gamma,
+            Self::Message::GammaChanged,
+        )
+        .step(0.05);
+        let split_view_checkbox = checkbox(
+            "Before/After",
+            self.display_settings.split_view,
+            Self::Message::SplitViewToggled,
+        );
+
+        let eyedropper_checkbox = checkbox(
+            "Eyedropper",
+            self.eyedropper,
+            Self::Message::EyedropperToggled,
+        );
+
+        // Display LUT, cleared by loading an empty path
+        let lut_path_input = text_input(
+            "Display LUT (.cube)",
+            &self.lut_path,
+            Self::Message::LutPathChanged,
+        )
+        .padding(10);
+        let load_lut_button = button(
+            text("Load LUT")
+                .width(Length::Fill)
+                .horizontal_alignment(iced::alignment::Horizontal::Center),
+        )
+        .on_press(Self::Message::LoadLutPressed)
+        .padding(10)
+        .width(120);
 
         // Progress Report
-        // let render_progress_bar = progress_bar(0.0..=100.0, self.current_render_progress);
+        let render_progress_bar = progress_bar(0.0..=100.0, self.current_render_progress);
         let render_progress_label = container(text(&self.render_progress_label).size(12))
             .width(Length::Fill)
             .center_x();
 
+        // Warn about the scene before staring at a black render
+        let scene_warnings = self
+            .scene_warnings
+            .iter()
+            .map(|warning| format!("Warning: {warning}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let scene_warnings_label = container(text(scene_warnings).size(12))
+            .width(Length::Fill)
+            .center_x();
+
         // Save text field
         let file_name_input = text_input(
             "Your file name",
@@ -124,13 +456,71 @@ impl Application for LTSRApp {
         .padding(10)
         .width(100);
 
+        // Precision of the saved file
+        let half_float_checkbox = checkbox(
+            "16bit half float",
+            self.exr_settings.bit_depth == ExrBitDepth::F16,
+            Self::Message::HalfFloatToggled,
+        );
+
+        // Compression of the saved file
+        let exr_compression_list = pick_list(
+            &ExrCompression::ALL[..],
+            Some(self.exr_settings.compression),
+            Self::Message::ExrCompressionSelected,
+        );
+
         // Final UI
         let content = column![
             row![rendered_image].padding(10).spacing(10),
             row![render_progress_label].padding(10).spacing(10),
-            // row![render_progress_bar].padding(10).spacing(10),
-            row![render_button].padding(10).spacing(10),
-            row![file_name_input, save_button].padding(10).spacing(10),
+            row![scene_warnings_label].padding(10).spacing(10),
+            row![render_progress_bar].padding(10).spacing(10),
+            row![
+                render_button,
+                higher_quality_button,
+                pause_button,
+                cancel_button,
+                preview_quality_checkbox,
+                demo_scene_list,
+                working_space_list,
+                aov_only_checkbox,
+                auto_exposure_button
+            ]
+            .padding(10)
+            .spacing(10)
+            .align_items(iced::Alignment::Center),
+            row![
+                display_pass_list,
+                tonemap_list,
+                output_color_space_list,
+                denoise_checkbox,
+                denoise_strength_slider,
+                edge_overlay_checkbox
+            ]
+            .padding(10)
+            .spacing(10)
+            .align_items(iced::Alignment::Center),
+            row![
+                text(format!("Gamma {:.2}", self.display_settings.gamma)),
+                gamma_slider,
+                split_view_checkbox,
+                eyedropper_checkbox,
+                lut_path_input,
+                load_lut_button
+            ]
+            .padding(10)
+            .spacing(10)
+            .align_items(iced::Alignment::Center),
+            row![
+                file_name_input,
+                half_float_checkbox,
+                exr_compression_list,
+                save_button
+            ]
+            .padding(10)
+            .spacing(10)
+            .align_items(iced::Alignment::Center),
         ]
         .max_width(800);
 
@@ -144,39 +534,80 @@ impl Application for LTSRApp {
 
     fn update(&mut self, message: Message) -> Command<Self::Message> {
         match message {
-            Message::RenderTaskFinished(Ok(render_buffer)) => {
-                self.render_progress_label =
-                    String::from("Main ACEScg Render finished, converting to sRGB..");
-
-                self.render_buffer = render_buffer.clone();
-
-                // TODO: Add checkbox for display colorspace conversion
-                let is_data_pass = false;
+            Message::RenderProgressTick => {
+                if self.is_rendering {
+                    self.current_render_progress = 100.0 * self.render_control.progress();
+                    if !self.render_control.is_paused() {
+                        self.render_progress_label = format!(
+                            "Rendering in the background.. {:.1}%",
+                            self.current_render_progress
+                        );
+                    }
+                }
 
-                Command::perform(
-                    RenderTask::convert_to_display_buffer(render_buffer, is_data_pass),
-                    Message::DisplayConversionTaskFinished,
-                )
+                Command::none()
+            }
+            Message::RenderTaskFinished(Ok(render_output)) => {
+                self.is_rendering = false;
+                self.render_settings = self.pending_render_settings.take();
+                self.rendered_demo_scene = self.pending_demo_scene.take();
+                let settings = self.render_settings.clone().unwrap_or_default();
+                if render_output.unrendered.is_empty() {
+                    self.current_render_progress = 100.0;
+                    self.render_progress_label = format!(
+                        "Main {} Render finished, converting to {}..",
+                        settings.working_space, self.display_settings.output_color_space
+                    );
+                } else {
+                    self.render_progress_label = format!(
+                        "Render cancelled, converting what was rendered to {}..",
+                        self.display_settings.output_color_space
+                    );
+                }
+                eprintln!("{}", render_output.stats);
+
+                self.has_beauty = settings.wants(RenderPass::Beauty);
+                self.render_buffer = render_output.linear;
+                self.aov_buffers = render_output.aovs;
+                self.image_origin = settings.image_origin;
+                self.render_working_space = settings.working_space;
+                self.render_metadata = Some(render_output.metadata);
+                self.unrendered_tiles = render_output.unrendered;
+
+                // The render converted itself with the display settings it started with,
+                // which are only worth showing if they haven't been changed since
+                let display_settings = self.pending_display_settings.take();
+                if self.display_pass == DisplayPass::Beauty
+                    && display_settings.as_ref() == Some(&self.display_settings)
+                {
+                    self.show_display_buffer(render_output.display);
+                    Command::none()
+                } else {
+                    self.convert_for_display()
+                }
             }
             Message::RenderTaskFinished(Err(err)) => {
                 eprintln!("Render failed: {err:?}");
+                self.is_rendering = false;
+                self.pending_render_settings = None;
+                self.pending_demo_scene = None;
+                self.pending_display_settings = None;
+                self.current_render_progress = 0.0;
+                self.render_progress_label = format!("Render failed: {err}.");
+
                 Command::none()
             }
             Message::DisplayConversionTaskFinished(Ok(display_buffer)) => {
-                self.rendered_image = image::Handle::from_pixels(
-                    RENDER_BUFFER_WIDTH as u32,
-                    RENDER_BUFFER_HEIGHT as u32,
-                    display_buffer.clone(),
-                );
-
-                self.render_progress_label =
-                    String::from("Converted from ACEScg to Display Color Space!");
+                self.show_display_buffer(display_buffer);
 
                 Command::none()
             }
 
             Message::DisplayConversionTaskFinished(Err(err)) => {
-                // TODO:
+                eprintln!("Display conversion failed: {err:?}");
+                // The render buffer is still there, so it can be saved or converted again
+                self.render_progress_label = format!("Display conversion failed: {err}.");
+
                 Command::none()
             }
 
@@ -184,33 +615,246 @@ impl Application for LTSRApp {
                 let message = String::from("Starting new Render in the background..");
                 self.render_progress_label = message;
 
-                // Schedule the background render
-                Command::perform(RenderTask::render_scene(), Message::RenderTaskFinished)
+                // Normals, depth and co. don't need the beauty to be path traced
+                let mut settings = self.quality.render_settings();
+                settings.working_space = self.working_space;
+                if let DisplayPass::Aov(aov) = self.display_pass {
+                    if self.aov_only && aov.render_pass() != RenderPass::Beauty {
+                        settings.passes = vec![aov.render_pass()];
+                    }
+                }
+
+                self.start_render(None, self.demo_scene, settings)
+            }
+            Message::HigherQualityPressed => {
+                let (Some(previous_settings), Some(demo_scene)) =
+                    (self.render_settings.clone(), self.rendered_demo_scene)
+                else {
+                    return Command::none();
+                };
+                let settings = previous_settings.refined();
+                self.render_progress_label = format!(
+                    "Rendering again with {} samples per pixel..",
+                    settings.samples_per_pixel
+                );
+
+                // The current render, to add the new samples to
+                let previous = RenderResult {
+                    beauty: Some(self.render_buffer.clone()),
+                    aovs: self.aov_buffers.clone(),
+                    image_origin: self.image_origin,
+                    working_space: self.render_working_space,
+                    stats: RenderStats::default(),
+                    unrendered: self.unrendered_tiles.clone(),
+                };
+
+                self.start_render(Some((previous, previous_settings)), demo_scene, settings)
+            }
+            Message::PausePressed => {
+                self.render_control.pause();
+                self.render_progress_label = String::from("Render paused.");
+
+                Command::none()
+            }
+            Message::ResumePressed => {
+                self.render_control.resume();
+                self.render_progress_label = String::from("Render resumed..");
+
+                Command::none()
+            }
+            Message::CancelPressed => {
+                self.render_control.cancel();
+                self.render_progress_label = String::from("Cancelling the render..");
+
+                Command::none()
+            }
+            Message::PreviewQualityToggled(is_preview) => {
+                self.quality = if is_preview {
+                    Quality::Preview
+                } else {
+                    Quality::Final
+                };
+
+                Command::none()
+            }
+            Message::SceneSelected(demo_scene) => {
+                self.demo_scene = demo_scene;
+                self.scene_warnings = demo_scene.warnings();
+
+                Command::none()
+            }
+            Message::WorkingSpaceSelected(working_space) => {
+                self.working_space = working_space;
+
+                Command::none()
+            }
+            Message::AovOnlyToggled(aov_only) => {
+                self.aov_only = aov_only;
+
+                Command::none()
+            }
+            Message::AutoExposurePressed => {
+                let exposure = compute_auto_exposure(&self.render_buffer);
+                self.display_settings.exposure = exposure;
+                self.render_progress_label = format!("Auto exposure set to {exposure:+.2} stops");
+
+                // Redo the display conversion with the new exposure
+                self.convert_for_display()
+            }
+            Message::DisplayPassSelected(display_pass) => {
+                self.display_pass = display_pass;
+                self.convert_for_display()
+            }
+            Message::TonemapSelected(tonemap) => {
+                self.display_settings.tonemap = tonemap;
+                self.convert_for_display()
+            }
+            Message::OutputColorSpaceSelected(output_color_space) => {
+                self.display_settings.output_color_space = output_color_space;
+                self.convert_for_display()
+            }
+            Message::DenoiseToggled(denoise) => {
+                self.display_settings.denoise = denoise;
+                self.convert_for_display()
+            }
+            Message::DenoiseStrengthChanged(strength) => {
+                self.display_settings.denoise_strength = strength;
+
+                if self.display_settings.denoise {
+                    self.convert_for_display()
+                } else {
+                    Command::none()
+                }
+            }
+            Message::EdgeOverlayToggled(edge_overlay) => {
+                self.display_settings.edge_overlay = edge_overlay;
+                self.convert_for_display()
+            }
+            Message::GammaChanged(gamma) => {
+                self.display_settings.gamma = gamma;
+                self.convert_for_display()
+            }
+            Message::SplitViewToggled(split_view) => {
+                self.display_settings.split_view = split_view;
+                self.convert_for_display()
+            }
+            Message::LutPathChanged(lut_path) => {
+                self.lut_path = lut_path;
+
+                Command::none()
+            }
+            Message::LoadLutPressed => {
+                if self.lut_path.trim().is_empty() {
+                    self.display_settings.lut = None;
+                    self.render_progress_label = String::from("Display LUT cleared.");
+
+                    return self.convert_for_display();
+                }
+
+                match load_cube_lut(self.lut_path.trim()) {
+                    Ok(lut) => {
+                        self.display_settings.lut = Some(Arc::new(lut));
+                        self.render_progress_label =
+                            format!("Loaded display LUT {}", self.lut_path);
+
+                        self.convert_for_display()
+                    }
+                    Err(e) => {
+                        eprintln!("{e}");
+                        self.render_progress_label = format!("{e}");
+
+                        Command::none()
+                    }
+                }
+            }
+            Message::EyedropperToggled(eyedropper) => {
+                self.eyedropper = eyedropper;
+
+                Command::none()
+            }
+            Message::PixelPicked(x, y) => {
+                self.render_progress_label = self.describe_pixel(x, y);
+
+                Command::none()
             }
             Message::FileNameChanged(new_name) => {
                 self.file_name = new_name;
-                self.file_name_with_ext = format!("{}.exr", self.file_name);
+
+                Command::none()
+            }
+            Message::HalfFloatToggled(is_half_float) => {
+                self.exr_settings.bit_depth = if is_half_float {
+                    ExrBitDepth::F16
+                } else {
+                    ExrBitDepth::F32
+                };
+
+                Command::none()
+            }
+            Message::ExrCompressionSelected(compression) => {
+                self.exr_settings.compression = compression;
 
                 Command::none()
             }
             Message::SaveFilePressed => {
-                let save_dir = PathBuf::from("outputs");
-                let save_path = save_dir.join(&self.file_name_with_ext);
+                let save_path = match output_file_path("outputs", &self.file_name, "exr") {
+                    Ok(save_path) => save_path,
+                    Err(e) => {
+                        eprintln!("failed to save image: {e}");
+                        self.render_progress_label = format!("{e}");
+
+                        return Command::none();
+                    }
+                };
                 eprintln!("Saving render buffer to {}", save_path.display());
 
+                // Renders in progress can be saved too, with what's been rendered so far
+                let snapshot = if self.is_rendering {
+                    self.render_control.snapshot()
+                } else {
+                    None
+                };
+                let (render_buffer, aov_buffers, image_origin, working_space) = match &snapshot {
+                    Some(partial) => (
+                        partial.beauty.as_deref(),
+                        &partial.aovs,
+                        partial.image_origin,
+                        partial.working_space,
+                    ),
+                    None => (
+                        self.has_beauty.then_some(self.render_buffer.as_slice()),
+                        &self.aov_buffers,
+                        self.image_origin,
+                        self.render_working_space,
+                    ),
+                };
+
                 match convert_to_openexr(
                     RENDER_BUFFER_WIDTH,
                     RENDER_BUFFER_HEIGHT,
-                    &self.render_buffer,
+                    render_buffer,
+                    aov_buffers,
+                    image_origin,
+                    working_space,
+                    self.exr_settings,
                 ) {
-                    Ok(image) => match save_exr_image_to_disk(image, save_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("failed to save image: {e:?}");
+                    Ok(mut image) => {
+                        // Snapshots aren't finished yet, there's no render time to tell
+                        if let (None, Some(metadata)) = (&snapshot, &self.render_metadata) {
+                            metadata.tag(&mut image);
+                        }
+                        match save_exr_image_to_disk(image, save_path) {
+                            Ok(_) => {}
+                            Err(e) => {
+                                eprintln!("failed to save image: {e:?}");
+                                self.render_progress_label = format!("Failed to save image: {e}");
+                            }
                         }
-                    },
+                    }
                     Err(e) => {
                         eprintln!("failed to save image: {e:?}");
+                        self.render_progress_label =
+                            format!("Failed to convert the image to OpenEXR: {e}");
                     }
                 }
 
@@ -219,7 +863,154 @@ impl Application for LTSRApp {
         }
     }
 
+    /// Polls the progress of the render running in the background
+    fn subscription(&self) -> Subscription<Self::Message> {
+        if self.is_rendering {
+            iced::time::every(RENDER_PROGRESS_INTERVAL).map(|_| Message::RenderProgressTick)
+        } else {
+            Subscription::none()
+        }
+    }
+
     fn theme(&self) -> Theme {
         Theme::Dark
     }
 }
+
+impl LTSRApp {
+    /// Scene-linear and display values of the pixel at column ``x``,
+    /// row ``y`` (from the top) of the rendered image
+    fn describe_pixel(&self, x: usize, y: usize) -> String {
+        let buffer_row = self.image_origin.buffer_row(y, RENDER_BUFFER_HEIGHT);
+        let render_index = (buffer_row * RENDER_BUFFER_WIDTH + x) * 4;
+        let display_index = (y * RENDER_BUFFER_WIDTH + x) * 4;
+
+        match (
+            self.render_buffer.get(render_index..render_index + 4),
+            self.display_buffer.get(display_index..display_index + 4),
+        ) {
+            (Some(linear), Some(display)) => format!(
+                "Pixel ({x}, {y}): {} ({:.4}, {:.4}, {:.4}, {:.4}), display ({}, {}, {})",
+                self.render_working_space,
+                linear[0],
+                linear[1],
+                linear[2],
+                linear[3],
+                display[0],
+                display[1],
+                display[2]
+            ),
+            _ => format!("Pixel ({x}, {y}) is outside of the image."),
+        }
+    }
+
+    /// Schedules a render of ``demo_scene`` in the background, from ``previous``
+    /// (a render of it and its settings) if any
+    fn start_render(
+        &mut self,
+        previous: Option<(RenderResult, RenderSettings)>,
+        demo_scene: DemoScene,
+        settings: RenderSettings,
+    ) -> Command<Message> {
+        self.is_rendering = true;
+        self.current_render_progress = 0.0;
+        self.render_control = Arc::new(RenderControl::new());
+        self.pending_render_settings = Some(settings.clone());
+        self.pending_demo_scene = Some(demo_scene);
+        self.pending_display_settings = Some(self.display_settings.clone());
+
+        Command::perform(
+            RenderTask::render_scene_from(
+                previous,
+                demo_scene,
+                settings,
+                self.display_settings.clone(),
+                self.render_control.clone(),
+            ),
+            |result| Message::RenderTaskFinished(result.map(Box::new)),
+        )
+    }
+
+    /// Schedules the conversion of the render buffer to the 8bit image shown in the GUI
+    fn convert_for_display(&self) -> Command<Message> {
+        // AOVs are remapped to RGBA and shown as they are, without tonemapping
+        let display_buffer = match self.display_pass {
+            DisplayPass::Beauty => self.render_buffer.clone(),
+            DisplayPass::Aov(aov) => match self.aov_buffers.get(&aov) {
+                Some(aov_buffer) => aov_to_rgba(aov, aov_buffer),
+                None => return Command::none(),
+            },
+        };
+
+        Command::perform(
+            RenderTask::convert_to_display_buffer(
+                display_buffer,
+                self.aov_buffers.clone(),
+                self.display_pass.is_data_pass(),
+                self.render_working_space,
+                self.display_settings.clone(),
+            ),
+            Message::DisplayConversionTaskFinished,
+        )
+    }
+
+    /// Shows ``display_buffer``, converted from the render buffer, in the viewer
+    fn show_display_buffer(&mut self, mut display_buffer: Vec<u8>) {
+        // The GUI shows images from the top row down
+        self.image_origin
+            .to_top_left(&mut display_buffer, RENDER_BUFFER_WIDTH * 4);
+        for tile in self.unrendered_tiles.iter() {
+            fill_with_placeholder(
+                &mut display_buffer,
+                RENDER_BUFFER_WIDTH,
+                *tile,
+                PLACEHOLDER_CHECKER_SIZE,
+            );
+        }
+
+        self.rendered_image = image::Handle::from_pixels(
+            RENDER_BUFFER_WIDTH as u32,
+            RENDER_BUFFER_HEIGHT as u32,
+            display_buffer.clone(),
+        );
+        self.display_buffer = display_buffer;
+
+        self.render_progress_label = format!(
+            "Converted from {} to {}!",
+            self.render_working_space, self.display_settings.output_color_space
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_renders_are_reported_and_can_be_started_again() {
+        let (mut app, _) = LTSRApp::new(());
+        let _ = app.update(Message::RenderPressed);
+        assert!(app.is_rendering);
+
+        let _ = app.update(Message::RenderTaskFinished(Err(AppError::RenderError)));
+        assert!(!app.is_rendering);
+        assert_eq!(app.current_render_progress, 0.0);
+        assert_eq!(
+            app.render_progress_label,
+            "Render failed: the render could not be completed."
+        );
+    }
+
+    #[test]
+    fn failed_display_conversions_are_reported() {
+        let (mut app, _) = LTSRApp::new(());
+
+        let _ = app.update(Message::DisplayConversionTaskFinished(Err(
+            AppError::DisplayConversionError,
+        )));
+        assert_eq!(
+            app.render_progress_label,
+            "Display conversion failed: the render buffer could not be converted for display."
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use glam::Vec3;
+
+use crate::ltsr::luminance;
+
+/// Luminance the auto exposure tries to map the median of the image to
+const AUTO_EXPOSURE_TARGET: f32 = 0.18;
+
+/// Range (in stops, log2 of the luminance) covered by the exposure histogram
+const HISTOGRAM_MIN_STOPS: f32 = -16.0;
+const HISTOGRAM_MAX_STOPS: f32 = 16.0;
+const HISTOGRAM_NUM_BINS: usize = 256;
+
+/// Builds a histogram of the log2 luminance of the RGBA pixels in ``render_buffer``.
+/// Black and non finite pixels are skipped, since they carry no exposure information.
+pub fn compute_luminance_histogram(render_buffer: &[f32]) -> [u32; HISTOGRAM_NUM_BINS] {
+    let mut histogram = [0; HISTOGRAM_NUM_BINS];
+    let stops_range = HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS;
+
+    for pixel in render_buffer.chunks_exact(4) {
+        let pixel_luminance = luminance(Vec3::new(pixel[0], pixel[1], pixel[2]));
+        if !pixel_luminance.is_finite() || pixel_luminance <= 0.0 {
+            continue;
+        }
+
+        let normalized = (pixel_luminance.log2() - HISTOGRAM_MIN_STOPS) / stops_range;
+        let bin = (normalized * HISTOGRAM_NUM_BINS as f32) as isize;
+        histogram[bin.clamp(0, HISTOGRAM_NUM_BINS as isize - 1) as usize] += 1;
+    }
+
+    histogram
+}
+
+/// Scans the linear ``render_buffer`` and returns the exposure (in stops)
+/// that maps its median luminance to 18% gray.
+/// Returns 0.0 (no change) when the buffer holds no usable pixels.
+pub fn compute_auto_exposure(render_buffer: &[f32]) -> f32 {
+    let histogram = compute_luminance_histogram(render_buffer);
+    let num_pixels: u32 = histogram.iter().sum();
+
+    if num_pixels == 0 {
+        return 0.0;
+    }
+
+    // Walk the histogram until we've seen half of the pixels
+    let mut seen_pixels = 0;
+    let mut median_bin = 0;
+    for (bin, count) in histogram.iter().enumerate() {
+        seen_pixels += count;
+        if seen_pixels * 2 >= num_pixels {
+            median_bin = bin;
+            break;
+        }
+    }
+
+    // Use the center of the bin as the median
+    let bin_size = (HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS) / HISTOGRAM_NUM_BINS as f32;
+    let median_stops = HISTOGRAM_MIN_STOPS + (median_bin as f32 + 0.5) * bin_size;
+
+    AUTO_EXPOSURE_TARGET.log2() - median_stops
+}
+
+/// Weights of the 5x5 B3-spline kernel used by the à-trous wavelet transform
+const ATROUS_KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ATROUS_ITERATIONS: u32 = 5;
+
+/// How much the normals and the albedo are allowed to differ
+/// before two pixels are considered to be on different surfaces
+const DENOISE_NORMAL_SIGMA: f32 = 0.3;
+const DENOISE_ALBEDO_SIGMA: f32 = 0.1;
+
+/// Removes the noise from the RGBA ``render_buffer`` via an edge-avoiding à-trous
+/// wavelet filter (Dammertz et al. 2010). The ``normals`` and ``albedo`` AOV buffers
+/// (3 floats per pixel) act as guides, so that the filter doesn't blur across edges.
+/// A higher ``strength`` smooths away larger differences in color.
+pub fn denoise(
+    render_buffer: &[f32],
+    normals: &[f32],
+    albedo: &[f32],
+    width: usize,
+    height: usize,
+    strength: f32,
+) -> Vec<f32> {
+    let mut current = render_buffer.to_vec();
+    let mut filtered = render_buffer.to_vec();
+
+    let get_vec3 = |buffer: &[f32], stride: usize, index: usize| {
+        Vec3::new(
+            buffer[index * stride],
+            buffer[index * stride + 1],
+            buffer[index * stride + 2],
+        )
+    };
+
+    // Tolerance to color differences, reduced at every iteration
+    // since the noise gets filtered out progressively
+    let mut color_sigma = 0.5 * strength.max(0.0);
+
+    for iteration in 0..ATROUS_ITERATIONS {
+        // The holes in the kernel (the 'trous') double at every iteration
+        let step = 1_isize << iteration;
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let color = get_vec3(&current, 4, index);
+                let normal = get_vec3(normals, 3, index);
+                let base_color = get_vec3(albedo, 3, index);
+
+                let mut sum = Vec3::ZERO;
+                let mut total_weight = 0.0;
+
+                for (ky, kernel_y) in ATROUS_KERNEL.iter().enumerate() {
+                    for (kx, kernel_x) in ATROUS_KERNEL.iter().enumerate() {
+                        let sample_x = x as isize + (kx as isize - 2) * step;
+                        let sample_y = y as isize + (ky as isize - 2) * step;
+
+                        if sample_x < 0
+                            || sample_y < 0
+                            || sample_x >= width as isize
+                            || sample_y >= height as isize
+                        {
+                            continue;
+                        }
+
+                        let sample_index = sample_y as usize * width + sample_x as usize;
+                        let sample_color = get_vec3(&current, 4, sample_index);
+                        let sample_normal = get_vec3(normals, 3, sample_index);
+                        let sample_albedo = get_vec3(albedo, 3, sample_index);
+
+                        let color_weight = if color_sigma > 0.0 {
+                            edge_stopping_weight(color, sample_color, color_sigma)
+                        } else if sample_index == index {
+                            // No strength: only the center pixel contributes
+                            1.0
+                        } else {
+                            0.0
+                        };
+                        let normal_weight =
+                            edge_stopping_weight(normal, sample_normal, DENOISE_NORMAL_SIGMA);
+                        let albedo_weight =
+                            edge_stopping_weight(base_color, sample_albedo, DENOISE_ALBEDO_SIGMA);
+
+                        let weight =
+                            kernel_x * kernel_y * color_weight * normal_weight * albedo_weight;
+
+                        sum += weight * sample_color;
+                        total_weight += weight;
+                    }
+                }
+
+                // The center pixel always contributes, so the weight can't be zero
+                let denoised = sum / total_weight;
+                filtered[index * 4..index * 4 + 3].copy_from_slice(&denoised.to_array());
+            }
+        }
+
+        std::mem::swap(&mut current, &mut filtered);
+        color_sigma *= 0.5;
+    }
+
+    current
+}
+
+/// Gaussian falloff based on how different two values are
+fn edge_stopping_weight(a: Vec3, b: Vec3, sigma: f32) -> f32 {
+    (-(a - b).length_squared() / (sigma * sigma)).exp()
+}
+
+/// Ranges over which the difference between two neighbouring pixels fades into an edge.
+/// Normals are compared via ``1 - cos`` of their angle, depths relative to the farthest one.
+/// Averaged normals and depths soften these differences along the silhouettes,
+/// which keeps the lines anti-aliased.
+const EDGE_NORMAL_RANGE: (f32, f32) = (0.1, 0.4);
+const EDGE_DEPTH_RANGE: (f32, f32) = (0.05, 0.2);
+
+/// Finds the geometric edges of the image by looking for discontinuities between
+/// the ``normals`` (3 floats per pixel) and the ``depth`` (1 float per pixel)
+/// of each pixel and its right and bottom neighbours.
+/// Returns how much each pixel sits on an edge, in the [0, 1] range.
+pub fn detect_edges(normals: &[f32], depth: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let mut edges = vec![0.0; width * height];
+
+    let get_normal = |index: usize| {
+        Vec3::new(
+            normals[index * 3],
+            normals[index * 3 + 1],
+            normals[index * 3 + 2],
+        )
+    };
+
+    let discontinuity = |index: usize, neighbour: usize| {
+        let normal_difference = 1.0 - get_normal(index).dot(get_normal(neighbour));
+
+        let farthest = depth[index].max(depth[neighbour]);
+        let depth_difference = if farthest > 0.0 {
+            (depth[index] - depth[neighbour]).abs() / farthest
+        } else {
+            0.0
+        };
+
+        ramp(normal_difference, EDGE_NORMAL_RANGE).max(ramp(depth_difference, EDGE_DEPTH_RANGE))
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let mut edge: f32 = 0.0;
+
+            if x + 1 < width {
+                edge = edge.max(discontinuity(index, index + 1));
+            }
+            if y + 1 < height {
+                edge = edge.max(discontinuity(index, index + width));
+            }
+
+            edges[index] = edge;
+        }
+    }
+
+    edges
+}
+
+/// Darkens the RGBA pixels of ``render_buffer`` that sit on the ``edges`` found by ``detect_edges``
+pub fn overlay_edges(render_buffer: &mut [f32], edges: &[f32]) {
+    for (pixel, edge) in render_buffer.chunks_exact_mut(4).zip(edges) {
+        for channel in &mut pixel[..3] {
+            *channel *= 1.0 - edge;
+        }
+    }
+}
+
+/// Linear ramp from 0.0 to 1.0 between the two ends of ``range``
+fn ramp(x: f32, range: (f32, f32)) -> f32 {
+    ((x - range.0) / (range.1 - range.0)).clamp(0.0, 1.0)
+}
+
+/// Color mixed into the pixels sitting on the focal plane
+const FOCUS_PLANE_TINT: Vec3 = Vec3::new(1.0, 0.1, 0.6);
+const FOCUS_PLANE_TINT_OPACITY: f32 = 0.5;
+
+/// Tints the RGBA pixels of ``render_buffer`` whose ``depth`` (1 float per pixel,
+/// 0.0 for the background) is within ``tolerance`` of ``focus_distance``,
+/// to show where the focal plane sits
+pub fn overlay_focus_plane(
+    render_buffer: &mut [f32],
+    depth: &[f32],
+    focus_distance: f32,
+    tolerance: f32,
+) {
+    for (pixel, pixel_depth) in render_buffer.chunks_exact_mut(4).zip(depth) {
+        if *pixel_depth <= 0.0 || (pixel_depth - focus_distance).abs() > tolerance {
+            continue;
+        }
+
+        let color = Vec3::new(pixel[0], pixel[1], pixel[2]);
+        let tinted = color.lerp(
+            FOCUS_PLANE_TINT * luminance(color).max(0.18),
+            FOCUS_PLANE_TINT_OPACITY,
+        );
+        pixel[..3].copy_from_slice(&tinted.to_array());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// RGBA pixel of a gray with the given luminance
+    fn gray(value: f32) -> [f32; 4] {
+        [value, value, value, 1.0]
+    }
+
+    #[test]
+    fn auto_exposure_maps_the_median_to_mid_gray() {
+        // Luminances spread over 8 stops around a median of 0.5, plus pixels
+        // carrying no exposure information
+        let mut render_buffer: Vec<f32> = (-40..=40)
+            .flat_map(|step| gray(0.5 * 2.0_f32.powf(step as f32 / 10.0)))
+            .collect();
+        render_buffer.extend(gray(0.0));
+        render_buffer.extend(gray(f32::NAN));
+
+        let exposure = compute_auto_exposure(&render_buffer);
+
+        // Within half a bin of the histogram
+        let expected = (AUTO_EXPOSURE_TARGET / 0.5).log2();
+        let half_bin =
+            (HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS) / HISTOGRAM_NUM_BINS as f32 / 2.0;
+        assert!(
+            (exposure - expected).abs() <= half_bin + 1e-4,
+            "{exposure} stops, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn black_images_keep_their_exposure() {
+        assert_eq!(compute_auto_exposure(&gray(0.0)), 0.0);
+        assert_eq!(compute_auto_exposure(&[]), 0.0);
+    }
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 16;
+
+    /// Whether a pixel is in the left half of the image
+    fn is_left(index: usize) -> bool {
+        index % WIDTH < WIDTH / 2
+    }
+
+    /// Mean and variance of the red channel of the pixels of one half of the image
+    fn half_stats(render_buffer: &[f32], left: bool) -> (f32, f32) {
+        let values: Vec<f32> = render_buffer
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(index, _)| is_left(*index) == left)
+            .map(|(_, pixel)| pixel[0])
+            .collect();
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f32>()
+            / values.len() as f32;
+        (mean, variance)
+    }
+
+    #[test]
+    fn denoising_smooths_the_noise_but_not_the_edges() {
+        // Two surfaces facing different ways, a darker one on the left and a brighter one
+        // on the right, both noisy
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut render_buffer = Vec::new();
+        let mut normals = Vec::new();
+        for index in 0..WIDTH * HEIGHT {
+            let (color, normal) = match is_left(index) {
+                true => (0.4, Vec3::Z),
+                false => (0.6, Vec3::X),
+            };
+            render_buffer.extend(gray(color + rng.gen_range(-0.05..0.05)));
+            normals.extend(normal.to_array());
+        }
+        let albedo = vec![0.5; WIDTH * HEIGHT * 3];
+
+        let denoised = denoise(&render_buffer, &normals, &albedo, WIDTH, HEIGHT, 1.0);
+
+        for left in [true, false] {
+            let (noisy_mean, noisy_variance) = half_stats(&render_buffer, left);
+            let (mean, variance) = half_stats(&denoised, left);
+            assert!(
+                variance < noisy_variance / 4.0,
+                "variance went from {noisy_variance} to {variance}"
+            );
+            assert!((mean - noisy_mean).abs() < 0.02, "{noisy_mean} to {mean}");
+        }
+
+        // Right by the edge, the pixels keep the color of their side
+        for y in 0..HEIGHT {
+            let left_of_edge = denoised[(y * WIDTH + WIDTH / 2 - 1) * 4];
+            let right_of_edge = denoised[(y * WIDTH + WIDTH / 2) * 4];
+            assert!((left_of_edge - 0.4).abs() < 0.03, "{left_of_edge}");
+            assert!((right_of_edge - 0.6).abs() < 0.03, "{right_of_edge}");
+        }
+    }
+
+    #[test]
+    fn edges_are_found_along_depth_steps() {
+        // A flat wall facing the camera, with its right half further away
+        let normals: Vec<f32> = (0..WIDTH * HEIGHT).flat_map(|_| [0.0, 0.0, 1.0]).collect();
+        let depth: Vec<f32> = (0..WIDTH * HEIGHT)
+            .map(|index| if is_left(index) { 2.0 } else { 4.0 })
+            .collect();
+
+        let edges = detect_edges(&normals, &depth, WIDTH, HEIGHT);
+
+        for (index, edge) in edges.iter().enumerate() {
+            let on_the_step = index % WIDTH == WIDTH / 2 - 1;
+            let expected = if on_the_step { 1.0 } else { 0.0 };
+            assert_eq!(*edge, expected, "pixel {index}");
+        }
+
+        let mut render_buffer: Vec<f32> = (0..WIDTH * HEIGHT).flat_map(|_| gray(0.5)).collect();
+        overlay_edges(&mut render_buffer, &edges);
+        assert_eq!(render_buffer[(WIDTH / 2 - 1) * 4], 0.0);
+        assert_eq!(render_buffer[(WIDTH / 2) * 4], 0.5);
+    }
+
+    #[test]
+    fn only_pixels_near_the_focus_distance_get_tinted() {
+        // In focus, at the edge of the tolerance, out of focus, and the background
+        let depth = [5.0, 5.4, 8.0, 0.0];
+        let original: Vec<f32> = (0..depth.len()).flat_map(|_| gray(0.5)).collect();
+        let mut render_buffer = original.clone();
+
+        overlay_focus_plane(&mut render_buffer, &depth, 5.0, 0.5);
+
+        let pixel = |buffer: &[f32], index: usize| buffer[index * 4..][..4].to_vec();
+        for index in [0, 1] {
+            let tinted = pixel(&render_buffer, index);
+            assert_ne!(tinted, pixel(&original, index), "pixel {index}");
+            // Pinker, with the alpha untouched
+            assert!(tinted[0] > tinted[1] && tinted[2] > tinted[1], "{tinted:?}");
+            assert_eq!(tinted[3], 1.0);
+        }
+        for index in [2, 3] {
+            assert_eq!(
+                pixel(&render_buffer, index),
+                pixel(&original, index),
+                "pixel {index}"
+            );
+        }
+    }
+}
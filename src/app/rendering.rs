@@ -1,30 +1,424 @@
 use std::boxed::Box;
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow;
-use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes};
-use glam::Vec3;
-use rand::Rng;
-use smallvec::smallvec;
+use exr::meta::attribute::Chromaticities;
+use exr::prelude::{
+    AnyChannel, AnyChannels, Compression, Encoding, FlatSamples, Image, Layer, LayerAttributes,
+    Vec2,
+};
+use glam::{DVec3, Vec3};
+use half::f16;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use smallvec::SmallVec;
 
 // Color
-use colstodian::spaces::{AcesCg, EncodedSrgb};
+use colstodian::spaces::{AcesCg, EncodedDisplayP3, EncodedSrgb, LinearSrgb};
 use colstodian::tonemap::{PerceptualTonemapper, PerceptualTonemapperParams, Tonemapper};
 use colstodian::{color, Color, Display};
 
+use crate::app::aovs::Aov;
+use crate::app::checkpoint::{load_checkpoint, save_checkpoint, Checkpoint};
+use crate::app::filesystem::save_exr_image_to_disk;
+use crate::app::lut::CubeLut;
+use crate::app::metadata::RenderMetadata;
+use crate::app::postprocessing::{denoise, detect_edges, overlay_edges, overlay_focus_plane};
+use crate::app::scenes::DemoScene;
 use crate::app::AppError;
-use crate::constants::{
-    NUM_SAMPLES_PER_PIXEL, RENDER_BUFFER_HEIGHT, RENDER_BUFFER_SIZE, RENDER_BUFFER_WIDTH,
+use crate::constants::{RENDER_BUFFER_HEIGHT, RENDER_BUFFER_SIZE, RENDER_BUFFER_WIDTH};
+use crate::ltsr::hash_grid::Accelerator;
+use crate::ltsr::materials::{Dielectric, Lambertian, Material, Metallic};
+use crate::ltsr::settings::{
+    headless_resolution, ImageOrigin, RenderPass, RenderSettings, TileOrder, WorkingSpace,
+};
+use crate::ltsr::spectral::wavelength_weight;
+use crate::ltsr::{
+    fit_range, pixel_seed, ray_lighting, ray_path, seed_sampler, Camera, CameraSampler, Lighting,
+    RayPathSegment, RunningVariance, Scene, Sphere, Visibility,
 };
-use crate::ltsr::materials::{Lambertian, Metallic};
-use crate::ltsr::{fit_range, ray_color, Camera, Scene, Sphere};
 
 pub type SimpleOpenEXRImage = Image<Layer<AnyChannels<FlatSamples>>>;
 
+/// Everything produced by a render: the RGBA beauty plus the AOVs
+#[derive(Debug, Clone)]
+pub struct RenderResult {
+    /// 32bit floating point RGBA pixels, scene linear in ``working_space``.
+    /// None if the Beauty pass wasn't requested
+    pub beauty: Option<Vec<f32>>,
+    pub aovs: HashMap<Aov, Vec<f32>>,
+    /// Order of the rows in ``beauty`` and in the AOVs
+    pub image_origin: ImageOrigin,
+    /// Color space of ``beauty`` and of the color AOVs
+    pub working_space: WorkingSpace,
+    pub stats: RenderStats,
+    /// Parts of the image left out because the render was cancelled
+    /// (empty if it went through), counted from the top left corner
+    pub unrendered: Vec<Tile>,
+}
+
+/// A whole render at once: the scene linear pixels, to save them, along with
+/// the same pixels converted for display, see ``render``
+#[derive(Debug, Clone)]
+pub struct RenderOutput {
+    /// 32bit floating point RGBA pixels, scene linear in the working space of the render
+    /// and ordered as its image origin (see ``RenderSettings``). All zeros when the
+    /// Beauty pass wasn't requested
+    pub linear: Vec<f32>,
+    /// 8bit RGBA pixels of ``linear``, see ``RenderTask::convert_to_display_buffer``
+    pub display: Vec<u8>,
+    /// Including the display conversion
+    pub stats: RenderStats,
+    pub aovs: HashMap<Aov, Vec<f32>>,
+    /// See ``RenderResult::unrendered``
+    pub unrendered: Vec<Tile>,
+    /// The camera and the settings of the render, to tag the images it's saved to
+    pub metadata: RenderMetadata,
+}
+
+impl RenderOutput {
+    /// Converts the beauty of ``result``, a render of ``camera`` with ``settings``
+    /// at ``image_size``, for display
+    pub fn new(
+        result: RenderResult,
+        camera: &Camera,
+        settings: &RenderSettings,
+        image_size: (usize, usize),
+        display_settings: &DisplaySettings,
+    ) -> Self {
+        let (width, height) = image_size;
+        let start_time = Instant::now();
+        let linear = result
+            .beauty
+            .unwrap_or_else(|| vec![0.0; width * height * 4]);
+        let display = convert_to_display(
+            linear.clone(),
+            &result.aovs,
+            false,
+            result.working_space,
+            display_settings,
+            width,
+            height,
+        );
+
+        let mut stats = result.stats;
+        stats.display_conversion = start_time.elapsed();
+        stats.total += stats.display_conversion;
+
+        RenderOutput {
+            linear,
+            display,
+            stats,
+            aovs: result.aovs,
+            unrendered: result.unrendered,
+            metadata: RenderMetadata::new(camera, settings, stats.total),
+        }
+    }
+}
+
+/// Where the time of a render went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Building the scene (the hash grid, if any, gets built by the first ray)
+    pub scene_setup: Duration,
+    /// Finding what the camera rays hit first, for the geometric AOVs.
+    /// When path tracing, the time of the samples of each pixel is split between this
+    /// and ``shading`` the way it was for the first sample
+    pub primary_rays: Duration,
+    /// Path tracing the samples: scattering off the materials and bouncing around the scene
+    pub shading: Duration,
+    /// Converting the render to the display buffer, zero until it's done
+    pub display_conversion: Duration,
+    /// All of the above, plus everything else (eg: allocating the buffers)
+    pub total: Duration,
+}
+
+impl std::fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phases = [
+            ("Scene setup", self.scene_setup),
+            ("Primary rays", self.primary_rays),
+            ("Shading", self.shading),
+            ("Display conversion", self.display_conversion),
+        ];
+
+        writeln!(f, "Render time breakdown:")?;
+        for (name, duration) in phases {
+            let percentage = match self.total.is_zero() {
+                true => 0.0,
+                false => 100.0 * duration.as_secs_f64() / self.total.as_secs_f64(),
+            };
+            writeln!(f, "  {name:<20}{duration:>12.3?} ({percentage:.1}%)")?;
+        }
+        write!(f, "  {:<20}{:>12.3?}", "Total", self.total)
+    }
+}
+
+/// Color spaces the display buffer can be encoded in.
+/// Saved OpenEXR images always stay scene linear, in the working space of the render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputColorSpace {
+    /// sRGB primaries with the sRGB transfer function, for standard displays
+    #[default]
+    Srgb,
+    /// sRGB/Rec.709 primaries, without any transfer function
+    LinearRec709,
+    /// Display P3 primaries with the sRGB transfer function, for wide gamut displays
+    DisplayP3,
+}
+
+impl OutputColorSpace {
+    pub const ALL: [OutputColorSpace; 3] = [
+        OutputColorSpace::Srgb,
+        OutputColorSpace::LinearRec709,
+        OutputColorSpace::DisplayP3,
+    ];
+}
+
+impl std::fmt::Display for OutputColorSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OutputColorSpace::Srgb => "sRGB",
+            OutputColorSpace::LinearRec709 => "Linear Rec.709",
+            OutputColorSpace::DisplayP3 => "Display P3",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Curves used to bring the HDR scene linear values into the display range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemap {
+    /// colstodian's perceptual tonemapper: neutral, preserves hues and saturation
+    #[default]
+    Perceptual,
+    /// Fit of the ACES RRT and sRGB ODT (Stephen Hill): more contrasty and 'filmic',
+    /// matches the look of other ACES pipelines
+    AcesRrtOdt,
+    /// Reinhard's curve (x / (1 + x)) per channel, with the color conversions and the
+    /// encoding done by hand: the conversion doesn't go through colstodian at all
+    Reinhard,
+}
+
+impl Tonemap {
+    pub const ALL: [Tonemap; 3] = [Tonemap::Perceptual, Tonemap::AcesRrtOdt, Tonemap::Reinhard];
+}
+
+impl std::fmt::Display for Tonemap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Tonemap::Perceptual => "Perceptual",
+            Tonemap::AcesRrtOdt => "ACES RRT+ODT",
+            Tonemap::Reinhard => "Reinhard",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Highlights the pixels at a given distance from the camera,
+/// to see where the focal plane sits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FocusPlaneOverlay {
+    pub focus_distance: f32,
+    /// How far from ``focus_distance`` pixels still get highlighted
+    pub tolerance: f32,
+}
+
+/// Controls how the scene linear render buffer gets converted for display
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplaySettings {
+    /// Exposure adjustment in stops, applied before tonemapping
+    pub exposure: f32,
+    /// Whether to denoise the render buffer (guided by the Normal and Albedo AOVs)
+    pub denoise: bool,
+    /// How aggressively the denoiser smooths out differences in color
+    pub denoise_strength: f32,
+    /// Whether to draw the geometric edges (found via the Normal and Depth AOVs) on top
+    /// of the image, to help inspecting the geometry
+    pub edge_overlay: bool,
+    /// When set, tints the pixels on the focal plane (found via the Depth AOV)
+    pub focus_plane_overlay: Option<FocusPlaneOverlay>,
+    pub tonemap: Tonemap,
+    pub output_color_space: OutputColorSpace,
+    /// Power applied to the encoded display values: above 1.0 darkens the midtones,
+    /// below 1.0 brightens them
+    pub gamma: f32,
+    /// Whether to show the left half of the image without tonemapping (just clamped),
+    /// to compare it with the tonemapped right half
+    pub split_view: bool,
+    /// When set, the 3D LUT (eg: a show LUT) applied to the encoded display values,
+    /// after the tonemap and before the gamma
+    pub lut: Option<Arc<CubeLut>>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            exposure: 0.0,
+            denoise: false,
+            denoise_strength: 1.0,
+            edge_overlay: false,
+            focus_plane_overlay: None,
+            tonemap: Tonemap::default(),
+            output_color_space: OutputColorSpace::default(),
+            gamma: 1.0,
+            split_view: false,
+            lut: None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RenderTask {}
 
+/// Shared between the GUI and a render running in the background, to pause
+/// the render and resume it later, to cancel it, or to peek at its progress
+#[derive(Debug, Default)]
+pub struct RenderControl {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    cancelled: AtomicBool,
+    /// Beauty of the render in progress, updated at the end of each scanline
+    partial: Mutex<Option<RenderResult>>,
+    /// Samples taken so far
+    samples_done: AtomicUsize,
+    /// Samples left out of the budget by pixels that converged early
+    samples_skipped: AtomicUsize,
+    /// Samples the whole image would take without adaptive sampling
+    sample_budget: AtomicUsize,
+}
+
+impl RenderControl {
+    pub fn new() -> Self {
+        RenderControl::default()
+    }
+
+    /// The render stops at the end of the current scanline
+    pub fn pause(&self) {
+        *self
+            .paused
+            .lock()
+            .expect("Render control lock was poisoned") = true;
+    }
+
+    /// Wakes up the render, which continues from where it stopped
+    pub fn resume(&self) {
+        *self
+            .paused
+            .lock()
+            .expect("Render control lock was poisoned") = false;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self
+            .paused
+            .lock()
+            .expect("Render control lock was poisoned")
+    }
+
+    /// The render stops at the end of the current tile (or scanline, for AOV-only
+    /// renders), even if it's paused, and returns what has been rendered so far
+    pub fn cancel(&self) {
+        // Set while holding the lock, so that a paused render can't miss the wake up
+        let _paused = self
+            .paused
+            .lock()
+            .expect("Render control lock was poisoned");
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.resumed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fraction (0 to 1) of the samples of the image that have been taken.
+    /// Unlike counting scanlines, this stays accurate when some pixels take
+    /// many more samples than others: the samples skipped by converged pixels
+    /// are taken out of the budget
+    pub fn progress(&self) -> f32 {
+        let budget = self.sample_budget.load(Ordering::Relaxed)
+            - self.samples_skipped.load(Ordering::Relaxed);
+        if budget == 0 {
+            return 0.0;
+        }
+
+        self.samples_done.load(Ordering::Relaxed) as f32 / budget as f32
+    }
+
+    fn begin_progress(&self, sample_budget: usize) {
+        self.samples_done.store(0, Ordering::Relaxed);
+        self.samples_skipped.store(0, Ordering::Relaxed);
+        self.sample_budget.store(sample_budget, Ordering::Relaxed);
+    }
+
+    fn add_samples(&self, samples_done: usize, samples_skipped: usize) {
+        self.samples_done.fetch_add(samples_done, Ordering::Relaxed);
+        self.samples_skipped
+            .fetch_add(samples_skipped, Ordering::Relaxed);
+    }
+
+    /// Copy of what has been rendered so far (the beauty only, without AOVs).
+    /// Pixels that haven't been rendered yet are transparent black.
+    /// None if no render has started
+    pub fn snapshot(&self) -> Option<RenderResult> {
+        self.partial
+            .lock()
+            .expect("Render control lock was poisoned")
+            .clone()
+    }
+
+    /// Starts tracking the progress of a render with an RGBA beauty of ``num_values`` floats
+    fn begin_partial(
+        &self,
+        num_values: usize,
+        image_origin: ImageOrigin,
+        working_space: WorkingSpace,
+    ) {
+        *self
+            .partial
+            .lock()
+            .expect("Render control lock was poisoned") = Some(RenderResult {
+            beauty: Some(vec![0.0; num_values]),
+            aovs: HashMap::new(),
+            image_origin,
+            working_space,
+            stats: RenderStats::default(),
+            unrendered: Vec::new(),
+        });
+    }
+
+    /// Copies freshly rendered ``values`` into the partial beauty, from index ``start``
+    fn update_partial(&self, start: usize, values: &[f32]) {
+        let mut partial = self
+            .partial
+            .lock()
+            .expect("Render control lock was poisoned");
+        if let Some(beauty) = partial.as_mut().and_then(|partial| partial.beauty.as_mut()) {
+            beauty[start..start + values.len()].copy_from_slice(values);
+        }
+    }
+
+    /// Blocks the calling thread for as long as the render is paused (and not cancelled)
+    pub fn wait_while_paused(&self) {
+        let paused = self
+            .paused
+            .lock()
+            .expect("Render control lock was poisoned");
+        let _resumed = self
+            .resumed
+            .wait_while(paused, |paused| *paused && !self.is_cancelled())
+            .expect("Render control lock was poisoned");
+    }
+}
+
 /// Sample function demostrating how to render a custom image
 pub fn render_bg_image() -> Vec<f32> {
     let mut render_buffer = vec![0.0; RENDER_BUFFER_SIZE];
@@ -50,7 +444,7 @@ pub fn render_bg_image() -> Vec<f32> {
                 color::acescg::<colstodian::Scene>(final_color.r, final_color.g, final_color.b);
 
             // R, G, B, A
-            render_buffer[index + 0] = rendered_color.r;
+            render_buffer[index] = rendered_color.r;
             render_buffer[index + 1] = rendered_color.g;
             render_buffer[index + 2] = rendered_color.b;
             render_buffer[index + 3] = 1.0;
@@ -63,226 +457,3112 @@ pub fn render_bg_image() -> Vec<f32> {
 }
 
 impl RenderTask {
-    /// Sample function performing the rendering of basic 3D scene
-    pub async fn render_scene() -> Result<Vec<f32>, AppError> {
-        let mut render_buffer = vec![0.0; RENDER_BUFFER_SIZE];
+    /// Sample function performing the rendering of one of the built-in scenes,
+    /// converted for display with ``display_settings``
+    pub async fn render_scene(
+        demo_scene: DemoScene,
+        settings: RenderSettings,
+        display_settings: DisplaySettings,
+        control: Arc<RenderControl>,
+    ) -> Result<RenderOutput, AppError> {
+        Self::render_scene_from(None, demo_scene, settings, display_settings, control).await
+    }
+
+    /// Like ``render_scene``, but starts from ``previous``: a render of the same scene,
+    /// along with its settings. Its samples are reused when they can be accumulated
+    /// with the new ones (see ``render_image_from``), otherwise it starts over
+    pub async fn render_scene_from(
+        previous: Option<(RenderResult, RenderSettings)>,
+        demo_scene: DemoScene,
+        settings: RenderSettings,
+        display_settings: DisplaySettings,
+        control: Arc<RenderControl>,
+    ) -> Result<RenderOutput, AppError> {
+        // Checked before allocating anything, so the previous render is left as it was
+        settings
+            .check_resolution(RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT)
+            .map_err(|e| AppError::InvalidSettings(e.to_string()))?;
 
-        eprintln!(
-            "Started rendering.. using {} rays per pixel",
-            NUM_SAMPLES_PER_PIXEL
-        );
         let start_time = Instant::now();
+        let (scene, camera) = demo_scene.build(&settings);
+        log_scene_warnings(&scene);
+        let scene_setup = start_time.elapsed();
 
-        // Shorthands
-        let image_width = RENDER_BUFFER_WIDTH as f32;
-        let image_height = RENDER_BUFFER_HEIGHT as f32;
-        let aspect_ratio: f32 = image_width / image_height;
+        let mut result = match previous {
+            Some((previous, previous_settings))
+                if settings.can_accumulate_with(&previous_settings) =>
+            {
+                render_image_from(
+                    &previous,
+                    &scene,
+                    &camera,
+                    &settings,
+                    RENDER_BUFFER_WIDTH,
+                    RENDER_BUFFER_HEIGHT,
+                    &control,
+                )
+            }
+            _ => render_image(
+                &scene,
+                &camera,
+                &settings,
+                RENDER_BUFFER_WIDTH,
+                RENDER_BUFFER_HEIGHT,
+                &control,
+            ),
+        };
+        result.stats.scene_setup = scene_setup;
+        result.stats.total = start_time.elapsed();
 
-        // Camera properties
-        let viewport_height = 2.0;
-        let viewport_width = aspect_ratio * viewport_height;
-        let camera = Camera::new(1.0, viewport_width, viewport_height);
+        Ok(RenderOutput::new(
+            result,
+            &camera,
+            &settings,
+            (RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT),
+            &display_settings,
+        ))
+    }
 
-        // Scene properties
-        let mut scene = Scene::new();
+    /// Takes the floating point pixels from ``render_buffer`` and performs the
+    /// math to store them in ``display_buffer``, ready to be presented as 8 bit
+    /// bytes in the GUI. ``render_buffer`` is scene linear in ``working_space``.
+    /// If ``is_data_pass`` is true, no color management will be applied.
+    /// ``aov_buffers`` are used as guides when denoising.
+    pub async fn convert_to_display_buffer(
+        render_buffer: Vec<f32>,
+        aov_buffers: HashMap<Aov, Vec<f32>>,
+        is_data_pass: bool,
+        working_space: WorkingSpace,
+        display_settings: DisplaySettings,
+    ) -> Result<Vec<u8>, AppError> {
+        Ok(convert_to_display(
+            render_buffer,
+            &aov_buffers,
+            is_data_pass,
+            working_space,
+            &display_settings,
+            RENDER_BUFFER_WIDTH,
+            RENDER_BUFFER_HEIGHT,
+        ))
+    }
+}
+
+/// Like ``RenderTask::convert_to_display_buffer``, for a render of any size
+pub fn convert_to_display(
+    render_buffer: Vec<f32>,
+    aov_buffers: &HashMap<Aov, Vec<f32>>,
+    is_data_pass: bool,
+    working_space: WorkingSpace,
+    display_settings: &DisplaySettings,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    eprintln!(
+        "Converting from {working_space} linear to {}",
+        display_settings.output_color_space
+    );
+    let start_time = Instant::now();
 
-        // Materials
-        let col_ground = Vec3::new(0.8, 0.8, 0.1);
-        let col_center = Vec3::new(0.7, 0.3, 0.3);
-        let col_left = Vec3::new(0.8, 0.8, 0.8);
-        let col_right = Vec3::new(0.8, 0.6, 0.2);
-
-        let mat_ground = Arc::new(Lambertian::new(col_ground.clone()));
-        let mat_center = Arc::new(Lambertian::new(col_center.clone()));
-        let mat_left = Arc::new(Metallic::new(col_left.clone(), 0.3));
-        let mat_right = Arc::new(Metallic::new(col_right.clone(), 1.0));
-
-        // Let's check if our ray intersects some spheres
-        let spheres_z = -1.0;
-        let spheres_z = -1.0;
-        let sphere_1 = Sphere::new(100.0, Vec3::new(0.0, -100.5, spheres_z), mat_ground.clone());
-        let sphere_2 = Sphere::new(0.5, Vec3::new(0.0, 0.0, spheres_z), mat_center.clone());
-        let sphere_3 = Sphere::new(0.5, Vec3::new(-1.0, 0.0, spheres_z), mat_left.clone());
-        let sphere_4 = Sphere::new(0.5, Vec3::new(1.0, 0.0, spheres_z), mat_right.clone());
-
-        scene.add_hittable(Box::new(sphere_1));
-        scene.add_hittable(Box::new(sphere_2));
-        scene.add_hittable(Box::new(sphere_3));
-        scene.add_hittable(Box::new(sphere_4));
-
-        // Sampling
-        let mut rng = rand::thread_rng();
-
-        // Ray bounces
-        const MAX_DEPTH: i32 = 5;
-
-        // Generate the image
-        let mut index: usize = 0;
-        for y in (0..RENDER_BUFFER_HEIGHT).rev() {
-            for x in 0..RENDER_BUFFER_WIDTH {
-                let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
+    // Denoising happens in scene linear, before any tonemapping
+    let guides = (aov_buffers.get(&Aov::Normal), aov_buffers.get(&Aov::Albedo));
+    let mut render_buffer = match guides {
+        (Some(normals), Some(albedo)) if display_settings.denoise && !is_data_pass => denoise(
+            &render_buffer,
+            normals,
+            albedo,
+            width,
+            height,
+            display_settings.denoise_strength,
+        ),
+        _ => render_buffer,
+    };
 
-                // Antialiasing: multiple samples per pixel
-                for _ in 0..NUM_SAMPLES_PER_PIXEL {
-                    // Get normalized U,V coordinates as we move through the image
-                    let u = fit_range(x as f32 + rng.gen::<f32>(), 0.0, image_width, 0.0, 1.0);
-                    let v = fit_range(y as f32 + rng.gen::<f32>(), 0.0, image_height, 0.0, 1.0);
+    if display_settings.edge_overlay {
+        if let (Some(normals), Some(depth)) =
+            (aov_buffers.get(&Aov::Normal), aov_buffers.get(&Aov::Depth))
+        {
+            let edges = detect_edges(normals, depth, width, height);
+            overlay_edges(&mut render_buffer, &edges);
+        }
+    }
 
-                    // Aim the camera based on the current u,v coordinates
-                    let ray = camera.get_ray_at_coords(u, v);
-                    pixel_color += ray_color(&ray, &scene, MAX_DEPTH);
-                }
-                // Divide by the num of samples to get the average
-                let scale = 1.0 / NUM_SAMPLES_PER_PIXEL as f32;
-                pixel_color *= scale;
+    if let (Some(overlay), Some(depth)) = (
+        display_settings.focus_plane_overlay,
+        aov_buffers.get(&Aov::Depth),
+    ) {
+        overlay_focus_plane(
+            &mut render_buffer,
+            depth,
+            overlay.focus_distance,
+            overlay.tolerance,
+        );
+    }
 
-                // Convert from display-referred (0..1) to scene-referred (0..infinity)
-                // TODO: Do the propert state conversion from Display to Scene
-                let rendered_color =
-                    color::acescg::<colstodian::Scene>(pixel_color.x, pixel_color.y, pixel_color.z);
+    // Create a new buffer to host the converted pixels
+    let mut display_buffer: Vec<u8> = vec![0; width * height * 4];
 
-                // R, G, B, A
-                render_buffer[index + 0] = rendered_color.r;
-                render_buffer[index + 1] = rendered_color.g;
-                render_buffer[index + 2] = rendered_color.b;
-                render_buffer[index + 3] = 1.0;
+    let exposure_scale = 2.0_f32.powf(display_settings.exposure);
+    let gamma_lut = display_gamma_lut(display_settings.gamma);
 
-                index += 4;
-            }
+    // Do the scene linear to display conversion. Pixels are converted independently
+    // of each other, so they can be spread over all the cores
+    render_buffer
+        .par_chunks_exact(4)
+        .zip(display_buffer.par_chunks_exact_mut(4))
+        .enumerate()
+        .for_each(|(pixel_index, (f32_pixel, u8_pixel))| {
+            let is_before_half = display_settings.split_view && pixel_index % width < width / 2;
+            u8_pixel.copy_from_slice(&display_pixel(
+                f32_pixel,
+                is_before_half,
+                is_data_pass,
+                working_space,
+                display_settings,
+                exposure_scale,
+                &gamma_lut,
+            ));
+        });
+
+    let elapsed_time = start_time.elapsed();
+    eprintln!(
+        "Conversion finished, elapsed time: {:?}, (~{} seconds)",
+        elapsed_time,
+        elapsed_time.as_secs()
+    );
+
+    display_buffer
+}
+
+/// Converts a pixel of the render buffer to display values, see ``convert_to_display``.
+/// Pixels in the left half of split views (``is_before_half``) don't get tonemapped
+fn display_pixel(
+    f32_pixel: &[f32],
+    is_before_half: bool,
+    is_data_pass: bool,
+    working_space: WorkingSpace,
+    display_settings: &DisplaySettings,
+    exposure_scale: f32,
+    gamma_lut: &[u8; 256],
+) -> [u8; 4] {
+    // If we're working with a utility pass (eg: normals, etc.)
+    // we only need to go from the 0-1 range to the 0-255 range
+    // without going through tonemapping at all
+    if is_data_pass {
+        let rgba = [
+            (f32_pixel[0] * 255.0) as u8,
+            (f32_pixel[1] * 255.0) as u8,
+            (f32_pixel[2] * 255.0) as u8,
+            (f32_pixel[3] * 255.0) as u8,
+        ];
+
+        return rgba;
+    }
+
+    let encoded = match display_settings.tonemap {
+        Tonemap::Reinhard => software_display_color(
+            Vec3::new(f32_pixel[0], f32_pixel[1], f32_pixel[2]) * exposure_scale,
+            working_space,
+            display_settings.output_color_space,
+            !is_before_half,
+        ),
+        _ => colstodian_display_color(
+            f32_pixel,
+            working_space,
+            display_settings,
+            exposure_scale,
+            is_before_half,
+        ),
+    };
+
+    // The LUT is the last transform, taking and giving encoded values
+    let encoded = match &display_settings.lut {
+        Some(lut) => lut.apply(encoded),
+        None => encoded,
+    };
+    let rgb = encoded
+        .to_array()
+        .map(|value| gamma_lut[unit_to_u8(value) as usize]);
+    let alpha = f32_pixel[3];
+
+    [rgb[0], rgb[1], rgb[2], (255.0 * alpha) as u8]
+}
+
+/// Tonemaps and encodes a pixel of the render buffer with colstodian, see ``display_pixel``.
+/// The left half of split views (``is_before_half``) only gets clamped to the display range
+fn colstodian_display_color(
+    f32_pixel: &[f32],
+    working_space: WorkingSpace,
+    display_settings: &DisplaySettings,
+    exposure_scale: f32,
+    is_before_half: bool,
+) -> Vec3 {
+    // For the sake of simplicity and saving memory, our array is composed of f32
+    // instead of colostodian Color structs. Here we recreate the colstodian struct
+    // on the fly so we can do the conversion to 8bit sRGB and go to display referred
+    // by applying default a SDR tone mapping. The tonemaps work in ACEScg
+    let (r, g, b) = (
+        f32_pixel[0] * exposure_scale,
+        f32_pixel[1] * exposure_scale,
+        f32_pixel[2] * exposure_scale,
+    );
+    let rendered_color: Color<AcesCg, colstodian::Scene> = match working_space {
+        WorkingSpace::AcesCg => color::acescg(r, g, b),
+        WorkingSpace::LinearSrgb => color::linear_srgb::<colstodian::Scene>(r, g, b).convert(),
+    };
+
+    // Use a standard Tonemap to go from ACEScg HDR to SDR
+    let tonemapped: Color<AcesCg, Display> = match display_settings.tonemap {
+        _ if is_before_half => color::acescg::<Display>(
+            rendered_color.r.clamp(0.0, 1.0),
+            rendered_color.g.clamp(0.0, 1.0),
+            rendered_color.b.clamp(0.0, 1.0),
+        ),
+        // Tonemapped without colstodian, see ``software_display_color``
+        Tonemap::Perceptual | Tonemap::Reinhard => {
+            let params = PerceptualTonemapperParams::default();
+            PerceptualTonemapper::tonemap(rendered_color, params).convert()
+        }
+        Tonemap::AcesRrtOdt => {
+            let fitted = aces_rrt_odt_fit(Vec3::new(
+                rendered_color.r,
+                rendered_color.g,
+                rendered_color.b,
+            ));
+            color::acescg::<Display>(fitted.x, fitted.y, fitted.z)
+        }
+    };
+
+    // Encode in the output color space so we're ready to display or write to an image
+    match display_settings.output_color_space {
+        OutputColorSpace::Srgb => {
+            let encoded = tonemapped.convert::<EncodedSrgb>();
+            Vec3::new(encoded.r, encoded.g, encoded.b)
+        }
+        OutputColorSpace::LinearRec709 => {
+            let linear = tonemapped.convert::<LinearSrgb>();
+            Vec3::new(linear.r, linear.g, linear.b)
+        }
+        OutputColorSpace::DisplayP3 => {
+            let encoded = tonemapped.convert::<EncodedDisplayP3>();
+            Vec3::new(encoded.r, encoded.g, encoded.b)
         }
+    }
+}
 
-        eprintln!("Finished rendering!");
+/// Converts a scene linear ``color`` in ``working_space`` to display values encoded in
+/// ``output_color_space``, tonemapped with Reinhard's curve (or only clamped when
+/// ``tonemap`` is false). Self contained: the matrices and the sRGB transfer function
+/// are spelled out here, so it doesn't depend on colstodian
+pub fn software_display_color(
+    color: Vec3,
+    working_space: WorkingSpace,
+    output_color_space: OutputColorSpace,
+    tonemap: bool,
+) -> Vec3 {
+    // From ACEScg (AP1, D60) to linear sRGB (D65, with a Bradford adaptation), as rows
+    const ACESCG_TO_LINEAR_SRGB: [Vec3; 3] = [
+        Vec3::new(1.705_051, -0.621_792, -0.083_259),
+        Vec3::new(-0.130_256, 1.140_805, -0.010_548),
+        Vec3::new(-0.024_003, -0.128_969, 1.152_972),
+    ];
+    // From linear sRGB to linear Display P3, both D65
+    const LINEAR_SRGB_TO_DISPLAY_P3: [Vec3; 3] = [
+        Vec3::new(0.822_462, 0.177_538, 0.0),
+        Vec3::new(0.033_194, 0.966_806, 0.0),
+        Vec3::new(0.017_083, 0.072_397, 0.910_520),
+    ];
 
-        let elapsed_time = start_time.elapsed();
-        eprintln!(
-            "Elapsed time: {:?} (~{} seconds)",
-            elapsed_time,
-            elapsed_time.as_secs()
-        );
+    let apply = |matrix: &[Vec3; 3], color: Vec3| {
+        Vec3::new(
+            matrix[0].dot(color),
+            matrix[1].dot(color),
+            matrix[2].dot(color),
+        )
+    };
 
-        Ok(render_buffer)
+    let linear_srgb = match working_space {
+        WorkingSpace::AcesCg => apply(&ACESCG_TO_LINEAR_SRGB, color),
+        WorkingSpace::LinearSrgb => color,
     }
+    .max(Vec3::ZERO);
 
-    /// Takes the floating point pixels from ``render_buffer`` and performs the
-    /// math to store them in ``display_buffer``, ready to be presented as 8 bit
-    /// bytes in the GUI. If ``is_data_pass`` is true, no color management will
-    /// be applied.
-    pub async fn convert_to_display_buffer(
-        render_buffer: Vec<f32>,
-        is_data_pass: bool,
-    ) -> Result<Vec<u8>, AppError> {
-        eprintln!("Converting from ACESCG linear to Display Color Space");
-        let start_time = Instant::now();
+    let display_linear = match tonemap {
+        true => linear_srgb / (Vec3::ONE + linear_srgb),
+        false => linear_srgb.min(Vec3::ONE),
+    };
+
+    match output_color_space {
+        OutputColorSpace::Srgb => display_linear.to_array().map(srgb_oetf).into(),
+        OutputColorSpace::LinearRec709 => display_linear,
+        OutputColorSpace::DisplayP3 => apply(&LINEAR_SRGB_TO_DISPLAY_P3, display_linear)
+            .to_array()
+            .map(srgb_oetf)
+            .into(),
+    }
+}
+
+/// The sRGB transfer function, from linear [0, 1] values to encoded ones
+pub fn srgb_oetf(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The camera the GUI renders ``default_scene`` with: at the origin looking down -Z,
+/// framing the whole render buffer with the pixel aspect ratio of ``settings``
+pub fn default_camera(settings: &RenderSettings) -> Camera {
+    let aspect_ratio = settings.viewport_aspect_ratio(RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT);
+    let viewport_height = 2.0;
+    let viewport_width = aspect_ratio * viewport_height;
+    Camera::new(1.0, viewport_width, viewport_height)
+}
+
+/// The default scene: three spheres (diffuse, shiny and rough metal) over a ground sphere
+pub fn default_scene() -> Scene {
+    let mut scene = Scene::new();
+
+    // Materials
+    let col_ground = Vec3::new(0.8, 0.8, 0.1);
+    let col_center = Vec3::new(0.7, 0.3, 0.3);
+    let col_left = Vec3::new(0.8, 0.8, 0.8);
+    let col_right = Vec3::new(0.8, 0.6, 0.2);
+
+    let mat_ground = Arc::new(Lambertian::new(col_ground));
+    let mat_center = Arc::new(Lambertian::new(col_center));
+    let mat_left = Arc::new(Metallic::new(col_left, 0.3));
+    let mat_right = Arc::new(Metallic::new(col_right, 1.0));
+
+    // Let's check if our ray intersects some spheres
+    let spheres_z = -1.0;
+    let sphere_1 = Sphere::new(100.0, Vec3::new(0.0, -100.5, spheres_z), mat_ground.clone());
+    let sphere_2 = Sphere::new(0.5, Vec3::new(0.0, 0.0, spheres_z), mat_center.clone());
+    let sphere_3 = Sphere::new(0.5, Vec3::new(-1.0, 0.0, spheres_z), mat_left.clone());
+    let sphere_4 = Sphere::new(0.5, Vec3::new(1.0, 0.0, spheres_z), mat_right.clone());
+
+    scene.add_hittable(Box::new(sphere_1));
+    scene.add_hittable(Box::new(sphere_2));
+    scene.add_hittable(Box::new(sphere_3));
+    scene.add_hittable(Box::new(sphere_4));
 
-        // Create a new buffer to host the converted pixels
-        let mut display_buffer: Vec<u8> = vec![0; RENDER_BUFFER_SIZE];
+    scene
+}
+
+/// A large ground sphere covered by ``count`` small spheres of random materials
+/// (mostly diffuse, some metals and a bit of glass), like the final scene of
+/// 'Ray Tracing in One Weekend'. The same ``seed`` always builds the same scene.
+/// The small spheres are jittered on a square grid centered on the origin,
+/// one unit apart, so the scene grows with ``count``.
+pub fn generate_random_scene(count: usize, seed: u64) -> Scene {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut scene = Scene::new();
+
+    let ground_material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    scene.add_hittable(Box::new(Sphere::new(
+        1000.0,
+        Vec3::new(0.0, -1000.0, 0.0),
+        ground_material,
+    )));
+
+    let radius = 0.2;
+    let grid_size = (count as f32).sqrt().ceil() as usize;
+    let grid_offset = grid_size as f32 / 2.0;
 
-        // Do the scene linear to display conversion
-        let it = std::iter::zip(
-            render_buffer.chunks_exact(4),
-            display_buffer.chunks_exact_mut(4),
+    for index in 0..count {
+        // Jitter within the cell, without overlapping the neighbouring spheres
+        let cell_x = (index % grid_size) as f32 - grid_offset;
+        let cell_z = (index / grid_size) as f32 - grid_offset;
+        let center = Vec3::new(
+            cell_x + 0.5 + 0.6 * (rng.gen::<f32>() - 0.5),
+            radius,
+            cell_z + 0.5 + 0.6 * (rng.gen::<f32>() - 0.5),
         );
 
-        for (f32_pixel, u8_pixel) in it {
-            // If we're working with a utility pass (eg: normals, etc.)
-            // we only need to go from the 0-1 range to the 0-255 range
-            // without going through tonemapping at all
-            if is_data_pass {
-                let rgba = [
-                    (f32_pixel[0] * 255.0) as u8,
-                    (f32_pixel[1] * 255.0) as u8,
-                    (f32_pixel[2] * 255.0) as u8,
-                    (f32_pixel[3] * 255.0) as u8,
-                ];
+        let choose_material: f32 = rng.gen();
+        let material: Arc<dyn Material> = if choose_material < 0.8 {
+            let albedo = Vec3::new(rng.gen(), rng.gen(), rng.gen())
+                * Vec3::new(rng.gen(), rng.gen(), rng.gen());
+            Arc::new(Lambertian::new(albedo))
+        } else if choose_material < 0.95 {
+            let albedo = Vec3::new(
+                rng.gen_range(0.5..1.0),
+                rng.gen_range(0.5..1.0),
+                rng.gen_range(0.5..1.0),
+            );
+            Arc::new(Metallic::new(albedo, rng.gen_range(0.0..0.5)))
+        } else {
+            Arc::new(Dielectric::new(1.5))
+        };
 
-                u8_pixel.copy_from_slice(&rgba);
-                continue;
-            }
+        scene.add_hittable(Box::new(Sphere::new(radius, center, material)));
+    }
 
-            // For the sake of simplicity and saving memory, our array is composed of f32
-            // instead of colostodian Color structs. Here we recreate the colstodian struct
-            // on the fly so we can do the conversion to 8bit sRGB and go to display referred
-            // by applying default a SDR tone mapping
-            let rendered_color =
-                colstodian::color::acescg(f32_pixel[0], f32_pixel[1], f32_pixel[2]);
+    // Many small spheres of the same size: what the hash grid is best at
+    scene.set_accelerator(Accelerator::HashGrid);
+    scene
+}
 
-            // Use a standard Tonemap to go from ACEScg HDR to SDR
-            let params = PerceptualTonemapperParams::default();
-            let tonemapped: Color<AcesCg, Display> =
-                PerceptualTonemapper::tonemap(rendered_color, params).convert();
+/// Renders the passes that only depend on what the primary rays hit
+/// (see ``RenderPass``), with one ray through the center of each pixel
+/// and no path tracing. ``control`` is checked after every scanline, to pause the render
+fn render_primary_passes(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+    control: &RenderControl,
+) -> RenderResult {
+    let num_pixels = width * height;
+    let mut aovs: HashMap<Aov, Vec<f32>> = Aov::ALL
+        .into_iter()
+        .filter(|aov| aov.render_pass() != RenderPass::Beauty && settings.wants(aov.render_pass()))
+        .map(|aov| (aov, vec![0.0; num_pixels * aov.num_channels()]))
+        .collect();
 
-            // Encode in sRGB so we're ready to display or write to an image
-            let encoded = tonemapped.convert::<EncodedSrgb>();
+    eprintln!("Started rendering {:?}..", settings.passes);
+    let start_time = Instant::now();
+    let mut primary_rays = Duration::ZERO;
+
+    control.begin_progress(num_pixels);
+
+    let mut unrendered = Vec::new();
+    for row in 0..height {
+        control.wait_while_paused();
+        if control.is_cancelled() {
+            // The rows left, whichever end of the image they are at
+            let first_row_from_top = usize::min(
+                settings.image_origin.buffer_row(row, height),
+                settings.image_origin.buffer_row(height - 1, height),
+            );
+            unrendered.push(Tile {
+                x: 0,
+                y: first_row_from_top,
+                width,
+                height: height - row,
+            });
+            break;
+        }
 
-            // Convert to 8bit
-            let rgb: [u8; 3] = encoded.to_u8();
-            let alpha = f32_pixel[3];
+        // The camera looks at rows from the bottom (v = 0) up
+        let y = height - 1 - settings.image_origin.buffer_row(row, height);
+        // Timed per row, without the pauses
+        let row_start_time = Instant::now();
 
-            // Can I avoid doing a copy here ?
-            let rgba: [u8; 4] = [rgb[0], rgb[1], rgb[2], (255 as f32 * alpha) as u8];
+        for x in 0..width {
+            let u = fit_range(x as f32 + 0.5, 0.0, width as f32, 0.0, 1.0);
+            let v = fit_range(y as f32 + 0.5, 0.0, height as f32, 0.0, 1.0);
+            let ray = camera.get_ray_at_coords(u, v);
 
-            u8_pixel.copy_from_slice(&rgba);
+            let hit = scene.hit_visible(&ray, 0.001, f32::INFINITY, Visibility::CAMERA);
+
+            let pixel_index = row * width + x;
+            for (aov, aov_buffer) in aovs.iter_mut() {
+                let hit = match &hit {
+                    Some(hit) => hit,
+                    None => {
+                        let values = settings.miss_values.channels(aov.render_pass());
+                        aov_buffer[pixel_index * values.len()..(pixel_index + 1) * values.len()]
+                            .copy_from_slice(&values);
+                        continue;
+                    }
+                };
+                let values = match aov {
+                    Aov::Normal => hit.normal.normalize_or_zero().to_array().to_vec(),
+                    Aov::Albedo => settings.material_for(hit).albedo().to_array().to_vec(),
+                    Aov::Depth => vec![hit.t * ray.direction.length()],
+                    Aov::ObjectId => vec![hit.object_id as f32],
+                    _ => unreachable!("The {aov} AOV comes from the beauty"),
+                };
+
+                let num_channels = aov.num_channels();
+                aov_buffer[pixel_index * num_channels..(pixel_index + 1) * num_channels]
+                    .copy_from_slice(&values);
+            }
         }
+        primary_rays += row_start_time.elapsed();
 
-        let elapsed_time = start_time.elapsed();
-        eprintln!(
-            "Conversion finished, elapsed time: {:?}, (~{} seconds)",
-            elapsed_time,
-            elapsed_time.as_secs()
-        );
+        control.add_samples(width, 0);
+    }
 
-        Ok(display_buffer)
+    eprintln!("Finished rendering in {:?}", start_time.elapsed());
+
+    RenderResult {
+        beauty: None,
+        aovs,
+        image_origin: settings.image_origin,
+        working_space: settings.working_space,
+        stats: RenderStats {
+            primary_rays,
+            total: start_time.elapsed(),
+            ..RenderStats::default()
+        },
+        unrendered,
     }
 }
 
-pub fn convert_to_openexr(
+/// A rectangle of pixels rendered in one go, counted from the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Splits an image in square tiles of ``tile_size`` pixels (smaller along the right
+/// and bottom borders, if the size doesn't divide the image), in rendering order
+pub fn image_tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    order
+        .schedule(columns, rows)
+        .into_iter()
+        .map(|(column, row)| {
+            let x = column * tile_size;
+            let y = row * tile_size;
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            }
+        })
+        .collect()
+}
+
+/// Renders the passes requested by ``settings`` of ``scene`` as seen by ``camera``,
+/// tile by tile. ``control`` is checked after every tile, to pause the render
+pub fn render_image(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
     width: usize,
     height: usize,
-    render_buffer: &Vec<f32>,
-) -> anyhow::Result<SimpleOpenEXRImage> {
-    let resolution = (width, height);
+    control: &RenderControl,
+) -> RenderResult {
+    // Normals, depth and co. don't need the beauty to be path traced
+    if !settings.wants(RenderPass::Beauty) {
+        return render_primary_passes(scene, camera, settings, width, height, control);
+    }
+
+    render_samples(scene, camera, settings, width, height, control, None)
+}
+
+/// Renders like ``render_image``, and converts the beauty for display with
+/// ``display_settings``: everything about the render comes back in one go
+pub fn render(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+    display_settings: &DisplaySettings,
+    control: &RenderControl,
+) -> RenderOutput {
+    let result = render_image(scene, camera, settings, width, height, control);
+    RenderOutput::new(result, camera, settings, (width, height), display_settings)
+}
+
+/// Renders ``settings.samples_per_pixel`` samples per pixel like ``render_image``, but
+/// starts from ``previous``, a render of the same scene and camera with the same size:
+/// only the samples it's missing get rendered, and averaged in with its own. With
+/// the per-sample seeds, this gives the same image as rendering all the samples in
+/// one go, as long as the settings of ``previous`` can accumulate with ``settings``
+/// (see ``RenderSettings::can_accumulate_with``). Renders that don't match in size
+/// start over
+pub fn render_image_from(
+    previous: &RenderResult,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+    control: &RenderControl,
+) -> RenderResult {
+    let num_pixels = width * height;
+    let previous_counts = match (&previous.beauty, previous.aovs.get(&Aov::SampleCount)) {
+        (Some(beauty), Some(counts))
+            if beauty.len() == num_pixels * 4 && counts.len() == num_pixels =>
+        {
+            counts
+        }
+        _ => {
+            eprintln!("The previous render doesn't match, rendering from scratch..");
+            return render_image(scene, camera, settings, width, height, control);
+        }
+    };
 
-    // A vec for each channel
-    let mut r_vec: Vec<f32> = Vec::new();
-    let mut g_vec: Vec<f32> = Vec::new();
-    let mut b_vec: Vec<f32> = Vec::new();
+    let next = render_samples(
+        scene,
+        camera,
+        settings,
+        width,
+        height,
+        control,
+        Some(previous_counts),
+    );
+
+    accumulate_results(previous, next, settings)
+}
 
-    // Fill in the RGB channels
-    for f32_color in render_buffer.chunks_exact(4) {
-        r_vec.push(f32_color[0]);
-        g_vec.push(f32_color[1]);
-        b_vec.push(f32_color[2]);
+/// Renders ``scene`` in ``passes`` passes, each one adding samples to the previous ones
+/// until ``settings.samples_per_pixel``, and saves a checkpoint to ``checkpoint_path``
+/// after each of them (and when cancelled). A long render that crashes can then pick
+/// up from its last checkpoint with ``resume_from_checkpoint``, instead of starting over.
+/// The settings must be able to accumulate samples (see ``RenderSettings::can_accumulate_with``)
+pub fn render_with_checkpoints(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    image_size: (usize, usize),
+    passes: usize,
+    checkpoint_path: impl AsRef<Path>,
+    control: &RenderControl,
+) -> anyhow::Result<RenderResult> {
+    let (width, height) = image_size;
+    settings.check_resolution(width, height)?;
+    if !settings.can_accumulate_with(settings) {
+        anyhow::bail!(
+            "Renders with checkpoints need random sampling without adaptive sampling, and the Beauty pass"
+        );
     }
 
-    // Save the data into the channels
-    let r_channel = AnyChannel::new("R", FlatSamples::F32(r_vec));
-    let g_channel = AnyChannel::new("G", FlatSamples::F32(g_vec));
-    let b_channel = AnyChannel::new("B", FlatSamples::F32(b_vec));
+    let passes = passes.max(1);
+    let first_pass = RenderSettings {
+        samples_per_pixel: pass_samples(settings.samples_per_pixel, 1, passes),
+        ..settings.clone()
+    };
+    let result = render_image(scene, camera, &first_pass, width, height, control);
 
-    let channels = AnyChannels::sort(smallvec![r_channel, g_channel, b_channel]);
+    let checkpoint = Checkpoint {
+        width,
+        height,
+        passes,
+        passes_done: usize::from(!control.is_cancelled()),
+        samples_per_pixel: settings.samples_per_pixel,
+        seed: settings.seed,
+        result,
+    };
+    save_checkpoint(&checkpoint_path, &checkpoint)?;
 
-    // The layer attributes can store additional metadata
-    let mut layer_attributes = LayerAttributes::named("rgb");
-    layer_attributes.comments = Some("Generated by vvzen from Rust".into());
-    layer_attributes.owner = Some("vvzen".into());
-    layer_attributes.software_name = Some("rust-tracer".into());
+    render_remaining_passes(
+        scene,
+        camera,
+        settings,
+        checkpoint,
+        checkpoint_path,
+        control,
+    )
+}
 
-    // The only layer in this image
-    let layer = Layer::new(
-        resolution,
-        layer_attributes,
-        Encoding::SMALL_LOSSLESS,
-        channels,
+/// Carries on with a render started by ``render_with_checkpoints``, from the last
+/// checkpoint it saved to ``checkpoint_path``. The scene, camera and settings must
+/// be the ones the render was started with, to get the image it would have given
+pub fn resume_from_checkpoint(
+    checkpoint_path: impl AsRef<Path>,
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    control: &RenderControl,
+) -> anyhow::Result<RenderResult> {
+    let checkpoint = load_checkpoint(&checkpoint_path)?;
+
+    settings.check_resolution(checkpoint.width, checkpoint.height)?;
+    let matches_settings = checkpoint.samples_per_pixel == settings.samples_per_pixel
+        && checkpoint.seed == settings.seed
+        && checkpoint.result.image_origin == settings.image_origin
+        && checkpoint.result.working_space == settings.working_space;
+    if !matches_settings || !settings.can_accumulate_with(settings) {
+        anyhow::bail!(
+            "The checkpoint {} was saved by a render with other settings",
+            checkpoint_path.as_ref().display()
+        );
+    }
+
+    match checkpoint.passes_done < checkpoint.passes {
+        true => eprintln!(
+            "Resuming from pass {}/{} of the checkpoint..",
+            checkpoint.passes_done + 1,
+            checkpoint.passes
+        ),
+        false => eprintln!("The checkpoint already holds the whole render"),
+    }
+    render_remaining_passes(
+        scene,
+        camera,
+        settings,
+        checkpoint,
+        checkpoint_path,
+        control,
+    )
+}
+
+/// Renders the passes left in ``checkpoint``, saving it again after each of them
+fn render_remaining_passes(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    mut checkpoint: Checkpoint,
+    checkpoint_path: impl AsRef<Path>,
+    control: &RenderControl,
+) -> anyhow::Result<RenderResult> {
+    while checkpoint.passes_done < checkpoint.passes && !control.is_cancelled() {
+        let pass_settings = RenderSettings {
+            samples_per_pixel: pass_samples(
+                settings.samples_per_pixel,
+                checkpoint.passes_done + 1,
+                checkpoint.passes,
+            ),
+            ..settings.clone()
+        };
+        checkpoint.result = render_image_from(
+            &checkpoint.result,
+            scene,
+            camera,
+            &pass_settings,
+            checkpoint.width,
+            checkpoint.height,
+            control,
+        );
+
+        // The samples of a cancelled pass are kept, it only needs finishing
+        if !control.is_cancelled() {
+            checkpoint.passes_done += 1;
+        }
+        save_checkpoint(&checkpoint_path, &checkpoint)?;
+    }
+
+    Ok(checkpoint.result)
+}
+
+/// Samples per pixel once ``pass`` out of ``passes`` is done
+fn pass_samples(samples_per_pixel: usize, pass: usize, passes: usize) -> usize {
+    (samples_per_pixel * pass).div_ceil(passes)
+}
+
+/// Path traces the beauty and the AOVs, see ``render_image``. When given,
+/// ``first_samples`` holds how many samples each pixel already has: the pixels only
+/// get the samples from there on, and the AOVs only count the new ones
+fn render_samples(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    width: usize,
+    height: usize,
+    control: &RenderControl,
+    first_samples: Option<&[f32]>,
+) -> RenderResult {
+    let num_pixels = width * height;
+    let mut render_buffer = vec![0.0; num_pixels * 4];
+    let mut variance_buffer = vec![0.0; num_pixels];
+    let mut sample_count_buffer = vec![0.0; num_pixels];
+    // The AOVs of the passes that weren't requested are never allocated
+    let aov_buffer = |aov: Aov| match settings.wants(aov.render_pass()) {
+        true => vec![0.0; num_pixels * aov.num_channels()],
+        false => Vec::new(),
+    };
+    let mut normal_buffer = aov_buffer(Aov::Normal);
+    let mut albedo_buffer = aov_buffer(Aov::Albedo);
+    let mut depth_buffer = aov_buffer(Aov::Depth);
+    let mut object_id_buffer = aov_buffer(Aov::ObjectId);
+    let mut direct_buffer = vec![0.0; num_pixels * Aov::Direct.num_channels()];
+    let mut indirect_buffer = vec![0.0; num_pixels * Aov::Indirect.num_channels()];
+
+    eprintln!(
+        "Started rendering.. using {} rays per pixel",
+        settings.primary_rays_per_pixel()
     );
+    let start_time = Instant::now();
+    let mut primary_rays = Duration::ZERO;
+    let mut shading = Duration::ZERO;
 
-    // The layer attributes can store additional metadata
-    let mut layer_attributes = LayerAttributes::named("rgb");
-    layer_attributes.comments = Some("Generated by vvzen from Rust".into());
-    layer_attributes.owner = Some("vvzen".into());
-    layer_attributes.software_name = Some("vv-ltsr".into());
+    // Shorthands
+    let image_width = width as f32;
+    let image_height = height as f32;
+    let lens_samples = settings.lens_samples.max(1);
+
+    // Generate the image
+    control.begin_partial(
+        render_buffer.len(),
+        settings.image_origin,
+        settings.working_space,
+    );
+    let samples_before =
+        |pixel_index: usize| first_samples.map_or(0, |counts| counts[pixel_index] as usize);
+    control.begin_progress(
+        (0..num_pixels)
+            .map(|pixel_index| {
+                settings
+                    .samples_per_pixel
+                    .saturating_sub(samples_before(pixel_index))
+            })
+            .sum(),
+    );
+
+    let tiles = image_tiles(width, height, settings.tile_size, settings.tile_order);
+    let mut unrendered = Vec::new();
+    for (tile_index, &tile) in tiles.iter().enumerate() {
+        control.wait_while_paused();
+        if control.is_cancelled() {
+            unrendered = tiles[tile_index..].to_vec();
+            break;
+        }
+        let mut tile_samples_done = 0;
+        let mut tile_samples_skipped = 0;
 
-    // Write the image to disk
-    let image = Image::from_layer(layer);
+        for row_from_top in tile.y..tile.y + tile.height {
+            let row = settings.image_origin.buffer_row(row_from_top, height);
+            // The camera looks at rows from the bottom (v = 0) up
+            let y = height - 1 - row_from_top;
 
-    Ok(image)
+            for x in tile.x..tile.x + tile.width {
+                let pixel_index = row * width + x;
+                let first_sample = samples_before(pixel_index);
+                let pixel_budget = settings.samples_per_pixel.saturating_sub(first_sample);
+
+                // Accumulated in double precision: summing thousands of small
+                // contributions in f32 would drift away from the true average
+                let mut pixel_color = DVec3::new(0.0, 0.0, 0.0);
+                let mut pixel_direct = DVec3::new(0.0, 0.0, 0.0);
+                let mut pixel_indirect = DVec3::new(0.0, 0.0, 0.0);
+                let mut pixel_normal = Vec3::new(0.0, 0.0, 0.0);
+                let mut pixel_albedo = Vec3::new(0.0, 0.0, 0.0);
+                let mut pixel_depth = 0.0;
+                let mut num_primary_hits = 0;
+                // How many samples hit each object
+                let mut object_coverage: SmallVec<[(u32, u32); 4]> = SmallVec::new();
+
+                // Statistics used for the Variance AOV and adaptive sampling.
+                // Coverage is 1.0 for primary rays hitting geometry, 0.0 otherwise
+                let mut luminance_stats = RunningVariance::new();
+                let mut coverage_stats = RunningVariance::new();
+
+                // Antialiasing: multiple samples per pixel
+                let mut num_samples = 0;
+                // Timing each sample would slow the render down noticeably, so only the
+                // first one is split between the primary ray and the shading
+                let samples_start_time = Instant::now();
+                let mut first_sample_times = (Duration::ZERO, Duration::ZERO);
+                while num_samples < pixel_budget {
+                    // Each sample gets its own random numbers, so it's the same
+                    // whatever order the pixels are rendered in
+                    let sample_index = first_sample + num_samples;
+                    let sample_seed = pixel_seed(settings.seed, x, y, sample_index);
+                    seed_sampler(sample_seed);
+                    let mut camera_sampler =
+                        CameraSampler::new(sample_seed, settings.sample_correlation);
+                    let (jitter_x, jitter_y) = settings
+                        .sampling_mode
+                        .sample_offset(sample_index, settings.samples_per_pixel);
+
+                    // Get normalized U,V coordinates as we move through the image
+                    let u = fit_range(x as f32 + jitter_x, 0.0, image_width, 0.0, 1.0);
+                    let v = fit_range(y as f32 + jitter_y, 0.0, image_height, 0.0, 1.0);
+
+                    // Depth of field: each sample goes through several points of the lens
+                    let mut sample_lighting = Lighting::default();
+                    let mut coverage = 0.0;
+                    for lens_index in 0..lens_samples {
+                        // Aim the camera based on the current u,v coordinates
+                        let (lens_sample, time) = camera_sampler.next(camera, settings);
+                        let ray = camera
+                            .get_ray_through_lens(u, v, lens_sample)
+                            .with_time(time)
+                            .with_wavelength(settings.sample_wavelength());
+
+                        // Geometric AOVs only care about what the primary ray hits
+                        if let Some(hit) =
+                            scene.hit_visible(&ray, 0.001, f32::INFINITY, Visibility::CAMERA)
+                        {
+                            pixel_normal += hit.normal;
+                            pixel_albedo += settings.material_for(&hit).albedo();
+                            pixel_depth += hit.t * ray.direction.length();
+                            num_primary_hits += 1;
+
+                            match object_coverage
+                                .iter_mut()
+                                .find(|(id, _)| *id == hit.object_id)
+                            {
+                                Some((_, count)) => *count += 1,
+                                None => object_coverage.push((hit.object_id, 1)),
+                            }
+                            coverage += 1.0;
+                        }
+                        let shading_start_time =
+                            (num_samples == 0 && lens_index == 0).then(Instant::now);
+
+                        let mut lighting = ray_lighting(&ray, scene, settings);
+                        // Back to RGB, for the single wavelength of spectral samples
+                        if let Some(wavelength) = ray.wavelength {
+                            lighting =
+                                lighting * wavelength_weight(wavelength, settings.working_space);
+                        }
+                        if let Some(shading_start_time) = shading_start_time {
+                            first_sample_times = (
+                                shading_start_time - samples_start_time,
+                                shading_start_time.elapsed(),
+                            );
+                        }
+                        sample_lighting = sample_lighting + lighting;
+                    }
+                    // A sample is the average of its rays through the lens
+                    let lens_weight = 1.0 / lens_samples as f32;
+                    sample_lighting = sample_lighting * Vec3::splat(lens_weight);
+                    coverage *= lens_weight;
+
+                    let sample_color = sample_lighting.total();
+                    pixel_color += sample_color.as_dvec3();
+                    pixel_direct += sample_lighting.direct.as_dvec3();
+                    pixel_indirect += sample_lighting.indirect.as_dvec3();
+                    num_samples += 1;
+
+                    luminance_stats.push(settings.working_space.luminance(sample_color));
+                    coverage_stats.push(coverage);
+
+                    if let Some(adaptive_sampling) = settings.adaptive_sampling {
+                        if adaptive_sampling.is_converged(&luminance_stats, &coverage_stats) {
+                            break;
+                        }
+                    }
+                }
+                let (pixel_primary_rays, pixel_shading) =
+                    split_duration(samples_start_time.elapsed(), first_sample_times);
+                primary_rays += pixel_primary_rays;
+                shading += pixel_shading;
+
+                tile_samples_done += num_samples;
+                tile_samples_skipped += pixel_budget - num_samples;
+
+                // Divide by the num of samples to get the average
+                // (pixels that already had all their samples stay black)
+                let sample_divisor = num_samples.max(1);
+                let pixel_color = (pixel_color / sample_divisor as f64).as_vec3();
+                let pixel_direct = (pixel_direct / sample_divisor as f64).as_vec3();
+                let pixel_indirect = (pixel_indirect / sample_divisor as f64).as_vec3();
+                // The albedo gets summed for every ray through the lens
+                pixel_albedo /= (sample_divisor * lens_samples) as f32;
+
+                let index = pixel_index * 4;
+                // None of the primary rays hit anything
+                let miss_values = (num_primary_hits == 0).then_some(settings.miss_values);
+                if !normal_buffer.is_empty() {
+                    let normal = match miss_values {
+                        Some(miss_values) => miss_values.normal,
+                        None => pixel_normal.normalize_or_zero(),
+                    };
+                    normal_buffer[pixel_index * 3..pixel_index * 3 + 3]
+                        .copy_from_slice(&normal.to_array());
+                }
+                if !albedo_buffer.is_empty() {
+                    let albedo = match miss_values {
+                        Some(miss_values) => miss_values.albedo,
+                        None => pixel_albedo,
+                    };
+                    albedo_buffer[pixel_index * 3..pixel_index * 3 + 3]
+                        .copy_from_slice(&albedo.to_array());
+                }
+                direct_buffer[pixel_index * 3..pixel_index * 3 + 3]
+                    .copy_from_slice(&pixel_direct.to_array());
+                indirect_buffer[pixel_index * 3..pixel_index * 3 + 3]
+                    .copy_from_slice(&pixel_indirect.to_array());
+                variance_buffer[pixel_index] = luminance_stats.variance();
+                sample_count_buffer[pixel_index] = num_samples as f32;
+                // Averaging in the background would move the depth of the silhouettes
+                if !depth_buffer.is_empty() {
+                    depth_buffer[pixel_index] = match miss_values {
+                        Some(miss_values) => miss_values.depth,
+                        None => pixel_depth / num_primary_hits as f32,
+                    };
+                }
+                // IDs can't be averaged, keep the one covering most of the pixel
+                if !object_id_buffer.is_empty() {
+                    object_id_buffer[pixel_index] =
+                        match object_coverage.iter().max_by_key(|(_, count)| *count) {
+                            Some((id, _)) => *id as f32,
+                            None => settings.miss_values.object_id,
+                        };
+                }
+
+                // Convert from display-referred (0..1) to scene-referred (0..infinity)
+                // TODO: Do the propert state conversion from Display to Scene
+                let rendered_color =
+                    color::acescg::<colstodian::Scene>(pixel_color.x, pixel_color.y, pixel_color.z);
+
+                // R, G, B, A
+                render_buffer[index] = rendered_color.r;
+                render_buffer[index + 1] = rendered_color.g;
+                render_buffer[index + 2] = rendered_color.b;
+                render_buffer[index + 3] = 1.0;
+            }
+
+            // Keep the tile so it can be saved before the render is done
+            let row_start = (row * width + tile.x) * 4;
+            control.update_partial(
+                row_start,
+                &render_buffer[row_start..row_start + tile.width * 4],
+            );
+        }
+
+        control.add_samples(tile_samples_done, tile_samples_skipped);
+    }
+
+    eprintln!("Finished rendering!");
+
+    let elapsed_time = start_time.elapsed();
+    eprintln!(
+        "Elapsed time: {:?} (~{} seconds)",
+        elapsed_time,
+        elapsed_time.as_secs()
+    );
+
+    let mut aovs = HashMap::new();
+    aovs.insert(Aov::Variance, variance_buffer);
+    aovs.insert(Aov::SampleCount, sample_count_buffer);
+    aovs.insert(Aov::Normal, normal_buffer);
+    aovs.insert(Aov::Albedo, albedo_buffer);
+    aovs.insert(Aov::Depth, depth_buffer);
+    aovs.insert(Aov::ObjectId, object_id_buffer);
+    aovs.insert(Aov::Direct, direct_buffer);
+    aovs.insert(Aov::Indirect, indirect_buffer);
+    aovs.retain(|_, aov_buffer| !aov_buffer.is_empty());
+
+    RenderResult {
+        beauty: Some(render_buffer),
+        aovs,
+        image_origin: settings.image_origin,
+        working_space: settings.working_space,
+        stats: RenderStats {
+            primary_rays,
+            shading,
+            total: start_time.elapsed(),
+            ..RenderStats::default()
+        },
+        unrendered,
+    }
+}
+
+/// Follows the path of one sample of a pixel (counted from the top left corner of an
+/// image of the given size), bounce by bounce. With the same ``settings``, it's the
+/// same path ``render_image`` traces for the ``sample_index``-th sample of the pixel
+pub fn trace_pixel_path(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    pixel: (usize, usize),
+    image_size: (usize, usize),
+    sample_index: usize,
+) -> Vec<RayPathSegment> {
+    let (x, row_from_top) = pixel;
+    let (width, height) = image_size;
+    // The camera looks at rows from the bottom (v = 0) up
+    let y = height - 1 - row_from_top;
+
+    let sample_seed = pixel_seed(settings.seed, x, y, sample_index);
+    seed_sampler(sample_seed);
+    let (jitter_x, jitter_y) = settings
+        .sampling_mode
+        .sample_offset(sample_index, settings.samples_per_pixel);
+
+    let u = fit_range(x as f32 + jitter_x, 0.0, width as f32, 0.0, 1.0);
+    let v = fit_range(y as f32 + jitter_y, 0.0, height as f32, 0.0, 1.0);
+
+    let (lens_sample, time) =
+        CameraSampler::new(sample_seed, settings.sample_correlation).next(camera, settings);
+    let ray = camera
+        .get_ray_through_lens(u, v, lens_sample)
+        .with_time(time)
+        .with_wavelength(settings.sample_wavelength());
+    ray_path(&ray, scene, settings)
+}
+
+/// Averages the samples of ``next`` into the ones of ``previous``, pixel by pixel,
+/// weighted by how many samples each of them took (see ``render_image_from``)
+fn accumulate_results(
+    previous: &RenderResult,
+    next: RenderResult,
+    settings: &RenderSettings,
+) -> RenderResult {
+    let (Some(previous_counts), Some(next_counts)) = (
+        previous.aovs.get(&Aov::SampleCount),
+        next.aovs.get(&Aov::SampleCount),
+    ) else {
+        return next;
+    };
+    let weights: Vec<(f32, f32)> = previous_counts
+        .iter()
+        .zip(next_counts.iter())
+        .map(|(&previous_count, &next_count)| {
+            let total = (previous_count + next_count).max(1.0);
+            (previous_count / total, next_count / total)
+        })
+        .collect();
+
+    // Weighted average of each channel
+    let blend = |previous: &[f32], next: &[f32], num_channels: usize| -> Vec<f32> {
+        previous
+            .iter()
+            .zip(next.iter())
+            .enumerate()
+            .map(|(index, (&a, &b))| {
+                let (weight_a, weight_b) = weights[index / num_channels];
+                a * weight_a + b * weight_b
+            })
+            .collect()
+    };
+
+    let mut accumulated = RenderResult {
+        beauty: match (&previous.beauty, &next.beauty) {
+            (Some(previous_beauty), Some(next_beauty)) => {
+                Some(blend(previous_beauty, next_beauty, 4))
+            }
+            _ => next.beauty.clone(),
+        },
+        aovs: HashMap::new(),
+        image_origin: next.image_origin,
+        working_space: next.working_space,
+        // The time it took to add the new samples
+        stats: next.stats,
+        // Only what neither of the renders got to is missing
+        unrendered: next
+            .unrendered
+            .iter()
+            .filter(|tile| previous.unrendered.contains(tile))
+            .copied()
+            .collect(),
+    };
+
+    for (aov, next_buffer) in next.aovs.iter() {
+        let Some(previous_buffer) = previous.aovs.get(aov) else {
+            continue;
+        };
+        let num_channels = aov.num_channels();
+
+        let buffer = match aov {
+            Aov::SampleCount => previous_buffer
+                .iter()
+                .zip(next_buffer.iter())
+                .map(|(a, b)| a + b)
+                .collect(),
+            Aov::Normal => {
+                let mut normals = blend(previous_buffer, next_buffer, num_channels);
+                for normal in normals.chunks_exact_mut(3) {
+                    let normalized = Vec3::from_slice(normal).normalize_or_zero();
+                    normal.copy_from_slice(&normalized.to_array());
+                }
+                normals
+            }
+            // Pixels that only saw the background in one of the renders
+            // take the depth of the other one, like ``render_image`` does
+            Aov::Depth => previous_buffer
+                .iter()
+                .zip(next_buffer.iter())
+                .zip(weights.iter())
+                .map(|((&a, &b), &(weight_a, weight_b))| {
+                    let miss_depth = settings.miss_values.depth;
+                    match (a == miss_depth, b == miss_depth) {
+                        (true, false) if weight_b > 0.0 => b,
+                        (false, true) if weight_a > 0.0 => a,
+                        _ => a * weight_a + b * weight_b,
+                    }
+                })
+                .collect(),
+            // IDs can't be averaged, keep the one from the render with the most samples
+            Aov::ObjectId => previous_buffer
+                .iter()
+                .zip(next_buffer.iter())
+                .zip(weights.iter())
+                .map(
+                    |((&a, &b), &(weight_a, weight_b))| match weight_a >= weight_b {
+                        true => a,
+                        false => b,
+                    },
+                )
+                .collect(),
+            // Combines the two sample variances (Chan et al.), with the
+            // means of the luminance taken from the beauties
+            Aov::Variance => {
+                let luminances = |result: &RenderResult| -> Vec<f32> {
+                    match &result.beauty {
+                        Some(beauty) => beauty
+                            .chunks_exact(4)
+                            .map(|pixel| {
+                                settings
+                                    .working_space
+                                    .luminance(Vec3::new(pixel[0], pixel[1], pixel[2]))
+                            })
+                            .collect(),
+                        None => vec![0.0; previous_counts.len()],
+                    }
+                };
+                let previous_means = luminances(previous);
+                let next_means = luminances(&next);
+
+                (0..previous_buffer.len())
+                    .map(|index| {
+                        let (count_a, count_b) = (previous_counts[index], next_counts[index]);
+                        let count = count_a + count_b;
+                        if count < 2.0 {
+                            return previous_buffer[index].max(next_buffer[index]);
+                        }
+
+                        let delta = next_means[index] - previous_means[index];
+                        let squared_differences = previous_buffer[index] * (count_a - 1.0).max(0.0)
+                            + next_buffer[index] * (count_b - 1.0).max(0.0)
+                            + delta * delta * count_a * count_b / count;
+                        squared_differences / (count - 1.0)
+                    })
+                    .collect()
+            }
+            Aov::Albedo | Aov::Direct | Aov::Indirect => {
+                blend(previous_buffer, next_buffer, num_channels)
+            }
+        };
+
+        accumulated.aovs.insert(*aov, buffer);
+    }
+
+    accumulated
+}
+
+/// Splits ``total`` in two, in the same proportions as the two parts of ``reference``
+fn split_duration(total: Duration, reference: (Duration, Duration)) -> (Duration, Duration) {
+    let reference_total = (reference.0 + reference.1).as_secs_f64();
+    if reference_total <= 0.0 {
+        return (Duration::ZERO, total);
+    }
+
+    let first = total.mul_f64(reference.0.as_secs_f64() / reference_total);
+    (first, total.saturating_sub(first))
+}
+
+/// Renders each scene of the batch (eg: the frames of an animation) in turn,
+/// and saves it as an OpenEXR image to its output path, at the ``headless_resolution``
+/// and with the samples and the seed of the environment, if any
+pub fn render_batch(batch: Vec<(Scene, Camera, RenderSettings, PathBuf)>) -> anyhow::Result<()> {
+    let num_scenes = batch.len();
+    let start_time = Instant::now();
+
+    for (scene_index, (scene, camera, settings, output_path)) in batch.into_iter().enumerate() {
+        let scene_start_time = Instant::now();
+
+        render_to_file(&scene, &camera, &settings, &output_path)?;
+
+        eprintln!(
+            "Scene {}/{} rendered in {:?}",
+            scene_index + 1,
+            num_scenes,
+            scene_start_time.elapsed()
+        );
+    }
+
+    eprintln!(
+        "Rendered {} scenes in {:?}",
+        num_scenes,
+        start_time.elapsed()
+    );
+
+    Ok(())
+}
+
+/// Orbits the camera around ``center`` at the given ``radius``, rendering ``frames``
+/// evenly spaced angles to numbered OpenEXR images in ``output_dir``.
+/// The first frame looks at the scene from +Z, the following ones go counterclockwise
+pub fn render_turntable(
+    scene: &Scene,
+    center: Vec3,
+    radius: f32,
+    frames: usize,
+    settings: &RenderSettings,
+    output_dir: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let (width, height) = headless_resolution();
+    let viewport_height = 2.0;
+    let viewport_width = settings.viewport_aspect_ratio(width, height) * viewport_height;
+
+    for (frame, position) in turntable_positions(center, radius, frames)
+        .into_iter()
+        .enumerate()
+    {
+        let camera = Camera::look_at(
+            position,
+            center,
+            Vec3::Y,
+            1.0,
+            viewport_width,
+            viewport_height,
+        );
+        let output_path = output_dir
+            .as_ref()
+            .join(format!("turntable.{:04}.exr", frame + 1));
+
+        let frame_start_time = Instant::now();
+        render_to_file(scene, &camera, settings, &output_path)?;
+        eprintln!(
+            "Turntable frame {}/{} rendered in {:?}",
+            frame + 1,
+            frames,
+            frame_start_time.elapsed()
+        );
+    }
+
+    Ok(())
+}
+
+/// Camera positions of a turntable: ``frames`` points evenly spaced on the horizontal
+/// circle of the given ``radius`` around ``center``, starting from +Z
+pub fn turntable_positions(center: Vec3, radius: f32, frames: usize) -> Vec<Vec3> {
+    (0..frames)
+        .map(|frame| {
+            let angle = std::f32::consts::TAU * frame as f32 / frames as f32;
+            center + radius * Vec3::new(angle.sin(), 0.0, angle.cos())
+        })
+        .collect()
+}
+
+/// Reports the likely mistakes in the setup of ``scene``, without stopping the render
+fn log_scene_warnings(scene: &Scene) {
+    if let Err(warnings) = scene.validate() {
+        for warning in warnings {
+            eprintln!("Scene warning: {warning}");
+        }
+    }
+}
+
+/// Renders a scene at the ``headless_resolution`` and saves it as an OpenEXR image.
+/// The samples and the seed of ``settings`` can be overridden from the environment,
+/// see ``RenderSettings::with_env_overrides``
+fn render_to_file(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    output_path: &Path,
+) -> anyhow::Result<()> {
+    let settings = &settings.with_env_overrides();
+    let (width, height) = headless_resolution();
+    settings.check_resolution(width, height)?;
+    log_scene_warnings(scene);
+
+    let output = render(
+        scene,
+        camera,
+        settings,
+        width,
+        height,
+        &DisplaySettings::default(),
+        &RenderControl::new(),
+    );
+    eprintln!("{}", output.stats);
+
+    let mut image = convert_to_openexr(
+        width,
+        height,
+        settings
+            .wants(RenderPass::Beauty)
+            .then_some(&output.linear[..]),
+        &output.aovs,
+        settings.image_origin,
+        settings.working_space,
+        ExrSettings::default(),
+    )?;
+    output.metadata.tag(&mut image);
+
+    save_exr_image_to_disk(image, output_path)
+}
+
+/// Approximates the ACES Reference Rendering Transform followed by the sRGB Output
+/// Device Transform, taking and returning AP1 colors (Stephen Hill's fit).
+/// The results are display referred, in the [0, 1] range
+fn aces_rrt_odt_fit(color: Vec3) -> Vec3 {
+    // Desaturation applied by the RRT and the ODT, as rows of a matrix
+    const RRT_SATURATION: [Vec3; 3] = [
+        Vec3::new(0.970_889, 0.026_963, 0.002_148),
+        Vec3::new(0.010_889, 0.986_963, 0.002_148),
+        Vec3::new(0.010_889, 0.026_963, 0.962_148),
+    ];
+    const ODT_SATURATION: [Vec3; 3] = [
+        Vec3::new(0.949_056, 0.047_185_7, 0.003_758_27),
+        Vec3::new(0.019_056, 0.977_186, 0.003_758_27),
+        Vec3::new(0.019_056, 0.047_185_7, 0.933_758),
+    ];
+
+    let apply = |matrix: &[Vec3; 3], color: Vec3| {
+        Vec3::new(
+            matrix[0].dot(color),
+            matrix[1].dot(color),
+            matrix[2].dot(color),
+        )
+    };
+
+    // Rational fit of the RRT and ODT tone curves
+    let v = apply(&RRT_SATURATION, color.max(Vec3::ZERO));
+    let a = v * (v + 0.024_578_6) - 0.000_090_537;
+    let b = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+
+    apply(&ODT_SATURATION, a / b).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+/// Quantizes a value in the [0, 1] range to 8 bits
+fn unit_to_u8(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Lookup table raising 8bit display values (as [0, 1]) to the power of ``gamma``
+pub fn display_gamma_lut(gamma: f32) -> [u8; 256] {
+    let mut lut = [0; 256];
+    for (value, mapped) in lut.iter_mut().enumerate() {
+        *mapped = if gamma == 1.0 {
+            value as u8
+        } else {
+            unit_to_u8((value as f32 / 255.0).powf(gamma))
+        };
+    }
+
+    lut
+}
+
+/// Size in pixels of the squares of the placeholder checkerboard
+pub const PLACEHOLDER_CHECKER_SIZE: usize = 16;
+
+/// Generates an 8bit RGBA checkerboard of two neutral grays, shown in the GUI
+/// until a render is available so that it's clear nothing has been rendered yet
+pub fn placeholder_display_buffer(width: usize, height: usize, checker_size: usize) -> Vec<u8> {
+    let mut display_buffer = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        for x in 0..width {
+            display_buffer.extend_from_slice(&placeholder_pixel(x, y, checker_size));
+        }
+    }
+
+    display_buffer
+}
+
+/// Draws the placeholder checkerboard over ``tile`` of an 8bit RGBA ``display_buffer``
+/// ``width`` pixels wide (stored from the top row down), eg: where a cancelled
+/// render didn't get to. The squares line up with the ones of the full placeholder
+pub fn fill_with_placeholder(
+    display_buffer: &mut [u8],
+    width: usize,
+    tile: Tile,
+    checker_size: usize,
+) {
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+            let index = (y * width + x) * 4;
+            display_buffer[index..index + 4].copy_from_slice(&placeholder_pixel(
+                x,
+                y,
+                checker_size,
+            ));
+        }
+    }
+}
+
+fn placeholder_pixel(x: usize, y: usize, checker_size: usize) -> [u8; 4] {
+    const LIGHT_GRAY: [u8; 4] = [102, 102, 102, 255];
+    const DARK_GRAY: [u8; 4] = [64, 64, 64, 255];
+
+    let checker_size = checker_size.max(1);
+    match (x / checker_size + y / checker_size).is_multiple_of(2) {
+        true => LIGHT_GRAY,
+        false => DARK_GRAY,
+    }
+}
+
+/// Precision of the samples stored in an OpenEXR image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExrBitDepth {
+    /// 16bit half float: plenty for display-range renders, and half the size on disk
+    F16,
+    /// 32bit full float
+    #[default]
+    F32,
+}
+
+/// Compression applied to the pixels of an OpenEXR image. All of them are lossless
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExrCompression {
+    Uncompressed,
+    /// Run-length encoding: fast, but only effective on flat areas
+    Rle,
+    /// ZIP over blocks of 16 scanlines: slow, but produces small files
+    #[default]
+    Zip,
+    /// Wavelet based: usually the smallest for noisy renders
+    Piz,
+}
+
+impl ExrCompression {
+    pub const ALL: [ExrCompression; 4] = [
+        ExrCompression::Uncompressed,
+        ExrCompression::Rle,
+        ExrCompression::Zip,
+        ExrCompression::Piz,
+    ];
+
+    fn encoding(&self) -> Encoding {
+        let compression = match self {
+            ExrCompression::Uncompressed => Compression::Uncompressed,
+            ExrCompression::Rle => Compression::RLE,
+            ExrCompression::Zip => Compression::ZIP16,
+            ExrCompression::Piz => Compression::PIZ,
+        };
+
+        Encoding {
+            compression,
+            ..Encoding::SMALL_LOSSLESS
+        }
+    }
+}
+
+impl std::fmt::Display for ExrCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExrCompression::Uncompressed => "Uncompressed",
+            ExrCompression::Rle => "RLE",
+            ExrCompression::Zip => "ZIP",
+            ExrCompression::Piz => "PIZ",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Controls how images are written to OpenEXR
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExrSettings {
+    pub bit_depth: ExrBitDepth,
+    pub compression: ExrCompression,
+}
+
+/// Stores the values of a channel with the requested precision
+fn to_flat_samples(values: Vec<f32>, bit_depth: ExrBitDepth) -> FlatSamples {
+    match bit_depth {
+        ExrBitDepth::F16 => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+        ExrBitDepth::F32 => FlatSamples::F32(values),
+    }
+}
+
+pub fn convert_to_openexr(
+    width: usize,
+    height: usize,
+    render_buffer: Option<&[f32]>,
+    aovs: &HashMap<Aov, Vec<f32>>,
+    image_origin: ImageOrigin,
+    working_space: WorkingSpace,
+    exr_settings: ExrSettings,
+) -> anyhow::Result<SimpleOpenEXRImage> {
+    let bit_depth = exr_settings.bit_depth;
+    let resolution = (width, height);
+
+    let mut channels = SmallVec::new();
+
+    // Renders without the beauty only get the channels of their AOVs
+    if let Some(render_buffer) = render_buffer {
+        if render_buffer.len() != width * height * 4 {
+            anyhow::bail!(
+                "Render buffer has {} values, expected {}",
+                render_buffer.len(),
+                width * height * 4
+            );
+        }
+
+        // A vec for each channel
+        let mut r_vec: Vec<f32> = Vec::new();
+        let mut g_vec: Vec<f32> = Vec::new();
+        let mut b_vec: Vec<f32> = Vec::new();
+
+        // OpenEXR images are stored from the top row down
+        let mut render_buffer = render_buffer.to_vec();
+        image_origin.to_top_left(&mut render_buffer, width * 4);
+
+        // Fill in the RGB channels
+        for f32_color in render_buffer.chunks_exact(4) {
+            r_vec.push(f32_color[0]);
+            g_vec.push(f32_color[1]);
+            b_vec.push(f32_color[2]);
+        }
+
+        // Save the data into the channels
+        channels.push(AnyChannel::new("R", to_flat_samples(r_vec, bit_depth)));
+        channels.push(AnyChannel::new("G", to_flat_samples(g_vec, bit_depth)));
+        channels.push(AnyChannel::new("B", to_flat_samples(b_vec, bit_depth)));
+    }
+
+    // Each channel of each AOV gets its own channel in the same layer
+    for (aov, aov_buffer) in aovs.iter() {
+        let num_channels = aov.num_channels();
+        if aov_buffer.len() != width * height * num_channels {
+            anyhow::bail!(
+                "AOV {:?} has {} values, expected {}",
+                aov,
+                aov_buffer.len(),
+                width * height * num_channels
+            );
+        }
+
+        let mut aov_buffer = aov_buffer.clone();
+        image_origin.to_top_left(&mut aov_buffer, width * num_channels);
+
+        for (channel_index, channel_name) in aov.channel_names().iter().enumerate() {
+            let channel_values: Vec<f32> = aov_buffer
+                .chunks_exact(num_channels)
+                .map(|pixel| pixel[channel_index])
+                .collect();
+
+            channels.push(AnyChannel::new(
+                *channel_name,
+                to_flat_samples(channel_values, bit_depth),
+            ));
+        }
+    }
+
+    let channels = AnyChannels::sort(channels);
+
+    // The layer attributes can store additional metadata
+    let mut layer_attributes = LayerAttributes::named("rgb");
+    layer_attributes.comments = Some("Generated by vvzen from Rust".into());
+    layer_attributes.owner = Some("vvzen".into());
+    layer_attributes.software_name = Some("rust-tracer".into());
+
+    // The only layer in this image
+    let layer = Layer::new(
+        resolution,
+        layer_attributes,
+        exr_settings.compression.encoding(),
+        channels,
+    );
+
+    // Write the image to disk, tagged with the primaries of the working space
+    // so that other applications read its colors right
+    let mut image = Image::from_layer(layer);
+    let [red, green, blue, white] = working_space.chromaticities().map(|(x, y)| Vec2(x, y));
+    image.attributes.chromaticities = Some(Chromaticities {
+        red,
+        green,
+        blue,
+        white,
+    });
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use exr::prelude::read_first_rgba_layer_from_file;
+
+    use super::*;
+    use crate::ltsr::bounds::Aabb;
+    use crate::ltsr::lights::PointLight;
+    use crate::ltsr::materials::Mirror;
+    use crate::ltsr::settings::{AdaptiveSampling, MissValues, Quality};
+    use crate::ltsr::{HitData, Hittable, PathEvent, Ray};
+
+    const TEST_SIZE: usize = 16;
+
+    /// A black sphere in the middle of the frame, over the background
+    fn test_scene() -> (Scene, Camera) {
+        let mut scene = Scene::new();
+        let black = Arc::new(Lambertian::new(Vec3::ZERO));
+        scene.add_hittable(Box::new(Sphere::new(0.5, Vec3::new(0.0, 0.0, -1.0), black)));
+
+        (scene, Camera::new(1.0, 2.0, 2.0))
+    }
+
+    fn test_settings(samples_per_pixel: usize) -> RenderSettings {
+        RenderSettings {
+            samples_per_pixel,
+            max_diffuse_depth: 2,
+            max_specular_depth: 2,
+            ..RenderSettings::default()
+        }
+    }
+
+    /// The default scene framed by a square camera, with some light bouncing around
+    fn lit_scene() -> (Scene, Camera) {
+        (default_scene(), Camera::new(1.0, 2.0, 2.0))
+    }
+
+    fn assert_buffers_close(actual: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (index, (a, b)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                (a - b).abs() <= tolerance * b.abs().max(1.0),
+                "value {index} is {a}, expected {b}"
+            );
+        }
+    }
+
+    /// Value of a single channel AOV at a pixel counted from the top left corner
+    fn aov_at(result: &RenderResult, aov: Aov, x: usize, row_from_top: usize) -> f32 {
+        let row = result.image_origin.buffer_row(row_from_top, TEST_SIZE);
+        result.aovs[&aov][row * TEST_SIZE + x]
+    }
+
+    #[test]
+    fn variance_is_higher_on_edges_than_on_the_background() {
+        let (scene, camera) = test_scene();
+        let settings = test_settings(32);
+        let result = render_image(
+            &scene,
+            &camera,
+            &settings,
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+
+        // The silhouette of the sphere crosses this pixel, the corner only sees the sky
+        let edge = aov_at(&result, Aov::Variance, 12, 7);
+        let background = aov_at(&result, Aov::Variance, 0, 0);
+        assert!(
+            edge > 100.0 * background,
+            "edge variance {edge} should dwarf the background variance {background}"
+        );
+        assert!(edge > 0.01, "edge variance {edge} is too low");
+    }
+
+    #[test]
+    fn pixels_get_the_same_samples_alone_and_in_the_full_image() {
+        let (scene, camera) = lit_scene();
+        let settings = test_settings(4);
+        let (width, height) = (32, 24);
+        let pixel_index = 20 * width + 10;
+        let control = RenderControl::new();
+
+        let full = render_image(&scene, &camera, &settings, width, height, &control);
+
+        // A previous render with all the samples of every pixel but this one, so
+        // that it's the only pixel left to render
+        let mut sample_counts = vec![settings.samples_per_pixel as f32; width * height];
+        sample_counts[pixel_index] = 0.0;
+        let previous = RenderResult {
+            beauty: Some(vec![0.0; width * height * 4]),
+            aovs: HashMap::from([(Aov::SampleCount, sample_counts)]),
+            image_origin: settings.image_origin,
+            working_space: settings.working_space,
+            stats: RenderStats::default(),
+            unrendered: Vec::new(),
+        };
+        let alone = render_image_from(
+            &previous, &scene, &camera, &settings, width, height, &control,
+        );
+
+        let pixel = |result: &RenderResult| {
+            result.beauty.as_ref().unwrap()[pixel_index * 4..pixel_index * 4 + 4].to_vec()
+        };
+        assert_buffers_close(&pixel(&alone), &pixel(&full), 1e-6);
+        assert_ne!(pixel(&alone), vec![0.0; 4]);
+    }
+
+    #[test]
+    fn renders_come_with_their_display_conversion() {
+        let (scene, camera) = test_scene();
+        let settings = test_settings(2);
+        let display_settings = DisplaySettings {
+            exposure: 1.0,
+            denoise: true,
+            ..DisplaySettings::default()
+        };
+        let control = RenderControl::new();
+
+        let output = render(
+            &scene,
+            &camera,
+            &settings,
+            TEST_SIZE,
+            TEST_SIZE,
+            &display_settings,
+            &control,
+        );
+
+        let result = render_image(&scene, &camera, &settings, TEST_SIZE, TEST_SIZE, &control);
+        assert_eq!(Some(&output.linear), result.beauty.as_ref());
+        let display = convert_to_display(
+            output.linear.clone(),
+            &output.aovs,
+            false,
+            settings.working_space,
+            &display_settings,
+            TEST_SIZE,
+            TEST_SIZE,
+        );
+        assert_eq!(output.display, display);
+    }
+
+    #[test]
+    fn accumulating_samples_matches_rendering_them_in_one_go() {
+        let (scene, camera) = lit_scene();
+        let control = RenderControl::new();
+        let first = render_image(
+            &scene,
+            &camera,
+            &test_settings(4),
+            TEST_SIZE,
+            TEST_SIZE,
+            &control,
+        );
+
+        let settings = test_settings(8);
+        let accumulated = render_image_from(
+            &first, &scene, &camera, &settings, TEST_SIZE, TEST_SIZE, &control,
+        );
+        let in_one_go = render_image(&scene, &camera, &settings, TEST_SIZE, TEST_SIZE, &control);
+
+        assert_buffers_close(
+            accumulated.beauty.as_ref().unwrap(),
+            in_one_go.beauty.as_ref().unwrap(),
+            1e-4,
+        );
+        assert_eq!(
+            accumulated.aovs[&Aov::SampleCount],
+            vec![8.0; TEST_SIZE * TEST_SIZE]
+        );
+    }
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_an_uninterrupted_render() {
+        let (scene, camera) = lit_scene();
+        let settings = test_settings(6);
+        let checkpoint_path =
+            std::env::temp_dir().join(format!("ltsr_test_{}.ckpt", std::process::id()));
+
+        // Interrupted before the first pass got to render anything
+        let interrupted = RenderControl::new();
+        interrupted.cancel();
+        render_with_checkpoints(
+            &scene,
+            &camera,
+            &settings,
+            (TEST_SIZE, TEST_SIZE),
+            3,
+            &checkpoint_path,
+            &interrupted,
+        )
+        .unwrap();
+
+        let resumed = resume_from_checkpoint(
+            &checkpoint_path,
+            &scene,
+            &camera,
+            &settings,
+            &RenderControl::new(),
+        );
+        std::fs::remove_file(&checkpoint_path).unwrap();
+        let resumed = resumed.unwrap();
+
+        let uninterrupted = render_image(
+            &scene,
+            &camera,
+            &settings,
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+        assert!(resumed.unrendered.is_empty());
+        assert_buffers_close(
+            resumed.beauty.as_ref().unwrap(),
+            uninterrupted.beauty.as_ref().unwrap(),
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn direct_and_indirect_lighting_add_up_to_the_beauty() {
+        let (scene, camera) = lit_scene();
+        let result = render_image(
+            &scene,
+            &camera,
+            &test_settings(4),
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+
+        let direct = &result.aovs[&Aov::Direct];
+        let indirect = &result.aovs[&Aov::Indirect];
+        assert!(indirect.iter().any(|&value| value > 0.0));
+
+        let sum: Vec<f32> = direct
+            .chunks_exact(3)
+            .zip(indirect.chunks_exact(3))
+            .flat_map(|(direct, indirect)| {
+                let rgb = Vec3::from_slice(direct) + Vec3::from_slice(indirect);
+                [rgb.x, rgb.y, rgb.z, 1.0]
+            })
+            .collect();
+        assert_buffers_close(&sum, result.beauty.as_ref().unwrap(), 1e-5);
+    }
+
+    /// Noisy RGBA pixels, over a few orders of magnitude
+    fn noisy_buffer() -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..TEST_SIZE * TEST_SIZE * 4)
+            .map(|_| rng.gen::<f32>() * 10.0_f32.powi(rng.gen_range(-2..3)))
+            .collect()
+    }
+
+    /// Saves ``render_buffer`` to an OpenEXR file with ``exr_settings``, and
+    /// returns the size of the file along with the RGB pixels read back from it
+    fn save_and_read_exr(
+        render_buffer: &[f32],
+        exr_settings: ExrSettings,
+        name: &str,
+    ) -> (u64, Vec<f32>) {
+        let image = convert_to_openexr(
+            TEST_SIZE,
+            TEST_SIZE,
+            Some(render_buffer),
+            &HashMap::new(),
+            ImageOrigin::TopLeft,
+            WorkingSpace::default(),
+            exr_settings,
+        )
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("ltsr_test_{name}_{}.exr", std::process::id()));
+        save_exr_image_to_disk(image, &path).unwrap();
+
+        let file_size = std::fs::metadata(&path).unwrap().len();
+        let image = read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| vec![0.0; resolution.width() * resolution.height() * 4],
+            |pixels: &mut Vec<f32>, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                let index = (position.y() * TEST_SIZE + position.x()) * 4;
+                pixels[index..index + 4].copy_from_slice(&[r, g, b, a]);
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        (file_size, image.unwrap().layer_data.channel_data.pixels)
+    }
+
+    #[test]
+    fn half_float_images_are_smaller_and_close_to_the_render() {
+        let render_buffer = noisy_buffer();
+        let half = ExrSettings {
+            bit_depth: ExrBitDepth::F16,
+            ..ExrSettings::default()
+        };
+        let full = ExrSettings {
+            bit_depth: ExrBitDepth::F32,
+            ..ExrSettings::default()
+        };
+
+        let (half_size, half_pixels) = save_and_read_exr(&render_buffer, half, "f16");
+        let (full_size, full_pixels) = save_and_read_exr(&render_buffer, full, "f32");
+
+        assert!(half_size < full_size, "{half_size} vs {full_size} bytes");
+        for (index, (read, written)) in half_pixels.iter().zip(render_buffer.iter()).enumerate() {
+            // Alpha isn't written
+            if index % 4 == 3 {
+                continue;
+            }
+            assert!(
+                (read - written).abs() <= written.abs() * 1e-3 + 1e-6,
+                "value {index} is {read}, written as {written}"
+            );
+            assert_eq!(full_pixels[index], *written);
+        }
+    }
+
+    #[test]
+    fn all_compressions_give_back_the_same_pixels() {
+        let render_buffer = noisy_buffer();
+        let read_with = |compression: ExrCompression| {
+            let exr_settings = ExrSettings {
+                compression,
+                ..ExrSettings::default()
+            };
+            let name = format!("{compression}").to_lowercase();
+            save_and_read_exr(&render_buffer, exr_settings, &name).1
+        };
+
+        let uncompressed = read_with(ExrCompression::Uncompressed);
+        for (pixel, written) in uncompressed
+            .chunks_exact(4)
+            .zip(render_buffer.chunks_exact(4))
+        {
+            assert_eq!(pixel[..3], written[..3]);
+        }
+        for compression in [
+            ExrCompression::Rle,
+            ExrCompression::Zip,
+            ExrCompression::Piz,
+        ] {
+            assert!(
+                read_with(compression) == uncompressed,
+                "{compression} changed the pixels"
+            );
+        }
+    }
+
+    #[test]
+    fn reinhard_maps_mid_gray_to_its_hand_computed_value() {
+        // 0.18 / 1.18 = 0.152542, then 1.055 * 0.152542^(1 / 2.4) - 0.055
+        let expected = Vec3::splat(0.426_946);
+
+        for working_space in [WorkingSpace::LinearSrgb, WorkingSpace::AcesCg] {
+            let display = software_display_color(
+                Vec3::splat(0.18),
+                working_space,
+                OutputColorSpace::Srgb,
+                true,
+            );
+            assert!(
+                display.abs_diff_eq(expected, 1e-4),
+                "{working_space}: {display}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn placeholders_alternate_two_colors_in_a_grid() {
+        let (width, height, checker_size) = (10, 6, 2);
+        let display_buffer = placeholder_display_buffer(width, height, checker_size);
+        assert_eq!(display_buffer.len(), width * height * 4);
+
+        let pixel = |x: usize, y: usize| &display_buffer[(y * width + x) * 4..][..4];
+        let colors = [pixel(0, 0), pixel(checker_size, 0)];
+        assert_ne!(colors[0], colors[1]);
+
+        for y in 0..height {
+            for x in 0..width {
+                let square = x / checker_size + y / checker_size;
+                assert_eq!(pixel(x, y), colors[square % 2], "pixel {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_more_samples_on_defocused_edges() {
+        let (scene, camera) = test_scene();
+        // Focused far behind the sphere, which gets blurry
+        let camera = camera.with_lens(0.2, 10.0);
+        let settings = RenderSettings {
+            adaptive_sampling: Some(AdaptiveSampling::default()),
+            ..test_settings(128)
+        };
+        let result = render_image(
+            &scene,
+            &camera,
+            &settings,
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+
+        // The blurred silhouette of the sphere, and the sky in the corner
+        let edge = aov_at(&result, Aov::SampleCount, 12, 7);
+        let background = aov_at(&result, Aov::SampleCount, 0, 0);
+        assert_eq!(background, AdaptiveSampling::default().min_samples as f32);
+        assert!(
+            edge > 2.0 * background,
+            "{edge} samples on the edge, {background} on the background"
+        );
+    }
+
+    #[test]
+    fn each_sphere_gets_its_own_object_id() {
+        let mut scene = Scene::new();
+        let black = Arc::new(Lambertian::new(Vec3::ZERO));
+        for x in [-1.0, 1.0] {
+            scene.add_hittable(Box::new(Sphere::new(
+                0.5,
+                Vec3::new(x, 0.0, -2.0),
+                black.clone(),
+            )));
+        }
+        let camera = Camera::new(1.0, 2.0, 2.0);
+        let settings = RenderSettings {
+            passes: vec![RenderPass::ObjectId],
+            ..test_settings(4)
+        };
+        let result = render_image(
+            &scene,
+            &camera,
+            &settings,
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+
+        // The centers of the two spheres, and the sky in the corner
+        let left = aov_at(&result, Aov::ObjectId, 4, 8);
+        let right = aov_at(&result, Aov::ObjectId, 12, 8);
+        let background = aov_at(&result, Aov::ObjectId, 0, 0);
+        // Numbered from 1 in the order they were added, 0 is left for misses
+        assert_eq!((left, right, background), (1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn aces_shows_mid_gray_darker_than_the_perceptual_tonemapper() {
+        let mid_gray = vec![0.18, 0.18, 0.18, 1.0];
+        let convert = |tonemap: Tonemap| {
+            convert_to_display(
+                mid_gray.clone(),
+                &HashMap::new(),
+                false,
+                WorkingSpace::AcesCg,
+                &DisplaySettings {
+                    tonemap,
+                    ..DisplaySettings::default()
+                },
+                1,
+                1,
+            )
+        };
+
+        // ACES maps 18% gray to about 10% of the display luminance
+        assert_eq!(convert(Tonemap::Perceptual), [117, 117, 117, 255]);
+        assert_eq!(convert(Tonemap::AcesRrtOdt), [91, 91, 91, 255]);
+    }
+
+    /// Counts the rays tested against the element it wraps. Being unbounded,
+    /// it gets tested by every ray traced in the scene
+    struct RayCounter {
+        element: Sphere,
+        rays: Arc<AtomicUsize>,
+    }
+
+    impl Hittable for RayCounter {
+        fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+            self.rays.fetch_add(1, Ordering::Relaxed);
+            self.element.hit(ray, t_min, t_max)
+        }
+    }
+
+    fn rays_traced(quality: Quality) -> usize {
+        let (mut scene, camera) = test_scene();
+        let rays = Arc::new(AtomicUsize::new(0));
+        let ground = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        scene.add_hittable(Box::new(RayCounter {
+            element: Sphere::new(100.0, Vec3::new(0.0, -100.5, -1.0), ground),
+            rays: rays.clone(),
+        }));
+
+        render_image(
+            &scene,
+            &camera,
+            &quality.render_settings(),
+            TEST_SIZE,
+            TEST_SIZE,
+            &RenderControl::new(),
+        );
+        rays.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn previews_trace_fewer_rays_than_final_renders() {
+        let preview = rays_traced(Quality::Preview);
+        let final_render = rays_traced(Quality::Final);
+
+        assert!(preview > 0);
+        assert!(
+            4 * preview < final_render,
+            "{preview} rays for the preview, {final_render} for the final render"
+        );
+    }
+
+    #[test]
+    fn batches_save_an_image_per_scene() {
+        // Only read by the renders without the GUI, which no other test goes through
+        std::env::set_var(crate::ltsr::settings::ENV_WIDTH, "8");
+        std::env::set_var(crate::ltsr::settings::ENV_HEIGHT, "4");
+
+        let settings = RenderSettings {
+            passes: vec![RenderPass::Beauty],
+            ..test_settings(1)
+        };
+        let (scene, camera) = test_scene();
+        let output_paths: Vec<PathBuf> = ["with_sphere", "empty"]
+            .iter()
+            .map(|name| {
+                std::env::temp_dir()
+                    .join(format!("ltsr_test_batch_{name}_{}.exr", std::process::id()))
+            })
+            .collect();
+
+        render_batch(vec![
+            (scene, camera, settings.clone(), output_paths[0].clone()),
+            (
+                Scene::new(),
+                Camera::new(1.0, 2.0, 2.0),
+                settings,
+                output_paths[1].clone(),
+            ),
+        ])
+        .unwrap();
+
+        for path in output_paths {
+            let image = read_first_rgba_layer_from_file(
+                &path,
+                |resolution, _| (resolution.width(), resolution.height()),
+                |_, _, _: (f32, f32, f32, f32)| {},
+            );
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(image.unwrap().layer_data.channel_data.pixels, (8, 4));
+        }
+    }
+
+    #[test]
+    fn turntables_go_around_the_center_in_quarter_turns() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let positions = turntable_positions(center, 5.0, 4);
+
+        let expected = [
+            Vec3::new(1.0, 2.0, 8.0),
+            Vec3::new(6.0, 2.0, 3.0),
+            Vec3::new(1.0, 2.0, -2.0),
+            Vec3::new(-4.0, 2.0, 3.0),
+        ];
+        assert_eq!(positions.len(), expected.len());
+        for (position, expected) in positions.iter().zip(expected) {
+            assert!(
+                position.abs_diff_eq(expected, 1e-5),
+                "{position}, expected {expected}"
+            );
+            assert!(((*position - center).length() - 5.0).abs() < 1e-5);
+
+            // Looking at the center from there, it sits in the middle of the frame
+            let camera = Camera::look_at(*position, center, Vec3::Y, 1.0, 2.0, 2.0);
+            let ray = camera.get_ray_at_coords(0.5, 0.5);
+            let towards_center = (center - *position).normalize();
+            assert!(ray.direction.normalize().abs_diff_eq(towards_center, 1e-5));
+        }
+    }
+
+    /// Where the elements of the scene are, in order
+    fn arrangement(scene: &Scene) -> Vec<Option<Aabb>> {
+        (0..scene.len())
+            .map(|index| scene.get(index).unwrap().bounding_box())
+            .collect()
+    }
+
+    #[test]
+    fn random_scenes_are_reproducible_from_their_seed() {
+        let scene = generate_random_scene(50, 7);
+        // The ground, then the small spheres
+        assert_eq!(scene.len(), 51);
+
+        let same_seed = generate_random_scene(50, 7);
+        assert_eq!(arrangement(&same_seed), arrangement(&scene));
+        let other_seed = generate_random_scene(50, 8);
+        assert_ne!(arrangement(&other_seed), arrangement(&scene));
+    }
+
+    #[test]
+    fn paused_renders_wait_for_resume_then_complete() {
+        // Scenes can't be shared across threads: each render builds its own
+        let render = |control: &RenderControl| {
+            let (scene, camera) = test_scene();
+            render_image(
+                &scene,
+                &camera,
+                &test_settings(4),
+                TEST_SIZE,
+                TEST_SIZE,
+                control,
+            )
+        };
+
+        let control = RenderControl::new();
+        control.pause();
+        let result = std::thread::scope(|scope| {
+            let render = scope.spawn(|| render(&control));
+
+            std::thread::sleep(Duration::from_millis(200));
+            assert!(!render.is_finished());
+            assert_eq!(control.progress(), 0.0);
+
+            control.resume();
+            render.join().unwrap()
+        });
+
+        assert!(result.unrendered.is_empty());
+        assert_eq!(control.progress(), 1.0);
+        assert_eq!(result.beauty, render(&RenderControl::new()).beauty);
+    }
+
+    #[test]
+    fn objects_hidden_from_the_camera_still_cast_shadows() {
+        let mut scene = Scene::new();
+        let black = Arc::new(Lambertian::new(Vec3::ZERO));
+        scene.add_hittable_with_visibility(
+            Box::new(Sphere::new(0.5, Vec3::new(0.0, 0.0, -1.0), black)),
+            Visibility::SECONDARY | Visibility::SHADOW,
+        );
+        let camera = Camera::new(1.0, 2.0, 2.0);
+        let settings = RenderSettings {
+            passes: vec![RenderPass::Beauty, RenderPass::ObjectId],
+            ..test_settings(4)
+        };
+        let render = |scene: &Scene| {
+            render_image(
+                scene,
+                &camera,
+                &settings,
+                TEST_SIZE,
+                TEST_SIZE,
+                &RenderControl::new(),
+            )
+        };
+
+        // Camera rays go through it as if the scene was empty
+        let result = render(&scene);
+        assert_eq!(result.beauty, render(&Scene::new()).beauty);
+        assert_eq!(aov_at(&result, Aov::ObjectId, 8, 8), 0.0);
+
+        // Rays scattered off other objects and shadow rays do see it
+        let ray = camera.get_ray_at_coords(0.5, 0.5);
+        assert!(scene
+            .hit_visible(&ray, 0.001, f32::INFINITY, Visibility::CAMERA)
+            .is_none());
+        assert!(scene
+            .hit_visible(&ray, 0.001, f32::INFINITY, Visibility::SECONDARY)
+            .is_some());
+        assert!(scene.hit_any(&ray, 0.001, f32::INFINITY));
+
+        // Light reaching the ground right below it, seen from straight above. The
+        // background lights the ground too: only the difference the light makes counts
+        let light_on_ground = |hidden_sphere: bool| {
+            let direct_on_ground = |light: bool| {
+                let mut scene = Scene::new();
+                let gray = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+                scene.add_hittable(Box::new(Sphere::new(
+                    100.0,
+                    Vec3::new(0.0, -100.5, 0.0),
+                    gray.clone(),
+                )));
+                if hidden_sphere {
+                    scene.add_hittable_with_visibility(
+                        Box::new(Sphere::new(0.5, Vec3::new(0.0, 0.5, 0.0), gray)),
+                        Visibility::SECONDARY | Visibility::SHADOW,
+                    );
+                }
+                if light {
+                    scene.add_light(PointLight::new(Vec3::new(0.0, 4.0, 0.0), Vec3::splat(20.0)));
+                }
+                let camera = Camera::look_at(
+                    Vec3::new(0.0, 10.0, 0.0),
+                    Vec3::ZERO,
+                    Vec3::NEG_Z,
+                    4.0,
+                    2.0,
+                    2.0,
+                );
+
+                let result = render_image(
+                    &scene,
+                    &camera,
+                    &settings,
+                    TEST_SIZE,
+                    TEST_SIZE,
+                    &RenderControl::new(),
+                );
+                // Red channel of the RGB AOV
+                let row = result.image_origin.buffer_row(8, TEST_SIZE);
+                result.aovs[&Aov::Direct][(row * TEST_SIZE + 8) * 3]
+            };
+
+            direct_on_ground(true) - direct_on_ground(false)
+        };
+        assert_eq!(light_on_ground(true), 0.0);
+        assert!(light_on_ground(false) > 0.1);
+    }
+
+    #[test]
+    fn saved_images_match_the_preview_row_for_row() {
+        // The background is white at the bottom, and bluer towards the top
+        let camera = Camera::new(1.0, 2.0, 2.0);
+
+        for image_origin in [ImageOrigin::TopLeft, ImageOrigin::BottomLeft] {
+            let settings = RenderSettings {
+                image_origin,
+                passes: vec![RenderPass::Beauty],
+                ..test_settings(1)
+            };
+            let result = render_image(
+                &Scene::new(),
+                &camera,
+                &settings,
+                TEST_SIZE,
+                TEST_SIZE,
+                &RenderControl::new(),
+            );
+            let beauty = result.beauty.unwrap();
+
+            // What the GUI shows
+            let mut preview = beauty.clone();
+            image_origin.to_top_left(&mut preview, TEST_SIZE * 4);
+            let (top_red, bottom_red) = (preview[0], preview[(TEST_SIZE * TEST_SIZE - 1) * 4]);
+            assert!(top_red < bottom_red, "{image_origin:?}: upside down");
+
+            let image = convert_to_openexr(
+                TEST_SIZE,
+                TEST_SIZE,
+                Some(&beauty),
+                &HashMap::new(),
+                image_origin,
+                WorkingSpace::default(),
+                ExrSettings::default(),
+            )
+            .unwrap();
+            let path = std::env::temp_dir().join(format!(
+                "ltsr_test_origin_{image_origin:?}_{}.exr",
+                std::process::id()
+            ));
+            save_exr_image_to_disk(image, &path).unwrap();
+            let saved = read_first_rgba_layer_from_file(
+                &path,
+                |resolution, _| vec![0.0; resolution.width() * resolution.height() * 4],
+                |pixels: &mut Vec<f32>, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                    let index = (position.y() * TEST_SIZE + position.x()) * 4;
+                    pixels[index..index + 4].copy_from_slice(&[r, g, b, a]);
+                },
+            );
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(saved.unwrap().layer_data.channel_data.pixels, preview);
+        }
+    }
+
+    #[test]
+    fn display_gamma_darkens_the_midtones() {
+        let identity = display_gamma_lut(1.0);
+        assert!(identity
+            .iter()
+            .enumerate()
+            .all(|(value, mapped)| *mapped as usize == value));
+
+        let lut = display_gamma_lut(2.0);
+        assert_eq!([lut[0], lut[64], lut[128], lut[255]], [0, 16, 64, 255]);
+
+        // Through the whole display conversion
+        let mid_gray = vec![0.18, 0.18, 0.18, 1.0];
+        let convert = |gamma: f32| {
+            convert_to_display(
+                mid_gray.clone(),
+                &HashMap::new(),
+                false,
+                WorkingSpace::AcesCg,
+                &DisplaySettings {
+                    gamma,
+                    ..DisplaySettings::default()
+                },
+                1,
+                1,
+            )
+        };
+        let without_gamma = convert(1.0);
+        assert_eq!(
+            without_gamma,
+            convert_to_display(
+                mid_gray.clone(),
+                &HashMap::new(),
+                false,
+                WorkingSpace::AcesCg,
+                &DisplaySettings::default(),
+                1,
+                1,
+            )
+        );
+        let with_gamma = convert(2.0);
+        assert_eq!(with_gamma[0], lut[without_gamma[0] as usize]);
+        assert!(with_gamma[0] < without_gamma[0]);
+        assert_eq!(with_gamma[3], 255);
+    }
+
+    #[test]
+    fn renders_in_progress_can_be_saved() {
+        const SIZE: usize = 48;
+        // Scenes can't be shared across threads: each render builds its own
+        let render = |control: &RenderControl| {
+            let (scene, camera) = lit_scene();
+            let settings = RenderSettings {
+                passes: vec![RenderPass::Beauty],
+                tile_size: 8,
+                ..test_settings(64)
+            };
+            render_image(&scene, &camera, &settings, SIZE, SIZE, control)
+        };
+
+        let control = RenderControl::new();
+        let (snapshot, result) = std::thread::scope(|scope| {
+            let render = scope.spawn(|| render(&control));
+
+            // Saved while the render is paused once some of the image is done. The rows
+            // of the tile in flight may or may not have made it into the snapshot
+            while control.progress() == 0.0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            control.pause();
+            let snapshot = control.snapshot().unwrap();
+
+            control.resume();
+            (snapshot, render.join().unwrap())
+        });
+
+        let image = convert_to_openexr(
+            SIZE,
+            SIZE,
+            snapshot.beauty.as_deref(),
+            &HashMap::new(),
+            snapshot.image_origin,
+            snapshot.working_space,
+            ExrSettings::default(),
+        )
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("ltsr_test_in_progress_{}.exr", std::process::id()));
+        save_exr_image_to_disk(image, &path).unwrap();
+        let saved = read_first_rgba_layer_from_file(
+            &path,
+            |resolution, _| vec![0.0; resolution.width() * resolution.height() * 4],
+            |pixels: &mut Vec<f32>, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                let index = (position.y() * SIZE + position.x()) * 4;
+                pixels[index..index + 4].copy_from_slice(&[r, g, b, a]);
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+        let saved = saved.unwrap().layer_data.channel_data.pixels;
+
+        // The pixels rendered so far (opaque in the snapshot) are those of the final
+        // image, the others are black. Only the RGB channels are saved
+        let final_beauty = result.beauty.unwrap();
+        let mut rendered_pixels = 0;
+        for (index, saved) in saved.chunks_exact(4).enumerate() {
+            let rendered = snapshot.beauty.as_ref().unwrap()[index * 4 + 3] == 1.0;
+            let expected = match rendered {
+                true => &final_beauty[index * 4..index * 4 + 3],
+                false => &[0.0; 3],
+            };
+            assert_eq!(&saved[..3], expected, "pixel {index}");
+            rendered_pixels += rendered as usize;
+        }
+        assert!(
+            rendered_pixels > 0 && rendered_pixels < SIZE * SIZE,
+            "{rendered_pixels} pixels rendered"
+        );
+    }
+
+    #[test]
+    fn cancelled_renders_show_the_placeholder_where_they_didnt_get_to() {
+        const SIZE: usize = 64;
+        const TILE_SIZE: usize = 8;
+        for image_origin in [ImageOrigin::TopLeft, ImageOrigin::BottomLeft] {
+            let control = RenderControl::new();
+            let result = std::thread::scope(|scope| {
+                let render = scope.spawn(|| {
+                    let (scene, camera) = lit_scene();
+                    let settings = RenderSettings {
+                        passes: vec![RenderPass::Beauty],
+                        tile_size: TILE_SIZE,
+                        image_origin,
+                        ..test_settings(64)
+                    };
+                    render_image(&scene, &camera, &settings, SIZE, SIZE, &control)
+                });
+
+                // Cancelling wakes the paused render up
+                while control.progress() < 0.5 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                control.pause();
+                control.cancel();
+                render.join().unwrap()
+            });
+
+            let num_tiles = (SIZE / TILE_SIZE).pow(2);
+            assert!(
+                !result.unrendered.is_empty() && result.unrendered.len() <= num_tiles / 2,
+                "{} tiles left out",
+                result.unrendered.len()
+            );
+
+            let beauty = result.beauty.unwrap();
+            let mut display_buffer = convert_to_display(
+                beauty.clone(),
+                &HashMap::new(),
+                false,
+                result.working_space,
+                &DisplaySettings::default(),
+                SIZE,
+                SIZE,
+            );
+            image_origin.to_top_left(&mut display_buffer, SIZE * 4);
+            for tile in result.unrendered.iter() {
+                fill_with_placeholder(&mut display_buffer, SIZE, *tile, 2);
+            }
+
+            let is_unrendered = |x: usize, y: usize| {
+                result.unrendered.iter().any(|tile| {
+                    (tile.x..tile.x + tile.width).contains(&x)
+                        && (tile.y..tile.y + tile.height).contains(&y)
+                })
+            };
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let index = (image_origin.buffer_row(y, SIZE) * SIZE + x) * 4;
+                    let shown = &display_buffer[(y * SIZE + x) * 4..][..4];
+                    match is_unrendered(x, y) {
+                        true => {
+                            assert_eq!(beauty[index + 3], 0.0, "pixel {x}, {y}");
+                            assert_eq!(shown, placeholder_pixel(x, y, 2), "pixel {x}, {y}");
+                        }
+                        false => assert_eq!(beauty[index + 3], 1.0, "pixel {x}, {y}"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn progress_counts_the_samples_taken_out_of_the_budget() {
+        let control = RenderControl::new();
+        assert_eq!(control.progress(), 0.0);
+
+        control.begin_progress(1000);
+        control.add_samples(500, 0);
+        assert_eq!(control.progress(), 0.5);
+
+        // Pixels that converged early take their samples out of the budget
+        control.begin_progress(1000);
+        control.add_samples(300, 400);
+        assert_eq!(control.progress(), 0.5);
+
+        // Renders end at 100%, even when most pixels stop early
+        let (scene, camera) = test_scene();
+        let settings = RenderSettings {
+            adaptive_sampling: Some(AdaptiveSampling::default()),
+            ..test_settings(128)
+        };
+        render_image(&scene, &camera, &settings, TEST_SIZE, TEST_SIZE, &control);
+        assert_eq!(control.progress(), 1.0);
+    }
+
+    /// Fails the test if anything tries to scatter a ray off it
+    struct PanicsWhenScattered;
+
+    impl Material for PanicsWhenScattered {
+        fn scatter(&self, _ray: &Ray, _data: &HitData) -> Option<(Vec3, Ray)> {
+            panic!("Only primary rays should be traced");
+        }
+    }
+
+    #[test]
+    fn normal_only_renders_trace_one_primary_ray_per_pixel() {
+        let (mut scene, camera) = test_scene();
+        let rays = Arc::new(AtomicUsize::new(0));
+        let ground = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        scene.add_hittable(Box::new(RayCounter {
+            element: Sphere::new(100.0, Vec3::new(0.0, -100.5, -1.0), ground),
+            rays: rays.clone(),
+        }));
+
+        let settings = RenderSettings {
+            passes: vec![RenderPass::Normal],
+            material_override: Some(Arc::new(PanicsWhenScattered)),
+            ..test_settings(64)
+        };
+        let control = RenderControl::new();
+        let result = render_image(&scene, &camera, &settings, TEST_SIZE, TEST_SIZE, &control);
+
+        assert_eq!(rays.load(Ordering::Relaxed), TEST_SIZE * TEST_SIZE);
+        assert_eq!(control.progress(), 1.0);
+        assert!(result.beauty.is_none());
+        assert_eq!(result.aovs.keys().collect::<Vec<_>>(), [&Aov::Normal]);
+
+        // The ray through the middle of the frame hits the front of the sphere
+        let normals = &result.aovs[&Aov::Normal];
+        let center = ((TEST_SIZE / 2) * TEST_SIZE + TEST_SIZE / 2) * 3;
+        let normal = Vec3::from_slice(&normals[center..center + 3]);
+        assert!(normal.distance(Vec3::Z) < 0.1, "{normal}");
+    }
+
+    /// The AOVs produced by a render, in the order of ``Aov::ALL``
+    fn aovs_produced(result: &RenderResult) -> Vec<Aov> {
+        Aov::ALL
+            .into_iter()
+            .filter(|aov| result.aovs.contains_key(aov))
+            .collect()
+    }
+
+    #[test]
+    fn renders_only_compute_the_passes_requested() {
+        let (scene, camera) = test_scene();
+        let render = |passes: Vec<RenderPass>| {
+            let settings = RenderSettings {
+                passes,
+                ..test_settings(4)
+            };
+            render_image(
+                &scene,
+                &camera,
+                &settings,
+                TEST_SIZE,
+                TEST_SIZE,
+                &RenderControl::new(),
+            )
+        };
+
+        let depth_only = render(vec![RenderPass::Depth]);
+        assert!(depth_only.beauty.is_none());
+        assert_eq!(aovs_produced(&depth_only), [Aov::Depth]);
+        // The front of the sphere is half a unit away from the camera
+        let depth = aov_at(&depth_only, Aov::Depth, TEST_SIZE / 2, TEST_SIZE / 2);
+        assert!((depth - 0.5).abs() < 0.01, "{depth}");
+
+        let image = convert_to_openexr(
+            TEST_SIZE,
+            TEST_SIZE,
+            None,
+            &depth_only.aovs,
+            depth_only.image_origin,
+            depth_only.working_space,
+            ExrSettings::default(),
+        )
+        .unwrap();
+        let channels: Vec<String> = image
+            .layer_data
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| channel.name.to_string())
+            .collect();
+        assert_eq!(channels, ["Z"]);
+
+        // The AOVs derived from the samples of the beauty come with it
+        let beauty_and_normal = render(vec![RenderPass::Beauty, RenderPass::Normal]);
+        assert!(beauty_and_normal.beauty.is_some());
+        assert_eq!(
+            aovs_produced(&beauty_and_normal),
+            [
+                Aov::Variance,
+                Aov::SampleCount,
+                Aov::Normal,
+                Aov::Direct,
+                Aov::Indirect
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_display_conversions_match_converting_one_pixel_at_a_time() {
+        let render_buffer = noisy_buffer();
+        let settings = [
+            (false, DisplaySettings::default()),
+            (
+                false,
+                DisplaySettings {
+                    tonemap: Tonemap::AcesRrtOdt,
+                    output_color_space: OutputColorSpace::DisplayP3,
+                    split_view: true,
+                    ..DisplaySettings::default()
+                },
+            ),
+            (true, DisplaySettings::default()),
+        ];
+
+        for (is_data_pass, display_settings) in settings {
+            let parallel = convert_to_display(
+                render_buffer.clone(),
+                &HashMap::new(),
+                is_data_pass,
+                WorkingSpace::AcesCg,
+                &display_settings,
+                TEST_SIZE,
+                TEST_SIZE,
+            );
+
+            let gamma_lut = display_gamma_lut(display_settings.gamma);
+            let sequential: Vec<u8> = render_buffer
+                .chunks_exact(4)
+                .enumerate()
+                .flat_map(|(pixel_index, f32_pixel)| {
+                    let is_before_half =
+                        display_settings.split_view && pixel_index % TEST_SIZE < TEST_SIZE / 2;
+                    display_pixel(
+                        f32_pixel,
+                        is_before_half,
+                        is_data_pass,
+                        WorkingSpace::AcesCg,
+                        &display_settings,
+                        2.0_f32.powf(display_settings.exposure),
+                        &gamma_lut,
+                    )
+                })
+                .collect();
+
+            assert_eq!(parallel, sequential);
+        }
+    }
+
+    #[test]
+    fn render_phases_add_up_to_no_more_than_the_total() {
+        let (scene, camera) = lit_scene();
+        for passes in [RenderPass::ALL.to_vec(), vec![RenderPass::Depth]] {
+            let settings = RenderSettings {
+                passes,
+                ..test_settings(8)
+            };
+            let result = render_image(
+                &scene,
+                &camera,
+                &settings,
+                TEST_SIZE,
+                TEST_SIZE,
+                &RenderControl::new(),
+            );
+            let is_path_traced = result.beauty.is_some();
+            let output = RenderOutput::new(
+                result,
+                &camera,
+                &settings,
+                (TEST_SIZE, TEST_SIZE),
+                &DisplaySettings::default(),
+            );
+
+            let stats = output.stats;
+            let phases =
+                stats.scene_setup + stats.primary_rays + stats.shading + stats.display_conversion;
+            assert!(phases <= stats.total, "{stats}");
+            assert!(stats.primary_rays > Duration::ZERO, "{stats}");
+            assert!(stats.display_conversion > Duration::ZERO, "{stats}");
+            assert_eq!(stats.shading > Duration::ZERO, is_path_traced, "{stats}");
+        }
+
+        // The time of the samples gets shared out like the time of the first one
+        let (primary_rays, shading) = split_duration(
+            Duration::from_millis(100),
+            (Duration::from_millis(1), Duration::from_millis(3)),
+        );
+        assert_eq!(primary_rays, Duration::from_millis(25));
+        assert_eq!(shading, Duration::from_millis(75));
+    }
+
+    #[test]
+    fn missed_pixels_get_the_miss_values_in_the_geometric_aovs() {
+        let (scene, camera) = test_scene();
+        let miss_values = MissValues {
+            normal: Vec3::NEG_Z,
+            depth: 1e6,
+            albedo: Vec3::splat(0.25),
+            object_id: -1.0,
+        };
+        let center = TEST_SIZE / 2;
+
+        for passes in [
+            RenderPass::ALL.to_vec(),
+            vec![RenderPass::ObjectId, RenderPass::Depth],
+        ] {
+            let render = |miss_values: MissValues| {
+                let settings = RenderSettings {
+                    passes: passes.clone(),
+                    miss_values,
+                    ..test_settings(4)
+                };
+                render_image(
+                    &scene,
+                    &camera,
+                    &settings,
+                    TEST_SIZE,
+                    TEST_SIZE,
+                    &RenderControl::new(),
+                )
+            };
+
+            let result = render(miss_values);
+            assert_eq!(aov_at(&result, Aov::ObjectId, 0, 0), -1.0);
+            assert_eq!(aov_at(&result, Aov::Depth, 0, 0), 1e6);
+            // The sphere keeps its own values
+            assert_eq!(aov_at(&result, Aov::ObjectId, center, center), 1.0);
+            assert!(aov_at(&result, Aov::Depth, center, center) < 1.0);
+
+            let defaults = render(MissValues::default());
+            assert_eq!(aov_at(&defaults, Aov::ObjectId, 0, 0), 0.0);
+            assert_eq!(aov_at(&defaults, Aov::Depth, 0, 0), 0.0);
+        }
+    }
+
+    #[test]
+    fn pixels_over_a_mirror_record_its_reflection() {
+        let mut scene = Scene::new();
+        let mirror = Arc::new(Mirror::new(Vec3::ONE));
+        scene.add_hittable(Box::new(Sphere::new(
+            0.5,
+            Vec3::new(0.0, 0.0, -1.0),
+            mirror,
+        )));
+        let camera = Camera::new(1.0, 2.0, 2.0);
+
+        let center = (TEST_SIZE / 2, TEST_SIZE / 2);
+        let path = trace_pixel_path(
+            &scene,
+            &camera,
+            &test_settings(1),
+            center,
+            (TEST_SIZE, TEST_SIZE),
+            0,
+        );
+
+        assert_eq!(path.len(), 2);
+        assert_eq!(path[0].event, PathEvent::Reflected { specular: true });
+        let vertex = path[0].hit.as_ref().unwrap();
+        assert_eq!(vertex.object_id, 1);
+        // Back towards the camera
+        assert!(path[1].direction.z > 0.0, "{}", path[1].direction);
+        assert_eq!(path[1].origin, vertex.hit_point);
+        assert!(path[1].hit.is_none());
+        assert_eq!(path[1].event, PathEvent::Escaped);
+    }
+
+    #[test]
+    fn each_antialiasing_sample_traces_its_lens_samples() {
+        // Seen by every ray, but never hit: only the camera rays get counted
+        let rays = Arc::new(AtomicUsize::new(0));
+        let mut scene = Scene::new();
+        let material = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        scene.add_hittable(Box::new(RayCounter {
+            element: Sphere::new(0.5, Vec3::new(0.0, 0.0, 10.0), material),
+            rays: rays.clone(),
+        }));
+        let camera = Camera::new(1.0, 2.0, 2.0).with_lens(0.2, 10.0);
+
+        let settings = RenderSettings {
+            passes: vec![RenderPass::Beauty],
+            lens_samples: 4,
+            ..test_settings(4)
+        };
+        assert_eq!(settings.primary_rays_per_pixel(), 16);
+        let result = render_image(&scene, &camera, &settings, 1, 1, &RenderControl::new());
+
+        // Each primary ray is tested once for the geometric AOVs, and once to shade it
+        assert_eq!(rays.load(Ordering::Relaxed), 2 * 16);
+        // Averaged into the 4 antialiasing samples
+        assert_eq!(result.aovs[&Aov::SampleCount], [4.0]);
+    }
+
+    #[test]
+    fn data_passes_skip_the_color_conversion() {
+        let hdr_pixel = vec![0.5, 2.0, 0.25, 1.0];
+        let convert = |is_data_pass: bool| {
+            convert_to_display(
+                hdr_pixel.clone(),
+                &HashMap::new(),
+                is_data_pass,
+                WorkingSpace::AcesCg,
+                &DisplaySettings::default(),
+                1,
+                1,
+            )
+        };
+
+        // Straight to 8 bits, values over 1 saturate
+        let data = convert(true);
+        assert_eq!(data, [127, 255, 63, 255]);
+
+        let beauty = convert(false);
+        assert_ne!(beauty, data);
+    }
+
+    #[test]
+    fn linear_srgb_reds_stay_plain_srgb_reds() {
+        let red = Vec3::new(0.8, 0.0, 0.0);
+        let display = |working_space, tonemap| {
+            let display_settings = DisplaySettings {
+                tonemap,
+                ..DisplaySettings::default()
+            };
+            convert_to_display(
+                vec![red.x, red.y, red.z, 1.0],
+                &HashMap::new(),
+                false,
+                working_space,
+                &display_settings,
+                1,
+                1,
+            )
+        };
+
+        // Outside of the sRGB gamut in ACEScg, so clipped to the most saturated red
+        assert_eq!(
+            display(WorkingSpace::AcesCg, Tonemap::Perceptual),
+            [255, 0, 0, 255]
+        );
+        assert_eq!(
+            display(WorkingSpace::AcesCg, Tonemap::AcesRrtOdt),
+            [238, 0, 0, 255]
+        );
+        // Desaturated a little by the tonemaps
+        assert_eq!(
+            display(WorkingSpace::LinearSrgb, Tonemap::Perceptual),
+            [228, 14, 6, 255]
+        );
+        assert_eq!(
+            display(WorkingSpace::LinearSrgb, Tonemap::AcesRrtOdt),
+            [198, 0, 6, 255]
+        );
+
+        // Without a tonemap, it's only encoded
+        let encoded =
+            software_display_color(red, WorkingSpace::LinearSrgb, OutputColorSpace::Srgb, false);
+        assert_eq!((encoded.y, encoded.z), (0.0, 0.0));
+        assert!((encoded.x - 0.906).abs() < 1e-3, "{encoded}");
+    }
+
+    #[test]
+    fn srgb_red_is_less_saturated_in_display_p3() {
+        // Pure sRGB red sits inside the wider P3 gamut
+        let encode = |output_color_space| {
+            software_display_color(Vec3::X, WorkingSpace::LinearSrgb, output_color_space, false)
+        };
+        assert!(encode(OutputColorSpace::Srgb).abs_diff_eq(Vec3::X, 1e-6));
+        let p3 = encode(OutputColorSpace::DisplayP3);
+        // (0.822, 0.033, 0.017) in linear P3, then encoded
+        let expected = Vec3::new(0.917, 0.200, 0.139);
+        assert!(p3.abs_diff_eq(expected, 2e-3), "{p3}, expected {expected}");
+
+        // colstodian agrees, after its tonemap
+        let red = vec![1.0, 0.0, 0.0, 1.0];
+        let display = |output_color_space| {
+            let display_settings = DisplaySettings {
+                output_color_space,
+                ..DisplaySettings::default()
+            };
+            convert_to_display(
+                red.clone(),
+                &HashMap::new(),
+                false,
+                WorkingSpace::LinearSrgb,
+                &display_settings,
+                1,
+                1,
+            )
+        };
+        let srgb = display(OutputColorSpace::Srgb);
+        let p3 = display(OutputColorSpace::DisplayP3);
+        assert!(p3[0] < srgb[0], "{p3:?} vs {srgb:?}");
+        assert!(p3[1] > srgb[1] && p3[2] > srgb[2], "{p3:?} vs {srgb:?}");
+    }
+
+    #[test]
+    fn averaging_a_million_samples_doesnt_drift() {
+        // Without a viewport, all the rays go straight ahead into the same sky color,
+        // whose green isn't a round number in binary
+        let camera = Camera::new(1.0, 0.0, 0.0);
+        let scene = Scene::new();
+        let settings = test_settings(1_000_000);
+
+        let result = render_image(&scene, &camera, &settings, 1, 1, &RenderControl::new());
+
+        let expected = scene.background.color(Vec3::NEG_Z);
+        let beauty = result.beauty.unwrap();
+        assert_buffers_close(&beauty[..3], &expected.to_array(), 1e-6);
+    }
 }
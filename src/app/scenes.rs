@@ -0,0 +1,240 @@
+use std::sync::{Arc, OnceLock};
+
+use glam::Vec3;
+
+use crate::app::rendering::{default_camera, default_scene, generate_random_scene};
+use crate::constants::{RENDER_BUFFER_HEIGHT, RENDER_BUFFER_WIDTH};
+use crate::ltsr::lights::PointLight;
+use crate::ltsr::materials::{
+    Coated, Dielectric, Lambertian, Material, Metallic, Mirror, SubsurfaceScattering, ThinFilm,
+};
+use crate::ltsr::settings::RenderSettings;
+use crate::ltsr::validation::SceneWarning;
+use crate::ltsr::{Camera, Scene, Sphere, Torus};
+
+/// The built-in scenes the GUI can render
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DemoScene {
+    /// Three spheres (diffuse, shiny and rough metal) over a ground sphere
+    #[default]
+    Spheres,
+    /// A box with a red and a green wall, holding a mirror and a glass sphere
+    CornellBox,
+    /// Hundreds of small spheres of random materials, as in 'Ray Tracing in One Weekend'
+    RandomSpheres,
+    /// A row of spheres, one for each kind of material
+    Materials,
+}
+
+impl DemoScene {
+    pub const ALL: [DemoScene; 4] = [
+        DemoScene::Spheres,
+        DemoScene::CornellBox,
+        DemoScene::RandomSpheres,
+        DemoScene::Materials,
+    ];
+
+    /// The scene, and the camera framing it for the render buffer
+    pub fn build(&self, settings: &RenderSettings) -> (Scene, Camera) {
+        match self {
+            DemoScene::Spheres => (default_scene(), default_camera(settings)),
+            DemoScene::CornellBox => cornell_box_scene(settings),
+            DemoScene::RandomSpheres => random_spheres_scene(settings),
+            DemoScene::Materials => materials_scene(settings),
+        }
+    }
+
+    /// What's wrong with the scene, see ``Scene::validate``. The scenes never change,
+    /// so each one is only built and checked the first time it's asked for
+    pub fn warnings(&self) -> Vec<SceneWarning> {
+        static WARNINGS: [OnceLock<Vec<SceneWarning>>; DemoScene::ALL.len()] = [
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+        ];
+
+        let index = DemoScene::ALL
+            .iter()
+            .position(|demo_scene| demo_scene == self)
+            .unwrap_or_default();
+
+        WARNINGS[index]
+            .get_or_init(|| {
+                let (scene, _) = self.build(&RenderSettings::default());
+                scene.validate().err().unwrap_or_default()
+            })
+            .clone()
+    }
+}
+
+impl std::fmt::Display for DemoScene {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DemoScene::Spheres => "Spheres",
+            DemoScene::CornellBox => "Cornell Box",
+            DemoScene::RandomSpheres => "Random Spheres",
+            DemoScene::Materials => "Materials",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// A camera at ``position`` looking at ``target``, seeing ``vertical_fov`` degrees
+/// from the bottom to the top of the render buffer
+fn demo_camera(
+    position: Vec3,
+    target: Vec3,
+    vertical_fov: f32,
+    settings: &RenderSettings,
+) -> Camera {
+    let viewport_height = 2.0 * (vertical_fov.to_radians() / 2.0).tan();
+    let viewport_width =
+        settings.viewport_aspect_ratio(RENDER_BUFFER_WIDTH, RENDER_BUFFER_HEIGHT) * viewport_height;
+
+    Camera::look_at(
+        position,
+        target,
+        Vec3::Y,
+        1.0,
+        viewport_width,
+        viewport_height,
+    )
+}
+
+/// A box open towards the camera, with its walls made of huge spheres (whose
+/// surfaces are nearly flat at this scale), lit by a light under the ceiling
+pub fn cornell_box_scene(settings: &RenderSettings) -> (Scene, Camera) {
+    let mut scene = Scene::new();
+
+    let white = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let red = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+    let green = Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+
+    // The box spans [-1, 1] along X and Y, and [-2, 0] along Z
+    let wall_radius = 1e4;
+    let walls: [(Vec3, Arc<dyn Material>); 5] = [
+        (Vec3::new(-1.0 - wall_radius, 0.0, -1.0), red),
+        (Vec3::new(1.0 + wall_radius, 0.0, -1.0), green),
+        (Vec3::new(0.0, -1.0 - wall_radius, -1.0), white.clone()),
+        (Vec3::new(0.0, 1.0 + wall_radius, -1.0), white.clone()),
+        (Vec3::new(0.0, 0.0, -2.0 - wall_radius), white),
+    ];
+    for (center, material) in walls {
+        scene.add_hittable(Box::new(Sphere::new(wall_radius, center, material)));
+    }
+
+    scene.add_hittable(Box::new(Sphere::new(
+        0.35,
+        Vec3::new(-0.45, -0.65, -1.3),
+        Arc::new(Mirror::new(Vec3::new(0.95, 0.95, 0.95))),
+    )));
+    scene.add_hittable(Box::new(Sphere::new(
+        0.35,
+        Vec3::new(0.45, -0.65, -0.8),
+        Arc::new(Dielectric::new(1.5)),
+    )));
+
+    scene.add_light(PointLight::new(
+        Vec3::new(0.0, 0.9, -1.0),
+        Vec3::new(1.0, 0.9, 0.75),
+    ));
+
+    let camera = demo_camera(
+        Vec3::new(0.0, 0.0, 2.4),
+        Vec3::new(0.0, 0.0, -1.0),
+        40.0,
+        settings,
+    );
+
+    (scene, camera)
+}
+
+/// See ``generate_random_scene``, looked at from above one of its corners
+pub fn random_spheres_scene(settings: &RenderSettings) -> (Scene, Camera) {
+    let scene = generate_random_scene(400, 1);
+    let camera = demo_camera(Vec3::new(13.0, 2.0, 3.0), Vec3::ZERO, 20.0, settings);
+
+    (scene, camera)
+}
+
+/// One sphere per material, left to right: diffuse, rough metal, mirror, glass,
+/// tinted glass, clear coated paint, a soap bubble like film, and wax (subsurface)
+pub fn materials_scene(settings: &RenderSettings) -> (Scene, Camera) {
+    let mut scene = Scene::new();
+
+    let red = Vec3::new(0.7, 0.1, 0.1);
+    let materials: [Arc<dyn Material>; 8] = [
+        Arc::new(Lambertian::new(red)),
+        Arc::new(Metallic::new(Vec3::new(0.8, 0.6, 0.2), 0.3)),
+        Arc::new(Mirror::new(Vec3::new(0.9, 0.9, 0.9))),
+        Arc::new(Dielectric::new(1.5)),
+        Arc::new(Dielectric::new(1.5).with_absorption(Vec3::new(0.1, 1.5, 2.0))),
+        Arc::new(Coated::new(Arc::new(Lambertian::new(red)), 1.5, 0.0)),
+        Arc::new(ThinFilm::new(
+            Arc::new(Metallic::new(Vec3::new(0.2, 0.2, 0.2), 0.0)),
+            400.0,
+            1.33,
+        )),
+        Arc::new(SubsurfaceScattering::new(Vec3::new(0.9, 0.8, 0.6), 0.3)),
+    ];
+
+    let spacing = 1.1;
+    let first_x = -spacing * (materials.len() - 1) as f32 / 2.0;
+    for (index, material) in materials.into_iter().enumerate() {
+        let center = Vec3::new(first_x + spacing * index as f32, 0.5, 0.0);
+        scene.add_hittable(Box::new(Sphere::new(0.5, center, material)));
+    }
+
+    // Something to see in the reflections and through the glass
+    scene.add_hittable(Box::new(Torus::new(
+        1.2,
+        0.15,
+        Vec3::new(0.0, 1.2, -3.0),
+        Vec3::Z,
+        Arc::new(Lambertian::new(Vec3::new(0.1, 0.3, 0.7))),
+    )));
+
+    scene.add_hittable(Box::new(Sphere::new(
+        1000.0,
+        Vec3::new(0.0, -1000.0, 0.0),
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+
+    scene.add_light(PointLight::new(Vec3::new(2.0, 5.0, 4.0), Vec3::splat(30.0)));
+
+    let camera = demo_camera(
+        Vec3::new(0.0, 3.5, 16.0),
+        Vec3::new(0.0, 0.5, 0.0),
+        30.0,
+        settings,
+    );
+
+    (scene, camera)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::rendering::{render_image, RenderControl};
+
+    #[test]
+    fn every_demo_scene_renders_something() {
+        let settings = RenderSettings {
+            samples_per_pixel: 4,
+            ..RenderSettings::default()
+        };
+
+        for demo_scene in DemoScene::ALL {
+            let (scene, camera) = demo_scene.build(&settings);
+            // Not empty either, which would be a warning
+            assert_eq!(scene.validate(), Ok(()), "{demo_scene}");
+
+            let result = render_image(&scene, &camera, &settings, 16, 16, &RenderControl::new());
+            let beauty = result.beauty.unwrap();
+            assert!(beauty.iter().all(|value| value.is_finite()), "{demo_scene}");
+            assert!(beauty.iter().any(|value| *value > 0.0), "{demo_scene}");
+        }
+    }
+}
@@ -4,4 +4,8 @@ pub const RENDER_BUFFER_WIDTH: usize = 2048;
 pub const RENDER_BUFFER_HEIGHT: usize = 2048;
 pub const RENDER_BUFFER_SIZE: usize = RENDER_BUFFER_WIDTH * RENDER_BUFFER_HEIGHT * 4;
 
+// Area of the UI the render is shown in
+pub const VIEWER_MAX_WIDTH: f32 = 800.0;
+pub const VIEWER_MAX_HEIGHT: f32 = 512.0;
+
 pub const NUM_SAMPLES_PER_PIXEL: usize = 32;
@@ -0,0 +1,3 @@
+pub mod app;
+pub mod constants;
+pub mod ltsr;
@@ -0,0 +1,92 @@
+use glam::{Affine3A, Vec3};
+
+use crate::ltsr::Ray;
+
+/// Axis aligned bounding box, see ``Hittable::bounding_box``
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Contains nothing, so that it disappears in unions
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    /// The box with the two given opposite corners, in any order
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Aabb {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// The box grown by ``padding`` on all sides, eg: to give flat objects some thickness
+    pub fn padded(&self, padding: f32) -> Aabb {
+        Aabb {
+            min: self.min - Vec3::splat(padding),
+            max: self.max + Vec3::splat(padding),
+        }
+    }
+
+    /// Size of the box along each axis
+    pub fn extent(&self) -> Vec3 {
+        (self.max - self.min).max(Vec3::ZERO)
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|corner| {
+            Vec3::select(
+                glam::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                self.max,
+                self.min,
+            )
+        })
+    }
+
+    /// The box bounding this one once moved by ``transform``
+    pub fn transformed(&self, transform: Affine3A) -> Aabb {
+        self.corners().iter().fold(Aabb::EMPTY, |bounds, corner| {
+            let corner = transform.transform_point3(*corner);
+            bounds.union(&Aabb::new(corner, corner))
+        })
+    }
+
+    /// Range of ``t`` in [t_min, t_max] for which the ray is inside the box
+    pub fn clip(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        // Slab test, axis by axis
+        let mut t_near = t_min;
+        let mut t_far = t_max;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            if inverse_direction < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // NaNs (rays parallel to the slab, starting on its border) keep the range as is
+            t_near = t_near.max(t0);
+            t_far = t_far.min(t1);
+            if t_far < t_near {
+                return None;
+            }
+        }
+
+        Some((t_near, t_far))
+    }
+}
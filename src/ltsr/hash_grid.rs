@@ -0,0 +1,267 @@
+use glam::Vec3;
+
+use crate::ltsr::bounds::Aabb;
+use crate::ltsr::{Hittable, Ray};
+
+/// How a ``Scene`` finds the elements a ray might hit, see ``Scene::set_accelerator``
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Accelerator {
+    /// Tests every element: nothing to build, fine for a handful of elements
+    #[default]
+    Linear,
+    /// Only tests the elements in the cells of a ``HashGrid`` the ray goes through.
+    /// Quick to build, and works best with many elements of similar sizes
+    HashGrid,
+}
+
+/// Cells per (bounded) element the grid aims for
+const CELLS_PER_ELEMENT: f32 = 4.0;
+/// Most cells along each axis of the grid
+const MAX_RESOLUTION: f32 = 256.0;
+/// Elements larger than this many times the median element are tested by every ray
+/// instead of filling most of the cells (eg: the huge ground sphere of the random scenes)
+const LARGE_ELEMENT_FACTOR: f32 = 16.0;
+
+/// A uniform grid over the elements of a scene, whose cells are only stored when
+/// they're occupied: each cell is hashed to a bucket listing the elements whose
+/// bounding boxes overlap it. Rays march from cell to cell (DDA) and only test
+/// the elements listed on the way
+pub struct HashGrid {
+    bounds: Aabb,
+    cell_size: Vec3,
+    resolution: [i32; 3],
+    /// Indices of the elements in each bucket. Cells sharing a hash share a bucket,
+    /// so buckets can list elements from other cells as well
+    buckets: Vec<Vec<u32>>,
+    /// Elements without bounds, or too large for the cells: every ray tests them
+    unbounded: Vec<u32>,
+}
+
+impl HashGrid {
+    pub fn new(elements: &[Box<dyn Hittable>]) -> Self {
+        let boxes: Vec<Option<Aabb>> = elements
+            .iter()
+            .map(|element| element.bounding_box().filter(|bounds| !bounds.is_empty()))
+            .collect();
+
+        let mut sizes: Vec<f32> = boxes
+            .iter()
+            .flatten()
+            .map(|bounds| bounds.extent().max_element())
+            .collect();
+        sizes.sort_by(f32::total_cmp);
+        let large_size = sizes.get(sizes.len() / 2).map_or(f32::INFINITY, |median| {
+            LARGE_ELEMENT_FACTOR * median.max(1e-4)
+        });
+
+        let mut unbounded = Vec::new();
+        let mut bounded = Vec::new();
+        for (index, bounds) in boxes.into_iter().enumerate() {
+            match bounds {
+                Some(bounds) if bounds.extent().max_element() <= large_size => {
+                    bounded.push((index as u32, bounds))
+                }
+                _ => unbounded.push(index as u32),
+            }
+        }
+
+        let bounds = bounded
+            .iter()
+            .fold(Aabb::EMPTY, |all, (_, bounds)| all.union(bounds))
+            .padded(1e-4);
+
+        // Roughly cubic cells, sized so that there are a few of them per element
+        let extent = bounds.extent().max(Vec3::splat(1e-4));
+        let cells_per_unit =
+            (CELLS_PER_ELEMENT * bounded.len() as f32 / (extent.x * extent.y * extent.z)).cbrt();
+        let resolution = (extent * cells_per_unit)
+            .ceil()
+            .clamp(Vec3::ONE, Vec3::splat(MAX_RESOLUTION));
+        let cell_size = extent / resolution;
+
+        let mut grid = HashGrid {
+            bounds,
+            cell_size,
+            resolution: resolution.as_ivec3().to_array(),
+            buckets: Vec::new(),
+            unbounded,
+        };
+        if bounded.is_empty() {
+            return grid;
+        }
+
+        let cell_ranges: Vec<(u32, [i32; 3], [i32; 3])> = bounded
+            .iter()
+            .map(|(index, bounds)| (*index, grid.cell_at(bounds.min), grid.cell_at(bounds.max)))
+            .collect();
+        let num_insertions: usize = cell_ranges
+            .iter()
+            .map(|(_, first, last)| {
+                (0..3)
+                    .map(|axis| (last[axis] - first[axis] + 1) as usize)
+                    .product::<usize>()
+            })
+            .sum();
+
+        grid.buckets = vec![Vec::new(); (2 * num_insertions).next_power_of_two()];
+        for (index, first, last) in cell_ranges {
+            for z in first[2]..=last[2] {
+                for y in first[1]..=last[1] {
+                    for x in first[0]..=last[0] {
+                        let bucket = grid.bucket_index([x, y, z]);
+                        grid.buckets[bucket].push(index);
+                    }
+                }
+            }
+        }
+        // Elements spanning colliding cells end up more than once in the same bucket
+        for bucket in grid.buckets.iter_mut() {
+            bucket.sort_unstable();
+            bucket.dedup();
+        }
+
+        grid
+    }
+
+    /// Marches ``ray`` through the cells it crosses within [t_min, t_max], from the
+    /// closest one. ``visit`` gets the indices of the elements listed in each cell,
+    /// along with the ``t`` at which the ray leaves it, and returns whether to stop.
+    /// It gets the elements outside of the cells first, with a ``t`` of -infinity.
+    /// Elements can be listed in more than one cell
+    pub fn march(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        mut visit: impl FnMut(&[u32], f32) -> bool,
+    ) {
+        if visit(&self.unbounded, f32::NEG_INFINITY) || self.buckets.is_empty() {
+            return;
+        }
+        let Some((t_enter, t_exit)) = self.bounds.clip(ray, t_min, t_max) else {
+            return;
+        };
+
+        // Grid marching (DDA): how far along the ray the next cell boundaries are,
+        // and how far apart they are from each other, along each axis
+        let start = ray.point_at_parameter(t_enter);
+        let mut cell = self.cell_at(start);
+        let mut step = [0; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let direction = ray.direction[axis];
+            let cell_start = self.bounds.min[axis] + cell[axis] as f32 * self.cell_size[axis];
+            if direction > 0.0 {
+                step[axis] = 1;
+                t_next[axis] =
+                    t_enter + (cell_start + self.cell_size[axis] - start[axis]) / direction;
+                t_delta[axis] = self.cell_size[axis] / direction;
+            } else if direction < 0.0 {
+                step[axis] = -1;
+                t_next[axis] = t_enter + (cell_start - start[axis]) / direction;
+                t_delta[axis] = -self.cell_size[axis] / direction;
+            }
+        }
+
+        loop {
+            let axis = match (
+                t_next[0] < t_next[1],
+                t_next[0] < t_next[2],
+                t_next[1] < t_next[2],
+            ) {
+                (true, true, _) => 0,
+                (false, _, true) => 1,
+                _ => 2,
+            };
+
+            let cell_exit = t_next[axis].min(t_exit);
+            if visit(&self.buckets[self.bucket_index(cell)], cell_exit) || t_next[axis] > t_exit {
+                return;
+            }
+
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= self.resolution[axis] {
+                return;
+            }
+            t_next[axis] += t_delta[axis];
+        }
+    }
+
+    /// The cell containing ``point``, clamped to the grid
+    fn cell_at(&self, point: Vec3) -> [i32; 3] {
+        let offset = ((point - self.bounds.min) / self.cell_size).floor();
+        [0, 1, 2].map(|axis| (offset[axis] as i32).clamp(0, self.resolution[axis] - 1))
+    }
+
+    /// Spatial hash of a cell (Teschner et al. 2003), wrapped to the number of buckets
+    fn bucket_index(&self, cell: [i32; 3]) -> usize {
+        let hash = (cell[0] as u32).wrapping_mul(73_856_093)
+            ^ (cell[1] as u32).wrapping_mul(19_349_663)
+            ^ (cell[2] as u32).wrapping_mul(83_492_791);
+        hash as usize & (self.buckets.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::ltsr::materials::{Lambertian, Material};
+    use crate::ltsr::{Scene, Sphere};
+
+    /// Small spheres of random sizes scattered in a box, over a huge ground sphere
+    fn random_scene(rng: &mut SmallRng, accelerator: Accelerator) -> Scene {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        let mut scene = Scene::new();
+        scene.add_hittable(Box::new(Sphere::new(
+            1000.0,
+            Vec3::new(0.0, -1000.0, 0.0),
+            material.clone(),
+        )));
+        for _ in 0..200 {
+            let center = Vec3::new(
+                rng.gen_range(-10.0..10.0),
+                rng.gen_range(0.0..5.0),
+                rng.gen_range(-10.0..10.0),
+            );
+            let radius = rng.gen_range(0.05..0.6);
+            scene.add_hittable(Box::new(Sphere::new(radius, center, material.clone())));
+        }
+        scene.set_accelerator(accelerator);
+        scene
+    }
+
+    #[test]
+    fn nearest_hit_matches_the_linear_scan() {
+        let linear = random_scene(&mut SmallRng::seed_from_u64(3), Accelerator::Linear);
+        let grid = random_scene(&mut SmallRng::seed_from_u64(3), Accelerator::HashGrid);
+
+        let mut rng = SmallRng::seed_from_u64(11);
+        for _ in 0..2000 {
+            let origin = Vec3::new(
+                rng.gen_range(-15.0..15.0),
+                rng.gen_range(0.5..8.0),
+                rng.gen_range(-15.0..15.0),
+            );
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let ray = Ray::new(origin, direction);
+
+            let expected = linear.hit(&ray, 0.001, f32::INFINITY);
+            let actual = grid.hit(&ray, 0.001, f32::INFINITY);
+            assert_eq!(
+                expected.as_ref().map(|hit| (hit.object_id, hit.t)),
+                actual.as_ref().map(|hit| (hit.object_id, hit.t)),
+                "ray from {origin} towards {direction}"
+            );
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+
+use crate::ltsr::bounds::Aabb;
+use crate::ltsr::materials::Material;
+use crate::ltsr::polynomials::solve_quadratic;
+use crate::ltsr::validation::SetupIssue;
+use crate::ltsr::{get_face_normal, HitData, Hittable, Ray, SurfaceHit};
+
+/// Extra room given to the bounds of the surface along Y,
+/// so that perfectly flat height fields still have some thickness
+const BOUNDS_PADDING: f32 = 1e-4;
+
+/// A terrain-like surface, displaced upwards from a rectangle on the XZ plane.
+/// Heights are sampled on a regular grid and bilinearly interpolated in between.
+pub struct HeightField {
+    /// ``columns * rows`` heights, row by row, from -Z to +Z and -X to +X
+    heights: Vec<f32>,
+    columns: usize,
+    rows: usize,
+    /// Corner of the rectangle with the smallest X and Z: heights are relative to its Y
+    pub origin: Vec3,
+    /// Extent of the rectangle along X and Z
+    pub size_x: f32,
+    pub size_z: f32,
+    pub material: Arc<dyn Material>,
+    /// Smallest and largest height, to bound the surface
+    min_height: f32,
+    max_height: f32,
+}
+
+impl HeightField {
+    /// Panics if there are less than 2x2 heights, or if they don't fill the grid
+    pub fn new(
+        heights: Vec<f32>,
+        columns: usize,
+        rows: usize,
+        origin: Vec3,
+        size_x: f32,
+        size_z: f32,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(
+            columns >= 2 && rows >= 2,
+            "A height field needs at least 2x2 heights"
+        );
+        assert_eq!(
+            heights.len(),
+            columns * rows,
+            "Expected {columns}x{rows} heights"
+        );
+
+        let min_height = heights.iter().copied().fold(f32::INFINITY, f32::min);
+        let max_height = heights.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        HeightField {
+            heights,
+            columns,
+            rows,
+            origin,
+            size_x,
+            size_z,
+            material,
+            min_height,
+            max_height,
+        }
+    }
+
+    fn height(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.columns + column]
+    }
+
+    fn cell_size(&self) -> (f32, f32) {
+        (
+            self.size_x / (self.columns - 1) as f32,
+            self.size_z / (self.rows - 1) as f32,
+        )
+    }
+
+    /// Range of ``t`` for which the ray is inside the bounding box of the surface
+    fn clip_to_bounds(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        self.bounds().clip(ray, t_min, t_max)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let min = self.origin + Vec3::new(0.0, self.min_height - BOUNDS_PADDING, 0.0);
+        let max =
+            self.origin + Vec3::new(self.size_x, self.max_height + BOUNDS_PADDING, self.size_z);
+        Aabb::new(min, max)
+    }
+
+    /// Intersects the bilinear patch of a cell over the ``[t_enter, t_exit]`` range of the ray
+    fn hit_cell(
+        &self,
+        ray: &Ray,
+        column: usize,
+        row: usize,
+        t_enter: f32,
+        t_exit: f32,
+    ) -> Option<SurfaceHit> {
+        let (cell_x, cell_z) = self.cell_size();
+
+        // Bilinear patch h(u, v) = a + b*u + c*v + d*u*v, with u and v in [0, 1]
+        let h00 = self.height(column, row);
+        let h10 = self.height(column + 1, row);
+        let h01 = self.height(column, row + 1);
+        let h11 = self.height(column + 1, row + 1);
+        let (a, b, c, d) = (h00, h10 - h00, h01 - h00, h00 - h10 - h01 + h11);
+
+        // The ray in the local coordinates of the cell, starting from where it enters
+        let start = ray.point_at_parameter(t_enter) - self.origin;
+        let u0 = (start.x - column as f32 * cell_x) / cell_x;
+        let v0 = (start.z - row as f32 * cell_z) / cell_z;
+        let du = ray.direction.x / cell_x;
+        let dv = ray.direction.z / cell_z;
+
+        // Height of the ray above the patch: a quadratic in ``s = t - t_enter``
+        let quadratic = (-d * du * dv) as f64;
+        let linear = (ray.direction.y - b * du - c * dv - d * (u0 * dv + v0 * du)) as f64;
+        let constant = (start.y - a - b * u0 - c * v0 - d * u0 * v0) as f64;
+
+        let roots = if quadratic.abs() < 1e-9 {
+            if linear == 0.0 {
+                vec![]
+            } else {
+                vec![-constant / linear]
+            }
+        } else {
+            solve_quadratic(linear / quadratic, constant / quadratic)
+        };
+
+        let length = (t_exit - t_enter) as f64;
+        let s = roots
+            .into_iter()
+            .filter(|s| (0.0..=length).contains(s))
+            .fold(f64::INFINITY, f64::min);
+        if !s.is_finite() {
+            return None;
+        }
+
+        let t = t_enter + s as f32;
+        let hit_point = ray.point_at_parameter(t);
+
+        // Normal from the gradient of the patch
+        let u = (u0 + du * s as f32).clamp(0.0, 1.0);
+        let v = (v0 + dv * s as f32).clamp(0.0, 1.0);
+        let dh_dx = (b + d * v) / cell_x;
+        let dh_dz = (c + d * u) / cell_z;
+        let outward_normal = Vec3::new(-dh_dx, 1.0, -dh_dz).normalize();
+
+        let (normal, front_face) = get_face_normal(ray, outward_normal);
+
+        Some(SurfaceHit {
+            t,
+            hit_point,
+            normal,
+            front_face,
+        })
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        let surface = self.hit_surface(ray, t_min, t_max)?;
+        Some(surface.with_material(&self.material))
+    }
+
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
+        let (t_near, t_far) = self.clip_to_bounds(ray, t_min, t_max)?;
+
+        let (cell_x, cell_z) = self.cell_size();
+        let num_columns = self.columns - 1;
+        let num_rows = self.rows - 1;
+
+        // Cell where the ray enters the bounds
+        let start = ray.point_at_parameter(t_near) - self.origin;
+        let mut column = ((start.x / cell_x).floor().max(0.0) as usize).min(num_columns - 1);
+        let mut row = ((start.z / cell_z).floor().max(0.0) as usize).min(num_rows - 1);
+
+        // Grid marching (DDA): how far along the ray the next cell boundaries are,
+        // and how far apart they are from each other, along X and Z
+        let step_column: isize = if ray.direction.x >= 0.0 { 1 } else { -1 };
+        let step_row: isize = if ray.direction.z >= 0.0 { 1 } else { -1 };
+
+        let mut t_next_x = if ray.direction.x == 0.0 {
+            f32::INFINITY
+        } else {
+            let boundary = if step_column > 0 { column + 1 } else { column };
+            (self.origin.x + boundary as f32 * cell_x - ray.origin.x) / ray.direction.x
+        };
+        let mut t_next_z = if ray.direction.z == 0.0 {
+            f32::INFINITY
+        } else {
+            let boundary = if step_row > 0 { row + 1 } else { row };
+            (self.origin.z + boundary as f32 * cell_z - ray.origin.z) / ray.direction.z
+        };
+        let t_delta_x = (cell_x / ray.direction.x).abs();
+        let t_delta_z = (cell_z / ray.direction.z).abs();
+
+        let mut t_enter = t_near;
+        loop {
+            let t_exit = t_next_x.min(t_next_z).min(t_far);
+
+            if let Some(hit) = self.hit_cell(ray, column, row, t_enter, t_exit) {
+                return Some(hit);
+            }
+
+            if t_exit >= t_far {
+                return None;
+            }
+
+            // Move to the neighbouring cell
+            if t_next_x < t_next_z {
+                let next = column as isize + step_column;
+                if next < 0 || next >= num_columns as isize {
+                    return None;
+                }
+                column = next as usize;
+                t_next_x += t_delta_x;
+            } else {
+                let next = row as isize + step_row;
+                if next < 0 || next >= num_rows as isize {
+                    return None;
+                }
+                row = next as usize;
+                t_next_z += t_delta_z;
+            }
+            t_enter = t_exit;
+        }
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let mut issues: Vec<SetupIssue> = [
+            SetupIssue::check_finite(&[self.origin.x, self.origin.y, self.origin.z]),
+            SetupIssue::check_finite(&self.heights),
+            SetupIssue::check_not_negative("size", &[self.size_x, self.size_z]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        issues.extend(self.material.setup_issues());
+        issues
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltsr::materials::Lambertian;
+
+    /// 4x4 heights over a 3x3 square from the origin, given by ``height(column, row)``
+    fn test_field(height: impl Fn(usize, usize) -> f32) -> HeightField {
+        let heights = (0..4)
+            .flat_map(|row| (0..4).map(move |column| (column, row)))
+            .map(|(column, row)| height(column, row))
+            .collect();
+        let material = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+
+        HeightField::new(heights, 4, 4, Vec3::ZERO, 3.0, 3.0, material)
+    }
+
+    #[test]
+    fn flat_fields_act_like_a_plane() {
+        let field = test_field(|_, _| 0.5);
+        let ray = Ray::new(Vec3::new(0.2, 3.0, 0.4), Vec3::new(0.3, -1.0, 0.2));
+
+        let hit = field.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-4, "hit at t = {}", hit.t);
+        assert!(hit.hit_point.abs_diff_eq(Vec3::new(0.95, 0.5, 0.9), 1e-4));
+        assert!(hit.normal.abs_diff_eq(Vec3::Y, 1e-4));
+
+        // Outside of the rectangle there's nothing to hit
+        let ray = Ray::new(Vec3::new(4.0, 3.0, 1.0), Vec3::NEG_Y);
+        assert!(field.hit_surface(&ray, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ramps_have_tilted_normals() {
+        // Rises by 0.5 for every unit along X
+        let field = test_field(|column, _| column as f32 * 0.5);
+        let ray = Ray::new(Vec3::new(1.5, 5.0, 1.5), Vec3::NEG_Y);
+
+        let hit = field.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 4.25).abs() < 1e-4, "hit at t = {}", hit.t);
+        let expected_normal = Vec3::new(-0.5, 1.0, 0.0).normalize();
+        assert!(
+            hit.normal.abs_diff_eq(expected_normal, 1e-4),
+            "normal {}",
+            hit.normal
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, Mat3A, Quat, Vec3};
+
+use crate::ltsr::bounds::Aabb;
+use crate::ltsr::validation::SetupIssue;
+use crate::ltsr::{HitData, Hittable, Ray, SurfaceHit};
+
+/// Places shared geometry in the scene with its own transform, so that
+/// the same object can appear many times without being duplicated
+pub struct Instance {
+    pub geometry: Arc<dyn Hittable>,
+    placement: Placement,
+}
+
+/// Where some geometry sits in world space
+struct Placement {
+    /// From the space of the geometry to world space
+    transform: Affine3A,
+    /// From world space to the space of the geometry
+    inverse: Affine3A,
+    /// Brings normals to world space (the inverse transpose of the linear part)
+    normal_matrix: Mat3A,
+}
+
+impl Instance {
+    /// Panics if ``transform`` can't be inverted (eg: it has a zero scale)
+    pub fn new(geometry: Arc<dyn Hittable>, transform: Affine3A) -> Self {
+        assert!(
+            transform.matrix3.determinant() != 0.0,
+            "The transform of an instance must be invertible"
+        );
+
+        Instance {
+            geometry,
+            placement: Placement::new(transform),
+        }
+    }
+
+    /// An instance moved by ``offset``
+    pub fn with_offset(geometry: Arc<dyn Hittable>, offset: Vec3) -> Self {
+        Instance::new(geometry, Affine3A::from_translation(offset))
+    }
+
+    pub fn transform(&self) -> Affine3A {
+        self.placement.transform
+    }
+}
+
+impl Placement {
+    fn new(transform: Affine3A) -> Self {
+        let inverse = transform.inverse();
+
+        Placement {
+            transform,
+            inverse,
+            normal_matrix: inverse.matrix3.transpose(),
+        }
+    }
+
+    /// The ray in the space of the geometry. The direction isn't normalized,
+    /// so that ``t`` means the same along both rays
+    fn to_local(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point3(ray.origin),
+            self.inverse.transform_vector3(ray.direction),
+        )
+        .following(ray)
+    }
+
+    /// A hit on the geometry, back in world space. Orienting the normals
+    /// against the ray survives the transform, as does ``front_face``
+    fn to_world(&self, ray: &Ray, surface: SurfaceHit) -> SurfaceHit {
+        SurfaceHit {
+            hit_point: ray.point_at_parameter(surface.t),
+            normal: (self.normal_matrix * surface.normal).normalize(),
+            ..surface
+        }
+    }
+
+    /// Hit against ``geometry`` placed here
+    fn hit<'a>(
+        &self,
+        geometry: &'a dyn Hittable,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitData<'a>> {
+        let hit = geometry.hit(&self.to_local(ray), t_min, t_max)?;
+        let surface = self.to_world(
+            ray,
+            SurfaceHit {
+                hit_point: hit.hit_point,
+                normal: hit.normal,
+                t: hit.t,
+                front_face: hit.front_face,
+            },
+        );
+
+        Some(HitData {
+            hit_point: surface.hit_point,
+            normal: surface.normal,
+            ..hit
+        })
+    }
+
+    fn hit_surface(
+        &self,
+        geometry: &dyn Hittable,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<SurfaceHit> {
+        let surface = geometry.hit_surface(&self.to_local(ray), t_min, t_max)?;
+        Some(self.to_world(ray, surface))
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        self.placement
+            .hit(self.geometry.as_ref(), ray, t_min, t_max)
+    }
+
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
+        self.placement
+            .hit_surface(self.geometry.as_ref(), ray, t_min, t_max)
+    }
+
+    fn hit_any(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.geometry
+            .hit_any(&self.placement.to_local(ray), t_min, t_max)
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let transform = self.placement.transform;
+        let mut issues: Vec<SetupIssue> = SetupIssue::check_finite(&transform.to_cols_array())
+            .into_iter()
+            .collect();
+        issues.extend(self.geometry.setup_issues());
+        issues
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bounds = self.geometry.bounding_box()?;
+        Some(bounds.transformed(self.placement.transform))
+    }
+}
+
+/// Geometry moving during the shutter (for motion blur): its transform goes from
+/// ``from`` when the shutter opens (``Ray::time`` of 0) to ``to`` when it closes (1).
+/// The translation and the scale are interpolated linearly, and the rotation along
+/// the shortest arc, so that objects spin without being squashed on the way
+pub struct AnimatedTransform {
+    pub object: Arc<dyn Hittable>,
+    from: (Vec3, Quat, Vec3),
+    to: (Vec3, Quat, Vec3),
+}
+
+impl AnimatedTransform {
+    /// Panics if ``from`` or ``to`` can't be inverted (eg: they have a zero scale)
+    pub fn new(object: Arc<dyn Hittable>, from: Affine3A, to: Affine3A) -> Self {
+        for transform in [from, to] {
+            assert!(
+                transform.matrix3.determinant() != 0.0,
+                "The transforms of an animated object must be invertible"
+            );
+        }
+
+        AnimatedTransform {
+            object,
+            from: from.to_scale_rotation_translation(),
+            to: to.to_scale_rotation_translation(),
+        }
+    }
+
+    /// The transform of the object at ``time``, in [0, 1] over the shutter
+    pub fn transform_at(&self, time: f32) -> Affine3A {
+        let (from_scale, from_rotation, from_translation) = self.from;
+        let (to_scale, to_rotation, to_translation) = self.to;
+
+        Affine3A::from_scale_rotation_translation(
+            from_scale.lerp(to_scale, time),
+            from_rotation.slerp(to_rotation, time),
+            from_translation.lerp(to_translation, time),
+        )
+    }
+
+    /// Where the object is for ``ray``
+    fn placement(&self, ray: &Ray) -> Placement {
+        Placement::new(self.transform_at(ray.time))
+    }
+}
+
+impl Hittable for AnimatedTransform {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        self.placement(ray)
+            .hit(self.object.as_ref(), ray, t_min, t_max)
+    }
+
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
+        self.placement(ray)
+            .hit_surface(self.object.as_ref(), ray, t_min, t_max)
+    }
+
+    fn hit_any(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.object
+            .hit_any(&self.placement(ray).to_local(ray), t_min, t_max)
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let transforms = [self.transform_at(0.0), self.transform_at(1.0)]
+            .map(|transform| transform.to_cols_array())
+            .concat();
+        let mut issues: Vec<SetupIssue> =
+            SetupIssue::check_finite(&transforms).into_iter().collect();
+        issues.extend(self.object.setup_issues());
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltsr::materials::Lambertian;
+    use crate::ltsr::{Scene, Sphere};
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(
+            1.0,
+            Vec3::ZERO,
+            Arc::new(Lambertian::new(Vec3::splat(0.5))),
+        ))
+    }
+
+    #[test]
+    fn instances_share_their_geometry() {
+        let sphere = unit_sphere();
+        let mut scene = Scene::new();
+        for x in [-3.0, 3.0] {
+            let offset = Affine3A::from_translation(Vec3::new(x, 0.0, 0.0));
+            scene.add_hittable(Box::new(Instance::new(sphere.clone(), offset)));
+        }
+        // Not cloned, only referenced by the two instances
+        assert_eq!(Arc::strong_count(&sphere), 3);
+
+        for x in [-3.0, 3.0] {
+            let ray = Ray::new(Vec3::new(x, 0.0, 10.0), Vec3::NEG_Z);
+            let hit = scene.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            assert!((hit.t - 9.0).abs() < 1e-5, "t = {}", hit.t);
+            assert!(hit.hit_point.abs_diff_eq(Vec3::new(x, 0.0, 1.0), 1e-5));
+            assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-5));
+        }
+        // Nothing in between
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::NEG_Z);
+        assert!(scene.hit(&ray, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn transformed_instances_match_the_transformed_geometry() {
+        let center = Vec3::new(1.0, 2.0, -3.0);
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            Quat::from_rotation_y(0.7) * Quat::from_rotation_x(-0.3),
+            center,
+        );
+        let instance = Instance::new(unit_sphere(), transform);
+        let reference = Sphere::new(2.0, center, Arc::new(Lambertian::new(Vec3::ONE)));
+
+        let rays = [
+            Ray::new(Vec3::new(5.0, 4.0, 3.0), Vec3::new(-0.5, -0.3, -0.8)),
+            Ray::new(Vec3::new(-4.0, 2.5, -3.0), Vec3::X),
+            // From the inside
+            Ray::new(center, Vec3::new(0.2, 1.0, 0.1)),
+        ];
+        for ray in rays {
+            let hit = instance.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            let expected = reference.hit(&ray, 0.001, f32::INFINITY).unwrap();
+
+            assert!((hit.t - expected.t).abs() < 1e-5, "{ray:?}");
+            assert!(hit.hit_point.abs_diff_eq(expected.hit_point, 1e-5));
+            assert!(hit.normal.abs_diff_eq(expected.normal, 1e-5));
+            assert_eq!(hit.front_face, expected.front_face);
+        }
+    }
+
+    #[test]
+    fn animated_objects_are_hit_where_they_are_at_the_time_of_the_ray() {
+        // Offset along X, then rotated a quarter turn about Y: from +X to -Z
+        let offset = Affine3A::from_translation(Vec3::new(3.0, 0.0, 0.0));
+        let rotated = Affine3A::from_rotation_y(std::f32::consts::FRAC_PI_2) * offset;
+        let animated = AnimatedTransform::new(unit_sphere(), offset, rotated);
+
+        let towards_start = |time| Ray::new(Vec3::new(3.0, 0.0, 10.0), Vec3::NEG_Z).with_time(time);
+        let towards_end = |time| Ray::new(Vec3::new(0.0, 10.0, -3.0), Vec3::NEG_Y).with_time(time);
+        for (time, (start_hit, end_hit)) in [(0.0, (true, false)), (1.0, (false, true))] {
+            let start = animated.hit(&towards_start(time), 0.001, f32::INFINITY);
+            let end = animated.hit(&towards_end(time), 0.001, f32::INFINITY);
+            assert_eq!(start.is_some(), start_hit, "time {time}");
+            assert_eq!(end.is_some(), end_hit, "time {time}");
+        }
+
+        let end = animated
+            .hit(&towards_end(1.0), 0.001, f32::INFINITY)
+            .unwrap();
+        assert!(end.hit_point.abs_diff_eq(Vec3::new(0.0, 1.0, -3.0), 1e-5));
+        assert!(end.normal.abs_diff_eq(Vec3::Y, 1e-5));
+    }
+}
@@ -0,0 +1,225 @@
+use glam::Vec3;
+use rand::Rng;
+
+use crate::ltsr::validation::SetupIssue;
+use crate::ltsr::{with_sampler, Color, Onb};
+
+/// How the light of a ``PointLight`` fades with the distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Falloff {
+    /// Physically based: the same light spreads over a sphere growing with
+    /// the square of the distance
+    #[default]
+    InverseSquare,
+    /// Softer than physical, lights reach further
+    Linear,
+    /// Constant, whatever the distance (eg: for stylized fill lights)
+    None,
+}
+
+impl Falloff {
+    /// What the intensity of a light gets multiplied by at ``distance`` from it
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        // Don't blow up when sitting right on top of the light
+        let distance = distance.max(1e-4);
+
+        match self {
+            Falloff::InverseSquare => 1.0 / (distance * distance),
+            Falloff::Linear => 1.0 / distance,
+            Falloff::None => 1.0,
+        }
+    }
+}
+
+/// A light shining equally in all directions from a point. It can't be seen
+/// by the rays (nothing would ever hit it), only the surfaces it lights are.
+/// With a ``radius``, it stands for a small round light: it still shines from
+/// its center, but it casts soft shadows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Vec3,
+    /// Scene linear color of the light, scaled by its brightness at a distance of 1
+    pub intensity: Color,
+    pub falloff: Falloff,
+    /// When set, the light doesn't reach anything further than this distance,
+    /// and no shadow rays are traced towards it from there
+    pub range: Option<f32>,
+    /// Size of the light for its shadows. With a radius over 0, each shadow ray aims
+    /// at a random point of a disk of that radius facing the surface being lit, and
+    /// the edges of the shadows turn into penumbrae
+    pub radius: f32,
+}
+
+impl PointLight {
+    /// A light with a physically based falloff and no range cutoff
+    pub fn new(position: Vec3, intensity: Color) -> Self {
+        PointLight {
+            position,
+            intensity,
+            falloff: Falloff::default(),
+            range: None,
+            radius: 0.0,
+        }
+    }
+
+    /// Light reaching a point at ``distance`` from the light, before any occlusion
+    pub fn intensity_at(&self, distance: f32) -> Color {
+        match self.range {
+            Some(range) if distance > range => Color::ZERO,
+            _ => self.intensity * self.falloff.attenuation(distance),
+        }
+    }
+
+    /// Point of the light a shadow ray from ``from`` aims at: the position itself for
+    /// lights without a radius (which don't draw anything from the sampler), otherwise
+    /// a random point of the disk of the light facing ``from``
+    pub fn sample_point(&self, from: Vec3) -> Vec3 {
+        if self.radius <= 0.0 {
+            return self.position;
+        }
+
+        let facing = (from - self.position).try_normalize().unwrap_or(Vec3::Z);
+        let (radius_sample, angle_sample) =
+            with_sampler(|rng| (rng.gen::<f32>(), rng.gen::<f32>()));
+        // Uniform over the area of the disk
+        let radius = self.radius * radius_sample.sqrt();
+        let angle = std::f32::consts::TAU * angle_sample;
+        let on_disk = Vec3::new(radius * angle.cos(), radius * angle.sin(), 0.0);
+
+        self.position + Onb::from_normal(facing).local_to_world(on_disk)
+    }
+
+    /// Likely mistakes in how the light was set up, see ``Scene::validate``
+    pub fn setup_issues(&self) -> Vec<SetupIssue> {
+        let range = self.range.unwrap_or_default();
+        [
+            SetupIssue::check_finite(&self.position.to_array()),
+            SetupIssue::check_finite(&self.intensity.to_array()),
+            SetupIssue::check_not_negative("intensity", &self.intensity.to_array()),
+            SetupIssue::check_not_negative("range", &[range]),
+            SetupIssue::check_not_negative("radius", &[self.radius]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ltsr::materials::Lambertian;
+    use crate::ltsr::settings::RenderSettings;
+    use crate::ltsr::{pixel_seed, ray_color, seed_sampler, with_sampler, Ray, Scene, Sphere};
+
+    const ALBEDO: f32 = 0.5;
+
+    /// Light reflected towards a camera looking straight down at a flat Lambertian
+    /// ground, by ``light`` hanging above it: the same sample with and without the light
+    fn light_reflected_by_the_ground(light: PointLight) -> Color {
+        let mut scene = Scene::new();
+        let ground = Arc::new(Lambertian::new(Vec3::splat(ALBEDO)));
+        scene.add_hittable(Box::new(Sphere::new(
+            1000.0,
+            Vec3::new(0.0, -1000.0, 0.0),
+            ground,
+        )));
+        let settings = RenderSettings {
+            max_diffuse_depth: 1,
+            ..RenderSettings::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+
+        seed_sampler(pixel_seed(0, 0, 0, 0));
+        let unlit = ray_color(&ray, &scene, &settings);
+        scene.add_light(light);
+        seed_sampler(pixel_seed(0, 0, 0, 0));
+        ray_color(&ray, &scene, &settings) - unlit
+    }
+
+    fn light_above(height: f32, falloff: Falloff, range: Option<f32>) -> PointLight {
+        PointLight {
+            falloff,
+            range,
+            ..PointLight::new(Vec3::new(0.0, height, 0.0), Vec3::ONE)
+        }
+    }
+
+    #[test]
+    fn lights_fade_with_the_distance_as_their_falloff_says() {
+        let reflected = |height: f32, falloff: Falloff| {
+            light_reflected_by_the_ground(light_above(height, falloff, None)).x
+        };
+
+        for (falloff, ratio) in [
+            (Falloff::InverseSquare, 4.0),
+            (Falloff::Linear, 2.0),
+            (Falloff::None, 1.0),
+        ] {
+            let (near, far) = (reflected(1.0, falloff), reflected(2.0, falloff));
+            assert!(far > 0.0, "{falloff:?}");
+            assert!(
+                (near / far - ratio).abs() < 1e-3,
+                "{falloff:?}: {near} vs {far}"
+            );
+        }
+
+        let constant = reflected(2.0, Falloff::None);
+        let expected = ALBEDO / std::f32::consts::PI;
+        assert!((constant - expected).abs() < 1e-4, "{constant}");
+    }
+
+    #[test]
+    fn lights_dont_reach_past_their_range() {
+        let out_of_range = light_above(2.0, Falloff::InverseSquare, Some(1.5));
+        assert_eq!(light_reflected_by_the_ground(out_of_range), Color::ZERO);
+
+        let in_range = light_above(2.0, Falloff::InverseSquare, Some(2.5));
+        assert!(light_reflected_by_the_ground(in_range).x > 0.0);
+    }
+
+    #[test]
+    fn shadow_rays_spread_over_the_disk_of_lights_with_a_radius() {
+        let lit_point = Vec3::new(1.0, -4.0, 2.0);
+        let point_light = PointLight::new(Vec3::new(0.0, 3.0, 0.0), Vec3::ONE);
+
+        // Always the same target, without touching the sampler
+        seed_sampler(pixel_seed(0, 0, 0, 0));
+        for _ in 0..100 {
+            assert_eq!(point_light.sample_point(lit_point), point_light.position);
+        }
+        let next_random = with_sampler(|rng| rng.gen::<u32>());
+        seed_sampler(pixel_seed(0, 0, 0, 0));
+        assert_eq!(with_sampler(|rng| rng.gen::<u32>()), next_random);
+
+        let round_light = PointLight {
+            radius: 0.5,
+            ..point_light
+        };
+        let facing = (lit_point - round_light.position).normalize();
+        let targets: Vec<Vec3> = (0..1000)
+            .map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                round_light.sample_point(lit_point)
+            })
+            .collect();
+        for target in targets.iter() {
+            let offset = *target - round_light.position;
+            assert!(offset.length() <= 0.5 + 1e-5, "{target}");
+            assert!(
+                offset.dot(facing).abs() < 1e-5,
+                "{target} isn't on the disk"
+            );
+        }
+        // Over the whole disk, not just around its center
+        let furthest = targets
+            .iter()
+            .map(|target| target.distance(round_light.position))
+            .fold(0.0, f32::max);
+        assert!(furthest > 0.45, "{furthest}");
+        let mean = targets.iter().sum::<Vec3>() / targets.len() as f32;
+        assert!(mean.distance(round_light.position) < 0.05, "{mean}");
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::Vec3;
+use serde::Deserialize;
+
+use crate::ltsr::materials::{
+    Coated, Dielectric, Lambertian, Material, Metallic, Mirror, SubsurfaceScattering, ThinFilm,
+};
+
+/// Reusable materials, referenced by name
+pub type MaterialLibrary = HashMap<String, Arc<dyn Material>>;
+
+/// How a material is described in a library file, eg:
+/// ``{ "gold": { "type": "metallic", "albedo": [0.8, 0.6, 0.2], "roughness": 0.3 } }``
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: [f32; 3],
+    },
+    Metallic {
+        albedo: [f32; 3],
+        roughness: f32,
+    },
+    Mirror {
+        albedo: [f32; 3],
+    },
+    Dielectric {
+        ior: f32,
+        #[serde(default)]
+        priority: u32,
+        #[serde(default)]
+        absorption: [f32; 3],
+        #[serde(default)]
+        dispersion: f32,
+    },
+    SubsurfaceScattering {
+        albedo: [f32; 3],
+        mean_free_path: f32,
+    },
+    /// A clear coat over another material, eg:
+    /// ``{ "type": "coated", "base": { "type": "lambertian", "albedo": [0.6, 0.0, 0.0] }, "coat_ior": 1.5 }``
+    Coated {
+        base: Box<MaterialDescription>,
+        coat_ior: f32,
+        #[serde(default)]
+        coat_roughness: f32,
+    },
+    /// An iridescent film over another material, ``thickness`` in nanometers
+    ThinFilm {
+        base: Box<MaterialDescription>,
+        thickness: f32,
+        ior: f32,
+    },
+}
+
+impl MaterialDescription {
+    pub fn build(&self) -> Arc<dyn Material> {
+        match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Arc::new(Lambertian::new(Vec3::from_array(*albedo)))
+            }
+            MaterialDescription::Metallic { albedo, roughness } => {
+                Arc::new(Metallic::new(Vec3::from_array(*albedo), *roughness))
+            }
+            MaterialDescription::Mirror { albedo } => {
+                Arc::new(Mirror::new(Vec3::from_array(*albedo)))
+            }
+            MaterialDescription::Dielectric {
+                ior,
+                priority,
+                absorption,
+                dispersion,
+            } => Arc::new(
+                Dielectric::new(*ior)
+                    .with_priority(*priority)
+                    .with_absorption(Vec3::from_array(*absorption))
+                    .with_dispersion(*dispersion),
+            ),
+            MaterialDescription::SubsurfaceScattering {
+                albedo,
+                mean_free_path,
+            } => Arc::new(SubsurfaceScattering::new(
+                Vec3::from_array(*albedo),
+                *mean_free_path,
+            )),
+            MaterialDescription::Coated {
+                base,
+                coat_ior,
+                coat_roughness,
+            } => Arc::new(Coated::new(base.build(), *coat_ior, *coat_roughness)),
+            MaterialDescription::ThinFilm {
+                base,
+                thickness,
+                ior,
+            } => Arc::new(ThinFilm::new(base.build(), *thickness, *ior)),
+        }
+    }
+}
+
+/// Parses a JSON object mapping material names to their descriptions
+pub fn parse_material_library(json: &str) -> anyhow::Result<MaterialLibrary> {
+    let descriptions: HashMap<String, MaterialDescription> = serde_json::from_str(json)?;
+
+    Ok(descriptions
+        .into_iter()
+        .map(|(name, description)| (name, description.build()))
+        .collect())
+}
+
+/// Loads a material library (eg: ``materials.json``) from disk
+pub fn load_material_library(path: impl AsRef<Path>) -> anyhow::Result<MaterialLibrary> {
+    let path = path.as_ref();
+
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) => {
+            anyhow::bail!("Failed to read material library {}: {e}", path.display());
+        }
+    };
+
+    match parse_material_library(&json) {
+        Ok(library) => Ok(library),
+        Err(e) => {
+            anyhow::bail!("Failed to parse material library {}: {e}", path.display());
+        }
+    }
+}
+
+/// Looks up all the given material ``names`` in ``library``.
+/// If any of them is missing, the error lists all the missing names.
+pub fn resolve_materials(
+    library: &MaterialLibrary,
+    names: &[&str],
+) -> anyhow::Result<Vec<Arc<dyn Material>>> {
+    let missing: Vec<&str> = names
+        .iter()
+        .filter(|name| !library.contains_key(**name))
+        .copied()
+        .collect();
+
+    if !missing.is_empty() {
+        let mut available: Vec<&str> = library.keys().map(|name| name.as_str()).collect();
+        available.sort_unstable();
+
+        anyhow::bail!(
+            "Unknown material(s): {}. Available materials are: {}",
+            missing.join(", "),
+            available.join(", ")
+        );
+    }
+
+    Ok(names.iter().map(|name| library[*name].clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = r#"{
+        "gold": { "type": "metallic", "albedo": [0.8, 0.6, 0.2], "roughness": 0.3 },
+        "glass": { "type": "dielectric", "ior": 1.5, "priority": 2 }
+    }"#;
+
+    #[test]
+    fn materials_are_resolved_by_name() {
+        let library = parse_material_library(LIBRARY).unwrap();
+
+        let materials = resolve_materials(&library, &["glass", "gold"]).unwrap();
+        assert_eq!(materials.len(), 2);
+        let glass = materials[0].medium().unwrap();
+        assert_eq!((glass.ior, glass.priority), (1.5, 2));
+        assert_eq!(materials[1].albedo(), Vec3::new(0.8, 0.6, 0.2));
+        assert!(Arc::ptr_eq(&materials[1], &library["gold"]));
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let library = parse_material_library(LIBRARY).unwrap();
+
+        let error = resolve_materials(&library, &["gold", "silver", "copper"]).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("silver, copper"), "{message}");
+        assert!(message.contains("glass, gold"), "{message}");
+    }
+}
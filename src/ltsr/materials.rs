@@ -1,10 +1,169 @@
+use std::sync::Arc;
+
+use glam::Vec3;
+use rand::Rng;
+
+use crate::ltsr::spectral::cauchy_ior;
+use crate::ltsr::validation::SetupIssue;
 use crate::ltsr::{
-    near_zero, random_in_hemisphere, random_in_unit_sphere, reflect, Color, HitData, Ray,
+    near_zero, random_in_hemisphere, random_in_unit_sphere, reflect, reflectance, refract,
+    with_sampler, Color, HitData, Ray,
 };
 
-/// Anything that is considered a material should implement this trait
-pub trait Material {
+/// Anything that is considered a material should implement this trait.
+/// Materials are shared by the render settings, which get sent to the render task
+pub trait Material: Send + Sync {
     fn scatter(&self, ray: &Ray, data: &HitData) -> Option<(Color, Ray)>;
+
+    /// Whether the rays scattered by this material count as specular bounces
+    /// (as opposed to diffuse ones) when limiting the depth of a path
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Base color of the material, as used by the Albedo AOV
+    fn albedo(&self) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    /// Like ``scatter``, keeping the value of the BRDF and the pdf of the sampled
+    /// direction apart, so that samples can be weighted by ``scattering_pdf / pdf``.
+    /// By default, wraps ``scatter`` with a pdf of 1 (matching the default
+    /// ``scattering_pdf``), so that the attenuation stays as it is
+    fn scatter_record(&self, ray: &Ray, data: &HitData) -> Option<ScatterRecord> {
+        self.scatter(ray, data)
+            .map(|(attenuation, scattered)| ScatterRecord {
+                attenuation,
+                scattered,
+                pdf: 1.0,
+                is_specular: self.is_specular(),
+            })
+    }
+
+    /// Density of the material scattering ``ray`` (hitting at ``data``) into
+    /// ``scattered``, over the sphere of directions
+    fn scattering_pdf(&self, _ray: &Ray, _data: &HitData, _scattered: &Ray) -> f32 {
+        1.0
+    }
+
+    /// Fraction of the light arriving along ``to_light`` (a ray leaving the hit point)
+    /// scattered back along ``ray``, cosine term included. Used to light surfaces with
+    /// the lights of the scene. None for the materials that can only be sampled
+    /// (mirrors, glass, ..), which only get lit by the paths they scatter
+    fn evaluate(&self, _ray: &Ray, _data: &HitData, _to_light: &Ray) -> Option<Color> {
+        None
+    }
+
+    /// Whether the rays scattered inside objects made of this material are the steps
+    /// of a random walk through their volume, see ``SubsurfaceScattering``. The walk
+    /// doesn't spend the bounces of the path, it's limited by ``MAX_SUBSURFACE_STEPS``
+    fn walks_inside(&self) -> bool {
+        false
+    }
+
+    /// The medium filling objects made of this material, if light can travel through it
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Likely mistakes in the parameters of the material, see ``Scene::validate``
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        Vec::new()
+    }
+
+    /// Like ``scatter``, for rays crossing from a medium of index of refraction
+    /// ``incident_ior`` to one of index ``transmitted_ior``, instead of between
+    /// this material and the air
+    fn scatter_between_media(
+        &self,
+        ray: &Ray,
+        data: &HitData,
+        _incident_ior: f32,
+        _transmitted_ior: f32,
+    ) -> Option<(Color, Ray)> {
+        self.scatter(ray, data)
+    }
+}
+
+/// A ray scattered by a material, see ``Material::scatter_record``
+#[derive(Debug, Clone)]
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub scattered: Ray,
+    /// Density of ``scattered`` among the directions the material could have sampled
+    pub pdf: f32,
+    /// Specular rays (mirrors, glass, ..) have no meaningful pdf: only the
+    /// attenuation is used to weight them
+    pub is_specular: bool,
+}
+
+impl ScatterRecord {
+    /// How much of the light coming back along the scattered ray reaches the incoming one
+    pub fn weight(&self, material: &dyn Material, ray: &Ray, data: &HitData) -> Color {
+        if self.is_specular || self.pdf <= 0.0 {
+            self.attenuation
+        } else {
+            self.attenuation * material.scattering_pdf(ray, data, &self.scattered) / self.pdf
+        }
+    }
+}
+
+/// A medium light can travel through, like glass or water
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Medium {
+    /// Index of refraction
+    pub ior: f32,
+    /// Where media overlap (eg: an ice cube poking out of water), the one with the
+    /// highest priority fills the overlap, and the surfaces of the others are ignored
+    pub priority: u32,
+    /// How much of each channel gets absorbed per unit of distance travelled inside.
+    /// Zero for clear media
+    pub absorption: Color,
+    /// How much the index of refraction varies with the wavelength, splitting white
+    /// light into rainbows in spectral renders. See ``spectral::cauchy_ior``
+    pub dispersion: f32,
+}
+
+impl Medium {
+    /// Where all the paths start from
+    pub const AIR: Medium = Medium {
+        ior: 1.0,
+        priority: 0,
+        absorption: Color::ZERO,
+        dispersion: 0.0,
+    };
+
+    /// Index of refraction at ``wavelength`` (in nanometers), or the plain ``ior``
+    /// for rays carrying RGB light
+    pub fn ior_at(&self, wavelength: Option<f32>) -> f32 {
+        match wavelength {
+            Some(wavelength) if self.dispersion != 0.0 => {
+                cauchy_ior(self.ior, self.dispersion, wavelength)
+            }
+            _ => self.ior,
+        }
+    }
+
+    /// Fraction of the light surviving ``distance`` through the medium (Beer-Lambert law)
+    pub fn transmittance(&self, distance: f32) -> Color {
+        // Also keeps clear media from turning 0 * infinity into NaNs
+        if self.absorption == Color::ZERO {
+            return Color::ONE;
+        }
+
+        let optical_depth = self.absorption * distance;
+        Color::new(
+            (-optical_depth.x).exp(),
+            (-optical_depth.y).exp(),
+            (-optical_depth.z).exp(),
+        )
+    }
+}
+
+impl std::fmt::Debug for dyn Material {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Material {{ albedo: {} }}", self.albedo())
+    }
 }
 
 pub struct Lambertian {
@@ -32,35 +191,816 @@ impl Material for Lambertian {
 
         Some((self.albedo, new_ray.clone()))
     }
+
+    fn scatter_record(&self, ray: &Ray, data: &HitData) -> Option<ScatterRecord> {
+        // Directions are sampled uniformly over the hemisphere
+        let (attenuation, scattered) = self.scatter(ray, data)?;
+
+        Some(ScatterRecord {
+            attenuation,
+            scattered,
+            pdf: 1.0 / (2.0 * std::f32::consts::PI),
+            is_specular: false,
+        })
+    }
+
+    /// Cosine distribution: cos(theta) / pi
+    fn scattering_pdf(&self, _ray: &Ray, data: &HitData, scattered: &Ray) -> f32 {
+        let cos_theta = data.normal.dot(scattered.direction.normalize());
+        cos_theta.max(0.0) / std::f32::consts::PI
+    }
+
+    /// The albedo over pi, times the cosine
+    fn evaluate(&self, ray: &Ray, data: &HitData, to_light: &Ray) -> Option<Color> {
+        Some(self.albedo * self.scattering_pdf(ray, data, to_light))
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        SetupIssue::check_albedo(self.albedo).into_iter().collect()
+    }
+}
+
+/// How many times a rough reflection is perturbed again before giving up
+/// and mirroring it back above the surface
+const MAX_ROUGHNESS_RESAMPLES: usize = 4;
+
+/// Direction of ``direction`` reflected about ``normal``, perturbed by ``roughness``
+/// (in the [0, 1] range) to mimic the imperfections of the surface
+fn rough_reflection(direction: Vec3, normal: Vec3, roughness: f32) -> Vec3 {
+    let reflected_direction = reflect(direction, normal).normalize();
+
+    // Large perturbations can send the ray below the surface: instead of
+    // absorbing it (which turns rough metals noisy-black), try again a few times
+    let mut scattered_direction = reflected_direction;
+    for _ in 0..MAX_ROUGHNESS_RESAMPLES {
+        scattered_direction = reflected_direction + roughness * random_in_unit_sphere();
+
+        if scattered_direction.dot(normal) > 0.0 {
+            break;
+        }
+    }
+
+    // Still pointing inside the object: mirror it back above the surface
+    let below_surface = scattered_direction.dot(normal);
+    if below_surface <= 0.0 {
+        scattered_direction -= 2.0 * below_surface * normal;
+    }
+
+    // Perturbations exactly tangent to the surface are mirrored to themselves,
+    // so fall back to the perfect reflection
+    if scattered_direction.dot(normal) <= 0.0 {
+        scattered_direction = reflected_direction;
+    }
+
+    scattered_direction
 }
 
 pub struct Metallic {
     pub albedo: Color,
+    /// How much the reflected rays get perturbed, in the [0, 1] range
     pub roughness: f32,
 }
 
 impl Metallic {
+    /// Creates a new metallic material. ``roughness`` is clamped to [0, 1].
+    /// For perfectly smooth metals, ``Mirror`` skips the perturbation altogether
     pub fn new(albedo: Color, roughness: f32) -> Self {
-        Self { albedo, roughness }
+        Self {
+            albedo,
+            roughness: roughness.clamp(0.0, 1.0),
+        }
     }
 }
 
 impl Material for Metallic {
     fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
         // Scatter a new ray in a based on the Normal of the object that we have just hit
+        let scattered_direction = rough_reflection(ray_in.direction, data.normal, self.roughness);
+        let new_ray = Ray::new(data.hit_point, scattered_direction);
+
+        Some((self.albedo, new_ray))
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        [
+            SetupIssue::check_albedo(self.albedo),
+            SetupIssue::check_roughness(self.roughness),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// A perfect reflector: rays bounce off exactly across the normal, tinted by ``albedo``.
+/// Like a ``Metallic`` of roughness 0, without drawing (and then discarding) a perturbation
+pub struct Mirror {
+    pub albedo: Color,
+}
+
+impl Mirror {
+    pub fn new(albedo: Color) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Mirror {
+    fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
         let reflected_direction = reflect(ray_in.direction, data.normal).normalize();
+        let new_ray = Ray::new(data.hit_point, reflected_direction);
+
+        Some((self.albedo, new_ray))
+    }
 
-        // Mimic the metallic imperfections by moving the reflected ray a bit
-        // let roughness_perturbation = self.roughness * random_in_hemisphere(data.normal);
-        let roughness_perturbation = self.roughness * random_in_unit_sphere();
+    fn is_specular(&self) -> bool {
+        true
+    }
 
-        let new_ray = Ray::new(data.hit_point, reflected_direction + roughness_perturbation);
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
 
-        // If the new ray is not pointint outside the object, don't return it
-        if new_ray.direction.dot(data.normal) > 0.0 {
-            Some((self.albedo, new_ray.clone()))
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        SetupIssue::check_albedo(self.albedo).into_iter().collect()
+    }
+}
+
+/// Clear materials like glass or water, that both reflect and refract light
+pub struct Dielectric {
+    /// Index of refraction
+    pub ior: f32,
+    /// See ``Medium::priority``
+    pub priority: u32,
+    /// See ``Medium::absorption``: colored glass gets darker and more
+    /// saturated the longer the path through it
+    pub absorption: Color,
+    /// See ``Medium::dispersion``
+    pub dispersion: f32,
+}
+
+impl Dielectric {
+    pub fn new(ior: f32) -> Self {
+        Self {
+            ior,
+            priority: 0,
+            absorption: Color::ZERO,
+            dispersion: 0.0,
+        }
+    }
+
+    pub fn with_priority(self, priority: u32) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn with_absorption(self, absorption: Color) -> Self {
+        Self { absorption, ..self }
+    }
+
+    /// Glass splitting white light in rainbows (in spectral renders), eg: a prism.
+    /// ``ior`` is the index of refraction at ``spectral::REFERENCE_WAVELENGTH``
+    pub fn with_dispersion(self, dispersion: f32) -> Self {
+        Self { dispersion, ..self }
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
+        // Entering or leaving the medium, from or to the air
+        let ior = match self.medium() {
+            Some(medium) => medium.ior_at(ray_in.wavelength),
+            None => self.ior,
+        };
+        if data.front_face {
+            self.scatter_between_media(ray_in, data, 1.0, ior)
         } else {
-            None
+            self.scatter_between_media(ray_in, data, ior, 1.0)
         }
     }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(Medium {
+            ior: self.ior,
+            priority: self.priority,
+            absorption: self.absorption,
+            dispersion: self.dispersion,
+        })
+    }
+
+    fn scatter_between_media(
+        &self,
+        ray_in: &Ray,
+        data: &HitData,
+        incident_ior: f32,
+        transmitted_ior: f32,
+    ) -> Option<(Color, Ray)> {
+        // The surface itself absorbs nothing, the medium does (see ``Medium::transmittance``)
+        let attenuation = Color::new(1.0, 1.0, 1.0);
+
+        let refraction_ratio = incident_ior / transmitted_ior;
+
+        let unit_direction = ray_in.direction.normalize();
+        let cos_theta = (-unit_direction).dot(data.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        // Total internal reflection: there's no solution to Snell's law
+        let cannot_refract = refraction_ratio * sin_theta > 1.0;
+
+        let direction = if cannot_refract
+            || reflectance(cos_theta, refraction_ratio) > with_sampler(|rng| rng.gen::<f32>())
+        {
+            reflect(unit_direction, data.normal)
+        } else {
+            refract(unit_direction, data.normal, refraction_ratio)
+        };
+
+        Some((attenuation, Ray::new(data.hit_point, direction)))
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        [
+            SetupIssue::check_ior(self.ior),
+            SetupIssue::check_not_negative("absorption", &self.absorption.to_array()),
+            SetupIssue::check_finite(&[self.dispersion]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// A clear coat (eg: lacquer, or the varnish of car paint) over another material.
+/// Depending on the angle (following Fresnel), light either reflects off the coat
+/// or goes through it and scatters off the base
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    /// Index of refraction of the coat
+    pub coat_ior: f32,
+    /// Like ``Metallic::roughness``, for the reflections off the coat
+    pub coat_roughness: f32,
+}
+
+impl Coated {
+    /// Creates a new coated material. ``coat_roughness`` is clamped to [0, 1].
+    pub fn new(base: Arc<dyn Material>, coat_ior: f32, coat_roughness: f32) -> Self {
+        Self {
+            base,
+            coat_ior,
+            coat_roughness: coat_roughness.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Probability of ``ray`` (hitting at ``data``) reflecting off the coat:
+    /// low when looking straight at the surface, and going up to 1 at grazing angles
+    pub fn coat_reflectance(&self, ray: &Ray, data: &HitData) -> f32 {
+        let cos_theta = (-ray.direction.normalize())
+            .dot(data.normal)
+            .clamp(0.0, 1.0);
+        reflectance(cos_theta, 1.0 / self.coat_ior)
+    }
+
+    fn reflects_off_coat(&self, ray: &Ray, data: &HitData) -> bool {
+        self.coat_reflectance(ray, data) > with_sampler(|rng| rng.gen::<f32>())
+    }
+
+    fn coat_reflection(&self, ray: &Ray, data: &HitData) -> Ray {
+        let direction = rough_reflection(ray.direction, data.normal, self.coat_roughness);
+        Ray::new(data.hit_point, direction)
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
+        // The coat is clear: the reflections keep the color of the light
+        if self.reflects_off_coat(ray_in, data) {
+            Some((Color::ONE, self.coat_reflection(ray_in, data)))
+        } else {
+            self.base.scatter(ray_in, data)
+        }
+    }
+
+    fn scatter_record(&self, ray: &Ray, data: &HitData) -> Option<ScatterRecord> {
+        if self.reflects_off_coat(ray, data) {
+            Some(ScatterRecord {
+                attenuation: Color::ONE,
+                scattered: self.coat_reflection(ray, data),
+                pdf: 1.0,
+                is_specular: true,
+            })
+        } else {
+            self.base.scatter_record(ray, data)
+        }
+    }
+
+    /// Only used to weight the rays scattered by the base (the coat ones are specular)
+    fn scattering_pdf(&self, ray: &Ray, data: &HitData, scattered: &Ray) -> f32 {
+        self.base.scattering_pdf(ray, data, scattered)
+    }
+
+    /// The base, lit by what goes through the coat
+    fn evaluate(&self, ray: &Ray, data: &HitData, to_light: &Ray) -> Option<Color> {
+        let transmitted = 1.0 - self.coat_reflectance(ray, data);
+        Some(self.base.evaluate(ray, data, to_light)? * transmitted)
+    }
+
+    /// Bounces are counted like the ones of the base, which most of the rays scatter off
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn albedo(&self) -> Color {
+        self.base.albedo()
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let mut issues: Vec<SetupIssue> = [
+            SetupIssue::check_ior(self.coat_ior),
+            SetupIssue::check_roughness(self.coat_roughness),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        issues.extend(self.base.setup_issues());
+        issues
+    }
+}
+
+/// Wavelengths (in nanometers) the interference of a ``ThinFilm`` is computed at,
+/// with how much each one contributes to the red, green and blue channels
+/// (rough gaussian responses, each channel adding up to 1, so that a flat
+/// spectrum stays white)
+const THIN_FILM_SPECTRUM: [(f32, [f32; 3]); 13] = [
+    (400.0, [0.0000, 0.0003, 0.0453]),
+    (425.0, [0.0000, 0.0028, 0.1694]),
+    (450.0, [0.0001, 0.0149, 0.3165]),
+    (475.0, [0.0008, 0.0539, 0.2953]),
+    (500.0, [0.0057, 0.1324, 0.1376]),
+    (525.0, [0.0262, 0.2201, 0.0320]),
+    (550.0, [0.0813, 0.2474, 0.0037]),
+    (575.0, [0.1708, 0.1882, 0.0002]),
+    (600.0, [0.2428, 0.0969, 0.0000]),
+    (625.0, [0.2335, 0.0337, 0.0000]),
+    (650.0, [0.1519, 0.0080, 0.0000]),
+    (675.0, [0.0669, 0.0013, 0.0000]),
+    (700.0, [0.0200, 0.0001, 0.0000]),
+];
+
+/// A thin transparent film (eg: a soap bubble, or oil on water) over another material.
+/// The light reflected by the top and the bottom of the film interferes, reflecting
+/// some wavelengths more than others depending on the thickness and the angle,
+/// which gives the iridescent colors. The light going through the film scatters
+/// off the base, tinted by the complementary colors
+pub struct ThinFilm {
+    pub base: Arc<dyn Material>,
+    /// Thickness of the film, in nanometers (a few hundreds for visible colors)
+    pub thickness: f32,
+    /// Index of refraction of the film
+    pub ior: f32,
+}
+
+impl ThinFilm {
+    pub fn new(base: Arc<dyn Material>, thickness: f32, ior: f32) -> Self {
+        Self {
+            base,
+            thickness,
+            ior,
+        }
+    }
+
+    /// Fraction of the light reflected by the film, for light arriving with an angle
+    /// of ``cos_theta`` to the normal. The film is treated as having air on both
+    /// sides (like a soap bubble): the base only gets what goes through
+    pub fn reflectance(&self, cos_theta: f32) -> Color {
+        let cos_incident = cos_theta.clamp(0.0, 1.0);
+        let sin_transmitted = (1.0 - cos_incident * cos_incident).sqrt() / self.ior;
+        let cos_transmitted = (1.0 - sin_transmitted * sin_transmitted).max(0.0).sqrt();
+
+        // Fresnel coefficients (for the amplitude) at the top of the film, for both
+        // polarizations. The bottom of the film, back to the air, has the opposite ones
+        let r_s = (cos_incident - self.ior * cos_transmitted)
+            / (cos_incident + self.ior * cos_transmitted);
+        let r_p = (self.ior * cos_incident - cos_transmitted)
+            / (self.ior * cos_incident + cos_transmitted);
+
+        let optical_path = 4.0 * std::f32::consts::PI * self.ior * self.thickness * cos_transmitted;
+
+        let mut reflectance = Color::ZERO;
+        for (wavelength, weights) in THIN_FILM_SPECTRUM {
+            let cos_phase = (optical_path / wavelength).cos();
+
+            // Airy formula for a film between two identical media, averaged over
+            // the two polarizations (ie: for unpolarized light)
+            let airy = |r: f32| {
+                let r2 = r * r;
+                2.0 * r2 * (1.0 - cos_phase) / (1.0 - 2.0 * r2 * cos_phase + r2 * r2)
+            };
+            let wavelength_reflectance = 0.5 * (airy(r_s) + airy(r_p));
+
+            reflectance += Color::from_array(weights) * wavelength_reflectance;
+        }
+
+        reflectance
+    }
+
+    /// The film reflectance for ``ray``, and the probability of reflecting off the film
+    fn film_reflection(&self, ray: &Ray, data: &HitData) -> (Color, f32) {
+        let cos_theta = (-ray.direction.normalize()).dot(data.normal);
+        let reflectance = self.reflectance(cos_theta);
+        let probability = ((reflectance.x + reflectance.y + reflectance.z) / 3.0).clamp(0.0, 1.0);
+
+        (reflectance, probability)
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
+        let record = self.scatter_record(ray_in, data)?;
+        Some((record.attenuation, record.scattered))
+    }
+
+    fn scatter_record(&self, ray: &Ray, data: &HitData) -> Option<ScatterRecord> {
+        let (reflectance, probability) = self.film_reflection(ray, data);
+
+        // Reflect off the film or go through it, weighting the colors so that
+        // they add up to the reflectance (and what's left of it) on average
+        if probability > with_sampler(|rng| rng.gen::<f32>()) {
+            let direction = reflect(ray.direction.normalize(), data.normal);
+            Some(ScatterRecord {
+                attenuation: reflectance / probability,
+                scattered: Ray::new(data.hit_point, direction),
+                pdf: 1.0,
+                is_specular: true,
+            })
+        } else {
+            let mut record = self.base.scatter_record(ray, data)?;
+            record.attenuation *= (Color::ONE - reflectance) / (1.0 - probability);
+            Some(record)
+        }
+    }
+
+    /// Only used to weight the rays scattered by the base (the film ones are specular)
+    fn scattering_pdf(&self, ray: &Ray, data: &HitData, scattered: &Ray) -> f32 {
+        self.base.scattering_pdf(ray, data, scattered)
+    }
+
+    /// The base, lit by the complementary colors of the film
+    fn evaluate(&self, ray: &Ray, data: &HitData, to_light: &Ray) -> Option<Color> {
+        let (reflectance, _) = self.film_reflection(ray, data);
+        Some(self.base.evaluate(ray, data, to_light)? * (Color::ONE - reflectance))
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn albedo(&self) -> Color {
+        self.base.albedo()
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let mut issues: Vec<SetupIssue> = [
+            SetupIssue::check_ior(self.ior),
+            SetupIssue::check_not_negative("film thickness", &[self.thickness]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        issues.extend(self.base.setup_issues());
+        issues
+    }
+}
+
+/// Most steps a path can take in a single random walk through a ``SubsurfaceScattering``
+/// object. The few walks that get this long have lost most of their light by then
+pub const MAX_SUBSURFACE_STEPS: i32 = 256;
+
+/// Translucent materials like wax, skin or marble, where light enters the surface,
+/// bounces around inside the object and leaves it somewhere else.
+/// Modelled as a random walk in a medium with the same index of refraction as
+/// the outside: rays travel straight through the surface, and scatter in random
+/// directions inside after an exponentially distributed free flight.
+pub struct SubsurfaceScattering {
+    /// Fraction of the light surviving each scattering event inside the medium
+    pub albedo: Color,
+    /// Average distance travelled inside the medium between two scattering events.
+    /// The larger it is, the more translucent the object
+    pub mean_free_path: f32,
+}
+
+impl SubsurfaceScattering {
+    pub fn new(albedo: Color, mean_free_path: f32) -> Self {
+        Self {
+            albedo,
+            mean_free_path,
+        }
+    }
+}
+
+impl Material for SubsurfaceScattering {
+    fn scatter(&self, ray_in: &Ray, data: &HitData) -> Option<(Color, Ray)> {
+        let unit_direction = ray_in.direction.normalize();
+
+        // Entering the medium
+        if data.front_face {
+            return Some((Color::ONE, Ray::new(data.hit_point, unit_direction)));
+        }
+
+        // Inside the medium: sample how far the ray goes before scattering, and
+        // compare it with the distance to the surface it has reached
+        let distance_to_surface = data.t * ray_in.direction.length();
+        let free_flight = -self.mean_free_path * (1.0 - with_sampler(|rng| rng.gen::<f32>())).ln();
+
+        if free_flight >= distance_to_surface {
+            // Leaving the medium
+            return Some((Color::ONE, Ray::new(data.hit_point, unit_direction)));
+        }
+
+        // Scatter in a random direction from inside the medium
+        let scatter_point = ray_in.origin + free_flight * unit_direction;
+        let mut scatter_direction = random_in_unit_sphere();
+        if near_zero(&scatter_direction) {
+            scatter_direction = unit_direction;
+        }
+
+        Some((self.albedo, Ray::new(scatter_point, scatter_direction)))
+    }
+
+    fn walks_inside(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self) -> Color {
+        self.albedo
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        [
+            SetupIssue::check_albedo(self.albedo),
+            SetupIssue::check_not_negative("mean free path", &[self.mean_free_path]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Affine3A, Vec3};
+
+    use super::*;
+    use crate::ltsr::instance::Instance;
+    use crate::ltsr::settings::RenderSettings;
+    use crate::ltsr::{luminance, pixel_seed, ray_color, seed_sampler, Scene, Sphere};
+
+    /// A wide slab, 0.2 thick around y = 0, made of ``material``
+    fn slab_scene(material: Arc<dyn Material>) -> Scene {
+        let sphere = Arc::new(Sphere::new(1.0, Vec3::ZERO, material));
+        let flattened = Affine3A::from_scale(Vec3::new(20.0, 0.1, 20.0));
+
+        let mut scene = Scene::new();
+        scene.add_hittable(Box::new(Instance::new(sphere, flattened)));
+        scene
+    }
+
+    /// Average luminance of the light coming back up from the slab, looking straight down
+    fn slab_luminance(scene: &Scene) -> f32 {
+        let settings = RenderSettings::default();
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+
+        let num_samples = 2000;
+        let total: f32 = (0..num_samples)
+            .map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                luminance(ray_color(&ray, scene, &settings))
+            })
+            .sum();
+        total / num_samples as f32
+    }
+
+    /// A hit on a surface facing ``normal``, at the origin
+    fn hit_data(material: &Arc<dyn Material>, normal: Vec3) -> HitData<'_> {
+        HitData {
+            hit_point: Vec3::ZERO,
+            normal,
+            material,
+            t: 1.0,
+            front_face: true,
+            object_id: 1,
+        }
+    }
+
+    #[test]
+    fn rough_metals_never_reflect_below_the_surface() {
+        let material: Arc<dyn Material> = Arc::new(Metallic::new(Vec3::splat(0.8), 1.0));
+        let data = hit_data(&material, Vec3::Y);
+
+        // Grazing the surface, where most perturbations point below it
+        for incoming in [Vec3::new(1.0, -0.01, 0.0), Vec3::new(-0.3, -1e-4, 1.0)] {
+            let ray = Ray::new(Vec3::new(-1.0, 0.01, 0.0), incoming);
+            for sample_index in 0..1000 {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                let (_, scattered) = material.scatter(&ray, &data).unwrap();
+
+                assert!(
+                    scattered.direction.dot(data.normal) >= 0.0,
+                    "{} goes below the surface",
+                    scattered.direction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mirrors_reflect_exactly_across_the_normal() {
+        let material: Arc<dyn Material> = Arc::new(Mirror::new(Vec3::ONE));
+        let normal = Vec3::new(0.3, 1.0, -0.2).normalize();
+        let data = hit_data(&material, normal);
+
+        let incoming = Vec3::new(1.0, -2.0, 0.5);
+        let (attenuation, reflected) = material
+            .scatter(&Ray::new(Vec3::new(-1.0, 2.0, -0.5), incoming), &data)
+            .unwrap();
+
+        let expected = (incoming - 2.0 * incoming.dot(normal) * normal).normalize();
+        assert!(
+            reflected.direction.normalize().abs_diff_eq(expected, 1e-6),
+            "reflected to {}, expected {expected}",
+            reflected.direction
+        );
+        assert_eq!(reflected.origin, data.hit_point);
+        assert_eq!(attenuation, Vec3::ONE);
+    }
+
+    #[test]
+    fn lambertian_scattering_pdf_integrates_to_1() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        let normal = Vec3::new(0.2, 1.0, 0.4).normalize();
+        let data = hit_data(&material, normal);
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::NEG_Y);
+
+        // Midpoints of a grid of cells of equal area over the sphere of directions
+        let (num_heights, num_angles) = (400, 400);
+        let cell_area = 4.0 * std::f32::consts::PI / (num_heights * num_angles) as f32;
+        let mut integral = 0.0;
+        for height_index in 0..num_heights {
+            let z = -1.0 + 2.0 * (height_index as f32 + 0.5) / num_heights as f32;
+            let radius = (1.0 - z * z).sqrt();
+            for angle_index in 0..num_angles {
+                let phi = std::f32::consts::TAU * (angle_index as f32 + 0.5) / num_angles as f32;
+                let direction = Vec3::new(radius * phi.cos(), radius * phi.sin(), z);
+
+                let scattered = Ray::new(data.hit_point, direction);
+                integral += material.scattering_pdf(&ray, &data, &scattered) * cell_area;
+            }
+        }
+
+        assert!((integral - 1.0).abs() < 1e-3, "{integral}");
+    }
+
+    /// Fraction of the rays coming in at ``angle`` (in degrees, from the normal)
+    /// that reflect off the clear coat of ``material``, rather than its red base
+    fn coat_reflections(material: &Arc<dyn Material>, angle: f32) -> f32 {
+        let data = hit_data(material, Vec3::Y);
+        let angle = angle.to_radians();
+        let ray = Ray::new(Vec3::ZERO, Vec3::new(angle.sin(), -angle.cos(), 0.0));
+
+        let num_samples = 10_000;
+        let num_reflections = (0..num_samples)
+            .filter(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, *sample_index));
+                let (attenuation, _) = material.scatter(&ray, &data).unwrap();
+                attenuation == Color::ONE
+            })
+            .count();
+        num_reflections as f32 / num_samples as f32
+    }
+
+    #[test]
+    fn coats_reflect_at_grazing_angles_and_show_the_base_head_on() {
+        let red = Arc::new(Lambertian::new(Vec3::new(0.8, 0.1, 0.1)));
+        let coated: Arc<dyn Material> = Arc::new(Coated::new(red, 1.5, 0.0));
+
+        let head_on = coat_reflections(&coated, 0.0);
+        assert!((head_on - 0.04).abs() < 0.01, "{head_on}");
+        let grazing = coat_reflections(&coated, 89.0);
+        assert!(grazing > 0.85, "{grazing}");
+    }
+
+    /// Hue of ``color``, in degrees
+    fn hue(color: Color) -> f32 {
+        let (max, min) = (color.max_element(), color.min_element());
+        let chroma = max - min;
+        let hue = if max == color.x {
+            ((color.y - color.z) / chroma).rem_euclid(6.0)
+        } else if max == color.y {
+            (color.z - color.x) / chroma + 2.0
+        } else {
+            (color.x - color.y) / chroma + 4.0
+        };
+        60.0 * hue
+    }
+
+    #[test]
+    fn thicker_films_shift_the_hue_of_their_reflections() {
+        let base: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        let film = |thickness: f32| ThinFilm::new(base.clone(), thickness, 1.33).reflectance(1.0);
+
+        let hues: Vec<f32> = [200.0, 300.0, 350.0]
+            .into_iter()
+            .map(|thickness| hue(film(thickness)))
+            .collect();
+        for (a, b) in [(0, 1), (1, 2), (0, 2)] {
+            let difference = (hues[a] - hues[b]).abs();
+            let difference = difference.min(360.0 - difference);
+            assert!(difference > 45.0, "hues {hues:?}");
+        }
+
+        // Too thin to reflect anything, and too thick for the colors not to wash out
+        assert!(film(0.0).max_element() < 1e-4, "{}", film(0.0));
+        let thick = film(5000.0);
+        assert!(thick.max_element() - thick.min_element() < 0.01, "{thick}");
+    }
+
+    /// Average light coming through the center of a sphere of absorbing glass
+    fn light_through_absorbing_glass(radius: f32) -> Color {
+        let glass = Dielectric::new(1.5).with_absorption(Vec3::new(0.1, 0.4, 0.8));
+        let mut scene = Scene::new();
+        scene.add_hittable(Box::new(Sphere::new(radius, Vec3::ZERO, Arc::new(glass))));
+        let settings = RenderSettings::default();
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::NEG_Z);
+
+        let num_samples = 2000;
+        let total: Color = (0..num_samples)
+            .map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                ray_color(&ray, &scene, &settings)
+            })
+            .sum();
+        total / num_samples as f32
+    }
+
+    #[test]
+    fn longer_paths_through_colored_glass_are_darker_and_more_saturated() {
+        let short = light_through_absorbing_glass(0.5);
+        let long = light_through_absorbing_glass(2.0);
+
+        assert!(
+            long.cmplt(short).all(),
+            "{long} should be darker than {short}"
+        );
+        // The most absorbed channel falls off the fastest
+        let saturation = |color: Color| color.x / color.z;
+        assert!(
+            saturation(long) > 2.0 * saturation(short),
+            "{long} should be more saturated than {short}"
+        );
+
+        // The background (seen along the horizon) dimmed over the diameter, but for the
+        // few percent of the light reflected off the surface instead
+        let expected = Vec3::new(0.75, 0.85, 1.0) * (-Vec3::new(0.1, 0.4, 0.8)).exp();
+        assert!(
+            short.abs_diff_eq(expected, 0.05),
+            "{short}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn larger_mean_free_paths_transport_more_light() {
+        let albedo = Vec3::splat(0.8);
+        let dense = slab_scene(Arc::new(SubsurfaceScattering::new(albedo, 0.02)));
+        let translucent = slab_scene(Arc::new(SubsurfaceScattering::new(albedo, 0.5)));
+
+        let dense_luminance = slab_luminance(&dense);
+        let translucent_luminance = slab_luminance(&translucent);
+        assert!(
+            translucent_luminance > dense_luminance,
+            "{translucent_luminance} should be more than {dense_luminance}"
+        );
+    }
+
+    #[test]
+    fn walks_dont_spend_the_bounces_of_the_path() {
+        // Nothing gets absorbed: all of the light makes it out of the slab, no matter
+        // how many steps the walk takes. The background is at least this bright
+        let scene = slab_scene(Arc::new(SubsurfaceScattering::new(Vec3::ONE, 0.05)));
+
+        let slab_luminance = slab_luminance(&scene);
+        assert!(slab_luminance > 0.6, "{slab_luminance}");
+    }
 }
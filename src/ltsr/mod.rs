@@ -1,79 +1,442 @@
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
-use glam::Vec3;
+use glam::{DVec3, Quat, Vec3};
 use rand::distributions::{Distribution, Uniform};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use smallvec::SmallVec;
 
+pub mod bounds;
+pub mod hash_grid;
+pub mod height_field;
+pub mod instance;
+pub mod lights;
+pub mod material_library;
 pub mod materials;
+pub mod polynomials;
+pub mod settings;
+pub mod sky;
+pub mod spectral;
+pub mod sphere_csv;
+pub mod tessellation;
+pub mod validation;
 
-use materials::Material;
+use bounds::Aabb;
+use hash_grid::{Accelerator, HashGrid};
+use lights::PointLight;
+use materials::{Material, Medium, ScatterRecord, MAX_SUBSURFACE_STEPS};
+use settings::{RenderSettings, SampleCorrelation, WorkingSpace};
+use sky::Background;
+use validation::{SceneWarning, SetupIssue};
 
 type Color = Vec3;
 
-/// Holds information on a raycast hit event
-pub struct HitData {
-    hit_point: Vec3,
-    normal: Vec3,
-    material: Arc<dyn Material>,
-    t: f32,
+/// Holds information on a raycast hit event. The material is borrowed from the
+/// object that was hit: most hits get discarded for a closer one, so it only gets
+/// cloned when something needs to keep it around
+pub struct HitData<'a> {
+    pub hit_point: Vec3,
+    pub normal: Vec3,
+    pub material: &'a Arc<dyn Material>,
+    pub t: f32,
+    /// Whether the ray hit the surface from the outside
+    pub front_face: bool,
+    /// Identifies the object that was hit. Filled in by the Scene with the
+    /// index of the object plus 1, so that 0 is left for 'nothing'
+    pub object_id: u32,
+}
+
+/// The geometric part of a ``HitData``, without the material
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceHit {
+    pub hit_point: Vec3,
+    pub normal: Vec3,
+    pub t: f32,
+    /// Whether the ray hit the surface from the outside
+    pub front_face: bool,
+}
+
+impl SurfaceHit {
+    fn with_material(self, material: &Arc<dyn Material>) -> HitData<'_> {
+        HitData {
+            hit_point: self.hit_point,
+            normal: self.normal,
+            material,
+            t: self.t,
+            front_face: self.front_face,
+            object_id: 0,
+        }
+    }
 }
 
 /// Anything that can be hit should implement this trait!
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>>;
+
+    /// Like ``hit``, but only returns the geometry of the hit. Worth overriding
+    /// to skip looking up the material, for queries that don't need it (eg: picking)
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
+        self.hit(ray, t_min, t_max).map(|hit| SurfaceHit {
+            hit_point: hit.hit_point,
+            normal: hit.normal,
+            t: hit.t,
+            front_face: hit.front_face,
+        })
+    }
+
+    /// Whether the ray hits anything at all in [t_min, t_max]. Meant for shadow
+    /// rays, which don't care about which hit is the closest one
+    fn hit_any(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.hit(ray, t_min, t_max).is_some()
+    }
+
+    /// Likely mistakes in how the element (and its material) was set up,
+    /// see ``Scene::validate``
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        Vec::new()
+    }
+
+    /// Box containing all of the surface, for ``HashGrid``. None when it's
+    /// unbounded (or too hard to bound): then every ray has to test it
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
 }
 
 // Scene and Geometry
 // ----------------------------------------------------------------------------
 
+/// Which kinds of rays can see an object, as a bitmask.
+/// eg: ``Visibility::SECONDARY | Visibility::SHADOW`` hides an object from
+/// the camera, while keeping its reflections and the shadows it casts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility(u8);
+
+impl Visibility {
+    pub const NONE: Visibility = Visibility(0);
+    /// Primary rays, shot from the camera
+    pub const CAMERA: Visibility = Visibility(1 << 0);
+    /// Rays scattered by the materials (reflections, refractions and diffuse bounces)
+    pub const SECONDARY: Visibility = Visibility(1 << 1);
+    /// Occlusion queries, via ``Hittable::hit_any``
+    pub const SHADOW: Visibility = Visibility(1 << 2);
+    pub const ALL: Visibility = Visibility(0b111);
+
+    /// Whether all the rays in ``other`` can see the object
+    pub fn contains(&self, other: Visibility) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for Visibility {
+    type Output = Visibility;
+
+    fn bitor(self, other: Visibility) -> Visibility {
+        Visibility(self.0 | other.0)
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::ALL
+    }
+}
+
+#[derive(Default)]
 pub struct Scene {
-    pub elements: Vec<Box<dyn Hittable>>,
+    /// Only changed through the methods of the scene, so that the hash grid stays up to date
+    elements: Vec<Box<dyn Hittable>>,
+    /// Visibility of each element, aligned with ``elements``
+    visibility: Vec<Visibility>,
+    /// What the rays that don't hit any element see
+    pub background: Background,
+    /// Lights shining on the elements, on top of the background
+    pub lights: Vec<PointLight>,
+    /// How rays find the elements they hit, see ``set_accelerator``
+    accelerator: Accelerator,
+    /// Built by the first ray with ``Accelerator::HashGrid``, and dropped
+    /// whenever elements are added, replaced or removed
+    hash_grid: OnceLock<HashGrid>,
 }
 
 impl Scene {
     pub fn new() -> Self {
-        Scene { elements: vec![] }
+        Scene {
+            elements: vec![],
+            visibility: vec![],
+            background: Background::default(),
+            lights: vec![],
+            accelerator: Accelerator::default(),
+            hash_grid: OnceLock::new(),
+        }
+    }
+
+    /// Picks how rays find the elements they hit. The hash grid is built on the
+    /// first ray, and again after elements get added, replaced or removed
+    pub fn set_accelerator(&mut self, accelerator: Accelerator) {
+        self.accelerator = accelerator;
+        self.hash_grid = OnceLock::new();
+    }
+
+    pub fn accelerator(&self) -> Accelerator {
+        self.accelerator
+    }
+
+    /// The hash grid to find the elements with, if the scene uses one
+    fn hash_grid(&self) -> Option<&HashGrid> {
+        if self.accelerator != Accelerator::HashGrid {
+            return None;
+        }
+
+        Some(self.hash_grid.get_or_init(|| HashGrid::new(&self.elements)))
+    }
+
+    pub fn add_light(&mut self, light: PointLight) {
+        self.lights.push(light);
     }
 
-    pub fn add_hittable(self: &mut Self, hittable: Box<dyn Hittable>) {
+    pub fn add_hittable(&mut self, hittable: Box<dyn Hittable>) {
+        self.add_hittable_with_visibility(hittable, Visibility::ALL);
+    }
+
+    /// Adds an element that can only be seen by some kinds of rays
+    pub fn add_hittable_with_visibility(
+        &mut self,
+        hittable: Box<dyn Hittable>,
+        visibility: Visibility,
+    ) {
         self.elements.push(hittable);
+        self.visibility.push(visibility);
+        self.hash_grid = OnceLock::new();
+    }
+
+    /// Removes the element at ``index``, shifting all the following ones.
+    /// Returns None if ``index`` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Hittable>> {
+        if index < self.elements.len() {
+            self.visibility.remove(index);
+            self.hash_grid = OnceLock::new();
+            Some(self.elements.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Swaps the element at ``index`` for ``hittable``, keeping its visibility.
+    /// Returns the previous element, or None (and drops ``hittable``) if ``index``
+    /// is out of bounds.
+    pub fn replace(
+        &mut self,
+        index: usize,
+        hittable: Box<dyn Hittable>,
+    ) -> Option<Box<dyn Hittable>> {
+        let element = self.elements.get_mut(index)?;
+        self.hash_grid = OnceLock::new();
+        Some(std::mem::replace(element, hittable))
+    }
+
+    /// Removes all the elements and the lights from the scene
+    pub fn clear(&mut self) {
+        self.elements.clear();
+        self.visibility.clear();
+        self.lights.clear();
+        self.hash_grid = OnceLock::new();
+    }
+
+    /// Looks for the likely mistakes in the setup of the scene (eg: NaN positions, or
+    /// materials with out of range parameters) that tend to give black or empty renders
+    pub fn validate(&self) -> Result<(), Vec<SceneWarning>> {
+        let mut warnings = Vec::new();
+        if self.elements.is_empty() {
+            warnings.push(SceneWarning::Empty);
+        }
+
+        for (index, element) in self.elements.iter().enumerate() {
+            warnings.extend(
+                element
+                    .setup_issues()
+                    .into_iter()
+                    .map(|issue| SceneWarning::Element { index, issue }),
+            );
+        }
+        for (index, light) in self.lights.iter().enumerate() {
+            warnings.extend(
+                light
+                    .setup_issues()
+                    .into_iter()
+                    .map(|issue| SceneWarning::Light { index, issue }),
+            );
+        }
+
+        match warnings.is_empty() {
+            true => Ok(()),
+            false => Err(warnings),
+        }
+    }
+
+    pub fn visibility(&self, index: usize) -> Visibility {
+        self.visibility.get(index).copied().unwrap_or_default()
+    }
+
+    /// Like ``Hittable::hit``, but only considers the elements visible to ``ray_kind``
+    pub fn hit_visible(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        ray_kind: Visibility,
+    ) -> Option<HitData<'_>> {
+        self.closest_hit(ray, t_min, t_max, ray_kind, |index, closest_so_far| {
+            let mut hit_data = self.elements[index].hit(ray, t_min, closest_so_far)?;
+            hit_data.object_id = index as u32 + 1;
+            Some((hit_data.t, hit_data))
+        })
+    }
+
+    /// Closest of the hits found by ``hit_element`` on the elements visible to
+    /// ``ray_kind``. It's given the index of an element and the ``t`` of the closest
+    /// hit so far, and returns the ``t`` of its hit along with the hit itself
+    fn closest_hit<T>(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        ray_kind: Visibility,
+        mut hit_element: impl FnMut(usize, f32) -> Option<(f32, T)>,
+    ) -> Option<T> {
+        let mut closest_hit: Option<(f32, T)> = None;
+
+        let mut try_element = |index: usize, closest_hit: &mut Option<(f32, T)>| {
+            if !self.visibility(index).contains(ray_kind) {
+                return;
+            }
+
+            // We have a hit! Otherwise there's nothing to do,
+            // since the ray didn't hit anything
+            let closest_so_far = closest_hit.as_ref().map_or(t_max, |(t, _)| *t);
+            if let Some(hit) = hit_element(index, closest_so_far) {
+                *closest_hit = Some(hit);
+            }
+        };
+
+        match self.hash_grid() {
+            Some(grid) => grid.march(ray, t_min, t_max, |indices, cell_exit| {
+                for index in indices {
+                    try_element(*index as usize, &mut closest_hit);
+                }
+
+                // What's left can only be hit in the following cells, further away
+                closest_hit.as_ref().is_some_and(|(t, _)| *t <= cell_exit)
+            }),
+            // Iterate through all the elements in the scene
+            None => {
+                for index in 0..self.elements.len() {
+                    try_element(index, &mut closest_hit);
+                }
+            }
+        }
+
+        closest_hit.map(|(_, hit)| hit)
+    }
+
+    /// Closest element seen by the camera along ``ray``, for click-to-select.
+    /// Only looks at the geometry, so no materials get looked up
+    pub fn pick(&self, ray: &Ray) -> Option<PickResult> {
+        self.closest_hit(
+            ray,
+            0.001,
+            f32::INFINITY,
+            Visibility::CAMERA,
+            |index, closest_so_far| {
+                let surface = self.elements[index].hit_surface(ray, 0.001, closest_so_far)?;
+                let pick = PickResult {
+                    index,
+                    hit_point: surface.hit_point,
+                    normal: surface.normal,
+                    distance: surface.t * ray.direction.length(),
+                };
+                Some((surface.t, pick))
+            },
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&dyn Hittable> {
+        self.elements.get(index).map(|element| element.as_ref())
     }
 }
 
+/// What ``Scene::pick`` found under a ray
+#[derive(Debug, Clone, Copy)]
+pub struct PickResult {
+    /// Index of the element in the scene
+    pub index: usize,
+    pub hit_point: Vec3,
+    pub normal: Vec3,
+    /// Distance from the origin of the ray (which doesn't need to be normalized)
+    pub distance: f32,
+}
+
 impl Hittable for Scene {
-    fn hit(self: &Self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData> {
-        let mut closest_hit: Option<HitData> = None;
-        let mut closest_so_far = t_max;
-
-        // Iterate through all the elements in the scene
-        for element in self.elements.iter() {
-            match element.hit(ray, t_min, closest_so_far) {
-                // We have a hit!
-                Some(hit_data) => {
-                    closest_so_far = hit_data.t;
-                    closest_hit = Some(hit_data);
-                }
-                // Nothing to do, since the ray didn't hit anything
-                None => {}
+    /// Closest hit against all the elements, whatever their visibility
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        self.hit_visible(ray, t_min, t_max, Visibility::NONE)
+    }
+
+    /// Only the elements visible to ``Visibility::SHADOW`` can occlude
+    fn hit_any(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let occludes = |index: usize| {
+            self.visibility(index).contains(Visibility::SHADOW)
+                && self.elements[index].hit_any(ray, t_min, t_max)
+        };
+
+        match self.hash_grid() {
+            Some(grid) => {
+                let mut occluded = false;
+                grid.march(ray, t_min, t_max, |indices, _| {
+                    occluded = indices.iter().any(|index| occludes(*index as usize));
+                    occluded
+                });
+                occluded
             }
+            None => (0..self.elements.len()).any(occludes),
         }
+    }
 
-        closest_hit
+    /// Bounds of all the elements, None if any of them is unbounded
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.elements
+            .iter()
+            .try_fold(Aabb::EMPTY, |bounds, element| {
+                Some(bounds.union(&element.bounding_box()?))
+            })
     }
 }
 
 fn get_face_normal(ray: &Ray, outward_normal: Vec3) -> (Vec3, bool) {
     let is_front_face = ray.direction.dot(outward_normal) < 0.0;
 
-    let normal;
-    if is_front_face {
-        normal = outward_normal;
+    let normal = if is_front_face {
+        outward_normal
     } else {
-        normal = (-1.0) * outward_normal;
-    }
+        (-1.0) * outward_normal
+    };
 
     (normal, is_front_face)
 }
 
+/// A sphere with a negative radius has the same surface as its positive
+/// counterpart, but its normals point inwards: nesting one inside a glass
+/// sphere is the classic trick to render hollow glass
 pub struct Sphere {
     pub radius: f32,
     pub center: Vec3,
@@ -91,17 +454,32 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(self: &Self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        let surface = self.hit_surface(ray, t_min, t_max)?;
+        Some(surface.with_material(&self.material))
+    }
+
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
         // The quadratic polynomial ax^2 + bx + c has discriminant b^2 - 4ac
         // See https://en.wikipedia.org/wiki/Discriminant
 
+        // Degenerate sphere, and we'd divide by zero when computing the normal
+        if self.radius == 0.0 {
+            return None;
+        }
+
         let center_to_origin = ray.origin - self.center;
 
         let a = ray.direction.length_squared();
         let half_b = center_to_origin.dot(ray.direction);
         let c = center_to_origin.length_squared() - self.radius.powi(2);
 
-        let discriminant = half_b.powi(2) - (a * c);
+        // b^2 - 4ac cancels out catastrophically when the ray starts far from a large
+        // sphere (eg: the ground at the horizon). Instead, it's computed from the
+        // distance between the center and the line of the ray, which is the same
+        // discriminant once expanded (see 'Ray Tracing Gems', chapter 7)
+        let center_to_line = center_to_origin - (half_b / a) * ray.direction;
+        let discriminant = a * (self.radius.powi(2) - center_to_line.length_squared());
 
         // No hit!
         if discriminant < 0.0 {
@@ -111,199 +489,1906 @@ impl Hittable for Sphere {
         let discriminant_squared = discriminant.sqrt();
 
         // Quadratic formula: -b ± sqrt(b^2 - 4ac)
-        // The above^ can have 2 solutions (because of the ±)
-        // the first_solution is (-half_b + discriminant_squared) / a
-        // the second_solution is (-half_b - discriminant_squared) / a
-        // The smallest solution will be the closest to the ray origin
-
-        // Find the nearest 't' that lies in the acceptable range ([t_min, t_max])
-        let mut t = (-half_b - discriminant_squared) / a;
-
-        if t < t_min || t_max < t {
-            t = (-half_b + discriminant_squared) / a;
-            if t < t_min || t_max < t {
-                return None;
-            }
-        }
+        // The above^ can have 2 solutions (because of the ±), but subtracting two close
+        // numbers loses precision as well: only the solution adding numbers of the same
+        // sign is computed as such, and the other one follows from their product being c/a
+        let q = -(half_b + half_b.signum() * discriminant_squared);
+        let (t0, t1) = (c / q, q / a);
+
+        // Find the nearest 't' that lies in the acceptable range ([t_min, t_max]).
+        // Also rejects the NaNs of rays starting on the sphere, tangent to it (q == 0)
+        let t = [t0.min(t1), t0.max(t1)]
+            .into_iter()
+            .find(|t| (t_min..=t_max).contains(t))?;
 
         let hit_point = ray.point_at_parameter(t);
+
+        // Dividing by the signed radius flips the normals of negative radius
+        // spheres inwards, so rays entering them are treated as leaving a medium
         let outward_normal = (hit_point - self.center) / self.radius;
 
-        let (normal, is_front_face) = get_face_normal(ray, outward_normal);
+        let (normal, front_face) = get_face_normal(ray, outward_normal);
+
+        Some(SurfaceHit {
+            t,
+            hit_point,
+            normal,
+            front_face,
+        })
+    }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let mut issues: Vec<SetupIssue> = [
+            SetupIssue::check_finite(&[self.center.x, self.center.y, self.center.z, self.radius]),
+            (self.radius == 0.0).then_some(SetupIssue::ZeroRadius),
+            // Fine for hollow glass, see ``Sphere``
+            (self.radius < 0.0 && self.material.medium().is_none())
+                .then_some(SetupIssue::NegativeRadius),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        issues.extend(self.material.setup_issues());
+        issues
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let radius = Vec3::splat(self.radius.abs());
+        Some(Aabb::new(self.center - radius, self.center + radius))
+    }
+}
+
+/// A donut shaped surface, swept by a circle of radius ``minor_radius`` whose
+/// center goes around ``axis`` at a distance ``major_radius`` from ``center``
+pub struct Torus {
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub center: Vec3,
+    /// Normalized direction the hole of the torus is facing
+    pub axis: Vec3,
+    pub material: Arc<dyn Material>,
+    /// Rotates world space directions so that ``axis`` becomes +Y
+    to_local: Quat,
+}
+
+impl Torus {
+    pub fn new(
+        major_radius: f32,
+        minor_radius: f32,
+        center: Vec3,
+        axis: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let axis = axis.normalize();
+
+        Torus {
+            major_radius,
+            minor_radius,
+            center,
+            axis,
+            material,
+            to_local: Quat::from_rotation_arc(axis, Vec3::Y),
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitData<'_>> {
+        let surface = self.hit_surface(ray, t_min, t_max)?;
+        Some(surface.with_material(&self.material))
+    }
+
+    fn hit_surface(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<SurfaceHit> {
+        let direction_length = ray.direction.length();
+        if direction_length == 0.0 {
+            return None;
+        }
+
+        // Early out: the whole torus fits in a sphere of radius R + r
+        let bounding_radius = self.major_radius + self.minor_radius;
+        let center_to_origin = ray.origin - self.center;
+        let half_b = center_to_origin.dot(ray.direction);
+        let c = center_to_origin.length_squared() - bounding_radius.powi(2);
+        if half_b.powi(2) - ray.direction.length_squared() * c < 0.0 {
+            return None;
+        }
+
+        // Work in the local space of the torus (centered at the origin, around +Y)
+        // with a normalized direction, so the quartic is as well conditioned as possible
+        let origin = (self.to_local * center_to_origin).as_dvec3();
+        let direction = (self.to_local * (ray.direction / direction_length)).as_dvec3();
+
+        let major_squared = (self.major_radius as f64).powi(2);
+        let minor_squared = (self.minor_radius as f64).powi(2);
+
+        // Plugging the ray in the implicit equation of the torus:
+        // (|p|^2 + R^2 - r^2)^2 - 4R^2 (p.x^2 + p.z^2) = 0
+        // gives a quartic in t, since |direction| = 1
+        let n = origin.dot(direction);
+        let k = origin.length_squared() + major_squared - minor_squared;
+
+        let a3 = 4.0 * n;
+        let a2 = 4.0 * n * n + 2.0 * k
+            - 4.0 * major_squared * (direction.x.powi(2) + direction.z.powi(2));
+        let a1 =
+            4.0 * n * k - 8.0 * major_squared * (origin.x * direction.x + origin.z * direction.z);
+        let a0 = k * k - 4.0 * major_squared * (origin.x.powi(2) + origin.z.powi(2));
 
-        Some(HitData {
+        // Back to the parametrization of the original ray
+        let local_t_min = (t_min * direction_length) as f64;
+        let local_t_max = (t_max * direction_length) as f64;
+
+        // The solver can return spurious roots (eg: for rays grazing the surface):
+        // only keep the ones that, once plugged back, land close enough to the surface
+        let tolerance = 1e-3 * (self.minor_radius as f64).max(1e-3);
+        let local_t = polynomials::solve_quartic(a3, a2, a1, a0)
+            .into_iter()
+            .filter(|t| t.is_finite() && local_t_min <= *t && *t <= local_t_max)
+            .filter(|t| {
+                let p = origin + *t * direction;
+                let distance_from_ring = (p.x.hypot(p.z) - self.major_radius as f64).hypot(p.y);
+                (distance_from_ring - self.minor_radius as f64).abs() < tolerance
+            })
+            .min_by(|a, b| a.total_cmp(b))?;
+
+        let t = (local_t / direction_length as f64) as f32;
+        let hit_point = ray.point_at_parameter(t);
+
+        // The normal points away from the closest point on the ring that sweeps the tube
+        let local_point = self.to_local * (hit_point - self.center);
+        let ring_direction = Vec3::new(local_point.x, 0.0, local_point.z).normalize_or_zero();
+        let local_normal = (local_point - self.major_radius * ring_direction).normalize();
+        let outward_normal = self.to_local.inverse() * local_normal;
+
+        let (normal, front_face) = get_face_normal(ray, outward_normal);
+
+        Some(SurfaceHit {
             t,
             hit_point,
             normal,
-            material: self.material.clone(),
+            front_face,
         })
     }
+
+    fn setup_issues(&self) -> Vec<SetupIssue> {
+        let mut issues: Vec<SetupIssue> = [
+            SetupIssue::check_finite(&[
+                self.center.x,
+                self.center.y,
+                self.center.z,
+                self.major_radius,
+                self.minor_radius,
+            ]),
+            SetupIssue::check_finite(&self.axis.to_array()),
+            (self.minor_radius == 0.0).then_some(SetupIssue::ZeroRadius),
+            SetupIssue::check_not_negative("radius", &[self.major_radius, self.minor_radius]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        issues.extend(self.material.setup_issues());
+        issues
+    }
+
+    /// The ring sweeping the tube spans ``major_radius * sin(angle to the axis)``
+    /// along each world axis, the tube adds ``minor_radius`` all around
+    fn bounding_box(&self) -> Option<Aabb> {
+        let ring = self.major_radius
+            * (Vec3::ONE - self.axis * self.axis)
+                .max(Vec3::ZERO)
+                .powf(0.5);
+        let extent = ring + Vec3::splat(self.minor_radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// When the ray was traced, in [0, 1] over the shutter (see ``RenderSettings::motion_blur``).
+    /// Rays scattered or shadow rays keep the time of the ray they come from
+    pub time: f32,
+    /// The single wavelength (in nanometers) carried by the ray in spectral renders
+    /// (see ``RenderSettings::spectral``), None when it carries RGB light
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
-    fn new(origin: Vec3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+    /// A ray at the time the shutter opens
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
+    }
+
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Self {
+        Ray { wavelength, ..self }
+    }
+
+    /// Carries over the time and the wavelength of ``parent``, for the rays continuing its path
+    pub fn following(self, parent: &Ray) -> Self {
+        Ray {
+            time: parent.time,
+            wavelength: parent.wavelength,
+            ..self
+        }
     }
 
-    fn point_at_parameter(self: &Self, t: f32) -> Vec3 {
+    pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.origin + t * self.direction
     }
+
+    /// Whether the ray can't be traced: it has no direction,
+    /// or NaNs/infinities crept into it
+    pub fn is_degenerate(&self) -> bool {
+        !self.origin.is_finite()
+            || !self.direction.is_finite()
+            || self.direction.length_squared() == 0.0
+    }
 }
 
 pub struct Camera {
     position: Vec3,
     right: Vec3,
     up: Vec3,
-    back: Vec3,
-    focal_length: f32,
     lower_left_corner: Vec3,
+    focal_length: f32,
+    /// Radius of the thin lens, 0 for a pinhole camera where everything is sharp
+    lens_radius: f32,
+    /// Distance from the camera to the plane in focus, along the view direction
+    focus_distance: f32,
 }
 
 impl Camera {
+    /// A camera sitting at the origin and looking down -Z
     pub fn new(focal_length: f32, viewport_width: f32, viewport_height: f32) -> Self {
-        let origin = Vec3::new(0.0, 0.0, 0.0);
+        Camera::look_at(
+            Vec3::ZERO,
+            Vec3::NEG_Z,
+            Vec3::Y,
+            focal_length,
+            viewport_width,
+            viewport_height,
+        )
+    }
 
-        let right = Vec3::new(viewport_width, 0.0, 0.0);
-        let up = Vec3::new(0.0, viewport_height, 0.0);
-        let back = Vec3::new(0.0, 0.0, focal_length);
+    /// A camera sitting at ``position`` and looking towards ``target``,
+    /// rolled so that ``world_up`` points upwards in the image
+    pub fn look_at(
+        position: Vec3,
+        target: Vec3,
+        world_up: Vec3,
+        focal_length: f32,
+        viewport_width: f32,
+        viewport_height: f32,
+    ) -> Self {
+        // Orthonormal basis of the camera, looking down its -back axis
+        let back_axis = (position - target).normalize();
+        let right_axis = world_up.cross(back_axis).normalize();
+        let up_axis = back_axis.cross(right_axis);
 
-        let lower_left_corner = origin - (right / 2.0) - (up / 2.0) - back;
+        let right = viewport_width * right_axis;
+        let up = viewport_height * up_axis;
+        let back = focal_length * back_axis;
+
+        let lower_left_corner = position - (right / 2.0) - (up / 2.0) - back;
         eprintln!("Camera lower left corner: {lower_left_corner}");
 
         Camera {
-            position: origin,
+            position,
             right,
             up,
-            back,
             lower_left_corner,
             focal_length,
+            lens_radius: 0.0,
+            focus_distance: focal_length,
+        }
+    }
+
+    /// The camera with a thin lens of the given ``aperture`` (diameter), for depth
+    /// of field: only what's ``focus_distance`` away stays sharp
+    pub fn with_lens(self, aperture: f32, focus_distance: f32) -> Self {
+        Camera {
+            lens_radius: aperture.max(0.0) / 2.0,
+            focus_distance,
+            ..self
         }
     }
 
-    pub fn get_ray_at_coords(self: &Self, u: f32, v: f32) -> Ray {
+    pub fn has_lens(&self) -> bool {
+        self.lens_radius > 0.0
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    /// Angle between the bottom and the top of the image, in degrees
+    pub fn vertical_field_of_view(&self) -> f32 {
+        let half_height = self.up.length() / 2.0;
+        2.0 * half_height.atan2(self.focal_length).to_degrees()
+    }
+
+    pub fn get_ray_at_coords(&self, u: f32, v: f32) -> Ray {
         let camera_direction =
             self.lower_left_corner + u * self.right + v * self.up - self.position;
 
         Ray::new(self.position, camera_direction)
     }
+
+    /// Like ``get_ray_at_coords``, through the point of the lens at ``lens_sample``
+    /// (in [0, 1) x [0, 1), spread uniformly over the aperture). The rays through
+    /// the same u,v all meet on the plane in focus: what's off of it gets blurred
+    pub fn get_ray_through_lens(&self, u: f32, v: f32, lens_sample: (f32, f32)) -> Ray {
+        let pinhole_ray = self.get_ray_at_coords(u, v);
+        if !self.has_lens() {
+            return pinhole_ray;
+        }
+
+        // The pinhole ray ends on the image plane, at the focal length along the view
+        let focus_point =
+            self.position + pinhole_ray.direction * (self.focus_distance / self.focal_length);
+
+        let (radius_sample, angle_sample) = lens_sample;
+        let radius = self.lens_radius * radius_sample.sqrt();
+        let angle = std::f32::consts::TAU * angle_sample;
+        let origin = self.position
+            + radius * (angle.cos() * self.right.normalize() + angle.sin() * self.up.normalize());
+
+        Ray::new(origin, focus_point - origin)
+    }
+
+    /// Distance from the camera to whatever is visible through the center of a pixel,
+    /// counted from the top left corner of an image of the given size, along the view
+    /// direction like the focus distance of ``with_lens``. Useful to pick the focus
+    /// distance by clicking on the image.
+    /// Returns None when the pixel only sees the background.
+    pub fn focus_distance_at_pixel(
+        &self,
+        scene: &Scene,
+        x: usize,
+        y: usize,
+        image_width: usize,
+        image_height: usize,
+    ) -> Option<f32> {
+        // Rows are rendered from the bottom up, so flip them
+        let u = (x as f32 + 0.5) / image_width as f32;
+        let v = (image_height as f32 - y as f32 - 0.5) / image_height as f32;
+
+        let ray = self.get_ray_at_coords(u, v);
+        let hit = scene.hit_visible(&ray, 0.001, f32::INFINITY, Visibility::CAMERA)?;
+
+        // Camera rays go as far along the view direction as the focal length
+        Some(hit.t * self.focal_length)
+    }
 }
 
 /// Given a Ray and a Scene of objects, return the color
 /// resulting with the Ray intersecting the Scene
-pub fn ray_color(ray: &Ray, scene: &Scene, max_depth: i32) -> Color {
-    // Offset the min a bit from 0 to avoid self-intersections caused
-    // by rounding floating point conversions
-    let t_min = 0.001;
-    let t_max = f32::INFINITY;
+pub fn ray_color(ray: &Ray, scene: &Scene, settings: &RenderSettings) -> Color {
+    ray_lighting(ray, scene, settings).total()
+}
+
+/// Like ``ray_color``, but keeps the direct and the indirect lighting apart
+pub fn ray_lighting(ray: &Ray, scene: &Scene, settings: &RenderSettings) -> Lighting {
+    let state = PathState::new(settings);
+
+    let media = MediumStack::default();
+    let lighting = trace_ray(
+        ray,
+        scene,
+        settings,
+        state,
+        Visibility::CAMERA,
+        &media,
+        None,
+    );
+    let lighting = clamp_fireflies(lighting, settings, state);
 
-    // We've exceeded the maximum amount of bounces
-    // for the current object: return a black shadow!
-    if max_depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+    // A single NaN or infinite sample would poison the whole pixel: drop it instead
+    if lighting.total().is_finite() {
+        lighting
+    } else {
+        Lighting::default()
     }
+}
 
-    match scene.hit(ray, t_min, t_max) {
-        Some(object) => {
-            let new_max_depth = match max_depth.checked_sub(1) {
-                Some(n) => n,
-                None => {
-                    panic!("Integer underflow occurred!");
-                }
-            };
-
-            // Scatter the ray in the material of the object hit
-            let scatter_result = object.material.scatter(&ray, &object);
-            match scatter_result {
-                Some((color, new_ray)) => {
-                    // Shoot more rays to simulate ray bounces after scattering
-                    // in the current material
-                    let mut pixel_color = color.clone();
-                    pixel_color *= ray_color(&new_ray, &scene, new_max_depth);
-
-                    return pixel_color.clone();
-                }
-                None => {
-                    // No more scattering, the ray was absorbed: turn it darker
-                    return Color::new(0.0, 0.0, 0.0);
-                }
+/// Like ``ray_lighting``, but records every straight segment of the path traced
+/// from ``ray``, in order, eg: to see how light reaches a single pixel.
+/// The random numbers come from the sampler of the current thread, so seed it
+/// like the renderer does to follow the path of a given sample
+pub fn ray_path(ray: &Ray, scene: &Scene, settings: &RenderSettings) -> Vec<RayPathSegment> {
+    let state = PathState::new(settings);
+
+    let mut path = Vec::new();
+    let media = MediumStack::default();
+    trace_ray(
+        ray,
+        scene,
+        settings,
+        state,
+        Visibility::CAMERA,
+        &media,
+        Some(&mut path),
+    );
+
+    path
+}
+
+/// A straight stretch of a path, from a ray to whatever it hit, see ``ray_path``
+#[derive(Debug, Clone)]
+pub struct RayPathSegment {
+    pub origin: Vec3,
+    /// Direction of the ray, not normalized
+    pub direction: Vec3,
+    /// What the ray hit, None if it went off into the background
+    pub hit: Option<PathVertex>,
+    /// What happened to the path at the end of the segment
+    pub event: PathEvent,
+}
+
+/// The surface at the end of a ``RayPathSegment``
+#[derive(Debug, Clone)]
+pub struct PathVertex {
+    pub hit_point: Vec3,
+    /// Facing the incoming ray
+    pub normal: Vec3,
+    /// See ``HitData::object_id``
+    pub object_id: u32,
+    /// The material used for shading, after the material override
+    pub material: Arc<dyn Material>,
+    /// What the light coming back along the next segment gets multiplied by
+    /// (zero when the path ends here)
+    pub attenuation: Color,
+}
+
+/// How a path carries on at the end of a ``RayPathSegment``
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathEvent {
+    /// Scattered back to the side the ray came from
+    Reflected { specular: bool },
+    /// Scattered through the surface, into or out of its medium
+    Transmitted { specular: bool },
+    /// Went straight through a surface hidden by a higher priority medium
+    PassedThrough,
+    /// The material absorbed the ray
+    Absorbed,
+    /// The path had no bounces left for the material that was hit
+    OutOfBounces,
+    /// Nothing was hit: the path picks up the background
+    Escaped,
+}
+
+/// The light carried by a ray, split by how many times it scattered on its way.
+/// Direct lighting is the background seen by the camera, and the light of the
+/// lights or of the background reaching the first surface hit. Indirect lighting
+/// is everything that scattered more than once. Together they make the beauty
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Lighting {
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl Lighting {
+    /// Light from the background (or the lights), reaching a path that scattered
+    /// ``depth`` times
+    fn from_background(color: Color, depth: i32) -> Self {
+        if depth <= 1 {
+            Lighting {
+                direct: color,
+                indirect: Color::ZERO,
+            }
+        } else {
+            Lighting {
+                direct: Color::ZERO,
+                indirect: color,
             }
         }
-        None => {}
     }
 
-    // If we got here, it means that our ray didn't hit anything
-    // Let's draw our background!
-    get_background_color(ray)
+    pub fn total(&self) -> Color {
+        self.direct + self.indirect
+    }
 }
 
-fn get_background_color(ray: &Ray) -> Color {
-    let unit_direction = ray.direction.normalize();
+impl std::ops::Add for Lighting {
+    type Output = Lighting;
 
-    // Two colors of our background
-    let white = Vec3::new(1.0, 1.0, 1.0);
-    let blue = Vec3::new(0.5, 0.7, 1.0);
-
-    // Perform the lerp:
-    let t = 0.5 * (unit_direction.y + 1.0);
-    let color = white.lerp(blue, t);
-
-    Color::new(color.x, color.y, color.z)
+    fn add(self, other: Lighting) -> Lighting {
+        Lighting {
+            direct: self.direct + other.direct,
+            indirect: self.indirect + other.indirect,
+        }
+    }
 }
 
-// Utility functions
-// ----------------------------------------------------------------------------
+impl std::ops::Mul<Color> for Lighting {
+    type Output = Lighting;
 
-/// Linear remap a value in one range into another range (no clamping)
-pub fn fit_range(x: f32, imin: f32, imax: f32, omin: f32, omax: f32) -> f32 {
-    (omax - omin) * (x - imin) / (imax - imin) + omin
+    fn mul(self, attenuation: Color) -> Lighting {
+        Lighting {
+            direct: self.direct * attenuation,
+            indirect: self.indirect * attenuation,
+        }
+    }
 }
 
-/// Generate a random point in a unit sphere
-fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
+/// The (dielectric) media a path is inside of, with the objects they fill.
+/// Lets rays refract with the right indices of refraction where media touch,
+/// eg: glass in a liquid, instead of assuming the air is always on the other side
+#[derive(Debug, Clone, Default)]
+pub struct MediumStack(SmallVec<[(u32, Medium); 4]>);
 
-    // TODO: Investigate using a lazy_static for this
-    let range = Uniform::from(-1.0..1.0);
+impl MediumStack {
+    /// The medium the path is travelling through: the one with the highest priority
+    /// (the most recently entered between equals), or the air
+    pub fn current(&self) -> Medium {
+        self.0
+            .iter()
+            .rev()
+            .map(|(_, medium)| *medium)
+            .reduce(|current, medium| {
+                if medium.priority > current.priority {
+                    medium
+                } else {
+                    current
+                }
+            })
+            .unwrap_or(Medium::AIR)
+    }
 
-    loop {
-        let x: f32 = range.sample(&mut rng);
-        let y: f32 = range.sample(&mut rng);
-        let z: f32 = range.sample(&mut rng);
+    /// Indices of refraction (incident, transmitted) on both sides of the surface of
+    /// ``object_id``, filled with ``medium``, at the ``wavelength`` of the ray (if any).
+    /// None if the surface is hidden, because it lies inside a medium with a higher priority
+    pub fn boundary(
+        &self,
+        object_id: u32,
+        medium: Medium,
+        front_face: bool,
+        wavelength: Option<f32>,
+    ) -> Option<(f32, f32)> {
+        let current = self.current();
 
-        let p = Vec3::new(x, y, z);
+        match self.position(object_id) {
+            // Leaving the object
+            Some(index) => {
+                let mut outside = self.clone();
+                outside.0.remove(index);
 
-        if p.length_squared() < 1.0 {
-            return p;
+                let inside = self.0[index].1;
+                let outside = outside.current();
+                if outside.priority > inside.priority {
+                    return None;
+                }
+                Some((current.ior_at(wavelength), outside.ior_at(wavelength)))
+            }
+            // Entering it
+            None => {
+                let inside = Self::entered_medium(medium, front_face);
+                if inside.priority < current.priority {
+                    return None;
+                }
+                Some((current.ior_at(wavelength), inside.ior_at(wavelength)))
+            }
         }
     }
-}
 
-/// Generate a random point in a unit sphere
-/// but in the same hemisphere as a Normal vector
-fn random_in_hemisphere(normal: Vec3) -> Vec3 {
-    let in_unit_sphere = random_in_unit_sphere();
+    /// Enters or exits the medium of ``object_id``, for rays going through its surface
+    pub fn cross(&mut self, object_id: u32, medium: Medium, front_face: bool) {
+        match self.position(object_id) {
+            Some(index) => {
+                self.0.remove(index);
+            }
+            None => self
+                .0
+                .push((object_id, Self::entered_medium(medium, front_face))),
+        }
+    }
 
-    if Vec3::dot(in_unit_sphere, normal) > 0.0 {
-        return in_unit_sphere;
-    } else {
-        return -in_unit_sphere;
+    fn position(&self, object_id: u32) -> Option<usize> {
+        self.0.iter().rposition(|(id, _)| *id == object_id)
+    }
+
+    /// Going through the back of a surface the path never entered means going into
+    /// the air, eg: hollow glass made by nesting a negative radius sphere in a
+    /// positive one. Otherwise, the ray goes into the medium of the object
+    fn entered_medium(medium: Medium, front_face: bool) -> Medium {
+        if front_face {
+            medium
+        } else {
+            Medium {
+                priority: medium.priority,
+                ..Medium::AIR
+            }
+        }
     }
 }
 
-/// Returns whether or not the given ``vec`` is close enough to zero
-/// on this machine (uses f32::EPSILON as the 'clone enough' measure).
-fn near_zero(vec: &Vec3) -> bool {
-    vec.x.abs() <= f32::EPSILON && vec.y.abs() <= f32::EPSILON.abs() && vec.z <= f32::EPSILON
+/// Where a path is at: how many more times it can scatter off each kind of
+/// material, and how much of the light found further along reaches the camera
+#[derive(Debug, Clone, Copy)]
+struct PathState {
+    diffuse: i32,
+    specular: i32,
+    /// Steps left to the random walk the path is taking inside an object, if any
+    subsurface: i32,
+    /// Product of the colors of the scatterings so far
+    throughput: Color,
 }
 
-/// Given a vector ``vec`` and a normalized vector ``normal``,
-/// return back ``vec`` reflected against ``normal``.
+impl PathState {
+    /// A path starting from the camera
+    fn new(settings: &RenderSettings) -> Self {
+        PathState {
+            diffuse: settings.max_diffuse_depth,
+            specular: settings.max_specular_depth,
+            subsurface: MAX_SUBSURFACE_STEPS,
+            throughput: Color::ONE,
+        }
+    }
+}
+
+/// Set the first time a path goes over ``RenderSettings::max_throughput``,
+/// so that it's only logged once
+static THROUGHPUT_CLAMPED: AtomicBool = AtomicBool::new(false);
+
+/// Scales down the channels of ``color`` (the weight of a scattering) that would take
+/// the throughput of the path over ``RenderSettings::max_throughput``, see its docs
+fn clamp_throughput(color: Color, state: PathState, settings: &RenderSettings) -> Color {
+    let Some(max_throughput) = settings.max_throughput else {
+        return color;
+    };
+
+    let throughput = state.throughput * color;
+    if throughput.cmple(Color::splat(max_throughput)).all() {
+        return color;
+    }
+
+    if !THROUGHPUT_CLAMPED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "Warning: a path carried {throughput} times the light it found, clamped to \
+             {max_throughput}. A material likely reflects more light than it receives"
+        );
+    }
+    // Zero throughputs give infinite scales, which the min ignores
+    color * (Color::splat(max_throughput) / throughput).min(Color::ONE)
+}
+
+fn trace_ray(
+    ray: &Ray,
+    scene: &Scene,
+    settings: &RenderSettings,
+    state: PathState,
+    ray_kind: Visibility,
+    media: &MediumStack,
+    mut path: Option<&mut Vec<RayPathSegment>>,
+) -> Lighting {
+    // Nothing to trace (eg: a material scattering into a zero direction):
+    // the path ends here, without carrying any light
+    if ray.is_degenerate() {
+        return Lighting::default();
+    }
+
+    // Offset the min a bit from 0 to avoid self-intersections caused
+    // by rounding floating point conversions
+    let t_min = 0.001;
+    let t_max = f32::INFINITY;
+
+    if let Some(object) = scene.hit_visible(ray, t_min, t_max, ray_kind) {
+        let material = settings.material_for(&object);
+
+        // Records the segment ending on this hit, when following the path
+        let mut record_segment = |event: PathEvent, attenuation: Color| {
+            if let Some(path) = path.as_deref_mut() {
+                path.push(RayPathSegment {
+                    origin: ray.origin,
+                    direction: ray.direction,
+                    hit: Some(PathVertex {
+                        hit_point: object.hit_point,
+                        normal: object.normal,
+                        object_id: object.object_id,
+                        material: material.clone(),
+                        attenuation,
+                    }),
+                    event,
+                });
+            }
+        };
+
+        // Light reaching the ray from the hit gets absorbed by the medium along the way
+        let transmittance = media
+            .current()
+            .transmittance(object.t * ray.direction.length());
+
+        // Indices of refraction on both sides, for surfaces between two media
+        let medium = material.medium();
+        let iors = match medium {
+            Some(medium) => {
+                match media.boundary(object.object_id, medium, object.front_face, ray.wavelength) {
+                    Some(iors) => Some(iors),
+                    None => {
+                        // Hidden by a higher priority medium: carry on as if nothing was
+                        // hit, without using up a bounce
+                        record_segment(PathEvent::PassedThrough, Color::ONE);
+                        let mut new_media = media.clone();
+                        new_media.cross(object.object_id, medium, object.front_face);
+
+                        let new_ray = Ray::new(object.hit_point, ray.direction).following(ray);
+                        return trace_ray(
+                            &new_ray, scene, settings, state, ray_kind, &new_media, path,
+                        ) * transmittance;
+                    }
+                }
+            }
+            None => None,
+        };
+
+        // Diffuse and specular bounces are limited separately, so that
+        // specular chains can go deeper than the (more expensive) diffuse GI.
+        // Entering a subsurface object takes a single bounce for the whole walk
+        // inside of it, whose steps have a budget of their own
+        let mut new_state = state;
+        let walks_inside = material.walks_inside();
+        if walks_inside && object.front_face {
+            new_state.subsurface = MAX_SUBSURFACE_STEPS;
+        }
+        let remaining = if walks_inside && !object.front_face {
+            &mut new_state.subsurface
+        } else if material.is_specular() {
+            &mut new_state.specular
+        } else {
+            &mut new_state.diffuse
+        };
+
+        // We've exceeded the maximum amount of bounces
+        // for the current object: return a black shadow!
+        if *remaining <= 0 {
+            record_segment(PathEvent::OutOfBounces, Color::ZERO);
+            return Lighting::default();
+        }
+        *remaining -= 1;
+
+        // The lights can't be hit by the scattered rays, so they're sampled directly
+        let light = Lighting::from_background(
+            direct_lighting(ray, &object, material.as_ref(), scene),
+            scatter_depth(settings, state) + 1,
+        );
+
+        // Scatter the ray in the material of the object hit
+        let scatter_result = match iors {
+            Some((incident_ior, transmitted_ior)) => material
+                .scatter_between_media(ray, &object, incident_ior, transmitted_ior)
+                .map(|(attenuation, scattered)| ScatterRecord {
+                    attenuation,
+                    scattered,
+                    pdf: 1.0,
+                    is_specular: true,
+                }),
+            None => material.scatter_record(ray, &object),
+        };
+        match scatter_result {
+            Some(record) => {
+                let color = clamp_throughput(
+                    record.weight(material.as_ref(), ray, &object),
+                    state,
+                    settings,
+                );
+                new_state.throughput = state.throughput * color;
+                let new_ray = record.scattered.following(ray);
+                let is_transmitted = new_ray.direction.dot(object.normal) < 0.0;
+
+                record_segment(
+                    match is_transmitted {
+                        true => PathEvent::Transmitted {
+                            specular: record.is_specular,
+                        },
+                        false => PathEvent::Reflected {
+                            specular: record.is_specular,
+                        },
+                    },
+                    color,
+                );
+
+                // Rays going through the surface (instead of being reflected)
+                // enter or leave its medium
+                let mut new_media = media.clone();
+                if let Some(medium) = medium {
+                    if is_transmitted {
+                        new_media.cross(object.object_id, medium, object.front_face);
+                    }
+                }
+
+                // Shoot more rays to simulate ray bounces after scattering
+                // in the current material
+                let incoming = clamp_fireflies(
+                    trace_ray(
+                        &new_ray,
+                        scene,
+                        settings,
+                        new_state,
+                        Visibility::SECONDARY,
+                        &new_media,
+                        path,
+                    ),
+                    settings,
+                    new_state,
+                );
+
+                return (incoming * color + light) * transmittance;
+            }
+            None => {
+                // No more scattering, the ray was absorbed: turn it darker
+                record_segment(PathEvent::Absorbed, Color::ZERO);
+                return light * transmittance;
+            }
+        }
+    }
+
+    // If we got here, it means that our ray didn't hit anything
+    // Let's draw our background!
+    if let Some(path) = path {
+        path.push(RayPathSegment {
+            origin: ray.origin,
+            direction: ray.direction,
+            hit: None,
+            event: PathEvent::Escaped,
+        });
+    }
+    Lighting::from_background(
+        scene.background.color(ray.direction),
+        scatter_depth(settings, state),
+    )
+}
+
+/// Light from the lights of ``scene`` scattered by ``material`` along ``ray``
+/// at ``hit``, with a shadow ray towards each light that reaches it
+fn direct_lighting(ray: &Ray, hit: &HitData, material: &dyn Material, scene: &Scene) -> Color {
+    let mut color = Color::ZERO;
+
+    for light in scene.lights.iter() {
+        let intensity = light.intensity_at(light.position.distance(hit.hit_point));
+        if intensity == Color::ZERO {
+            continue;
+        }
+
+        // Lights with a radius get a different point of their disk for each sample
+        let to_light = light.sample_point(hit.hit_point) - hit.hit_point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            continue;
+        }
+
+        let shadow_ray = Ray::new(hit.hit_point, to_light / distance).following(ray);
+        let Some(scattered) = material.evaluate(ray, hit, &shadow_ray) else {
+            // The material ignores the lights
+            return Color::ZERO;
+        };
+        if scattered == Color::ZERO || scene.hit_any(&shadow_ray, 0.001, distance) {
+            continue;
+        }
+
+        color += scattered * intensity;
+    }
+
+    color
+}
+
+/// How many times a path has scattered, given how many more times it can
+fn scatter_depth(settings: &RenderSettings, state: PathState) -> i32 {
+    (settings.max_diffuse_depth - state.diffuse) + (settings.max_specular_depth - state.specular)
+}
+
+/// Applies the firefly clamp of the settings (if any) to the light carried by a ray
+/// at the given point of a path. The direct and indirect lighting
+/// are scaled together, so that they keep adding up to the clamped color
+fn clamp_fireflies(lighting: Lighting, settings: &RenderSettings, state: PathState) -> Lighting {
+    match &settings.firefly_clamp {
+        Some(firefly_clamp) => {
+            let scale = firefly_clamp.scale(lighting.total(), scatter_depth(settings, state));
+            lighting * Color::splat(scale)
+        }
+        None => lighting,
+    }
+}
+
+// Utility functions
+// ----------------------------------------------------------------------------
+
+/// Linear remap a value in one range into another range (no clamping)
+pub fn fit_range(x: f32, imin: f32, imax: f32, omin: f32, omax: f32) -> f32 {
+    (omax - omin) * (x - imin) / (imax - imin) + omin
+}
+
+/// Like ``fit_range``, but ``x`` is clamped to the input range first, so the result
+/// never leaves the output range. An empty input range maps everything to ``omin``
+pub fn fit_range_clamped(x: f32, imin: f32, imax: f32, omin: f32, omax: f32) -> f32 {
+    if imax == imin {
+        return omin;
+    }
+
+    // The input range can go either way
+    let x = x.clamp(imin.min(imax), imin.max(imax));
+    fit_range(x, imin, imax, omin, omax)
+}
+
+/// Running mean and variance of a stream of samples (Welford's online algorithm)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunningVariance {
+    count: usize,
+    mean: f32,
+    /// Sum of the squared differences from the mean
+    squared_differences: f32,
+}
+
+impl RunningVariance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.count += 1;
+
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f32;
+        self.squared_differences += delta * (sample - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    /// Unbiased sample variance (0.0 until there are at least 2 samples)
+    pub fn variance(&self) -> f32 {
+        if self.count > 1 {
+            self.squared_differences / (self.count - 1) as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// How far the mean is expected to be from the true value
+    pub fn standard_error(&self) -> f32 {
+        if self.count > 0 {
+            (self.variance() / self.count as f32).sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Relative luminance of a scene-linear ACEScg color (AP1 primaries, D60 white)
+pub fn luminance(color: Color) -> f32 {
+    0.272_228_7 * color.x + 0.674_081_8 * color.y + 0.053_689_5 * color.z
+}
+
+/// Range of color temperatures (in Kelvin) supported by ``blackbody``
+const BLACKBODY_MIN_KELVIN: f32 = 1000.0;
+const BLACKBODY_MAX_KELVIN: f32 = 12000.0;
+
+/// Color of the light emitted by an ideal black body at the given temperature
+/// (clamped to the [1000, 12000] K range), as a scene-linear ACEScg color with
+/// a luminance of 1.0. 6500K is close to neutral white (D65 sits slightly off the
+/// black body curve), lower temperatures are warmer and higher ones are cooler.
+pub fn blackbody(kelvin: f32) -> Color {
+    // Physical constants, in meters
+    const PLANCK: f64 = 6.626_070_15e-34;
+    const SPEED_OF_LIGHT: f64 = 2.997_924_58e8;
+    const BOLTZMANN: f64 = 1.380_649e-23;
+
+    let temperature = kelvin.clamp(BLACKBODY_MIN_KELVIN, BLACKBODY_MAX_KELVIN) as f64;
+
+    // Integrate Planck's law against the CIE 1931 color matching functions
+    let mut xyz = DVec3::ZERO;
+    for wavelength_nm in (380..=780).step_by(5) {
+        let wavelength = wavelength_nm as f64 * 1e-9;
+        let radiance = 2.0 * PLANCK * SPEED_OF_LIGHT.powi(2)
+            / wavelength.powi(5)
+            / ((PLANCK * SPEED_OF_LIGHT / (wavelength * BOLTZMANN * temperature)).exp() - 1.0);
+
+        xyz += radiance * spectral::cie_xyz(wavelength_nm as f32).as_dvec3();
+    }
+
+    // Only the chromaticity matters, brightness is up to the user
+    let xyz = xyz / xyz.y;
+
+    // Bradford chromatic adaptation from D65 to D60, the white point of ACEScg,
+    // so that D65 white comes out neutral instead of slightly blue
+    let xyz = DVec3::new(
+        1.013_034_9 * xyz.x + 0.006_105_3 * xyz.y - 0.014_970_9 * xyz.z,
+        0.007_698_2 * xyz.x + 0.998_163_4 * xyz.y - 0.005_032_0 * xyz.z,
+        -0.002_841_3 * xyz.x + 0.004_685_2 * xyz.y + 0.924_506_1 * xyz.z,
+    );
+
+    // CIE XYZ (D60) to ACEScg
+    let acescg = spectral::xyz_to_rgb(WorkingSpace::AcesCg) * xyz.as_vec3();
+
+    acescg.max(Vec3::ZERO)
+}
+
+thread_local! {
+    /// Source of all the random numbers used while tracing paths. The renderer
+    /// reseeds it for each sample, so samples don't depend on which thread
+    /// renders them, or in which order
+    static SAMPLER: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Restarts the random numbers of the paths traced by the current thread from ``seed``
+pub fn seed_sampler(seed: u64) {
+    SAMPLER.with(|sampler| *sampler.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// Runs ``f`` with the random number generator of the current thread
+pub fn with_sampler<T>(f: impl FnOnce(&mut SmallRng) -> T) -> T {
+    SAMPLER.with(|sampler| f(&mut sampler.borrow_mut()))
+}
+
+/// Seed of a single sample of a pixel, decorrelated from the ones of its neighbours
+pub fn pixel_seed(global_seed: u64, x: usize, y: usize, sample_index: usize) -> u64 {
+    // SplitMix64 finalizer, applied after mixing in each coordinate
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    [x as u64, y as u64, sample_index as u64]
+        .into_iter()
+        .fold(mix(global_seed), |hash, value| {
+            mix(hash ^ value.wrapping_add(0x9e3779b97f4a7c15))
+        })
+}
+
+/// Picks the times and the points of the lens of the camera rays of a sample.
+/// They're drawn from streams of random numbers of their own, seeded from the
+/// sample (see ``pixel_seed``), rather than from the sampler of the thread: the
+/// paths get the same random numbers with or without motion blur or depth of field
+pub struct CameraSampler {
+    correlation: SampleCorrelation,
+    time: SmallRng,
+    lens: SmallRng,
+}
+
+impl CameraSampler {
+    pub fn new(sample_seed: u64, correlation: SampleCorrelation) -> Self {
+        // seed_from_u64 scrambles its seed, so flipping a few bits is enough
+        // to get unrelated streams
+        CameraSampler {
+            correlation,
+            time: SmallRng::seed_from_u64(sample_seed ^ 0x74696d65),
+            lens: SmallRng::seed_from_u64(sample_seed ^ 0x6c656e73),
+        }
+    }
+
+    /// The point of the lens (for ``Camera::get_ray_through_lens``) and the time
+    /// (in [0, 1] over the shutter) of the next camera ray. Pinhole cameras only
+    /// have the one point, and the time is 0 without motion blur
+    pub fn next(&mut self, camera: &Camera, settings: &RenderSettings) -> ((f32, f32), f32) {
+        let lens_sample = match camera.has_lens() {
+            true => (self.lens.gen(), self.lens.gen()),
+            false => (0.0, 0.0),
+        };
+
+        let time = match (settings.motion_blur, self.correlation) {
+            (false, _) => 0.0,
+            (true, SampleCorrelation::Correlated) if camera.has_lens() => {
+                settings.shutter_curve.sample_time(lens_sample.0)
+            }
+            (true, _) => settings.shutter_curve.sample_time(self.time.gen()),
+        };
+
+        (lens_sample, time)
+    }
+}
+
+/// Generate a random point in a unit sphere.
+/// Rejection sampling benchmarks slightly faster than the polar method
+/// (see benches/hot_paths.rs): the trigonometry and the cube root cost more
+/// than the rejected tries
+fn random_in_unit_sphere() -> Vec3 {
+    random_in_unit_sphere_rejection()
+}
+
+/// Tries given to the rejection sampling of the unit sphere before giving up.
+/// With a working RNG, the odds of needing more than a handful are negligible
+const MAX_UNIT_SPHERE_TRIES: usize = 64;
+
+/// Generate a random point in a unit sphere by picking points in the
+/// enclosing cube until one falls inside (~1.9 tries on average)
+pub fn random_in_unit_sphere_rejection() -> Vec3 {
+    with_sampler(random_in_unit_sphere_from)
+}
+
+/// Rejection sampling of the unit sphere with the given ``rng``. So that it can
+/// never hang, it falls back to a point on the surface of the sphere after
+/// ``MAX_UNIT_SPHERE_TRIES`` rejected tries
+pub fn random_in_unit_sphere_from(rng: &mut impl Rng) -> Vec3 {
+    // TODO: Investigate using a lazy_static for this
+    let range = Uniform::from(-1.0..1.0);
+
+    for _ in 0..MAX_UNIT_SPHERE_TRIES {
+        let x: f32 = range.sample(rng);
+        let y: f32 = range.sample(rng);
+        let z: f32 = range.sample(rng);
+
+        let p = Vec3::new(x, y, z);
+
+        if p.length_squared() < 1.0 {
+            return p;
+        }
+    }
+
+    // A random direction never needs rejecting, whatever numbers the RNG gives
+    let (u, v): (f32, f32) = rng.gen();
+    let cos_theta = (1.0 - 2.0 * u).clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Generate a random point in a unit sphere without any rejection, by picking
+/// a uniform direction and a radius that accounts for the volume growing with r^3
+pub fn random_in_unit_sphere_polar() -> Vec3 {
+    let (u, v, w): (f32, f32, f32) = with_sampler(|rng| rng.gen());
+
+    let cos_theta = 1.0 - 2.0 * u;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    let radius = w.cbrt();
+
+    radius * Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Generate a random point in a unit sphere
+/// but in the same hemisphere as a Normal vector
+fn random_in_hemisphere(normal: Vec3) -> Vec3 {
+    let in_unit_sphere = random_in_unit_sphere();
+
+    if Vec3::dot(in_unit_sphere, normal) > 0.0 {
+        in_unit_sphere
+    } else {
+        -in_unit_sphere
+    }
+}
+
+/// Orthonormal basis around a normal, to move directions sampled
+/// in a local frame (where the normal is +Z) to world space
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Onb {
+    /// Builds the basis around a normalized ``normal``, with the branchless
+    /// construction from Duff et al., "Building an Orthonormal Basis, Revisited" (2017)
+    pub fn from_normal(normal: Vec3) -> Self {
+        let sign = 1.0_f32.copysign(normal.z);
+        let a = -1.0 / (sign + normal.z);
+        let b = normal.x * normal.y * a;
+
+        Onb {
+            tangent: Vec3::new(
+                1.0 + sign * normal.x * normal.x * a,
+                sign * b,
+                -sign * normal.x,
+            ),
+            bitangent: Vec3::new(b, sign + normal.y * normal.y * a, -normal.y),
+            normal,
+        }
+    }
+
+    /// Expresses a direction given in the local frame of the basis in world space
+    pub fn local_to_world(&self, local: Vec3) -> Vec3 {
+        local.x * self.tangent + local.y * self.bitangent + local.z * self.normal
+    }
+}
+
+/// Returns whether or not the given ``vec`` is close enough to zero
+/// on this machine (uses f32::EPSILON as the 'clone enough' measure).
+fn near_zero(vec: &Vec3) -> bool {
+    vec.x.abs() <= f32::EPSILON && vec.y.abs() <= f32::EPSILON.abs() && vec.z <= f32::EPSILON
+}
+
+/// Given a vector ``vec`` and a normalized vector ``normal``,
+/// return back ``vec`` reflected against ``normal``.
 fn reflect(vec: Vec3, normal: Vec3) -> Vec3 {
     vec - 2.0 * vec.dot(normal) * normal
 }
+
+/// Given a normalized vector ``unit_vec`` and a normalized vector ``normal``,
+/// return back ``unit_vec`` refracted through the surface based on Snell's law.
+/// ``refraction_ratio`` is the ratio between the incident and the transmitted IORs.
+fn refract(unit_vec: Vec3, normal: Vec3, refraction_ratio: f32) -> Vec3 {
+    let cos_theta = (-unit_vec).dot(normal).min(1.0);
+    let perpendicular = refraction_ratio * (unit_vec + cos_theta * normal);
+    let parallel = -(1.0 - perpendicular.length_squared()).abs().sqrt() * normal;
+
+    perpendicular + parallel
+}
+
+/// Schlick's approximation of the Fresnel reflectance
+fn reflectance(cosine: f32, refraction_ratio: f32) -> f32 {
+    let r0 = ((1.0 - refraction_ratio) / (1.0 + refraction_ratio)).powi(2);
+
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Affine3A;
+    use rand::RngCore;
+
+    use super::*;
+    use instance::Instance;
+
+    #[test]
+    fn neighbouring_samples_get_different_seeds() {
+        let seed = pixel_seed(0, 10, 20, 0);
+
+        assert_ne!(seed, pixel_seed(1, 10, 20, 0));
+        assert_ne!(seed, pixel_seed(0, 11, 20, 0));
+        assert_ne!(seed, pixel_seed(0, 10, 21, 0));
+        assert_ne!(seed, pixel_seed(0, 10, 20, 1));
+        // Swapping the coordinates doesn't give the same seed back
+        assert_ne!(pixel_seed(0, 20, 10, 0), seed);
+    }
+
+    fn test_material() -> Arc<dyn Material> {
+        Arc::new(materials::Lambertian::new(Vec3::splat(0.5)))
+    }
+
+    /// Lying flat, with a ring of radius 2 and a tube of radius 0.5
+    fn test_torus() -> Torus {
+        Torus::new(2.0, 0.5, Vec3::ZERO, Vec3::Y, test_material())
+    }
+
+    #[test]
+    fn rays_through_the_ring_body_hit_the_torus() {
+        let torus = test_torus();
+
+        // Straight down onto the top of the tube
+        let ray = Ray::new(Vec3::new(2.0, 5.0, 0.0), Vec3::NEG_Y);
+        let hit = torus.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-4, "hit at t = {}", hit.t);
+        assert!(hit.normal.abs_diff_eq(Vec3::Y, 1e-4));
+
+        // Sideways, into the outer side of the tube
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X);
+        let hit = torus.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-4, "hit at t = {}", hit.t);
+        assert!(hit.normal.abs_diff_eq(Vec3::NEG_X, 1e-4));
+    }
+
+    #[test]
+    fn rays_through_the_hole_miss_the_torus() {
+        let torus = test_torus();
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::NEG_Y);
+
+        assert!(torus.hit_surface(&ray, 0.001, f32::INFINITY).is_none());
+    }
+
+    /// Nearest intersection of ``ray`` with ``sphere`` from the quadratic formula, in f64
+    fn reference_sphere_hit(sphere: &Sphere, ray: &Ray) -> Option<f64> {
+        let center_to_origin = (ray.origin - sphere.center).as_dvec3();
+        let direction = ray.direction.as_dvec3();
+
+        let a = direction.length_squared();
+        let half_b = center_to_origin.dot(direction);
+        let c = center_to_origin.length_squared() - (sphere.radius as f64).powi(2);
+        let discriminant = half_b * half_b - a * c;
+
+        (discriminant >= 0.0).then(|| (-half_b - discriminant.sqrt()) / a)
+    }
+
+    #[test]
+    fn rays_grazing_large_distant_spheres_hit_where_they_should() {
+        let sphere = Sphere::new(100.0, Vec3::new(0.0, 0.0, -10_000.0), test_material());
+
+        for offset in [99.0, 99.9, 99.99] {
+            // Passes just within the silhouette of the sphere
+            let ray = Ray::new(Vec3::ZERO, Vec3::new(offset, 0.0, -10_000.0));
+
+            let expected = reference_sphere_hit(&sphere, &ray).unwrap();
+            let hit = sphere.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+            let error = (hit.t as f64 - expected).abs() / expected;
+            assert!(
+                error < 1e-5,
+                "grazing at {offset}: hit at t = {}, expected {expected}",
+                hit.t
+            );
+        }
+    }
+
+    #[test]
+    fn fit_range_clamped_stays_in_the_output_range() {
+        // In range, like fit_range
+        assert_eq!(fit_range_clamped(0.25, 0.0, 1.0, 10.0, 20.0), 12.5);
+        // Below and above the input range
+        assert_eq!(fit_range_clamped(-3.0, 0.0, 1.0, 10.0, 20.0), 10.0);
+        assert_eq!(fit_range_clamped(7.0, 0.0, 1.0, 10.0, 20.0), 20.0);
+        // No input range to divide by
+        assert_eq!(fit_range_clamped(0.5, 2.0, 2.0, 10.0, 20.0), 10.0);
+    }
+
+    /// Rejects every point: all of its random numbers are as large as they can be
+    struct MaxedOutRng;
+
+    impl RngCore for MaxedOutRng {
+        fn next_u32(&mut self) -> u32 {
+            u32::MAX
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::MAX
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(u8::MAX);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn unit_sphere_sampling_gives_up_on_rngs_it_cant_use() {
+        let p = random_in_unit_sphere_from(&mut MaxedOutRng);
+
+        assert!(p.is_finite(), "{p}");
+        // The fallback, on the surface of the sphere
+        assert!(
+            (p.length() - 1.0).abs() < 1e-5,
+            "{p} isn't on the unit sphere"
+        );
+    }
+
+    #[test]
+    fn onb_vectors_are_orthonormal() {
+        let normals = [
+            Vec3::Z,
+            Vec3::NEG_Z,
+            Vec3::X,
+            Vec3::NEG_Y,
+            // Close to the axes, where the construction is the least stable
+            Vec3::new(1e-4, -1e-4, -1.0),
+            Vec3::new(-1e-6, 1e-6, 1.0),
+            Vec3::new(1.0, 1e-5, 0.0),
+            Vec3::new(0.3, -0.5, 0.8),
+            Vec3::new(-0.9, 0.1, -0.2),
+        ];
+
+        for normal in normals.map(Vec3::normalize) {
+            let onb = Onb::from_normal(normal);
+            for vector in [onb.tangent, onb.bitangent, onb.normal] {
+                assert!(
+                    (vector.length() - 1.0).abs() < 1e-5,
+                    "{vector} isn't normalized, around {normal}"
+                );
+            }
+            for (a, b) in [
+                (onb.tangent, onb.bitangent),
+                (onb.tangent, onb.normal),
+                (onb.bitangent, onb.normal),
+            ] {
+                assert!(a.dot(b).abs() < 1e-5, "{a} and {b}, around {normal}");
+            }
+            assert!(onb.local_to_world(Vec3::Z).abs_diff_eq(normal, 1e-6));
+        }
+    }
+
+    #[test]
+    fn polar_sampling_fills_the_unit_sphere_uniformly() {
+        const COUNT: usize = 100_000;
+        seed_sampler(7);
+        let points: Vec<Vec3> = (0..COUNT).map(|_| random_in_unit_sphere_polar()).collect();
+
+        assert!(points.iter().all(|p| p.length() <= 1.0 + 1e-6));
+
+        // Half the volume of the sphere lies within a radius of 0.5^(1/3)
+        let inner = points.iter().filter(|p| p.length().powi(3) < 0.5).count();
+        let inner_fraction = inner as f32 / COUNT as f32;
+        assert!((inner_fraction - 0.5).abs() < 0.01, "{inner_fraction}");
+
+        let mut octants = [0usize; 8];
+        for p in &points {
+            let octant =
+                (p.x > 0.0) as usize | ((p.y > 0.0) as usize) << 1 | ((p.z > 0.0) as usize) << 2;
+            octants[octant] += 1;
+        }
+        for count in octants {
+            let fraction = count as f32 / COUNT as f32;
+            assert!((fraction - 0.125).abs() < 0.005, "{octants:?}");
+        }
+
+        let mean = points.iter().sum::<Vec3>() / COUNT as f32;
+        assert!(mean.length() < 0.01, "{mean}");
+    }
+
+    /// How many times the paths of ``ray`` scattered, over a few samples
+    fn scatter_counts(ray: &Ray, scene: &Scene, settings: &RenderSettings) -> Vec<usize> {
+        (0..20)
+            .map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                ray_path(ray, scene, settings)
+                    .iter()
+                    .filter(|segment| {
+                        matches!(
+                            segment.event,
+                            PathEvent::Reflected { .. } | PathEvent::Transmitted { .. }
+                        )
+                    })
+                    .count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diffuse_and_specular_bounces_have_their_own_limits() {
+        let settings = RenderSettings {
+            max_diffuse_depth: 3,
+            max_specular_depth: 12,
+            ..RenderSettings::default()
+        };
+
+        // Diffuse paths bouncing between two slabs, until they run out of bounces
+        let scene = parallel_slabs(Arc::new(materials::Lambertian::new(Vec3::splat(0.5))));
+        let diffuse_counts = scatter_counts(&ray_between_slabs(), &scene, &settings);
+        assert!(
+            diffuse_counts.iter().all(|count| *count <= 3),
+            "{diffuse_counts:?}"
+        );
+        assert!(diffuse_counts.contains(&3), "{diffuse_counts:?}");
+
+        // A row of glass spheres, going in and out of each of them
+        let mut scene = Scene::new();
+        for index in 0..5 {
+            let center = Vec3::new(0.0, 0.0, -3.0 * (index + 1) as f32);
+            let glass = Arc::new(materials::Dielectric::new(1.5));
+            scene.add_hittable(Box::new(Sphere::new(1.0, center, glass)));
+        }
+        let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+        let specular_counts = scatter_counts(&ray, &scene, &settings);
+        assert!(
+            specular_counts.iter().all(|count| *count <= 12),
+            "{specular_counts:?}"
+        );
+        assert!(
+            specular_counts.iter().any(|count| *count > 3),
+            "{specular_counts:?}"
+        );
+    }
+
+    /// Two wide slabs of ``material``, 0.2 thick around y = 0 and y = 1
+    fn parallel_slabs(material: Arc<dyn Material>) -> Scene {
+        let slab = Arc::new(Sphere::new(1.0, Vec3::ZERO, material));
+        let flattened = Affine3A::from_scale(Vec3::new(20.0, 0.1, 20.0));
+
+        let mut scene = Scene::new();
+        for y in [0.0, 1.0] {
+            let transform = Affine3A::from_translation(Vec3::new(0.0, y, 0.0)) * flattened;
+            scene.add_hittable(Box::new(Instance::new(slab.clone(), transform)));
+        }
+        scene
+    }
+
+    /// A ray leaving from between the ``parallel_slabs``
+    fn ray_between_slabs() -> Ray {
+        Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::new(1.0, -1.0, 0.0))
+    }
+
+    /// Brightest color of the paths bouncing between two parallel slabs reflecting
+    /// twice the light they receive
+    fn brightest_path_between_amplifying_slabs(max_throughput: Option<f32>) -> f32 {
+        let amplifier = Arc::new(materials::Lambertian::new(Vec3::splat(2.0)));
+        let scene = parallel_slabs(amplifier);
+
+        let settings = RenderSettings {
+            max_diffuse_depth: 16,
+            max_throughput,
+            ..RenderSettings::default()
+        };
+        let ray = ray_between_slabs();
+        (0..500)
+            .map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                ray_color(&ray, &scene, &settings).max_element()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn clamped_paths_dont_blow_up_on_materials_adding_light() {
+        // The background is never brighter than 1, so neither are the clamped paths
+        let max_throughput = 1.5;
+        let clamped = brightest_path_between_amplifying_slabs(Some(max_throughput));
+        assert!(
+            clamped <= max_throughput * (1.0 + 1e-5),
+            "{clamped} is over {max_throughput}"
+        );
+
+        let unclamped = brightest_path_between_amplifying_slabs(None);
+        assert!(unclamped > 4.0, "{unclamped}");
+    }
+
+    /// Direction a ray leaving from the right of the axis comes out of ``scene`` with,
+    /// along the first path that only goes through its surfaces
+    fn transmitted_direction(scene: &Scene) -> Vec3 {
+        let ray = Ray::new(Vec3::new(0.25, 0.0, 5.0), Vec3::NEG_Z);
+        let settings = RenderSettings::default();
+
+        (0..100)
+            .find_map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                let path = ray_path(&ray, scene, &settings);
+                let transmitted = path
+                    .iter()
+                    .filter(|segment| segment.hit.is_some())
+                    .all(|segment| matches!(segment.event, PathEvent::Transmitted { .. }));
+                transmitted.then(|| path.last().unwrap().direction.normalize())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn negative_radius_spheres_make_glass_hollow() {
+        let glass = || -> Arc<dyn Material> { Arc::new(materials::Dielectric::new(1.5)) };
+        let inner = Sphere::new(-0.4, Vec3::ZERO, glass());
+
+        // Hit from the outside, like leaving the glass
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::NEG_Z);
+        let hit = inner.hit_surface(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!(!hit.front_face);
+        assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-6), "{}", hit.normal);
+
+        let mut solid = Scene::new();
+        solid.add_hittable(Box::new(Sphere::new(0.5, Vec3::ZERO, glass())));
+        let mut hollow = Scene::new();
+        hollow.add_hittable(Box::new(Sphere::new(0.5, Vec3::ZERO, glass())));
+        hollow.add_hittable(Box::new(inner));
+
+        // A thin shell of glass barely bends the rays, unlike a ball of glass
+        let solid_direction = transmitted_direction(&solid);
+        let hollow_direction = transmitted_direction(&hollow);
+        assert!(solid_direction.x < -0.3, "{solid_direction}");
+        assert!(
+            hollow_direction.x.abs() < solid_direction.x.abs() / 2.0,
+            "{hollow_direction} vs {solid_direction}"
+        );
+    }
+
+    /// Scatters every ray it gets into ``direction``, with an ``attenuation`` of choice
+    struct ScattersInto {
+        direction: Vec3,
+        attenuation: Color,
+    }
+
+    impl Material for ScattersInto {
+        fn scatter(&self, _ray: &Ray, data: &HitData) -> Option<(Color, Ray)> {
+            Some((self.attenuation, Ray::new(data.hit_point, self.direction)))
+        }
+    }
+
+    #[test]
+    fn degenerate_rays_give_black_instead_of_nans() {
+        let scene = Scene::new();
+        let settings = RenderSettings::default();
+        let degenerate_rays = [
+            Ray::new(Vec3::ZERO, Vec3::ZERO),
+            Ray::new(Vec3::ZERO, Vec3::new(f32::NAN, 0.0, -1.0)),
+            Ray::new(Vec3::splat(f32::INFINITY), Vec3::NEG_Z),
+        ];
+        for ray in degenerate_rays {
+            assert!(ray.is_degenerate());
+            assert_eq!(ray_color(&ray, &scene, &settings), Vec3::ZERO);
+        }
+
+        let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+        assert!(!ray.is_degenerate());
+        assert!(ray_color(&ray, &scene, &settings).min_element() > 0.0);
+
+        // Misbehaving materials can't poison the pixels either
+        let materials = [
+            ScattersInto {
+                direction: Vec3::ZERO,
+                attenuation: Vec3::ONE,
+            },
+            ScattersInto {
+                direction: Vec3::Y,
+                attenuation: Vec3::splat(f32::NAN),
+            },
+        ];
+        for material in materials {
+            let mut scene = Scene::new();
+            let center = Vec3::new(0.0, 0.0, -2.0);
+            scene.add_hittable(Box::new(Sphere::new(1.0, center, Arc::new(material))));
+
+            seed_sampler(pixel_seed(0, 0, 0, 0));
+            let color = ray_color(&ray, &scene, &settings);
+            assert!(color.is_finite(), "{color}");
+        }
+    }
+
+    /// Pearson correlation coefficient of the ``pairs``
+    fn correlation(pairs: &[(f32, f32)]) -> f32 {
+        let count = pairs.len() as f32;
+        let mean_a = pairs.iter().map(|(a, _)| a).sum::<f32>() / count;
+        let mean_b = pairs.iter().map(|(_, b)| b).sum::<f32>() / count;
+        let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+        for (a, b) in pairs {
+            covariance += (a - mean_a) * (b - mean_b);
+            variance_a += (a - mean_a).powi(2);
+            variance_b += (b - mean_b).powi(2);
+        }
+        covariance / (variance_a * variance_b).sqrt()
+    }
+
+    /// The times and lens points ``(time, u, v)`` of the camera rays of many samples,
+    /// with ``correlation``
+    fn camera_samples(correlation: SampleCorrelation) -> Vec<(f32, f32, f32)> {
+        let camera = Camera::new(1.0, 2.0, 2.0).with_lens(0.2, 5.0);
+        let settings = RenderSettings {
+            motion_blur: true,
+            ..RenderSettings::default()
+        };
+
+        (0..65536)
+            .map(|sample_index| {
+                let sample_seed = pixel_seed(0, sample_index % 256, sample_index / 256, 0);
+                let ((u, v), time) =
+                    CameraSampler::new(sample_seed, correlation).next(&camera, &settings);
+                (time, u, v)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn times_and_lens_points_are_independent_by_default() {
+        let samples = camera_samples(SampleCorrelation::Decorrelated);
+        let pairs = |pick: fn(&(f32, f32, f32)) -> (f32, f32)| -> Vec<(f32, f32)> {
+            samples.iter().map(pick).collect()
+        };
+        for (name, pairs) in [
+            ("time and lens u", pairs(|(time, u, _)| (*time, *u))),
+            ("time and lens v", pairs(|(time, _, v)| (*time, *v))),
+            ("lens u and v", pairs(|(_, u, v)| (*u, *v))),
+        ] {
+            let correlation = correlation(&pairs);
+            assert!(correlation.abs() < 0.02, "{name}: {correlation}");
+        }
+
+        // Deliberately tied together
+        let correlated: Vec<(f32, f32)> = camera_samples(SampleCorrelation::Correlated)
+            .into_iter()
+            .map(|(time, u, _)| (time, u))
+            .collect();
+        assert!((correlation(&correlated) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn material_overrides_make_everything_scatter_white() {
+        let colors = [
+            Vec3::new(0.9, 0.1, 0.1),
+            Vec3::new(0.1, 0.9, 0.1),
+            Vec3::new(0.1, 0.1, 0.9),
+        ];
+        let mut scene = Scene::new();
+        for (index, color) in colors.iter().enumerate() {
+            let center = Vec3::new(3.0 * index as f32, 0.0, -5.0);
+            let material = Arc::new(materials::Lambertian::new(*color));
+            scene.add_hittable(Box::new(Sphere::new(1.0, center, material)));
+        }
+        let clay: Arc<dyn Material> = Arc::new(materials::Lambertian::new(Vec3::ONE));
+        let settings = RenderSettings {
+            material_override: Some(clay.clone()),
+            max_diffuse_depth: 1,
+            ..RenderSettings::default()
+        };
+
+        for (index, color) in colors.iter().enumerate() {
+            let ray = Ray::new(Vec3::new(3.0 * index as f32, 0.0, 0.0), Vec3::NEG_Z);
+            seed_sampler(pixel_seed(0, index, 0, 0));
+            let path = ray_path(&ray, &scene, &settings);
+            let vertex = path[0].hit.as_ref().unwrap();
+
+            assert!(Arc::ptr_eq(&vertex.material, &clay));
+            let attenuation = vertex.attenuation;
+            assert!(attenuation.x > 0.0, "{attenuation}");
+            assert!(
+                attenuation.abs_diff_eq(Vec3::splat(attenuation.x), 1e-6),
+                "{attenuation} isn't white, over a sphere of color {color}"
+            );
+            assert_eq!(
+                settings
+                    .material_for(&scene.hit(&ray, 0.001, f32::INFINITY).unwrap())
+                    .albedo(),
+                Vec3::ONE
+            );
+        }
+    }
+
+    #[test]
+    fn clicking_on_a_sphere_focuses_on_it() {
+        let mut scene = Scene::new();
+        scene.add_hittable(Box::new(Sphere::new(
+            1.0,
+            Vec3::new(0.0, 0.0, -4.0),
+            test_material(),
+        )));
+        scene.add_hittable(Box::new(Sphere::new(
+            0.5,
+            Vec3::new(2.0, 1.0, -6.0),
+            test_material(),
+        )));
+        let camera = Camera::new(1.0, 2.0, 2.0);
+        let (width, height) = (21, 21);
+
+        // Right in the middle of the image, in front of the first sphere
+        let distance = camera.focus_distance_at_pixel(&scene, 10, 10, width, height);
+        assert!((distance.unwrap() - 3.0).abs() < 0.01, "{distance:?}");
+        assert_eq!(
+            camera.focus_distance_at_pixel(&scene, 0, 20, width, height),
+            None
+        );
+
+        // Off to the side, over the second sphere: all the rays through the lens
+        // meet where the pixel sees the sphere
+        let (x, y) = (14, 8);
+        let focus_distance = camera
+            .focus_distance_at_pixel(&scene, x, y, width, height)
+            .unwrap();
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (height as f32 - y as f32 - 0.5) / height as f32;
+        let pinhole_ray = camera.get_ray_at_coords(u, v);
+        let hit = scene.hit(&pinhole_ray, 0.001, f32::INFINITY).unwrap();
+
+        let camera = camera.with_lens(0.5, focus_distance);
+        for lens_sample in [(1.0, 0.0), (1.0, 0.25), (0.5, 0.6)] {
+            let ray = camera.get_ray_through_lens(u, v, lens_sample);
+            let to_hit = hit.hit_point - ray.origin;
+            let miss = to_hit.reject_from(ray.direction).length();
+            assert!(miss < 1e-4, "{lens_sample:?} misses by {miss}");
+        }
+    }
+
+    /// A unit sphere centered at ``x`` on the X axis
+    fn sphere_at(x: f32) -> Box<dyn Hittable> {
+        Box::new(Sphere::new(1.0, Vec3::new(x, 0.0, 0.0), test_material()))
+    }
+
+    fn center_x(scene: &Scene, index: usize) -> f32 {
+        let bounds = scene.get(index).unwrap().bounding_box().unwrap();
+        (bounds.min.x + bounds.max.x) / 2.0
+    }
+
+    #[test]
+    fn any_hits_agree_with_closest_hits() {
+        let mut scene = Scene::new();
+        for x in [0.0, 3.0, 6.0] {
+            scene.add_hittable(sphere_at(x));
+        }
+
+        // Straight down from above, with the top of the spheres 4 units away
+        let down = |x: f32| Ray::new(Vec3::new(x, 5.0, 0.0), Vec3::NEG_Y);
+        let cases = [
+            (down(0.0), f32::INFINITY, true),
+            (down(6.2), f32::INFINITY, true),
+            (down(1.5), f32::INFINITY, false),
+            // The occluder lies past the light
+            (down(3.0), 3.5, false),
+            (down(3.0), 4.5, true),
+            // Along the row, through all the spheres
+            (
+                Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::X),
+                f32::INFINITY,
+                true,
+            ),
+            (
+                Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::NEG_X),
+                f32::INFINITY,
+                false,
+            ),
+        ];
+
+        for (ray, t_max, occluded) in cases {
+            assert_eq!(scene.hit_any(&ray, 0.001, t_max), occluded, "{ray:?}");
+            assert_eq!(scene.hit(&ray, 0.001, t_max).is_some(), occluded, "{ray:?}");
+        }
+    }
+
+    #[test]
+    fn picking_gives_the_closest_sphere_and_where_it_was_hit() {
+        let mut scene = Scene::new();
+        for x in [0.0, 3.0, 6.0] {
+            scene.add_hittable(sphere_at(x));
+        }
+
+        // Straight down onto the top of the middle sphere
+        let pick = scene
+            .pick(&Ray::new(Vec3::new(3.0, 5.0, 0.0), Vec3::NEG_Y))
+            .unwrap();
+        assert_eq!(pick.index, 1);
+        assert!(pick.hit_point.abs_diff_eq(Vec3::new(3.0, 1.0, 0.0), 1e-4));
+        assert!(pick.normal.abs_diff_eq(Vec3::Y, 1e-4));
+        assert!((pick.distance - 4.0).abs() < 1e-4);
+
+        // Along the row, the first sphere hides the others
+        let pick = scene
+            .pick(&Ray::new(Vec3::new(10.0, 0.0, 0.0), Vec3::NEG_X))
+            .unwrap();
+        assert_eq!(pick.index, 2);
+        assert!(pick.hit_point.abs_diff_eq(Vec3::new(7.0, 0.0, 0.0), 1e-4));
+
+        assert!(scene
+            .pick(&Ray::new(Vec3::new(1.5, 5.0, 0.0), Vec3::NEG_Y))
+            .is_none());
+    }
+
+    #[test]
+    fn removing_an_element_keeps_the_others_in_order() {
+        let mut scene = Scene::new();
+        scene.add_hittable(sphere_at(-3.0));
+        scene.add_hittable_with_visibility(sphere_at(0.0), Visibility::CAMERA);
+        scene.add_hittable_with_visibility(sphere_at(3.0), Visibility::SHADOW);
+
+        assert!(scene.remove(1).is_some());
+        assert!(scene.remove(2).is_none());
+
+        assert_eq!(scene.len(), 2);
+        assert_eq!(center_x(&scene, 0), -3.0);
+        assert_eq!(center_x(&scene, 1), 3.0);
+        assert_eq!(scene.visibility(0), Visibility::ALL);
+        assert_eq!(scene.visibility(1), Visibility::SHADOW);
+    }
+
+    #[test]
+    fn replacing_an_element_updates_the_hash_grid() {
+        let mut scene = Scene::new();
+        scene.set_accelerator(Accelerator::HashGrid);
+        for x in [-6.0, -3.0, 0.0, 3.0] {
+            scene.add_hittable(sphere_at(x));
+        }
+
+        let ray = Ray::new(Vec3::new(6.0, 0.0, 5.0), Vec3::NEG_Z);
+        assert!(scene.hit(&ray, 0.001, f32::INFINITY).is_none());
+
+        // Built by the first ray, before the last sphere moved under the next one
+        scene.replace(3, sphere_at(6.0)).unwrap();
+        let hit = scene.hit(&ray, 0.001, f32::INFINITY).unwrap();
+        assert_eq!(hit.object_id, 4);
+        assert!((hit.t - 4.0).abs() < 1e-4, "hit at t = {}", hit.t);
+    }
+
+    #[test]
+    fn blackbody_at_6500k_is_neutral() {
+        let color = blackbody(6500.0);
+
+        assert!((luminance(color) - 1.0).abs() < 0.01, "{color}");
+        assert!(
+            color.abs_diff_eq(Vec3::ONE, 0.05),
+            "6500K should be close to white, got {color}"
+        );
+    }
+
+    #[test]
+    fn blackbody_at_3000k_is_warm() {
+        let color = blackbody(3000.0);
+
+        assert!(color.x > color.y && color.y > color.z, "{color}");
+    }
+}
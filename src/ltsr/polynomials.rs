@@ -0,0 +1,135 @@
+//! Closed form solvers for the real roots of polynomials, based on
+//! Jochen Schwarze's "Cubic and Quartic Roots" (Graphics Gems, 1990).
+//! Everything happens in f64, since the quartic is very sensitive to precision.
+
+use std::f64::consts::PI;
+
+/// Values closer than this to zero are considered to be zero
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+/// Real roots of x^2 + b*x + c = 0
+pub fn solve_quadratic(b: f64, c: f64) -> Vec<f64> {
+    let p = b / 2.0;
+    let discriminant = p * p - c;
+
+    if is_zero(discriminant) {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        vec![]
+    } else {
+        let sqrt_discriminant = discriminant.sqrt();
+        vec![-p - sqrt_discriminant, -p + sqrt_discriminant]
+    }
+}
+
+/// Real roots of x^3 + a*x^2 + b*x + c = 0
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Substitute x = y - a/3 to get the depressed cubic y^3 + 3p*y + 2q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let mut roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            // One triple root
+            vec![0.0]
+        } else {
+            // One single and one double root
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // Three distinct real roots (casus irreducibilis)
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + PI / 3.0).cos(),
+            -t * (phi - PI / 3.0).cos(),
+        ]
+    } else {
+        // One real root
+        let sqrt_discriminant = discriminant.sqrt();
+        let u = (sqrt_discriminant - q).cbrt();
+        let v = -(sqrt_discriminant + q).cbrt();
+        vec![u + v]
+    };
+
+    // Resubstitute
+    for root in roots.iter_mut() {
+        *root -= a / 3.0;
+    }
+
+    roots
+}
+
+/// Real roots of x^4 + a*x^3 + b*x^2 + c*x + d = 0, in no particular order.
+/// Each root is refined with a few Newton iterations to reduce the error
+/// introduced by the closed form solution.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Substitute x = y - a/4 to get the depressed quartic y^4 + p*y^2 + q*y + r = 0
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * c / 4.0 + d;
+
+    let mut roots = if is_zero(r) {
+        // No absolute term: y * (y^3 + p*y + q) = 0
+        let mut roots = solve_cubic(0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // Take one root of the resolvent cubic..
+        let z = solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+
+        // ..and use it to split the quartic into two quadratics
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+
+        let v = if q < 0.0 { -v } else { v };
+
+        let mut roots = solve_quadratic(v, z - u);
+        roots.extend(solve_quadratic(-v, z + u));
+        roots
+    };
+
+    // Resubstitute and polish
+    for root in roots.iter_mut() {
+        *root -= a / 4.0;
+
+        for _ in 0..2 {
+            let x = *root;
+            let value = (((x + a) * x + b) * x + c) * x + d;
+            let derivative = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+
+            if derivative.abs() > EPSILON {
+                *root = x - value / derivative;
+            }
+        }
+    }
+
+    roots
+}
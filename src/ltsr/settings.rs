@@ -0,0 +1,1004 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use glam::Vec3;
+use rand::Rng;
+
+use crate::constants::{NUM_SAMPLES_PER_PIXEL, RENDER_BUFFER_HEIGHT, RENDER_BUFFER_WIDTH};
+use crate::ltsr::materials::Material;
+use crate::ltsr::spectral::{MAX_WAVELENGTH, MIN_WAVELENGTH};
+use crate::ltsr::{luminance, with_sampler, Color, HitData, RunningVariance};
+
+/// Knobs controlling how a scene gets rendered
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Maximum number of samples taken for each pixel
+    pub samples_per_pixel: usize,
+    /// Maximum number of bounces off diffuse materials before a path is terminated
+    pub max_diffuse_depth: i32,
+    /// Maximum number of bounces off specular materials (metals, glass, ..)
+    /// before a path is terminated
+    pub max_specular_depth: i32,
+    /// When set, replaces the materials of all the objects in the scene,
+    /// eg: a flat gray Lambertian for a "clay" render
+    pub material_override: Option<Arc<dyn Material>>,
+    /// When set, pixels stop being sampled as soon as they're converged
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// Width of a pixel relative to its height. Values above 1.0 widen the
+    /// horizontal field of view, rendering squeezed (anamorphic) frames
+    pub pixel_aspect_ratio: f32,
+    /// When set, limits how bright the light carried by each ray can be,
+    /// trading a bit of energy for less fireflies
+    pub firefly_clamp: Option<FireflyClamp>,
+    /// When set, no channel of the product of the colors scattered along a path can
+    /// go over this value: a sanity check against materials returning more light
+    /// than they receive, whose paths would otherwise blow up with each bounce.
+    /// Logs a warning the first time it kicks in
+    pub max_throughput: Option<f32>,
+    /// In which order the rows of the rendered buffers are stored
+    pub image_origin: ImageOrigin,
+    /// Seed of the random numbers of all the samples: the same seed gives the same noise
+    pub seed: u64,
+    /// Where the samples of each pixel are placed
+    pub sampling_mode: SamplingMode,
+    /// Width and height in pixels of the square tiles the image is rendered in
+    pub tile_size: usize,
+    /// In which order the tiles are rendered
+    pub tile_order: TileOrder,
+    /// Outputs to compute: only these get allocated and filled
+    pub passes: Vec<RenderPass>,
+    /// What the geometric AOVs hold where the camera rays don't hit anything
+    pub miss_values: MissValues,
+    /// Memory the buffers of a render can take at most, see ``check_resolution``
+    pub max_buffer_bytes: usize,
+    /// Color space the material colors and the rendered colors are in
+    pub working_space: WorkingSpace,
+    /// When set, each camera ray is traced at a random time while the shutter is open,
+    /// blurring the animated objects (see ``AnimatedTransform``). Otherwise they're
+    /// all frozen where they are when the shutter opens
+    pub motion_blur: bool,
+    /// How much each moment of the shutter counts in the motion blur: the times of
+    /// the camera rays are drawn following it
+    pub shutter_curve: ShutterCurve,
+    /// When set, each camera ray carries a single random wavelength instead of RGB
+    /// light, so that dispersive glass (see ``Medium::dispersion``) splits it in rainbows.
+    /// Noisier in color, so it's only worth it for scenes with dispersion
+    pub spectral: bool,
+    /// Rays traced through the lens of the camera for each of the ``samples_per_pixel``
+    /// (antialiasing) samples, each through its own point of the aperture. Smooths out
+    /// the depth of field without sampling the pixels any more. Cameras without a lens
+    /// trace the same ray again, so keep it at 1 for them
+    pub lens_samples: usize,
+    /// How the times (motion blur) and the points of the lens (depth of field)
+    /// of the camera rays relate to each other
+    pub sample_correlation: SampleCorrelation,
+}
+
+/// Default of ``RenderSettings::max_buffer_bytes``: 512MiB, about 5000x5000 pixels
+/// with all the passes
+pub const DEFAULT_MAX_BUFFER_BYTES: usize = 512 * 1024 * 1024;
+
+/// Environment variables setting the defaults of the renders without the GUI
+/// (see ``RenderSettings::with_env_overrides`` and ``headless_resolution``)
+pub const ENV_WIDTH: &str = "LTSR_WIDTH";
+pub const ENV_HEIGHT: &str = "LTSR_HEIGHT";
+pub const ENV_SAMPLES: &str = "LTSR_SAMPLES";
+pub const ENV_SEED: &str = "LTSR_SEED";
+
+/// Ranges the values of the environment variables get clamped to
+const ENV_RESOLUTION_RANGE: RangeInclusive<usize> = 1..=16384;
+const ENV_SAMPLES_RANGE: RangeInclusive<usize> = 1..=65536;
+
+/// Resolution of the renders without the GUI: ``LTSR_WIDTH`` x ``LTSR_HEIGHT``,
+/// each falling back to the size of the render buffer when it isn't set or valid
+pub fn headless_resolution() -> (usize, usize) {
+    (
+        env_value(ENV_WIDTH, ENV_RESOLUTION_RANGE).unwrap_or(RENDER_BUFFER_WIDTH),
+        env_value(ENV_HEIGHT, ENV_RESOLUTION_RANGE).unwrap_or(RENDER_BUFFER_HEIGHT),
+    )
+}
+
+/// Parses the environment variable ``name``, clamped to ``range``.
+/// None when it isn't set, or (with a warning) when it isn't a valid number
+fn env_value<T>(name: &str, range: RangeInclusive<T>) -> Option<T>
+where
+    T: FromStr + PartialOrd + Copy + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    let value = std::env::var(name).ok()?;
+    let parsed: T = match value.trim().parse() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Ignoring {name}='{value}': {e}");
+            return None;
+        }
+    };
+
+    let (min, max) = (*range.start(), *range.end());
+    let clamped = match parsed {
+        parsed if parsed < min => min,
+        parsed if parsed > max => max,
+        parsed => parsed,
+    };
+    if clamped != parsed {
+        eprintln!("{name}={parsed} is outside of [{min}, {max}], using {clamped}");
+    }
+
+    Some(clamped)
+}
+
+/// The RGB color space a render works in. Material colors are read in it, and
+/// the beauty (and the color AOVs) come out in it: only the conversions to the
+/// display and the metadata of saved images depend on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkingSpace {
+    /// ACES AP1 primaries, with the ACES (~D60) white point: a wide gamut,
+    /// where colors mix more like real light does
+    #[default]
+    AcesCg,
+    /// sRGB/Rec.709 primaries without any transfer function, as in most
+    /// ray tracing tutorials, for comparing renders with theirs
+    LinearSrgb,
+}
+
+impl WorkingSpace {
+    pub const ALL: [WorkingSpace; 2] = [WorkingSpace::AcesCg, WorkingSpace::LinearSrgb];
+
+    /// Relative luminance of a color in this working space
+    pub fn luminance(&self, color: Color) -> f32 {
+        match self {
+            WorkingSpace::AcesCg => luminance(color),
+            WorkingSpace::LinearSrgb => {
+                0.212_639 * color.x + 0.715_169 * color.y + 0.072_192 * color.z
+            }
+        }
+    }
+
+    /// CIE xy coordinates of the red, green and blue primaries, and of the white point
+    pub fn chromaticities(&self) -> [(f32, f32); 4] {
+        match self {
+            WorkingSpace::AcesCg => [
+                (0.713, 0.293),
+                (0.165, 0.830),
+                (0.128, 0.044),
+                (0.321_68, 0.337_67),
+            ],
+            WorkingSpace::LinearSrgb => {
+                [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), (0.312_7, 0.329_0)]
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for WorkingSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WorkingSpace::AcesCg => "ACEScg",
+            WorkingSpace::LinearSrgb => "Linear sRGB",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// An output a render can compute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RenderPass {
+    /// The path traced image, along with what comes from its samples
+    /// (variance, sample count, direct and indirect lighting)
+    Beauty,
+    /// The passes below only depend on what the primary rays hit:
+    /// without the beauty, they're rendered with a single ray per pixel
+    Normal,
+    Depth,
+    Albedo,
+    ObjectId,
+}
+
+impl RenderPass {
+    pub const ALL: [RenderPass; 5] = [
+        RenderPass::Beauty,
+        RenderPass::Normal,
+        RenderPass::Depth,
+        RenderPass::Albedo,
+        RenderPass::ObjectId,
+    ];
+
+    /// Number of f32 values stored for each pixel when the pass is computed
+    pub fn floats_per_pixel(&self) -> usize {
+        match self {
+            // RGBA, plus the variance, sample count, direct and indirect AOVs
+            RenderPass::Beauty => 4 + 1 + 1 + 3 + 3,
+            RenderPass::Normal | RenderPass::Albedo => 3,
+            RenderPass::Depth | RenderPass::ObjectId => 1,
+        }
+    }
+}
+
+/// Values of the geometric AOVs for the pixels where none of the primary rays
+/// hit anything, so that composited AOVs behave predictably along the edges
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MissValues {
+    pub normal: Vec3,
+    /// The post-processing (edge detection, focus plane overlay, ..)
+    /// treats depths of 0.0 and below as "nothing was hit"
+    pub depth: f32,
+    pub albedo: Color,
+    /// Objects are numbered from 1, see ``HitData::object_id``
+    pub object_id: f32,
+}
+
+impl Default for MissValues {
+    fn default() -> Self {
+        MissValues {
+            normal: Vec3::ZERO,
+            depth: 0.0,
+            // Black, so that the background doesn't add to the color of the objects
+            albedo: Color::ZERO,
+            object_id: 0.0,
+        }
+    }
+}
+
+impl MissValues {
+    /// The channels written to the ``pass`` AOV for a missed pixel.
+    /// Empty for the beauty, which gets the background instead
+    pub fn channels(&self, pass: RenderPass) -> Vec<f32> {
+        match pass {
+            RenderPass::Beauty => Vec::new(),
+            RenderPass::Normal => self.normal.to_array().to_vec(),
+            RenderPass::Depth => vec![self.depth],
+            RenderPass::Albedo => self.albedo.to_array().to_vec(),
+            RenderPass::ObjectId => vec![self.object_id],
+        }
+    }
+}
+
+/// Order in which the tiles of an image get rendered. With the pixel seeds,
+/// it changes how the image fills in (and the cache reuse), never the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    /// Row by row, from the top left corner
+    #[default]
+    Scanline,
+    /// Z-order curve: recursively, the top left, top right, bottom left
+    /// and bottom right quadrants
+    Morton,
+    /// Hilbert curve, generalized to any number of tiles: consecutive tiles always
+    /// touch (besides a single diagonal step when the grid has an odd width
+    /// and an even height, or vice versa), so the image fills in compact blobs
+    Hilbert,
+}
+
+impl TileOrder {
+    /// Column and row (from the top) of each of the ``columns * rows`` tiles
+    /// of a grid, in the order they should be rendered in
+    pub fn schedule(&self, columns: usize, rows: usize) -> Vec<(usize, usize)> {
+        let mut tiles = Vec::with_capacity(columns * rows);
+
+        match self {
+            TileOrder::Scanline => {
+                for row in 0..rows {
+                    for column in 0..columns {
+                        tiles.push((column, row));
+                    }
+                }
+            }
+            TileOrder::Morton => {
+                for row in 0..rows {
+                    for column in 0..columns {
+                        tiles.push((column, row));
+                    }
+                }
+                tiles.sort_by_key(|(column, row)| morton_code(*column, *row));
+            }
+            TileOrder::Hilbert => {
+                if columns >= rows {
+                    gilbert(
+                        &mut tiles,
+                        (0, 0),
+                        (columns as isize, 0),
+                        (0, rows as isize),
+                    );
+                } else {
+                    gilbert(
+                        &mut tiles,
+                        (0, 0),
+                        (0, rows as isize),
+                        (columns as isize, 0),
+                    );
+                }
+            }
+        }
+
+        tiles
+    }
+}
+
+/// Interleaves the bits of ``x`` and ``y`` (x in the even bits)
+fn morton_code(x: usize, y: usize) -> u64 {
+    let spread = |value: usize| {
+        let mut value = value as u64 & 0xffff_ffff;
+        value = (value | (value << 16)) & 0x0000_ffff_0000_ffff;
+        value = (value | (value << 8)) & 0x00ff_00ff_00ff_00ff;
+        value = (value | (value << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+        (value | (value << 1)) & 0x5555_5555_5555_5555
+    };
+
+    spread(x) | (spread(y) << 1)
+}
+
+/// Generalized Hilbert curve ("gilbert", by Jakub Červený) filling the rectangle
+/// spanned by ``a`` (the major direction) and ``b`` from ``start``
+fn gilbert(
+    tiles: &mut Vec<(usize, usize)>,
+    start: (isize, isize),
+    a: (isize, isize),
+    b: (isize, isize),
+) {
+    let (mut x, mut y) = start;
+    let width = (a.0 + a.1).abs();
+    let height = (b.0 + b.1).abs();
+    let (dax, day) = (a.0.signum(), a.1.signum());
+    let (dbx, dby) = (b.0.signum(), b.1.signum());
+
+    // Trivial rows and columns
+    if height == 1 || width == 1 {
+        let (step_x, step_y, length) = if height == 1 {
+            (dax, day, width)
+        } else {
+            (dbx, dby, height)
+        };
+        for _ in 0..length {
+            tiles.push((x as usize, y as usize));
+            x += step_x;
+            y += step_y;
+        }
+        return;
+    }
+
+    let mut a2 = (a.0.div_euclid(2), a.1.div_euclid(2));
+    let mut b2 = (b.0.div_euclid(2), b.1.div_euclid(2));
+    let width2 = (a2.0 + a2.1).abs();
+    let height2 = (b2.0 + b2.1).abs();
+
+    if 2 * width > 3 * height {
+        // Long rectangle: split it in two halves along the major direction,
+        // preferring even sizes
+        if width2 % 2 != 0 && width > 2 {
+            a2 = (a2.0 + dax, a2.1 + day);
+        }
+        gilbert(tiles, (x, y), a2, b);
+        gilbert(tiles, (x + a2.0, y + a2.1), (a.0 - a2.0, a.1 - a2.1), b);
+    } else {
+        // One step up, a long stretch along the major direction, and one step down
+        if height2 % 2 != 0 && height > 2 {
+            b2 = (b2.0 + dbx, b2.1 + dby);
+        }
+        gilbert(tiles, (x, y), b2, a2);
+        gilbert(tiles, (x + b2.0, y + b2.1), a, (b.0 - b2.0, b.1 - b2.1));
+        gilbert(
+            tiles,
+            (
+                x + (a.0 - dax) + (b2.0 - dbx),
+                y + (a.1 - day) + (b2.1 - dby),
+            ),
+            (-b2.0, -b2.1),
+            (-(a.0 - a2.0), -(a.1 - a2.1)),
+        );
+    }
+}
+
+/// How the samples of a pixel are spread over its area (for antialiasing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingMode {
+    /// Uniformly random positions
+    #[default]
+    Random,
+    /// The same fixed positions in every pixel, on a square grid rotated so that no
+    /// two samples share a row or a column. Without the variance of random jitter,
+    /// the antialiasing is repeatable, eg: for comparisons against reference images.
+    /// Best with a square number of samples per pixel (4, 16, 64..): otherwise
+    /// the last row of the grid is only partially filled
+    RotatedGrid,
+}
+
+impl SamplingMode {
+    /// Position of the ``sample_index``-th of ``samples_per_pixel`` samples,
+    /// as an offset in [0, 1) from the corner of the pixel
+    pub fn sample_offset(&self, sample_index: usize, samples_per_pixel: usize) -> (f32, f32) {
+        match self {
+            SamplingMode::Random => with_sampler(|rng| rng.gen()),
+            SamplingMode::RotatedGrid => {
+                let grid_size = (samples_per_pixel as f32).sqrt().ceil().max(1.0) as usize;
+                let column = (sample_index % grid_size) as f32;
+                let row = (sample_index / grid_size % grid_size) as f32;
+                let n = grid_size as f32;
+
+                // A n x n grid rotated by atan(1/n): each sample gets its own
+                // sub-column and sub-row out of n^2, eg: for 4 samples,
+                // (0.375, 0.125), (0.875, 0.375), (0.125, 0.625) and (0.625, 0.875)
+                (
+                    (column + (n - 1.0 - row + 0.5) / n) / n,
+                    (row + (column + 0.5) / n) / n,
+                )
+            }
+        }
+    }
+}
+
+/// How the random numbers picking the time and the point of the lens of the camera
+/// rays relate to each other (see ``CameraSampler``). Either way, they're independent
+/// from the position of the samples in the pixel, and from the rest of the paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleCorrelation {
+    /// Times and points of the lens come from streams of random numbers of their own:
+    /// any point of the lens sees any moment of the shutter, and the motion blur and
+    /// the depth of field blend into plain noise. Turning either of them on or off
+    /// doesn't change the noise of the other one
+    #[default]
+    Decorrelated,
+    /// Each ray is traced at a time given by its distance from the center of the lens,
+    /// from the shutter opening at the center to it closing at the edge. The blurs
+    /// are then tied together: moving objects that are out of focus get a structured
+    /// blur instead of noise, and it doesn't converge to the same image. Only meant
+    /// for comparisons, or to show what bad correlation looks like
+    Correlated,
+}
+
+/// How open the shutter of the camera is over the time of an exposure, weighting
+/// the moments the motion blur is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutterCurve {
+    /// Fully open from start to end: every moment counts the same
+    #[default]
+    Box,
+    /// Opening and closing linearly, fully open only at mid-exposure. Like real
+    /// shutters, which take time to move: the blurred trails fade out at both ends
+    Triangle,
+}
+
+impl ShutterCurve {
+    /// Time (in [0, 1] over the shutter) for a uniform ``random`` number in [0, 1),
+    /// going through the inverse of the CDF of the curve
+    pub fn sample_time(&self, random: f32) -> f32 {
+        match self {
+            ShutterCurve::Box => random,
+            // The CDF is 2t² over the first half, and 1 - 2(1 - t)² over the second
+            ShutterCurve::Triangle => match random < 0.5 {
+                true => (random / 2.0).sqrt(),
+                false => 1.0 - ((1.0 - random) / 2.0).sqrt(),
+            },
+        }
+    }
+}
+
+/// Where the first row of an image buffer sits in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageOrigin {
+    /// The first row is the top one, like in OpenEXR files and in the GUI
+    #[default]
+    TopLeft,
+    /// The first row is the bottom one, like in OpenGL textures
+    BottomLeft,
+}
+
+impl ImageOrigin {
+    /// Index in the buffer of the row that's ``row_from_top`` rows from the top
+    /// of an image ``height`` rows tall. This works both ways: it also gives how
+    /// far from the top the row stored at a given index of the buffer is
+    pub fn buffer_row(&self, row_from_top: usize, height: usize) -> usize {
+        match self {
+            ImageOrigin::TopLeft => row_from_top,
+            ImageOrigin::BottomLeft => height - 1 - row_from_top,
+        }
+    }
+
+    /// Reorders the rows of ``buffer`` (``row_length`` values each) so that
+    /// the first one is the top row
+    pub fn to_top_left<T>(&self, buffer: &mut [T], row_length: usize) {
+        if *self == ImageOrigin::BottomLeft {
+            let height = buffer.len() / row_length;
+            for row in 0..height / 2 {
+                let (top, bottom) = buffer.split_at_mut((height - 1 - row) * row_length);
+                top[row * row_length..(row + 1) * row_length]
+                    .swap_with_slice(&mut bottom[..row_length]);
+            }
+        }
+    }
+}
+
+/// Controls how many samples each pixel gets, based on how noisy it is
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    /// Samples always taken before checking whether a pixel is converged
+    pub min_samples: usize,
+    /// Maximum standard error (relative for the luminance) left in a converged pixel
+    pub tolerance: f32,
+}
+
+impl AdaptiveSampling {
+    /// A pixel is converged when both:
+    /// - the standard error of its mean luminance is within ``tolerance``
+    ///   (relative to the luminance itself, so dark pixels aren't oversampled)
+    /// - the standard error of its coverage (the fraction of primary rays that
+    ///   hit geometry) is within ``tolerance``
+    ///
+    /// The coverage term catches the edges (geometric, defocused or motion
+    /// blurred) where the luminance on both sides happens to be similar:
+    /// partially covered pixels have a high coverage variance, so they keep
+    /// receiving samples until their alpha is clean too.
+    pub fn is_converged(&self, luminance: &RunningVariance, coverage: &RunningVariance) -> bool {
+        if luminance.count() < self.min_samples.max(2) {
+            return false;
+        }
+
+        // Avoid dividing by zero on black pixels
+        let relative_error = luminance.standard_error() / luminance.mean().abs().max(1e-2);
+
+        relative_error <= self.tolerance && coverage.standard_error() <= self.tolerance
+    }
+}
+
+/// Clamps the light carried by rays, more and more aggressively the deeper they are
+/// in a path: deep indirect bounces rarely carry legitimately huge energy, while
+/// clamping the primary hits would dull the highlights
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FireflyClamp {
+    /// Maximum value of any channel of the light carried by camera rays
+    pub max_value: f32,
+    /// How much the maximum value changes with each bounce, usually in (0, 1]
+    pub depth_multiplier: f32,
+}
+
+impl FireflyClamp {
+    /// Maximum value of any channel of the light carried by a ray
+    /// after ``depth`` bounces (0 for camera rays)
+    pub fn max_value_at_depth(&self, depth: i32) -> f32 {
+        self.max_value * self.depth_multiplier.powi(depth)
+    }
+
+    /// Scales ``color`` down so that none of its channels exceed the maximum value
+    /// at the given ``depth``, preserving its hue
+    pub fn apply(&self, color: Color, depth: i32) -> Color {
+        color * self.scale(color, depth)
+    }
+
+    /// What ``apply`` multiplies ``color`` by: 1.0 when it's within the maximum value
+    pub fn scale(&self, color: Color, depth: i32) -> f32 {
+        let max_value = self.max_value_at_depth(depth);
+        let brightest = color.max_element();
+
+        if brightest > max_value {
+            max_value / brightest
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Default for FireflyClamp {
+    fn default() -> Self {
+        FireflyClamp {
+            max_value: 10.0,
+            depth_multiplier: 0.5,
+        }
+    }
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling {
+            min_samples: 8,
+            tolerance: 0.02,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The material that should be used to shade the given hit
+    pub fn material_for<'a>(&'a self, hit: &HitData<'a>) -> &'a Arc<dyn Material> {
+        self.material_override.as_ref().unwrap_or(hit.material)
+    }
+
+    /// Whether ``pass`` should be computed
+    pub fn wants(&self, pass: RenderPass) -> bool {
+        self.passes.contains(&pass)
+    }
+
+    /// Bytes taken by the buffers of the requested passes for an image of the
+    /// given size, None if it doesn't even fit in a ``usize``
+    pub fn buffer_bytes(&self, width: usize, height: usize) -> Option<usize> {
+        let floats_per_pixel: usize = self.passes.iter().map(|pass| pass.floats_per_pixel()).sum();
+
+        width
+            .checked_mul(height)?
+            .checked_mul(floats_per_pixel)?
+            .checked_mul(std::mem::size_of::<f32>())
+    }
+
+    /// Checks that an image of the given size can be rendered: it isn't empty, and its
+    /// buffers fit within ``max_buffer_bytes``. Meant to run before allocating anything,
+    /// so that a mistyped resolution fails cleanly instead of running out of memory
+    pub fn check_resolution(&self, width: usize, height: usize) -> anyhow::Result<()> {
+        if width == 0 || height == 0 {
+            anyhow::bail!("Invalid resolution {width}x{height}: it must be at least 1x1");
+        }
+
+        match self.buffer_bytes(width, height) {
+            Some(bytes) if bytes <= self.max_buffer_bytes => Ok(()),
+            bytes => {
+                let needed = match bytes {
+                    Some(bytes) => format!("{:.1}MiB", bytes as f64 / (1024.0 * 1024.0)),
+                    None => String::from("more memory than can be addressed"),
+                };
+                anyhow::bail!(
+                    "Resolution {width}x{height} is too large: its buffers would take {needed}, \
+                     over the limit of {:.1}MiB",
+                    self.max_buffer_bytes as f64 / (1024.0 * 1024.0)
+                );
+            }
+        }
+    }
+
+    /// Whether the samples of a render with ``other`` can be averaged with the ones of a
+    /// render with these settings, see ``render_image_from``: the settings can only
+    /// differ by their number of samples. Each sample must also land in the same place
+    /// whatever the number of samples, which rules out adaptive sampling and the rotated grid
+    pub fn can_accumulate_with(&self, other: &RenderSettings) -> bool {
+        let same_override = match (&self.material_override, &other.material_override) {
+            (Some(material), Some(other_material)) => Arc::ptr_eq(material, other_material),
+            (None, None) => true,
+            _ => false,
+        };
+
+        same_override
+            && self.adaptive_sampling.is_none()
+            && other.adaptive_sampling.is_none()
+            && self.sampling_mode == SamplingMode::Random
+            && other.sampling_mode == SamplingMode::Random
+            && self.max_diffuse_depth == other.max_diffuse_depth
+            && self.max_specular_depth == other.max_specular_depth
+            && self.pixel_aspect_ratio == other.pixel_aspect_ratio
+            && self.firefly_clamp == other.firefly_clamp
+            && self.max_throughput == other.max_throughput
+            && self.image_origin == other.image_origin
+            && self.seed == other.seed
+            && self.wants(RenderPass::Beauty)
+            && self.passes == other.passes
+            && self.miss_values == other.miss_values
+            && self.working_space == other.working_space
+            && self.motion_blur == other.motion_blur
+            && self.shutter_curve == other.shutter_curve
+            && self.spectral == other.spectral
+            && self.lens_samples == other.lens_samples
+            && self.sample_correlation == other.sample_correlation
+    }
+
+    /// Camera rays traced for each pixel: every antialiasing sample goes through the
+    /// lens ``lens_samples`` times
+    pub fn primary_rays_per_pixel(&self) -> usize {
+        self.samples_per_pixel * self.lens_samples.max(1)
+    }
+
+    /// Wavelength of a camera ray, in nanometers: drawn from the sampler in spectral
+    /// renders, None for RGB ones
+    pub fn sample_wavelength(&self) -> Option<f32> {
+        self.spectral
+            .then(|| with_sampler(|rng| rng.gen_range(MIN_WAVELENGTH..MAX_WAVELENGTH)))
+    }
+
+    /// These settings, with the samples per pixel and the seed taken from the
+    /// ``LTSR_SAMPLES`` and ``LTSR_SEED`` environment variables when they're set,
+    /// to sweep them from scripts. Invalid values are ignored, with a warning
+    pub fn with_env_overrides(&self) -> RenderSettings {
+        RenderSettings {
+            samples_per_pixel: env_value(ENV_SAMPLES, ENV_SAMPLES_RANGE)
+                .unwrap_or(self.samples_per_pixel),
+            seed: env_value(ENV_SEED, 0..=u64::MAX).unwrap_or(self.seed),
+            ..self.clone()
+        }
+    }
+
+    /// Settings to render again at a higher quality: twice the samples,
+    /// and at least the samples and the bounces of ``Quality::Final``
+    pub fn refined(&self) -> RenderSettings {
+        let final_settings = Quality::Final.render_settings();
+
+        RenderSettings {
+            samples_per_pixel: (2 * self.samples_per_pixel).max(final_settings.samples_per_pixel),
+            max_diffuse_depth: self.max_diffuse_depth.max(final_settings.max_diffuse_depth),
+            max_specular_depth: self
+                .max_specular_depth
+                .max(final_settings.max_specular_depth),
+            ..self.clone()
+        }
+    }
+
+    /// Aspect ratio of the area seen by the camera for an image of the given size
+    pub fn viewport_aspect_ratio(&self, image_width: usize, image_height: usize) -> f32 {
+        image_width as f32 * self.pixel_aspect_ratio / image_height as f32
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            samples_per_pixel: NUM_SAMPLES_PER_PIXEL,
+            max_diffuse_depth: 5,
+            max_specular_depth: 5,
+            material_override: None,
+            adaptive_sampling: None,
+            pixel_aspect_ratio: 1.0,
+            firefly_clamp: None,
+            max_throughput: None,
+            image_origin: ImageOrigin::default(),
+            seed: 0,
+            sampling_mode: SamplingMode::default(),
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            passes: RenderPass::ALL.to_vec(),
+            miss_values: MissValues::default(),
+            max_buffer_bytes: DEFAULT_MAX_BUFFER_BYTES,
+            working_space: WorkingSpace::default(),
+            motion_blur: false,
+            shutter_curve: ShutterCurve::default(),
+            spectral: false,
+            lens_samples: 1,
+            sample_correlation: SampleCorrelation::default(),
+        }
+    }
+}
+
+/// Presets trading quality for speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    /// Few samples and short paths, for near instant feedback
+    Preview,
+    /// The full settings
+    #[default]
+    Final,
+}
+
+impl Quality {
+    /// Concrete settings implementing the preset
+    pub fn render_settings(&self) -> RenderSettings {
+        match self {
+            Quality::Preview => RenderSettings {
+                samples_per_pixel: 4,
+                max_diffuse_depth: 2,
+                max_specular_depth: 2,
+                ..RenderSettings::default()
+            },
+            Quality::Final => RenderSettings::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The environment is shared by the tests running in parallel:
+    // each test sets variables of its own
+
+    #[test]
+    fn env_values_are_parsed_and_clamped() {
+        std::env::set_var("LTSR_TEST_VALID", " 64 ");
+        std::env::set_var("LTSR_TEST_TOO_LARGE", "100000");
+        std::env::set_var("LTSR_TEST_ZERO", "0");
+
+        assert_eq!(env_value("LTSR_TEST_VALID", ENV_SAMPLES_RANGE), Some(64));
+        assert_eq!(
+            env_value("LTSR_TEST_TOO_LARGE", ENV_SAMPLES_RANGE),
+            Some(65536)
+        );
+        assert_eq!(env_value("LTSR_TEST_ZERO", ENV_SAMPLES_RANGE), Some(1));
+        assert_eq!(env_value("LTSR_TEST_UNSET", ENV_SAMPLES_RANGE), None);
+    }
+
+    #[test]
+    fn invalid_env_values_are_ignored() {
+        std::env::set_var("LTSR_TEST_NOT_A_NUMBER", "lots");
+        std::env::set_var("LTSR_TEST_NEGATIVE", "-4");
+        std::env::set_var("LTSR_TEST_EMPTY", "");
+
+        for name in [
+            "LTSR_TEST_NOT_A_NUMBER",
+            "LTSR_TEST_NEGATIVE",
+            "LTSR_TEST_EMPTY",
+        ] {
+            assert_eq!(env_value(name, ENV_SAMPLES_RANGE), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn env_overrides_replace_the_samples_and_the_seed() {
+        let settings = RenderSettings {
+            samples_per_pixel: 8,
+            seed: 3,
+            ..RenderSettings::default()
+        };
+
+        std::env::set_var(ENV_SAMPLES, "32");
+        std::env::set_var(ENV_SEED, "1234");
+        let overridden = settings.with_env_overrides();
+        assert_eq!(overridden.samples_per_pixel, 32);
+        assert_eq!(overridden.seed, 1234);
+
+        // Invalid values fall back to the settings
+        std::env::set_var(ENV_SAMPLES, "many");
+        std::env::set_var(ENV_SEED, "-1");
+        let fallback = settings.with_env_overrides();
+        assert_eq!(fallback.samples_per_pixel, 8);
+        assert_eq!(fallback.seed, 3);
+
+        std::env::remove_var(ENV_SAMPLES);
+        std::env::remove_var(ENV_SEED);
+        let unset = settings.with_env_overrides();
+        assert_eq!(unset.samples_per_pixel, 8);
+        assert_eq!(unset.seed, 3);
+    }
+
+    #[test]
+    fn oversized_resolutions_are_rejected() {
+        let settings = RenderSettings::default();
+        assert!(settings.check_resolution(1920, 1080).is_ok());
+
+        let error = settings.check_resolution(100_000, 100_000).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("100000x100000"), "{message}");
+        assert!(message.contains("MiB"), "{message}");
+    }
+
+    #[test]
+    fn empty_resolutions_are_rejected() {
+        let settings = RenderSettings::default();
+
+        assert!(settings.check_resolution(0, 0).is_err());
+        assert!(settings.check_resolution(0, 1080).is_err());
+        assert!(settings.check_resolution(1920, 0).is_err());
+    }
+
+    #[test]
+    fn resolutions_overflowing_the_buffer_size_are_rejected() {
+        let settings = RenderSettings {
+            max_buffer_bytes: usize::MAX,
+            ..RenderSettings::default()
+        };
+        assert_eq!(settings.buffer_bytes(usize::MAX, 2), None);
+
+        let error = settings.check_resolution(usize::MAX, 2).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("more memory than can be addressed"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn tile_orders_visit_every_tile_once() {
+        let orders = [TileOrder::Scanline, TileOrder::Morton, TileOrder::Hilbert];
+
+        for (columns, rows) in [(5, 3), (4, 4), (1, 7), (7, 1), (6, 3), (3, 8)] {
+            let mut all_tiles = TileOrder::Scanline.schedule(columns, rows);
+            all_tiles.sort();
+
+            for order in orders {
+                let mut tiles = order.schedule(columns, rows);
+                assert_eq!(tiles.len(), columns * rows, "{order:?} on {columns}x{rows}");
+
+                tiles.sort();
+                tiles.dedup();
+                assert_eq!(tiles, all_tiles, "{order:?} on {columns}x{rows}");
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_tiles_follow_each_other() {
+        for (columns, rows) in [(5, 3), (4, 4), (1, 7), (8, 8), (6, 3), (3, 8), (7, 4)] {
+            let tiles = TileOrder::Hilbert.schedule(columns, rows);
+
+            let mut diagonal_steps = 0;
+            for step in tiles.windows(2) {
+                let dx = step[0].0.abs_diff(step[1].0);
+                let dy = step[0].1.abs_diff(step[1].1);
+                match (dx, dy) {
+                    (0, 1) | (1, 0) => {}
+                    (1, 1) => diagonal_steps += 1,
+                    _ => panic!("{:?} to {:?} on {columns}x{rows}", step[0], step[1]),
+                }
+            }
+
+            // Only grids with an odd and an even side need a diagonal step
+            let max_diagonal_steps = usize::from(columns % 2 != rows % 2);
+            assert!(
+                diagonal_steps <= max_diagonal_steps,
+                "{diagonal_steps} diagonal steps on {columns}x{rows}"
+            );
+        }
+    }
+
+    #[test]
+    fn rotated_grids_of_4_samples_land_on_fixed_offsets() {
+        let offsets: Vec<_> = (0..4)
+            .map(|sample_index| SamplingMode::RotatedGrid.sample_offset(sample_index, 4))
+            .collect();
+
+        assert_eq!(
+            offsets,
+            [
+                (0.375, 0.125),
+                (0.875, 0.375),
+                (0.125, 0.625),
+                (0.625, 0.875)
+            ]
+        );
+    }
+
+    #[test]
+    fn anamorphic_pixels_widen_the_viewport() {
+        let square = RenderSettings::default();
+        let squeezed = RenderSettings {
+            pixel_aspect_ratio: 2.0,
+            ..RenderSettings::default()
+        };
+
+        for (width, height) in [(1920, 1080), (64, 64), (100, 300)] {
+            let expected = width as f32 / height as f32;
+            assert_eq!(square.viewport_aspect_ratio(width, height), expected);
+            assert_eq!(
+                squeezed.viewport_aspect_ratio(width, height),
+                2.0 * expected
+            );
+        }
+    }
+
+    #[test]
+    fn fireflies_get_clamped_harder_deeper_in_the_paths() {
+        let clamp = FireflyClamp::default();
+        let firefly = Vec3::new(20.0, 10.0, 5.0);
+
+        assert_eq!(clamp.apply(firefly, 0), Vec3::new(10.0, 5.0, 2.5));
+        assert_eq!(clamp.apply(firefly, 4), Vec3::new(0.625, 0.3125, 0.15625));
+        // Dim enough values are left alone
+        assert_eq!(clamp.apply(Vec3::splat(0.5), 4), Vec3::splat(0.5));
+    }
+
+    /// How many of ``num_samples`` evenly spread random numbers ``curve`` turns into
+    /// times falling in each tenth of the shutter
+    fn shutter_histogram(curve: ShutterCurve, num_samples: usize) -> [usize; 10] {
+        let mut histogram = [0; 10];
+        for sample_index in 0..num_samples {
+            let random = (sample_index as f32 + 0.5) / num_samples as f32;
+            let time = curve.sample_time(random);
+            assert!((0.0..=1.0).contains(&time), "{time}");
+            histogram[((time * 10.0) as usize).min(9)] += 1;
+        }
+        histogram
+    }
+
+    #[test]
+    fn triangle_shutters_sample_mid_exposure_more_than_the_ends() {
+        let num_samples = 100_000;
+        let histogram = shutter_histogram(ShutterCurve::Triangle, num_samples);
+        // The fraction of the area under the triangle over each tenth
+        let expected = [0.02, 0.06, 0.10, 0.14, 0.18, 0.18, 0.14, 0.10, 0.06, 0.02];
+        for (count, expected) in histogram.iter().zip(expected) {
+            let fraction = *count as f32 / num_samples as f32;
+            assert!((fraction - expected).abs() < 1e-3, "{histogram:?}");
+        }
+        assert_eq!(ShutterCurve::Triangle.sample_time(0.5), 0.5);
+
+        // Box shutters keep the times uniform
+        let histogram = shutter_histogram(ShutterCurve::Box, num_samples);
+        assert!(
+            histogram.iter().all(|count| *count == num_samples / 10),
+            "{histogram:?}"
+        );
+    }
+}
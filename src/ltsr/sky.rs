@@ -0,0 +1,163 @@
+use glam::Vec3;
+
+use crate::ltsr::{luminance, Color};
+
+/// Zenith of a clear sky with the sun high up
+const NOON_ZENITH: Color = Vec3::new(0.25, 0.45, 0.95);
+/// Zenith of a clear sky with the sun on the horizon
+const DUSK_ZENITH: Color = Vec3::new(0.08, 0.12, 0.35);
+/// Horizon with the sun high up
+const NOON_HORIZON: Color = Vec3::new(0.95, 0.93, 0.88);
+/// Horizon with the sun on it
+const SUNSET_HORIZON: Color = Vec3::new(1.0, 0.55, 0.25);
+
+/// What the rays that don't hit anything see
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Background {
+    /// White at the nadir to light blue at the zenith
+    #[default]
+    Gradient,
+    /// A sky over a ground plane, see ``Sky``
+    Sky(Sky),
+}
+
+impl Background {
+    /// Light coming from ``direction`` (which doesn't need to be normalized)
+    pub fn color(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Gradient => {
+                let unit_direction = direction.normalize();
+
+                // Two colors of our background
+                let white = Vec3::new(1.0, 1.0, 1.0);
+                let blue = Vec3::new(0.5, 0.7, 1.0);
+
+                // Perform the lerp:
+                let t = 0.5 * (unit_direction.y + 1.0);
+                white.lerp(blue, t)
+            }
+            Background::Sky(sky) => sky.color(direction),
+        }
+    }
+}
+
+/// A daylight sky over a flat ground, loosely after Hosek-Wilkie: a blue zenith fading
+/// into a brighter and warmer horizon, with a glow around the sun. It isn't physically
+/// based, but it gives outdoor scenes a believable dome out of a handful of parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    /// Haziness of the air, from 1 (pristine) to 10 (hazy): hazier skies are paler,
+    /// with a wider horizon and a wider glow around the sun
+    pub turbidity: f32,
+    /// Angle between the sun and the horizon, in degrees. The lower the sun,
+    /// the darker the sky and the more orange the horizon
+    pub sun_elevation: f32,
+    /// Angle of the sun around the vertical, in degrees, from -Z towards +X
+    pub sun_azimuth: f32,
+    /// Seen below the horizon
+    pub ground_color: Color,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky {
+            turbidity: 3.0,
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            ground_color: Vec3::new(0.3, 0.28, 0.25),
+        }
+    }
+}
+
+impl Sky {
+    /// Unit vector pointing towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let elevation = self.sun_elevation.to_radians();
+        let azimuth = self.sun_azimuth.to_radians();
+
+        Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        )
+    }
+
+    /// Light coming from ``direction`` (which doesn't need to be normalized)
+    pub fn color(&self, direction: Vec3) -> Color {
+        let direction = direction.normalize();
+        if direction.y < 0.0 {
+            return self.ground_color;
+        }
+
+        let haze = (self.turbidity.clamp(1.0, 10.0) - 1.0) / 9.0;
+        // 1 with the sun at the zenith, 0 once it has set
+        let daylight = self.sun_elevation.to_radians().sin().clamp(0.0, 1.0).sqrt();
+
+        // Haze washes the blue of the zenith out
+        let zenith = DUSK_ZENITH.lerp(NOON_ZENITH, daylight);
+        let zenith = zenith.lerp(Vec3::splat(luminance(zenith)), 0.6 * haze);
+        let horizon = SUNSET_HORIZON.lerp(NOON_HORIZON, daylight);
+
+        // The horizon band spreads higher up in hazy skies
+        let horizon_falloff = 8.0 - 6.0 * haze;
+        let horizon_weight = (1.0 - direction.y).powf(horizon_falloff);
+        let sky = zenith.lerp(horizon, horizon_weight);
+
+        let sun_alignment = direction.dot(self.sun_direction()).max(0.0);
+        let glow = sun_alignment.powf(32.0 - 24.0 * haze) * (0.3 + 0.7 * haze);
+
+        (sky + horizon * glow) * (0.25 + 0.75 * daylight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How blue a color is, relative to its red
+    fn blueness(color: Color) -> f32 {
+        color.z / color.x
+    }
+
+    #[test]
+    fn the_zenith_is_bluer_than_the_horizon() {
+        let sky = Sky::default();
+
+        // Looking away from the sun
+        let zenith = sky.color(Vec3::Y);
+        let horizon = sky.color(Vec3::new(0.0, 0.01, 1.0));
+        assert!(
+            blueness(zenith) > blueness(horizon),
+            "{zenith} vs {horizon}"
+        );
+    }
+
+    #[test]
+    fn below_the_horizon_is_the_ground() {
+        let sky = Sky::default();
+
+        for direction in [
+            Vec3::NEG_Y,
+            Vec3::new(1.0, -0.01, 0.0),
+            Vec3::new(-3.0, -2.0, 1.0),
+        ] {
+            assert_eq!(sky.color(direction), sky.ground_color);
+        }
+        assert_eq!(Background::Sky(sky).color(Vec3::NEG_Y), sky.ground_color);
+    }
+
+    #[test]
+    fn sunsets_warm_the_horizon_up() {
+        let noon = Sky {
+            sun_elevation: 80.0,
+            ..Sky::default()
+        };
+        let sunset = Sky {
+            sun_elevation: 2.0,
+            ..Sky::default()
+        };
+
+        let horizon = Vec3::new(0.0, 0.01, 1.0);
+        assert!(blueness(sunset.color(horizon)) < blueness(noon.color(horizon)));
+    }
+}
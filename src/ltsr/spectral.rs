@@ -0,0 +1,179 @@
+use std::sync::OnceLock;
+
+use glam::{Mat3, Vec3};
+
+use crate::ltsr::settings::WorkingSpace;
+use crate::ltsr::Color;
+
+/// Range of visible wavelengths sampled by spectral renders, in nanometers
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// Wavelength the indices of refraction are given at (the sodium D line), in nanometers
+pub const REFERENCE_WAVELENGTH: f32 = 589.3;
+
+/// Index of refraction at ``wavelength`` (in nanometers) of a medium with index ``ior``
+/// at the ``REFERENCE_WAVELENGTH``, following Cauchy's equation: n = A + B / λ².
+/// ``dispersion`` is B, in μm² (eg: ~0.0042 for crown glass, ~0.01 for dense flint)
+pub fn cauchy_ior(ior: f32, dispersion: f32, wavelength: f32) -> f32 {
+    let inverse_square = |nanometers: f32| {
+        let micrometers = nanometers / 1000.0;
+        1.0 / (micrometers * micrometers)
+    };
+
+    ior + dispersion * (inverse_square(wavelength) - inverse_square(REFERENCE_WAVELENGTH))
+}
+
+/// CIE 1931 2° color matching functions at ``wavelength`` (in nanometers), using the
+/// multi-lobe fit from "Simple Analytic Approximations to the CIE XYZ Color Matching
+/// Functions" (Wyman, Sloan and Shirley, 2013)
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+    // A gaussian with a different width on each side of its peak
+    let lobe = |mean: f32, left_width: f32, right_width: f32| {
+        let width = if wavelength < mean {
+            left_width
+        } else {
+            right_width
+        };
+        let t = (wavelength - mean) / width;
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// What a sample traced at ``wavelength`` gets multiplied by, to turn the light it
+/// carries into the color that wavelength contributes in ``working_space``.
+/// Averaged over all the visible wavelengths, the weights give back white, so that
+/// spectral renders only differ from RGB ones where the wavelength matters (dispersion)
+pub fn wavelength_weight(wavelength: f32, working_space: WorkingSpace) -> Color {
+    static NORMALIZATION: OnceLock<[Color; 2]> = OnceLock::new();
+
+    let normalization = NORMALIZATION.get_or_init(|| {
+        WorkingSpace::ALL.map(|working_space| {
+            // Average of the weights over the visible range, 1nm at a time
+            let steps = (MAX_WAVELENGTH - MIN_WAVELENGTH) as usize;
+            let sum: Color = (0..steps)
+                .map(|step| unnormalized_weight(MIN_WAVELENGTH + step as f32 + 0.5, working_space))
+                .sum();
+            sum / steps as f32
+        })
+    });
+    let index = WorkingSpace::ALL
+        .iter()
+        .position(|space| *space == working_space)
+        .unwrap_or_default();
+
+    unnormalized_weight(wavelength, working_space) / normalization[index]
+}
+
+/// The color matching functions in ``working_space``. Most spectral colors lie outside
+/// of its gamut: negative values are clipped, so that single samples can't go negative
+fn unnormalized_weight(wavelength: f32, working_space: WorkingSpace) -> Color {
+    (xyz_to_rgb(working_space) * cie_xyz(wavelength)).max(Color::ZERO)
+}
+
+/// From CIE XYZ to the primaries of ``working_space``, without adapting the white point:
+/// the spectral weights are normalized to white instead, and ``blackbody`` adapts its
+/// colors itself
+pub fn xyz_to_rgb(working_space: WorkingSpace) -> Mat3 {
+    match working_space {
+        WorkingSpace::AcesCg => Mat3::from_cols_array(&[
+            1.641_023_4,
+            -0.663_662_9,
+            0.011_721_9,
+            -0.324_803_3,
+            1.615_331_6,
+            -0.008_284_4,
+            -0.236_424_7,
+            0.016_756_3,
+            0.988_394_9,
+        ]),
+        WorkingSpace::LinearSrgb => Mat3::from_cols_array(&[
+            3.240_97,
+            -0.969_243_6,
+            0.055_630_1,
+            -1.537_383_2,
+            1.875_967_5,
+            -0.203_977,
+            -0.498_610_8,
+            0.041_555_1,
+            1.056_971_5,
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ltsr::materials::{Dielectric, Material};
+    use crate::ltsr::{pixel_seed, seed_sampler, HitData, Ray};
+
+    /// Direction a ray of ``wavelength`` refracts into ``glass`` with, coming in
+    /// at 45° to a flat surface facing up
+    fn refracted_direction(glass: Dielectric, wavelength: f32) -> Vec3 {
+        let material: Arc<dyn Material> = Arc::new(glass);
+        let data = HitData {
+            hit_point: Vec3::ZERO,
+            normal: Vec3::Y,
+            material: &material,
+            t: 1.0,
+            front_face: true,
+            object_id: 1,
+        };
+        let ray = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0))
+            .with_wavelength(Some(wavelength));
+
+        // The first sample that doesn't get reflected
+        (0..100)
+            .find_map(|sample_index| {
+                seed_sampler(pixel_seed(0, 0, 0, sample_index));
+                let (_, scattered) = material.scatter(&ray, &data).unwrap();
+                let direction = scattered.direction.normalize();
+                (direction.y < 0.0).then_some(direction)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn dispersive_glass_bends_blue_more_than_red() {
+        let dispersive = || Dielectric::new(1.5).with_dispersion(0.01);
+        let blue = refracted_direction(dispersive(), 450.0);
+        let red = refracted_direction(dispersive(), 650.0);
+        // Closer to the normal
+        assert!(blue.x < red.x - 1e-3, "{blue} vs {red}");
+
+        let plain = || Dielectric::new(1.5);
+        let blue = refracted_direction(plain(), 450.0);
+        let red = refracted_direction(plain(), 650.0);
+        assert!(blue.abs_diff_eq(red, 1e-6), "{blue} vs {red}");
+    }
+
+    #[test]
+    fn wavelength_weights_average_to_white() {
+        for working_space in WorkingSpace::ALL {
+            let steps = 4000;
+            let average = (0..steps)
+                .map(|step| {
+                    let t = (step as f32 + 0.5) / steps as f32;
+                    let wavelength = MIN_WAVELENGTH + t * (MAX_WAVELENGTH - MIN_WAVELENGTH);
+                    wavelength_weight(wavelength, working_space)
+                })
+                .sum::<Color>()
+                / steps as f32;
+            assert!(average.abs_diff_eq(Vec3::ONE, 1e-2), "{average}");
+
+            let blue = wavelength_weight(450.0, working_space);
+            assert!(blue.z > blue.x && blue.z > blue.y, "{blue}");
+            let red = wavelength_weight(650.0, working_space);
+            assert!(red.x > red.y && red.x > red.z, "{red}");
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::Vec3;
+
+use crate::ltsr::materials::{Dielectric, Lambertian, Material, Metallic};
+use crate::ltsr::{Scene, Sphere};
+
+/// Columns expected in each row, after the header
+const CSV_COLUMNS: [&str; 8] = ["cx", "cy", "cz", "radius", "r", "g", "b", "material_type"];
+
+/// Index of refraction given to the dielectric spheres (glass)
+const CSV_DIELECTRIC_IOR: f32 = 1.5;
+
+/// Parses spheres from CSV rows of ``cx,cy,cz,radius,r,g,b,material_type``,
+/// where ``material_type`` is one of ``lambertian``, ``metallic`` (perfect mirror)
+/// or ``dielectric`` (glass, the color is ignored).
+/// The first line is a header and gets skipped, as do empty lines.
+pub fn parse_spheres_csv(csv: &str) -> anyhow::Result<Vec<Sphere>> {
+    let mut spheres = Vec::new();
+
+    for (line_index, line) in csv.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if fields.len() != CSV_COLUMNS.len() {
+            anyhow::bail!(
+                "Line {line_number}: expected {} columns ({}), found {}",
+                CSV_COLUMNS.len(),
+                CSV_COLUMNS.join(","),
+                fields.len()
+            );
+        }
+
+        let mut numbers = [0.0; 7];
+        for (index, number) in numbers.iter_mut().enumerate() {
+            *number = match fields[index].parse() {
+                Ok(number) => number,
+                Err(e) => {
+                    anyhow::bail!(
+                        "Line {line_number}: invalid {} '{}': {e}",
+                        CSV_COLUMNS[index],
+                        fields[index]
+                    );
+                }
+            };
+        }
+
+        let [cx, cy, cz, radius, r, g, b] = numbers;
+        let color = Vec3::new(r, g, b);
+
+        let material: Arc<dyn Material> = match fields[7] {
+            "lambertian" => Arc::new(Lambertian::new(color)),
+            "metallic" => Arc::new(Metallic::new(color, 0.0)),
+            "dielectric" => Arc::new(Dielectric::new(CSV_DIELECTRIC_IOR)),
+            other => {
+                anyhow::bail!(
+                    "Line {line_number}: unknown material_type '{other}', \
+                    expected lambertian, metallic or dielectric"
+                );
+            }
+        };
+
+        spheres.push(Sphere::new(radius, Vec3::new(cx, cy, cz), material));
+    }
+
+    Ok(spheres)
+}
+
+/// Loads a scene made only of the spheres listed in a CSV file (see ``parse_spheres_csv``)
+pub fn load_spheres_csv(path: impl AsRef<Path>) -> anyhow::Result<Scene> {
+    let path = path.as_ref();
+
+    let csv = match std::fs::read_to_string(path) {
+        Ok(csv) => csv,
+        Err(e) => {
+            anyhow::bail!("Failed to read spheres file {}: {e}", path.display());
+        }
+    };
+
+    let spheres = match parse_spheres_csv(&csv) {
+        Ok(spheres) => spheres,
+        Err(e) => {
+            anyhow::bail!("Failed to parse spheres file {}: {e}", path.display());
+        }
+    };
+
+    let mut scene = Scene::new();
+    for sphere in spheres {
+        scene.add_hittable(Box::new(sphere));
+    }
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPHERES: &str = "cx,cy,cz,radius,r,g,b,material_type
+        0,-100.5,-1,100,0.5,0.5,0.5,lambertian
+
+        1.0, 0.0, -1.0, 0.5, 0.8, 0.6, 0.2, metallic
+        -1,0,-1,0.5,1,1,1,dielectric
+    ";
+
+    #[test]
+    fn each_row_gives_a_sphere() {
+        let spheres = parse_spheres_csv(SPHERES).unwrap();
+
+        assert_eq!(spheres.len(), 3);
+        assert_eq!(spheres[0].center, Vec3::new(0.0, -100.5, -1.0));
+        assert_eq!(spheres[0].radius, 100.0);
+        assert_eq!(spheres[0].material.albedo(), Vec3::splat(0.5));
+        assert_eq!(spheres[1].center, Vec3::new(1.0, 0.0, -1.0));
+        assert_eq!(spheres[1].material.albedo(), Vec3::new(0.8, 0.6, 0.2));
+        let glass = spheres[2].material.medium().unwrap();
+        assert_eq!(glass.ior, CSV_DIELECTRIC_IOR);
+    }
+
+    #[test]
+    fn parse_errors_give_the_line_and_the_column() {
+        let csv = "cx,cy,cz,radius,r,g,b,material_type\n0,0,0,1,1,1,1,lambertian\n0,0,0,big,1,1,1,lambertian";
+
+        let Err(error) = parse_spheres_csv(csv) else {
+            panic!("the radius isn't a number");
+        };
+        let message = error.to_string();
+        assert!(message.contains("Line 3"), "{message}");
+        assert!(message.contains("radius 'big'"), "{message}");
+    }
+}
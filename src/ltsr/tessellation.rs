@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::ltsr::Sphere;
+
+/// Triangles sharing their vertices, eg: to compare an analytic surface against
+/// a meshed one, or to hand it over to other tools (see ``TriangleMesh::to_obj``)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub vertices: Vec<Vec3>,
+    /// Shading normal of each vertex
+    pub normals: Vec<Vec3>,
+    /// Indices of the vertices of each triangle, counterclockwise seen from the outside
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    /// The mesh in the Wavefront OBJ format, with a normal per vertex
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::new();
+
+        // Writing to a String can't fail
+        for vertex in self.vertices.iter() {
+            let _ = writeln!(obj, "v {} {} {}", vertex.x, vertex.y, vertex.z);
+        }
+        for normal in self.normals.iter() {
+            let _ = writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z);
+        }
+        // OBJ indices start from 1
+        for face in self.faces.iter() {
+            let [a, b, c] = face.map(|index| index + 1);
+            let _ = writeln!(obj, "f {a}//{a} {b}//{b} {c}//{c}");
+        }
+
+        obj
+    }
+
+    /// Saves the mesh as an OBJ file (eg: ``sphere.obj``)
+    pub fn save_obj(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        match std::fs::write(path, self.to_obj()) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                anyhow::bail!("Failed to write mesh {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Meshes ``sphere`` as an icosphere: an icosahedron whose triangles are split
+/// in 4 ``subdivisions`` times, with all the vertices pushed out to the surface.
+/// This gives ``20 * 4^subdivisions`` triangles of nearly the same size,
+/// sharing ``10 * 4^subdivisions + 2`` vertices
+pub fn tessellate_sphere(sphere: &Sphere, subdivisions: usize) -> TriangleMesh {
+    // The 12 vertices of an icosahedron lie on 3 orthogonal golden rectangles
+    let phi = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut directions: Vec<Vec3> = [
+        (-1.0, phi, 0.0),
+        (1.0, phi, 0.0),
+        (-1.0, -phi, 0.0),
+        (1.0, -phi, 0.0),
+        (0.0, -1.0, phi),
+        (0.0, 1.0, phi),
+        (0.0, -1.0, -phi),
+        (0.0, 1.0, -phi),
+        (phi, 0.0, -1.0),
+        (phi, 0.0, 1.0),
+        (-phi, 0.0, -1.0),
+        (-phi, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+
+    let mut faces = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Neighbouring triangles share the midpoints of their common edges
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize, directions: &mut Vec<Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                directions.push((directions[a] + directions[b]).normalize());
+                directions.len() - 1
+            })
+        };
+
+        let mut subdivided_faces = Vec::with_capacity(faces.len() * 4);
+        for [a, b, c] in faces {
+            let ab = midpoint(a, b, &mut directions);
+            let bc = midpoint(b, c, &mut directions);
+            let ca = midpoint(c, a, &mut directions);
+
+            subdivided_faces.extend_from_slice(&[
+                [a, ab, ca],
+                [b, bc, ab],
+                [c, ca, bc],
+                [ab, bc, ca],
+            ]);
+        }
+        faces = subdivided_faces;
+    }
+
+    TriangleMesh {
+        vertices: directions
+            .iter()
+            .map(|direction| sphere.center + sphere.radius * *direction)
+            .collect(),
+        normals: directions,
+        faces,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ltsr::materials::Lambertian;
+
+    fn test_sphere() -> Sphere {
+        let material = Arc::new(Lambertian::new(Vec3::splat(0.5)));
+        Sphere::new(2.5, Vec3::new(1.0, -2.0, 3.0), material)
+    }
+
+    #[test]
+    fn each_subdivision_splits_the_faces_in_4_on_the_surface() {
+        let sphere = test_sphere();
+        for subdivisions in 0..4 {
+            let mesh = tessellate_sphere(&sphere, subdivisions);
+
+            let splits = 4_usize.pow(subdivisions as u32);
+            assert_eq!(mesh.vertices.len(), 10 * splits + 2);
+            assert_eq!(mesh.normals.len(), mesh.vertices.len());
+            assert_eq!(mesh.faces.len(), 20 * splits);
+
+            for vertex in mesh.vertices.iter() {
+                let distance = vertex.distance(sphere.center);
+                assert!(
+                    (distance - sphere.radius).abs() < 1e-5,
+                    "{vertex} is {distance} away"
+                );
+            }
+            // Counterclockwise from the outside
+            for [a, b, c] in mesh.faces.iter().map(|face| face.map(|i| mesh.vertices[i])) {
+                let outwards = (a + b + c) / 3.0 - sphere.center;
+                assert!((b - a).cross(c - a).dot(outwards) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn obj_faces_point_at_their_vertices_and_normals_from_1() {
+        let obj = tessellate_sphere(&test_sphere(), 1).to_obj();
+        let lines_starting_with =
+            |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+
+        assert_eq!(lines_starting_with("v "), 42);
+        assert_eq!(lines_starting_with("vn "), 42);
+        assert_eq!(lines_starting_with("f "), 80);
+        assert!(obj.contains("f 1//1 "));
+        assert!(!obj.contains("//0"));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::ltsr::Color;
+
+/// A likely mistake in the setup of a scene, see ``Scene::validate``
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneWarning {
+    /// There's nothing to render: the image would only show the background
+    Empty,
+    /// Something is off with the element at ``index`` in ``Scene::elements``
+    Element { index: usize, issue: SetupIssue },
+    /// Something is off with the light at ``index`` in ``Scene::lights``
+    Light { index: usize, issue: SetupIssue },
+}
+
+/// What looks wrong with an element (its geometry or its material) or a light
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetupIssue {
+    /// A position or a size is NaN or infinite
+    NonFinite,
+    /// A sphere (or a torus tube) of radius 0 can't be hit
+    ZeroRadius,
+    /// A negative radius turns the normals of a sphere inwards: that's how glass is made
+    /// hollow, but other materials (without a medium) render inside out
+    NegativeRadius,
+    /// Roughness outside of [0, 1]
+    RoughnessOutOfRange(f32),
+    /// Index of refraction that isn't a positive number
+    InvalidIor(f32),
+    /// Colors outside of [0, 1] reflect more light than they receive (or less than none)
+    AlbedoOutOfRange(Color),
+    /// The parameter with the given name should not be negative
+    /// (eg: an absorption, or the intensity of a light)
+    Negative(&'static str),
+}
+
+impl SetupIssue {
+    pub fn check_finite(values: &[f32]) -> Option<SetupIssue> {
+        (!values.iter().all(|value| value.is_finite())).then_some(SetupIssue::NonFinite)
+    }
+
+    pub fn check_roughness(roughness: f32) -> Option<SetupIssue> {
+        (!(0.0..=1.0).contains(&roughness)).then_some(SetupIssue::RoughnessOutOfRange(roughness))
+    }
+
+    pub fn check_ior(ior: f32) -> Option<SetupIssue> {
+        (!(ior.is_finite() && ior > 0.0)).then_some(SetupIssue::InvalidIor(ior))
+    }
+
+    pub fn check_albedo(albedo: Color) -> Option<SetupIssue> {
+        let in_range = albedo.cmpge(Color::ZERO).all() && albedo.cmple(Color::ONE).all();
+        (!in_range).then_some(SetupIssue::AlbedoOutOfRange(albedo))
+    }
+
+    /// ``values`` of the parameter called ``name``
+    pub fn check_not_negative(name: &'static str, values: &[f32]) -> Option<SetupIssue> {
+        // NaNs aren't negative, but they're no better
+        (!values.iter().all(|value| *value >= 0.0)).then_some(SetupIssue::Negative(name))
+    }
+}
+
+impl std::fmt::Display for SceneWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneWarning::Empty => write!(f, "The scene is empty, only the background will show"),
+            SceneWarning::Element { index, issue } => write!(f, "Element {index}: {issue}"),
+            SceneWarning::Light { index, issue } => write!(f, "Light {index}: {issue}"),
+        }
+    }
+}
+
+impl std::fmt::Display for SetupIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetupIssue::NonFinite => write!(f, "NaN or infinite position or size"),
+            SetupIssue::ZeroRadius => write!(f, "radius of 0, it can't be hit"),
+            SetupIssue::NegativeRadius => {
+                write!(
+                    f,
+                    "negative radius on a material without a medium, it renders inside out"
+                )
+            }
+            SetupIssue::RoughnessOutOfRange(roughness) => {
+                write!(f, "roughness {roughness} outside of [0, 1]")
+            }
+            SetupIssue::InvalidIor(ior) => write!(f, "index of refraction {ior} isn't positive"),
+            SetupIssue::AlbedoOutOfRange(albedo) => write!(f, "albedo {albedo} outside of [0, 1]"),
+            SetupIssue::Negative(name) => write!(f, "negative {name}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::Vec3;
+
+    use super::*;
+    use crate::ltsr::lights::PointLight;
+    use crate::ltsr::materials::{Dielectric, Lambertian, Material, Metallic};
+    use crate::ltsr::{Scene, Sphere};
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vec3::splat(0.5)))
+    }
+
+    fn sphere(radius: f32, material: Arc<dyn Material>) -> Box<Sphere> {
+        Box::new(Sphere::new(radius, Vec3::new(0.0, 0.0, -1.0), material))
+    }
+
+    #[test]
+    fn empty_scenes_only_show_the_background() {
+        assert_eq!(Scene::new().validate(), Err(vec![SceneWarning::Empty]));
+    }
+
+    #[test]
+    fn each_mistake_is_reported_with_what_it_was_found_on() {
+        let mut scene = Scene::new();
+        // Fine, hollow glass included
+        scene.add_hittable(sphere(1.0, gray()));
+        scene.add_hittable(sphere(-0.9, Arc::new(Dielectric::new(1.5))));
+        scene.add_light(PointLight::new(Vec3::Y, Vec3::ONE));
+        assert_eq!(scene.validate(), Ok(()));
+
+        let nan_center = Sphere::new(1.0, Vec3::new(f32::NAN, 0.0, 0.0), gray());
+        let rough_metal = Metallic {
+            albedo: Vec3::splat(0.5),
+            roughness: 2.0,
+        };
+        let absorbing = Dielectric::new(1.5).with_absorption(Vec3::new(0.1, -0.2, 0.3));
+        scene.add_hittable(Box::new(nan_center));
+        scene.add_hittable(sphere(0.0, gray()));
+        scene.add_hittable(sphere(-1.0, gray()));
+        scene.add_hittable(sphere(1.0, Arc::new(rough_metal)));
+        scene.add_hittable(sphere(1.0, Arc::new(Dielectric::new(-1.5))));
+        scene.add_hittable(sphere(1.0, Arc::new(Lambertian::new(Vec3::splat(1.5)))));
+        scene.add_hittable(sphere(1.0, Arc::new(absorbing)));
+        scene.add_light(PointLight::new(Vec3::Y, Vec3::splat(-1.0)));
+
+        let element = |index, issue| SceneWarning::Element { index, issue };
+        assert_eq!(
+            scene.validate(),
+            Err(vec![
+                element(2, SetupIssue::NonFinite),
+                element(3, SetupIssue::ZeroRadius),
+                element(4, SetupIssue::NegativeRadius),
+                element(5, SetupIssue::RoughnessOutOfRange(2.0)),
+                element(6, SetupIssue::InvalidIor(-1.5)),
+                element(7, SetupIssue::AlbedoOutOfRange(Vec3::splat(1.5))),
+                element(8, SetupIssue::Negative("absorption")),
+                SceneWarning::Light {
+                    index: 1,
+                    issue: SetupIssue::Negative("intensity")
+                },
+            ])
+        );
+    }
+}
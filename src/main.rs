@@ -1,15 +1,13 @@
 use iced::Application;
 use iced::Settings;
 
-mod app;
-mod constants;
-mod ltsr;
-
-use app::LTSRApp;
-use constants::FONT_BYTES;
+use lets_trace_some_rays_in_rust::app::LTSRApp;
+use lets_trace_some_rays_in_rust::constants::FONT_BYTES;
 
 fn main() {
-    let mut settings = Settings::default();
-    settings.default_font = Some(FONT_BYTES);
+    let settings = Settings {
+        default_font: Some(FONT_BYTES),
+        ..Settings::default()
+    };
     LTSRApp::run(settings).unwrap();
 }
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use exr::prelude::read_first_rgba_layer_from_file;
+
+use lets_trace_some_rays_in_rust::app::filesystem::save_exr_image_to_disk;
+use lets_trace_some_rays_in_rust::app::rendering::{
+    convert_to_openexr, default_scene, render_image, ExrSettings, RenderControl,
+};
+use lets_trace_some_rays_in_rust::ltsr::settings::{ImageOrigin, RenderPass, RenderSettings};
+use lets_trace_some_rays_in_rust::ltsr::Camera;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// Set to regenerate the reference images instead of comparing against them
+const UPDATE_REFERENCES: &str = "LTSR_UPDATE_REFERENCES";
+
+/// Largest mean squared error allowed between a render and its reference. Renders
+/// are exactly reproducible with a fixed seed: this only leaves room for the last bits
+/// of the floating point math, which can differ across platforms
+const MAX_MEAN_SQUARED_ERROR: f64 = 1e-6;
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("references")
+        .join(format!("{name}.exr"))
+}
+
+/// RGB pixels of an OpenEXR image, from the top row down
+fn read_rgb(path: &Path) -> Vec<f32> {
+    let image = read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| vec![0.0; resolution.width() * resolution.height() * 3],
+        |pixels: &mut Vec<f32>, position, (r, g, b, _): (f32, f32, f32, f32)| {
+            let index = (position.y() * WIDTH + position.x()) * 3;
+            pixels[index..index + 3].copy_from_slice(&[r, g, b]);
+        },
+    )
+    .unwrap_or_else(|e| {
+        panic!(
+            "Failed to read the reference image {}: {e}. Generate it with \
+             {UPDATE_REFERENCES}=1 cargo test --test reference_render",
+            path.display()
+        )
+    });
+
+    image.layer_data.channel_data.pixels
+}
+
+/// The three spheres of the default scene, over the ground sphere. After an intended
+/// change to the look of the renders, regenerate the reference image with
+/// ``LTSR_UPDATE_REFERENCES=1 cargo test --test reference_render``
+#[test]
+fn default_scene_matches_its_reference() {
+    let scene = default_scene();
+    let camera = Camera::new(1.0, 4.0, 2.0);
+    let settings = RenderSettings {
+        samples_per_pixel: 16,
+        seed: 1,
+        passes: vec![RenderPass::Beauty],
+        image_origin: ImageOrigin::TopLeft,
+        ..RenderSettings::default()
+    };
+
+    let result = render_image(
+        &scene,
+        &camera,
+        &settings,
+        WIDTH,
+        HEIGHT,
+        &RenderControl::new(),
+    );
+    let beauty = result.beauty.expect("The render has no beauty");
+
+    let path = reference_path("default_scene");
+    if std::env::var_os(UPDATE_REFERENCES).is_some() {
+        let image = convert_to_openexr(
+            WIDTH,
+            HEIGHT,
+            Some(&beauty),
+            &HashMap::new(),
+            settings.image_origin,
+            settings.working_space,
+            ExrSettings::default(),
+        )
+        .unwrap();
+        save_exr_image_to_disk(image, &path).unwrap();
+        return;
+    }
+
+    let reference = read_rgb(&path);
+    let rendered: Vec<f32> = beauty
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    assert_eq!(rendered.len(), reference.len());
+
+    let mean_squared_error = rendered
+        .iter()
+        .zip(reference.iter())
+        .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+        .sum::<f64>()
+        / rendered.len() as f64;
+    assert!(
+        mean_squared_error <= MAX_MEAN_SQUARED_ERROR,
+        "The render differs from {} (mean squared error {mean_squared_error:e}). If the change \
+         is intended, regenerate it with {UPDATE_REFERENCES}=1 cargo test --test reference_render",
+        path.display()
+    );
+}